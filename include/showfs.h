@@ -0,0 +1,42 @@
+/*
+ * the C API of showfs, built with the ffi feature as a shared library:
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * handles are not thread safe, each tree and its files must be used by one thread.
+ * functions returning pointers set errno and return NULL on errors, the others return
+ * negative errnos.
+ */
+#ifndef SHOWFS_H
+#define SHOWFS_H
+
+#include <stdint.h>
+#include <sys/types.h>
+
+typedef struct showfs_tree showfs_tree;
+typedef struct showfs_file showfs_file;
+
+typedef struct showfs_entry {
+    char *name;
+    /* the type and the permissions, like st_mode. */
+    uint32_t mode;
+    uint64_t size;
+    int64_t mtime;
+} showfs_entry;
+
+/* opens the tree of the target, a file or a directory, archives in which are shown as
+ * directories. up to max_cache bytes of archived files are cached. */
+showfs_tree *showfs_tree_open(const char *target, uint64_t max_cache);
+void showfs_tree_free(showfs_tree *tree);
+
+/* lists the directory at the path like "/a.zip/dir", entries are freed by
+ * showfs_entries_free. */
+int showfs_list_dir(showfs_tree *tree, const char *path, showfs_entry **entries, size_t *len);
+void showfs_entries_free(showfs_entry *entries, size_t len);
+
+showfs_file *showfs_file_open(showfs_tree *tree, const char *path);
+/* reads up to len bytes at the offset, returns the bytes read, less than len only at the end. */
+ssize_t showfs_file_read(showfs_file *file, uint64_t offset, void *buf, size_t len);
+void showfs_file_close(showfs_file *file);
+
+#endif
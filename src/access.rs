@@ -0,0 +1,101 @@
+use std::ffi::OsString;
+use std::fs;
+use std::str::FromStr;
+
+/// Subject is whom a rule applies to, a user or the executable of a process by its name,
+/// like firefox for /usr/lib/firefox/firefox.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Subject {
+    Uid(u32),
+    Exe(OsString),
+}
+
+impl FromStr for Subject {
+    type Err = String;
+
+    // parses uid=UID or exe=NAME.
+    fn from_str(s: &str) -> Result<Subject, String> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("uid"), Some(uid)) => uid
+                .parse()
+                .map(Subject::Uid)
+                .map_err(|_| format!("invalid uid: {}", uid)),
+            (Some("exe"), Some(name)) if !name.is_empty() => Ok(Subject::Exe(name.into())),
+            _ => Err(format!("invalid subject: {}", s)),
+        }
+    }
+}
+
+// the name of the executable of the process, None if it is gone or not readable.
+fn exe_name(pid: u32) -> Option<OsString> {
+    let path = fs::read_link(format!("/proc/{}/exe", pid)).ok()?;
+    path.file_name().map(|name| name.to_os_string())
+}
+
+/// AccessPolicy decides which processes may open files and directories of the mount, by the
+/// first rule matching the uid or the executable of the process. if no rule matches, opens are
+/// denied if there are allow rules, else allowed.
+#[derive(Default)]
+pub struct AccessPolicy {
+    rules: Vec<(bool, Subject)>,
+}
+
+impl AccessPolicy {
+    pub fn new() -> AccessPolicy {
+        AccessPolicy::default()
+    }
+
+    pub fn allow(&mut self, subject: Subject) {
+        self.rules.push((true, subject));
+    }
+
+    pub fn deny(&mut self, subject: Subject) {
+        self.rules.push((false, subject));
+    }
+
+    fn decide<F: Fn() -> Option<OsString>>(&self, uid: u32, exe: F) -> bool {
+        // the executable is read only if a rule needs it.
+        let mut name = None;
+        for &(allow, ref subject) in self.rules.iter() {
+            let matched = match *subject {
+                Subject::Uid(u) => u == uid,
+                Subject::Exe(ref e) => name.get_or_insert_with(&exe).as_ref() == Some(e),
+            };
+            if matched {
+                return allow;
+            }
+        }
+        !self.rules.iter().any(|&(allow, _)| allow)
+    }
+
+    /// whether the process of the request may open.
+    pub fn permits(&self, uid: u32, pid: u32) -> bool {
+        self.decide(uid, || exe_name(pid))
+    }
+}
+
+#[test]
+fn test_access_policy() {
+    let exe = |name: &'static str| move || Some(OsString::from(name));
+    let mut policy = AccessPolicy::new();
+    assert!(policy.decide(1000, exe("cat")));
+    policy.deny("exe=curl".parse().unwrap());
+    assert!(!policy.decide(1000, exe("curl")));
+    assert!(policy.decide(1000, exe("cat")));
+    policy.allow("uid=1000".parse().unwrap());
+    policy.allow("exe=smbd".parse().unwrap());
+    assert!(!policy.decide(1000, exe("curl")));
+    assert!(policy.decide(1000, exe("cat")));
+    assert!(policy.decide(0, exe("smbd")));
+    assert!(!policy.decide(0, exe("cat")));
+    assert!(!policy.decide(0, || None));
+    assert!("uid=a".parse::<Subject>().is_err());
+    assert!("exe=".parse::<Subject>().is_err());
+    assert!("gid=1".parse::<Subject>().is_err());
+
+    // this process.
+    let policy = AccessPolicy::new();
+    assert!(policy.permits(0, std::process::id()));
+    assert!(exe_name(std::process::id()).is_some());
+}
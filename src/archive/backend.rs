@@ -0,0 +1,44 @@
+use libc;
+use time;
+
+use self::time::Timespec;
+use crate::fs::SeekableRead;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// Metadata is an owned copy of the header of an entry.
+#[derive(Clone)]
+pub struct Metadata {
+    pub path: PathBuf,
+    // negative if the header does not tell it.
+    pub size: i64,
+    pub filetype: libc::mode_t,
+    pub perm: libc::mode_t,
+    // uid and gid, None if the archive does not record them.
+    pub owner: Option<(u32, u32)>,
+    pub atime: Option<Timespec>,
+    pub mtime: Option<Timespec>,
+    pub ctime: Option<Timespec>,
+    pub birthtime: Option<Timespec>,
+    // target of the symbolic link, None if the archive keeps it as the contents.
+    pub symlink: Option<PathBuf>,
+    // the data can not be read without a passphrase.
+    pub encrypted: bool,
+}
+
+/// ArchiveBackend is an opened archive, which is read from the beginning.
+pub trait ArchiveBackend {
+    // iterates metadata of the rest of entries.
+    fn entries(&mut self) -> Box<dyn Iterator<Item = Result<Metadata>> + '_>;
+    // opens the first entry at the path in the rest.
+    fn open_entry(self: Box<Self>, path: &Path) -> Option<Result<Box<dyn SeekableRead>>>;
+    // whether open_next can be used, which is worth keeping the archive if reading it from
+    // the beginning again is costly, like a compressed stream.
+    fn resumable(&self) -> bool {
+        false
+    }
+    // like open_entry, but the archive is kept after the entry to open later entries.
+    fn open_next(&mut self, _path: &Path) -> Option<Result<Box<dyn SeekableRead>>> {
+        None
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::io::{Read, Result};
+
+// the size of a keystream block.
+const BLOCK: usize = 64;
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// Cipher encrypts cached contents by ChaCha20 with a key which is only in memory,
+/// so that the temporary file behind the cache does not keep plaintext of archives.
+/// contents are keyed by the id of the allocation and the offset in it, which is the nonce
+/// and the counter of the original ChaCha20 with 64 bit ones.
+pub struct Cipher {
+    key: [u32; 8],
+}
+
+impl Cipher {
+    // a new random key, which is lost when the mount exits.
+    pub fn ephemeral() -> Result<Cipher> {
+        let mut bytes = [0; 32];
+        fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        Ok(Cipher::new(bytes))
+    }
+
+    fn new(bytes: [u8; 32]) -> Cipher {
+        let mut key = [0; 8];
+        for (i, k) in key.iter_mut().enumerate() {
+            *k = u32::from_le_bytes([
+                bytes[i * 4],
+                bytes[i * 4 + 1],
+                bytes[i * 4 + 2],
+                bytes[i * 4 + 3],
+            ]);
+        }
+        Cipher { key: key }
+    }
+
+    fn block(&self, id: u64, counter: u64) -> [u8; BLOCK] {
+        let mut input = [0u32; 16];
+        // "expand 32-byte k"
+        input[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+        input[4..12].copy_from_slice(&self.key);
+        input[12] = counter as u32;
+        input[13] = (counter >> 32) as u32;
+        input[14] = id as u32;
+        input[15] = (id >> 32) as u32;
+        let mut s = input;
+        for _ in 0..10 {
+            quarter_round(&mut s, 0, 4, 8, 12);
+            quarter_round(&mut s, 1, 5, 9, 13);
+            quarter_round(&mut s, 2, 6, 10, 14);
+            quarter_round(&mut s, 3, 7, 11, 15);
+            quarter_round(&mut s, 0, 5, 10, 15);
+            quarter_round(&mut s, 1, 6, 11, 12);
+            quarter_round(&mut s, 2, 7, 8, 13);
+            quarter_round(&mut s, 3, 4, 9, 14);
+        }
+        let mut out = [0; BLOCK];
+        for i in 0..16 {
+            let word = s[i].wrapping_add(input[i]).to_le_bytes();
+            out[i * 4..i * 4 + 4].copy_from_slice(&word);
+        }
+        out
+    }
+
+    // encrypts or decrypts the data at the offset of the allocation in place.
+    pub fn apply(&self, id: u64, offset: usize, data: &mut [u8]) {
+        let mut i = 0;
+        while i < data.len() {
+            let pos = offset + i;
+            let block = self.block(id, (pos / BLOCK) as u64);
+            let start = pos % BLOCK;
+            let n = (BLOCK - start).min(data.len() - i);
+            for (d, k) in data[i..i + n].iter_mut().zip(block[start..].iter()) {
+                *d ^= *k;
+            }
+            i += n;
+        }
+    }
+}
+
+#[test]
+fn test_cipher() {
+    // the block of section 2.3.2 of RFC 7539, whose 32 bit counter and 96 bit nonce are
+    // laid out in the 64 bit counter and id here.
+    let mut key = [0; 32];
+    for (i, k) in key.iter_mut().enumerate() {
+        *k = i as u8;
+    }
+    let cipher = Cipher::new(key);
+    let block = cipher.block(0x4a00_0000, 0x0900_0000_0000_0001);
+    assert_eq!(block[..8], [0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15]);
+
+    // pieces at any offsets are the same as the whole.
+    let plain: Vec<u8> = (0..200).map(|i| i as u8).collect();
+    let mut whole = plain.clone();
+    cipher.apply(7, 3, &mut whole);
+    let mut pieces = plain.clone();
+    let (a, b) = pieces.split_at_mut(70);
+    cipher.apply(7, 3, a);
+    cipher.apply(7, 73, b);
+    assert_eq!(whole, pieces);
+    assert_ne!(whole, plain);
+    cipher.apply(7, 3, &mut whole);
+    assert_eq!(whole, plain);
+}
@@ -0,0 +1,117 @@
+use fuse;
+
+use self::fuse::{FileAttr, FileType};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+// entries of a directory in the order of the archive, and their indices by the name.
+#[derive(Default)]
+struct Children {
+    entries: Vec<(OsString, FileAttr)>,
+    by_name: HashMap<OsString, usize>,
+}
+
+/// Dents are entries of an archive by their directories, which are added as members are read,
+/// so that listing or looking up a directory only touches its own entries.
+/// directories which are not in the archive but parents of members are added with the
+/// attributes of the archive.
+pub struct Dents {
+    dirs: HashMap<PathBuf, Children>,
+    // attributes of directories which are not in the archive.
+    implied: FileAttr,
+}
+
+impl Dents {
+    pub fn new(implied: FileAttr) -> Dents {
+        let mut dirs = HashMap::new();
+        dirs.insert(PathBuf::new(), Children::default());
+        Dents {
+            dirs: dirs,
+            implied: implied,
+        }
+    }
+
+    // adds the member, and its parents if they are not yet.
+    // the first one of members of the same path is kept.
+    pub fn insert(&mut self, path: &Path, attr: FileAttr) {
+        let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return,
+        };
+        if !self.dirs.contains_key(parent) {
+            let implied = self.implied;
+            self.insert(parent, implied);
+        }
+        // the parent may be a file, whose children are never listed.
+        let children = self.dirs.entry(parent.to_path_buf()).or_default();
+        if children.by_name.contains_key(name) {
+            return;
+        }
+        children
+            .by_name
+            .insert(name.to_os_string(), children.entries.len());
+        children.entries.push((name.to_os_string(), attr));
+        if attr.kind == FileType::Directory {
+            self.dirs.entry(path.to_path_buf()).or_default();
+        }
+    }
+
+    // the number of entries, including directories which are not in the archive.
+    pub fn len(&self) -> usize {
+        self.dirs.values().map(|c| c.entries.len()).sum()
+    }
+
+    pub fn implied(&self) -> FileAttr {
+        self.implied
+    }
+
+    // the entries of the directory.
+    pub fn children(&self, dir: &Path) -> &[(OsString, FileAttr)] {
+        match self.dirs.get(dir) {
+            Some(children) => &children.entries,
+            None => &[],
+        }
+    }
+
+    pub fn get(&self, dir: &Path, name: &OsStr) -> Option<FileAttr> {
+        let children = self.dirs.get(dir)?;
+        let &i = children.by_name.get(name)?;
+        Some(children.entries[i].1)
+    }
+}
+
+#[test]
+fn test_dents() {
+    use std::mem::zeroed;
+
+    let attr = |kind, size| {
+        let mut attr = unsafe { zeroed::<FileAttr>() };
+        attr.kind = kind;
+        attr.size = size;
+        attr
+    };
+    let mut dents = Dents::new(attr(FileType::Directory, 1));
+    dents.insert(Path::new("a/b/c"), attr(FileType::RegularFile, 2));
+    dents.insert(Path::new("a/b"), attr(FileType::Directory, 3));
+    dents.insert(Path::new("d"), attr(FileType::Directory, 4));
+    dents.insert(Path::new("a/e"), attr(FileType::RegularFile, 5));
+    dents.insert(Path::new("a/e"), attr(FileType::RegularFile, 6));
+
+    let names = |dir| -> Vec<_> {
+        dents
+            .children(Path::new(dir))
+            .iter()
+            .map(|e| e.0.clone())
+            .collect()
+    };
+    assert_eq!(names(""), vec!["a", "d"]);
+    assert_eq!(names("a"), vec!["b", "e"]);
+    assert!(names("d").is_empty());
+    assert!(names("none").is_empty());
+    // implied directories are not replaced by later ones.
+    assert_eq!(dents.get(Path::new("a"), OsStr::new("b")).unwrap().size, 1);
+    assert_eq!(dents.get(Path::new("a"), OsStr::new("e")).unwrap().size, 5);
+    assert!(dents.get(Path::new("a/b"), OsStr::new("none")).is_none());
+    assert_eq!(dents.len(), 5);
+}
@@ -0,0 +1,408 @@
+use time;
+
+use self::time::Timespec;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Result;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::backend::Metadata;
+use super::options::{Backend, Filter, Format, Options};
+use super::retry::RetryPolicy;
+use super::throttle::Throttle;
+use super::warnings::Warnings;
+use super::{open_backend, read_source};
+use crate::confine::Confinement;
+
+// archives waiting for workers, more are indexed on access.
+const QUEUE_SIZE: usize = 256;
+// results kept, over which the least recently used are forgotten.
+const MAX_RESULTS: usize = 1024;
+
+/// Stamp is the mtime and the size of an archive, which tell if it is modified since it is
+/// read.
+pub type Stamp = (Timespec, u64);
+
+enum State {
+    Pending,
+    Done(Indexed),
+}
+
+struct Indexed {
+    // of the archive when it is read.
+    stamp: Stamp,
+    entries: Result<Vec<Metadata>>,
+    // the clock when it is read or taken last.
+    used: u64,
+}
+
+#[derive(Default)]
+struct Table {
+    states: HashMap<PathBuf, State>,
+    // counts reads and takes of results, to forget the least recently used.
+    clock: u64,
+}
+
+impl Table {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn finish(&mut self, path: PathBuf, stamp: Stamp, entries: Result<Vec<Metadata>>) {
+        let indexed = Indexed {
+            stamp: stamp,
+            entries: entries,
+            used: self.tick(),
+        };
+        self.states.insert(path, State::Done(indexed));
+        if self.states.len() <= MAX_RESULTS {
+            return;
+        }
+        let oldest = self
+            .states
+            .iter()
+            .filter_map(|(path, state)| match *state {
+                State::Done(ref indexed) => Some((indexed.used, path)),
+                State::Pending => None,
+            })
+            .min()
+            .map(|(_, path)| path.clone());
+        if let Some(path) = oldest {
+            self.states.remove(&path);
+        }
+    }
+}
+
+struct Results {
+    table: Mutex<Table>,
+    // set when the indexer is dropped, after which queued archives are not read.
+    stopped: AtomicBool,
+}
+
+// Options is not Send for the passphrase callback, so the rest is sent to workers.
+struct SendOptions {
+    formats: Vec<Format>,
+    filters: Vec<Filter>,
+    block_size: usize,
+    read_options: Vec<String>,
+    passphrases: Vec<String>,
+    backend: Backend,
+    throttle: Option<Arc<Throttle>>,
+    retry_policy: Option<RetryPolicy>,
+    warnings: Arc<Warnings>,
+}
+
+impl SendOptions {
+    fn new(options: &Options) -> SendOptions {
+        SendOptions {
+            formats: options.formats().to_vec(),
+            filters: options.filters().to_vec(),
+            block_size: options.block_size(),
+            read_options: options.read_options().to_vec(),
+            passphrases: options.passphrases().to_vec(),
+            backend: options.backend(),
+            throttle: options.throttle(),
+            retry_policy: options.retry_policy().cloned(),
+            warnings: options.warnings().clone(),
+        }
+    }
+
+    fn to_options(&self) -> Options {
+        let mut options = Options::default();
+        options.set_formats(self.formats.clone());
+        options.set_filters(self.filters.clone());
+        options.set_block_size(self.block_size);
+        for o in self.read_options.iter() {
+            options.add_read_option(o.clone());
+        }
+        for p in self.passphrases.iter() {
+            options.add_passphrase(p.clone());
+        }
+        options.set_backend(self.backend);
+        options.set_throttle(self.throttle.clone());
+        options.set_warnings(self.warnings.clone());
+        if let Some(ref policy) = self.retry_policy {
+            options.set_retry_policy(policy.clone());
+        }
+        options
+    }
+}
+
+fn stamp(m: &std::fs::Metadata) -> Stamp {
+    (Timespec::new(m.mtime(), m.mtime_nsec() as i32), m.len())
+}
+
+// the stamp of the archive when it is opened, and its entries.
+fn index(path: &Path, options: &Options) -> (Stamp, Result<Vec<Metadata>>) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return ((Timespec::new(0, 0), 0), Err(e)),
+    };
+    let stamp = match file.metadata() {
+        Ok(m) => stamp(&m),
+        Err(e) => return ((Timespec::new(0, 0), 0), Err(e)),
+    };
+    let entries = open_backend(read_source(Box::new(file), options), options, path)
+        .and_then(|mut archive| archive.entries().collect::<Result<Vec<_>>>());
+    (stamp, entries)
+}
+
+fn work(jobs: Arc<Mutex<Receiver<PathBuf>>>, results: Arc<Results>, options: SendOptions) {
+    let options = options.to_options();
+    loop {
+        let path = match jobs.lock().unwrap().recv() {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        if results.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        let _span = debug_span!("index", path = %path.display()).entered();
+        let (stamp, entries) = index(&path, &options);
+        results.table.lock().unwrap().finish(path, stamp, entries);
+    }
+}
+
+/// Progress is how far a worker has read the entries of an archive.
+pub enum Progress {
+    Indexing,
+    // the number of entries.
+    Indexed(usize),
+    Failed,
+}
+
+/// Indexer reads entries of archives by a pool of threads, before they are accessed.
+/// results are kept for views of the archives later, until the archives are modified or the
+/// results are the least recently used of too many.
+pub struct Indexer {
+    jobs: SyncSender<PathBuf>,
+    results: Arc<Results>,
+}
+
+impl Indexer {
+    // if confinement is given, workers confine themselves before reading any archive, and
+    // the indexer fails if any of them can not.
+    pub fn new(
+        threads: usize,
+        options: &Options,
+        confinement: Option<&Confinement>,
+    ) -> Result<Indexer> {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+        let jobs = Arc::new(Mutex::new(rx));
+        let results = Arc::new(Results {
+            table: Mutex::new(Table::default()),
+            stopped: AtomicBool::new(false),
+        });
+        let (confined_tx, confined_rx) = mpsc::channel();
+        for i in 0..threads {
+            let jobs = jobs.clone();
+            let results = results.clone();
+            let options = SendOptions::new(options);
+            let confinement = confinement.cloned();
+            let confined = confined_tx.clone();
+            thread::Builder::new()
+                .name(format!("indexer-{}", i))
+                .spawn(move || {
+                    let result = confinement.map_or(Ok(()), |c| c.apply());
+                    let failed = result.is_err();
+                    let _ = confined.send(result);
+                    if !failed {
+                        work(jobs, results, options)
+                    }
+                })?;
+        }
+        // workers which started exit once the queue is dropped.
+        for _ in 0..threads {
+            confined_rx.recv().unwrap()?;
+        }
+        Ok(Indexer {
+            jobs: tx,
+            results: results,
+        })
+    }
+
+    // queues the archive, unless it is queued already, it is read already with the stamp, or
+    // the queue is full.
+    pub fn submit(&self, path: &Path, stamp: Stamp) {
+        let mut table = self.results.table.lock().unwrap();
+        match table.states.get(path) {
+            Some(State::Pending) => return,
+            Some(State::Done(indexed)) if indexed.stamp == stamp => return,
+            _ => (),
+        }
+        match self.jobs.try_send(path.to_path_buf()) {
+            Ok(()) => {
+                table.states.insert(path.to_path_buf(), State::Pending);
+            }
+            Err(TrySendError::Full(_)) => debug!("index queue is full"),
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    // the progress of the archive without waiting for it, None if it is not queued or taken.
+    pub fn progress(&self, path: &Path) -> Option<Progress> {
+        match self.results.table.lock().unwrap().states.get(path)? {
+            State::Pending => Some(Progress::Indexing),
+            State::Done(Indexed {
+                entries: Ok(entries),
+                ..
+            }) => Some(Progress::Indexed(entries.len())),
+            State::Done(Indexed {
+                entries: Err(_), ..
+            }) => Some(Progress::Failed),
+        }
+    }
+
+    // a copy of the entries of the archive read by a worker, without waiting for it.
+    // None if it is not read yet, failed or modified since, and the caller reads it by itself.
+    pub fn take(&self, path: &Path, stamp: Stamp) -> Option<Vec<Metadata>> {
+        let mut table = self.results.table.lock().unwrap();
+        let used = table.tick();
+        match table.states.get_mut(path) {
+            Some(State::Done(indexed)) if indexed.stamp == stamp => {
+                indexed.used = used;
+                match indexed.entries {
+                    Ok(ref entries) => Some(entries.clone()),
+                    Err(ref e) => {
+                        debug!(error = ?e, "failed to index {}", path.display());
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // forgets results of archives which are modified or gone since, as they would never be
+    // taken. returns how many are forgotten.
+    pub fn forget_stale(&self) -> usize {
+        let mut table = self.results.table.lock().unwrap();
+        let before = table.states.len();
+        table.states.retain(|path, state| match *state {
+            State::Done(ref indexed) => {
+                std::fs::metadata(path).map_or(false, |m| stamp(&m) == indexed.stamp)
+            }
+            State::Pending => true,
+        });
+        before - table.states.len()
+    }
+}
+
+// workers exit once they are done with the current archive, like when the mount is unmounted.
+impl Drop for Indexer {
+    fn drop(&mut self) {
+        self.results.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_indexer() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.tar");
+    let mut builder = tar::Builder::new(File::create(&path).unwrap());
+    let mut header = tar::Header::new_ustar();
+    header.set_path("file").unwrap();
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    builder.append(&header, &b"hello"[..]).unwrap();
+    builder.finish().unwrap();
+    drop(builder);
+    let stamp = stamp(&std::fs::metadata(&path).unwrap());
+    let wait = |indexer: &Indexer| {
+        while let Some(Progress::Indexing) = indexer.progress(&path) {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+    };
+
+    let mut options = Options::default();
+    options.set_backend(Backend::Rust);
+    let indexer = Indexer::new(2, &options, None).unwrap();
+    assert!(indexer.take(&path, stamp).is_none());
+    assert!(indexer.progress(&path).is_none());
+    indexer.submit(&path, stamp);
+    assert!(indexer.progress(&path).is_some());
+    wait(&indexer);
+    let entries = indexer.take(&path, stamp).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].path, Path::new("file"));
+    // results are kept for later views, which do not queue the archive again.
+    indexer.submit(&path, stamp);
+    assert!(match indexer.progress(&path) {
+        Some(Progress::Indexed(1)) => true,
+        _ => false,
+    });
+    assert_eq!(indexer.take(&path, stamp).unwrap().len(), 1);
+    // a modified archive is read again by the caller, and queued again.
+    let modified = (Timespec::new(0, 0), stamp.1);
+    assert!(indexer.take(&path, modified).is_none());
+    indexer.submit(&path, modified);
+    wait(&indexer);
+    // results of archives gone since are forgotten.
+    assert_eq!(indexer.forget_stale(), 0);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(indexer.forget_stale(), 1);
+    assert!(indexer.progress(&path).is_none());
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_confined_indexer() {
+    let allowed = tempfile::tempdir().unwrap();
+    let other = tempfile::tempdir().unwrap();
+    let tar = crate::testing::tar_bytes(&[("file", b"hello")]);
+    let (a, b) = (allowed.path().join("a.tar"), other.path().join("b.tar"));
+    std::fs::write(&a, &tar).unwrap();
+    std::fs::write(&b, &tar).unwrap();
+    let mut confinement = Confinement::new();
+    confinement.allow_read(allowed.path());
+
+    let mut options = Options::default();
+    options.set_backend(Backend::Rust);
+    let indexer = match Indexer::new(1, &options, Some(&confinement)) {
+        Ok(indexer) => indexer,
+        // the kernel may not have landlock.
+        Err(_) => return,
+    };
+    for path in [&a, &b].iter() {
+        let stamp = stamp(&std::fs::metadata(path).unwrap());
+        indexer.submit(path, stamp);
+        while let Some(Progress::Indexing) = indexer.progress(path) {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+    assert!(match indexer.progress(&a) {
+        Some(Progress::Indexed(1)) => true,
+        _ => false,
+    });
+    assert!(match indexer.progress(&b) {
+        Some(Progress::Failed) => true,
+        _ => false,
+    });
+    // the test runner is not confined.
+    assert!(std::fs::metadata(&b).is_ok());
+}
+
+#[test]
+fn test_forget_least_recently_used() {
+    let mut table = Table::default();
+    let stamp = (Timespec::new(0, 0), 0);
+    for i in 0..MAX_RESULTS {
+        table.finish(PathBuf::from(format!("/{}", i)), stamp, Ok(Vec::new()));
+    }
+    table
+        .states
+        .insert(PathBuf::from("/pending"), State::Pending);
+    table.finish(PathBuf::from("/last"), stamp, Ok(Vec::new()));
+    assert_eq!(table.states.len(), MAX_RESULTS + 1);
+    assert!(!table.states.contains_key(Path::new("/0")));
+    assert!(table.states.contains_key(Path::new("/pending")));
+}
@@ -1,23 +1,67 @@
 use fuse;
 use libc;
+use memmap;
+use time;
 
 use self::fuse::{FileAttr, FileType};
-use std::cell::RefCell;
-use std::collections::HashSet;
-use std::convert::From;
-use std::ffi::OsStr;
-use std::io::{Error, Result};
+use self::memmap::Mmap;
+use self::time::Timespec;
+use std::cell::{Cell, RefCell};
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::io::{Cursor, Error, Read, Result, Seek, SeekFrom};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
 use std::vec::Vec;
 
+use crate::confine::Confinement;
+use crate::control;
+use crate::error;
 use crate::fs;
+use crate::physical;
+use crate::shared_cache::{self, Publisher};
+use crate::stats;
+mod backend;
 mod buffer;
+mod cipher;
+mod dents;
+mod indexer;
 mod link;
+mod options;
 mod page;
 mod reader;
+mod retry;
+#[cfg(feature = "rust-backend")]
+mod rust;
+mod sandbox;
+mod scrub;
+mod throttle;
+mod warnings;
+#[cfg(feature = "libarchive")]
 mod wrapper;
 
+#[cfg(not(any(feature = "libarchive", feature = "rust-backend")))]
+compile_error!("either libarchive or rust-backend feature is required");
+
+use self::backend::ArchiveBackend;
+pub(crate) use self::backend::Metadata;
+use self::dents::Dents;
+use self::indexer::{Indexer, Progress};
+pub use self::options::{
+    Backend, Capabilities, EncryptedPolicy, Filter, Format, Options, SizeLimit, SpecialFiles,
+};
+#[cfg(all(test, feature = "rust-backend"))]
+pub(crate) use self::page::PageManager;
+pub use self::retry::RetryPolicy;
+use self::retry::Retrying;
+pub use self::sandbox::{helper_main as sandbox_helper, HELPER_ARG as SANDBOX_HELPER_ARG};
+pub use self::scrub::Scrubber;
+use self::throttle::Throttled;
+
 fn to_fuse_file_type(file_type: libc::mode_t) -> FileType {
     match file_type & libc::S_IFMT {
         libc::S_IFLNK => FileType::Symlink,
@@ -30,11 +74,13 @@ fn to_fuse_file_type(file_type: libc::mode_t) -> FileType {
     }
 }
 
+// sizes are 64 bits on any target, negative ones are unknown.
 fn to_fuse_file_attr(size: i64, file_type: libc::mode_t, attr: FileAttr) -> FileAttr {
+    let size = max(size, 0) as u64;
     FileAttr {
         ino: 0, // dummy
-        size: size as u64,
-        blocks: (size as u64 + 4095) / 4096,
+        size: size,
+        blocks: (size + 4095) / 4096,
         atime: attr.atime,
         mtime: attr.mtime,
         ctime: attr.ctime,
@@ -49,50 +95,713 @@ fn to_fuse_file_attr(size: i64, file_type: libc::mode_t, attr: FileAttr) -> File
     }
 }
 
+// the name of the archive tells which archive warnings are of in logs. the rust backend has
+// no warnings.
+#[allow(unreachable_patterns, unused_variables)]
+fn open_backend(
+    r: Box<dyn fs::SeekableRead>,
+    options: &Options,
+    name: &Path,
+) -> Result<Box<dyn ArchiveBackend>> {
+    match options.backend() {
+        #[cfg(feature = "libarchive")]
+        Backend::Libarchive => Ok(Box::new(wrapper::Archive::new(r, options)?.named(name))),
+        #[cfg(feature = "rust-backend")]
+        Backend::Rust => rust::open(r, options),
+        backend => {
+            let e = error::Error::UnsupportedFormat(format!("{:?} backend is not built", backend));
+            Err(e.into())
+        }
+    }
+}
+
+// opens the mapped archive file. libarchive reads it as a single block without copies.
+fn open_mapped(map: Mmap, options: &Options, name: &Path) -> Result<Box<dyn ArchiveBackend>> {
+    match options.backend() {
+        #[cfg(feature = "libarchive")]
+        Backend::Libarchive => {
+            let archive = wrapper::Archive::from_memory(map, options)?;
+            Ok(Box::new(archive.named(name)))
+        }
+        _ => open_backend(Box::new(Cursor::new(map)), options, name),
+    }
+}
+
+// reads an archive file on the disk by the retry policy and the throttle of the options.
+fn read_source(r: Box<dyn fs::SeekableRead>, options: &Options) -> Box<dyn fs::SeekableRead> {
+    let r: Box<dyn fs::SeekableRead> = match options.retry_policy() {
+        Some(policy) => Box::new(Retrying::new(r, policy.clone())),
+        None => r,
+    };
+    match options.throttle() {
+        Some(throttle) => Box::new(Throttled::new(r, throttle)),
+        None => r,
+    }
+}
+
+// sets up the process to read archives.
+fn initialize() {
+    #[cfg(feature = "libarchive")]
+    wrapper::initialize();
+}
+
+// Source is an archive file and how to read it.
+struct Source {
+    file: Box<dyn fs::File>,
+    options: Rc<Options>,
+    sandbox: sandbox::Sandbox,
+    indexer: Option<Rc<Indexer>>,
+    // None until the archive is read.
+    index: RefCell<Option<Index>>,
+    // paths of encrypted members shown by the deny or mark policy.
+    encrypted: RefCell<HashSet<PathBuf>>,
+    // original types of special members shown as regular files.
+    special: RefCell<HashMap<PathBuf, FileType>>,
+    // targets of symbolic links which the headers tell.
+    links: RefCell<HashMap<PathBuf, PathBuf>>,
+    // sizes of members whose headers do not tell them, None until they are read to the end.
+    sizes: RefCell<HashMap<PathBuf, Option<u64>>>,
+    // whether the last read of the entries failed.
+    failed: Cell<bool>,
+    // the order of members in the archive, and the bytes of the members before them.
+    positions: RefCell<HashMap<PathBuf, (usize, u64)>>,
+    // the bytes of the members in positions.
+    positioned_bytes: Cell<u64>,
+    // the archive after the last opened member and its position, kept for opens of later
+    // members if the archive is costly to read from the beginning again.
+    resume: RefCell<Option<(Box<dyn ArchiveBackend>, usize)>>,
+    // caches of the members, tracked only if the archive is scrubbed.
+    caches: RefCell<Option<Vec<Weak<RefCell<reader::Cache>>>>>,
+}
+
+// the entries of an archive which are read so far.
+enum Index {
+    // lookups read members until they find theirs. the archive is kept after the last read
+    // header for the next scan to resume, with the number of members read, which are skipped
+    // if the archive is opened again, like after a failed read.
+    Partial(Dents, usize, Option<Box<dyn ArchiveBackend>>),
+    Complete(Rc<Dents>),
+}
+
+// the attributes of the member, over those of the archive.
+fn member_attr(m: &Metadata, archive_attr: FileAttr) -> FileAttr {
+    let mut attr = to_fuse_file_attr(m.size, m.filetype, archive_attr);
+    // prefer timestamps recorded in the archive.
+    attr.atime = m.atime.unwrap_or(attr.atime);
+    attr.mtime = m.mtime.unwrap_or(attr.mtime);
+    attr.ctime = m.ctime.unwrap_or(attr.ctime);
+    attr.crtime = m.birthtime.unwrap_or(attr.crtime);
+    if m.perm != 0 {
+        attr.perm = m.perm as u16;
+    }
+    if let Some((uid, gid)) = m.owner {
+        attr.uid = uid;
+        attr.gid = gid;
+    }
+    attr
+}
+
+impl Source {
+    fn new(file: Box<dyn fs::File>, options: Rc<Options>, indexer: Option<Rc<Indexer>>) -> Self {
+        Source {
+            file: file,
+            options: options,
+            sandbox: sandbox::Sandbox::new(),
+            indexer: indexer,
+            index: RefCell::new(None),
+            encrypted: RefCell::new(HashSet::new()),
+            special: RefCell::new(HashMap::new()),
+            links: RefCell::new(HashMap::new()),
+            sizes: RefCell::new(HashMap::new()),
+            failed: Cell::new(false),
+            positions: RefCell::new(HashMap::new()),
+            positioned_bytes: Cell::new(0),
+            resume: RefCell::new(None),
+            caches: RefCell::new(None),
+        }
+    }
+
+    // the archive file on the disk, for tests.
+    #[cfg(test)]
+    fn of_path(path: &Path, options: Options) -> Rc<Source> {
+        let ctx = Rc::new(physical::Context::new(std::time::Duration::from_secs(1)));
+        let file = physical::File::new(path.to_path_buf(), ctx);
+        Rc::new(Source::new(Box::new(file), Rc::new(options), None))
+    }
+
+    fn open(&self) -> Result<Box<dyn ArchiveBackend>> {
+        if self.options.sandbox() {
+            let path = self.file.physical_path();
+            return self.sandbox.open(path, || self.read(), &self.options);
+        }
+        if let Some(map) = self.map()? {
+            return open_mapped(map, &self.options, self.path());
+        }
+        open_backend(self.read()?, &self.options, self.path())
+    }
+
+    // the archive file mapped into memory, if it is on the disk and mapping is enabled.
+    // the mapping is neither retried nor throttled.
+    fn map(&self) -> Result<Option<Mmap>> {
+        if !self.options.mmap() {
+            return Ok(None);
+        }
+        match self.file.physical_path() {
+            Some(path) => physical::map(path),
+            None => Ok(None),
+        }
+    }
+
+    // the contents of the archive file, which are retried and throttled if it is on the disk.
+    // archives in archives are read from the cache, whose archive is read so already.
+    fn read(&self) -> Result<Box<dyn fs::SeekableRead>> {
+        let r = self.file.open()?;
+        if self.file.physical_path().is_none() {
+            return Ok(r);
+        }
+        Ok(read_source(r, &self.options))
+    }
+
+    // the entries read by the indexer, if the archive is not modified since.
+    fn indexed(&self, attr: &FileAttr) -> Option<Vec<Metadata>> {
+        let indexer = self.indexer.as_ref()?;
+        indexer.take(self.file.physical_path()?, (attr.mtime, attr.size))
+    }
+
+    // the attributes of the archive shown as the directory.
+    fn dir_attr(&self) -> Result<FileAttr> {
+        let mut attr = self.file.getattr()?;
+        attr.kind = FileType::Directory;
+        Ok(attr)
+    }
+
+    // reads members into the index until found tells the wanted entry is in it, or to the end.
+    fn scan<F: Fn(&Dents) -> bool>(&self, found: F) -> Result<()> {
+        let result = self.read_index(found);
+        self.failed.set(result.is_err());
+        result
+    }
+
+    fn read_index<F: Fn(&Dents) -> bool>(&self, found: F) -> Result<()> {
+        let mut index = self.index.borrow_mut();
+        if index.is_none() {
+            let attr = self.dir_attr()?;
+            self.positioned_bytes.set(0);
+            *index = Some(match self.indexed(&attr) {
+                Some(entries) => {
+                    let mut dents = Dents::new(attr);
+                    for (i, m) in entries.into_iter().enumerate() {
+                        self.record_position(&m, i);
+                        self.insert_member(&mut dents, &m);
+                    }
+                    Index::Complete(Rc::new(dents))
+                }
+                None => Index::Partial(Dents::new(attr), 0, None),
+            });
+        }
+        if let Some(Index::Partial(ref mut dents, ref mut scanned, ref mut kept)) = *index {
+            if found(dents) {
+                return Ok(());
+            }
+            let (mut archive, skip) = match kept.take() {
+                Some(archive) => (archive, 0),
+                None => (self.open()?, *scanned),
+            };
+            let mut rest = false;
+            for m in archive.entries().skip(skip) {
+                let m = m?;
+                self.record_position(&m, *scanned);
+                self.insert_member(dents, &m);
+                *scanned += 1;
+                if found(dents) {
+                    rest = true;
+                    break;
+                }
+            }
+            if rest {
+                *kept = Some(archive);
+                return Ok(());
+            }
+        }
+        *index = match index.take() {
+            Some(Index::Partial(dents, _, _)) => Some(Index::Complete(Rc::new(dents))),
+            complete => complete,
+        };
+        Ok(())
+    }
+
+    fn record_position(&self, m: &Metadata, position: usize) {
+        let before = self.positioned_bytes.get();
+        let mut positions = self.positions.borrow_mut();
+        positions.insert(m.path.clone(), (position, before));
+        self.positioned_bytes.set(before + max(m.size, 0) as u64);
+    }
+
+    // opens the member, continuing the kept archive if the member is after the last opened one.
+    fn open_member(&self, path: &Path) -> Result<Box<dyn fs::SeekableRead>> {
+        let position = self.positions.borrow().get(path).map(|p| p.0);
+        let kept = self.resume.borrow_mut().take();
+        let mut archive = match (kept, position) {
+            (Some((archive, next)), Some(position)) if next <= position => archive,
+            _ => {
+                let archive = self.open()?;
+                if !archive.resumable() || position.is_none() {
+                    return archive
+                        .open_entry(path)
+                        .unwrap_or_else(|| Err(Error::from_raw_os_error(libc::ENOENT)));
+                }
+                archive
+            }
+        };
+        let reader = archive
+            .open_next(path)
+            .unwrap_or_else(|| Err(Error::from_raw_os_error(libc::ENOENT)))?;
+        if let Some(position) = position {
+            *self.resume.borrow_mut() = Some((archive, position + 1));
+        }
+        Ok(reader)
+    }
+
+    // adds the member to the index, by the policy if it is encrypted without passphrases,
+    // and by the mode if it is a special file.
+    fn insert_member(&self, dents: &mut Dents, m: &Metadata) {
+        let mut attr = member_attr(m, dents.implied());
+        // shown as empty until the size is found by reading the member.
+        if m.size < 0 && attr.kind == FileType::RegularFile {
+            let mut sizes = self.sizes.borrow_mut();
+            sizes.entry(m.path.clone()).or_insert(None);
+        }
+        if let Some(ref target) = m.symlink {
+            self.links
+                .borrow_mut()
+                .insert(m.path.clone(), target.clone());
+        }
+        if special_type_name(attr.kind).is_some() {
+            match self.options.special_files() {
+                SpecialFiles::Keep => {}
+                SpecialFiles::Hide => return,
+                SpecialFiles::Regular => {
+                    self.special.borrow_mut().insert(m.path.clone(), attr.kind);
+                    attr.kind = FileType::RegularFile;
+                    attr.size = 0;
+                    attr.blocks = 0;
+                }
+            }
+        }
+        if !m.encrypted || self.options.has_passphrase() {
+            dents.insert(&m.path, attr);
+            return;
+        }
+        match self.options.encrypted_policy() {
+            EncryptedPolicy::Read => dents.insert(&m.path, attr),
+            EncryptedPolicy::Hide => {}
+            EncryptedPolicy::Deny => {
+                self.encrypted.borrow_mut().insert(m.path.clone());
+                dents.insert(&m.path, attr);
+            }
+            EncryptedPolicy::Mark => {
+                let mut path = m.path.clone().into_os_string();
+                path.push(".ENCRYPTED");
+                let path = PathBuf::from(path);
+                attr.size = 0;
+                attr.blocks = 0;
+                self.encrypted.borrow_mut().insert(path.clone());
+                dents.insert(&path, attr);
+            }
+        }
+    }
+
+    // the state of reading the entries, none, indexing, complete or failed, and the number of
+    // entries read so far.
+    fn index_state(&self) -> (&'static str, usize) {
+        if self.index.borrow().is_none() && !self.failed.get() {
+            let progress = match (&self.indexer, self.file.physical_path()) {
+                (Some(indexer), Some(path)) => indexer.progress(path),
+                _ => None,
+            };
+            match progress {
+                Some(Progress::Indexing) => return ("indexing", 0),
+                Some(Progress::Failed) => return ("failed", 0),
+                // takes the entries, which does not wait for the indexer.
+                Some(Progress::Indexed(_)) => {
+                    let _ = self.scan(|_| false);
+                }
+                None => return ("none", 0),
+            }
+        }
+        if self.failed.get() {
+            return ("failed", 0);
+        }
+        match *self.index.borrow() {
+            Some(Index::Partial(ref dents, _, _)) => ("indexing", dents.len()),
+            Some(Index::Complete(ref dents)) => ("complete", dents.len()),
+            None => ("none", 0),
+        }
+    }
+
+    // all entries of the archive.
+    fn dents(&self) -> Result<Rc<Dents>> {
+        self.scan(|_| false)?;
+        match *self.index.borrow() {
+            Some(Index::Complete(ref dents)) => Ok(dents.clone()),
+            _ => unreachable!(),
+        }
+    }
+
+    // the attributes of the entry, reading the archive only until it is found.
+    fn lookup(&self, dir: &Path, name: &OsStr) -> Result<Option<FileAttr>> {
+        self.scan(|dents| dents.get(dir, name).is_some())?;
+        Ok(match *self.index.borrow() {
+            Some(Index::Partial(ref dents, _, _)) => dents.get(dir, name),
+            Some(Index::Complete(ref dents)) => dents.get(dir, name),
+            None => None,
+        })
+    }
+
+    // whether the archive file on the disk is modified or gone since the entries are read.
+    fn is_stale(&self) -> bool {
+        let path = match self.file.physical_path() {
+            Some(path) => path,
+            None => return false,
+        };
+        let indexed = match *self.index.borrow() {
+            Some(Index::Partial(ref dents, _, _)) => dents.implied(),
+            Some(Index::Complete(ref dents)) => dents.implied(),
+            None => return false,
+        };
+        match std::fs::metadata(path) {
+            Ok(m) => {
+                let mtime = Timespec::new(m.mtime(), m.mtime_nsec() as i32);
+                m.len() != indexed.size || mtime != indexed.mtime
+            }
+            Err(_) => true,
+        }
+    }
+
+    // forgets the entries and the cached contents of the members, which are read again.
+    // entries looked up already keep their attributes until reindexed.
+    fn evict(&self) {
+        *self.index.borrow_mut() = None;
+        self.encrypted.borrow_mut().clear();
+        self.special.borrow_mut().clear();
+        self.links.borrow_mut().clear();
+        self.sizes.borrow_mut().clear();
+        self.positions.borrow_mut().clear();
+        self.failed.set(false);
+        *self.resume.borrow_mut() = None;
+        if let Some(ref caches) = *self.caches.borrow() {
+            for cache in caches.iter().filter_map(|cache| cache.upgrade()) {
+                cache.borrow_mut().evict();
+            }
+        }
+    }
+
+    // the path of the archive file, or the name if it is in another archive.
+    fn path(&self) -> &Path {
+        self.file
+            .physical_path()
+            .unwrap_or_else(|| Path::new(self.file.name()))
+    }
+
+    // the path of the member at the path in the origin, through the archives which the
+    // archive is in. None for the archive itself on the disk.
+    fn member(&self, path: &Path) -> Option<PathBuf> {
+        match self.file.member() {
+            Some(outer) if path.as_os_str().is_empty() => Some(outer),
+            Some(outer) => Some(outer.join(path)),
+            None if path.as_os_str().is_empty() => None,
+            None => Some(path.to_path_buf()),
+        }
+    }
+
+    // the origin xattr of the member at the path.
+    fn origin_xattr(&self, path: &Path) -> Result<Vec<u8>> {
+        let origin = self.file.origin();
+        match (origin, self.member(path)) {
+            (Some(origin), Some(member)) => Ok(origin_value(origin, &member)),
+            _ => Err(Error::from_raw_os_error(libc::ENODATA)),
+        }
+    }
+
+    // adds the archive and the member to the error, to tell which failed in logs.
+    fn context(&self, member: Option<&Path>, e: Error) -> Error {
+        error::Error::archive(self.path(), member, e)
+    }
+}
+
 struct ArchivedFile {
-    archive: Rc<Box<dyn fs::File>>,
+    archive: Rc<Source>,
     attr: FileAttr,
     path: PathBuf,
 }
 
 impl ArchivedFile {
-    fn new(archive: Rc<Box<dyn fs::File>>, attr: FileAttr, path: PathBuf) -> ArchivedFile {
+    fn new(archive: Rc<Source>, attr: FileAttr, path: PathBuf) -> ArchivedFile {
         ArchivedFile {
             archive: archive,
             attr: attr,
             path: path,
         }
     }
+
+    // the type of the special member which is shown as a regular file.
+    fn original_type(&self) -> Option<FileType> {
+        self.archive.special.borrow().get(&self.path).cloned()
+    }
+
+    // whether the header does not tell the size, and the member is not read to the end yet.
+    fn size_unknown(&self) -> bool {
+        match self.archive.sizes.borrow().get(&self.path) {
+            Some(&None) => true,
+            _ => false,
+        }
+    }
+
+    // records the size found by reading the member to the end.
+    fn set_size(&self, size: u64) {
+        if let Some(s) = self.archive.sizes.borrow_mut().get_mut(&self.path) {
+            debug!(path = %self.path.display(), size = size, "found the size");
+            *s = Some(size);
+        }
+    }
+
+    // the key of the member among caches of the mount, which changes if the archive is
+    // modified. members of archives in archives have none.
+    fn load_key(&self) -> Option<String> {
+        let archive = self.archive.file.physical_path()?;
+        let attr = self.archive.file.getattr().ok()?;
+        Some(shared_cache::member_key(
+            archive, attr.mtime, attr.size, &self.path,
+        ))
+    }
+
+    // the key of the member in the shared cache, if any. members of archives in archives
+    // are not shared.
+    fn shared_key(&self) -> Option<String> {
+        self.archive.options.shared_cache()?;
+        if self.archive.sizes.borrow().contains_key(&self.path) {
+            return None;
+        }
+        if self.attr.size > shared_cache::MAX_MEMBER_BYTES {
+            return None;
+        }
+        let archive = std::fs::canonicalize(self.archive.file.physical_path()?).ok()?;
+        let attr = self.archive.file.getattr().ok()?;
+        Some(shared_cache::member_key(
+            &archive, attr.mtime, attr.size, &self.path,
+        ))
+    }
+
+    // the contents kept by another mount, if the server has them.
+    fn shared(&self, key: &str) -> Option<Vec<u8>> {
+        let client = self.archive.options.shared_cache()?;
+        match client.get(key) {
+            Ok(Some(data)) if data.len() as u64 == self.attr.size => Some(data),
+            Ok(_) => None,
+            Err(e) => {
+                debug!(error = %e, "failed to get from the cache server");
+                None
+            }
+        }
+    }
 }
 
 impl fs::File for ArchivedFile {
     fn getattr(&self) -> Result<FileAttr> {
-        Ok(self.attr)
+        let mut attr = self.attr;
+        if let Some(&Some(size)) = self.archive.sizes.borrow().get(&self.path) {
+            attr.size = size;
+            attr.blocks = (size + 4095) / 4096;
+        }
+        Ok(attr)
     }
 
     fn open(&self) -> Result<Box<dyn fs::SeekableRead>> {
-        let archive = wrapper::Archive::new(self.archive.open()?);
-        let reader = archive
-            .find_open(|e| e.pathname() == self.path)
-            .unwrap_or(Err(Error::from_raw_os_error(libc::ENOENT)))?;
-        Ok(Box::new(reader))
+        if self.archive.encrypted.borrow().contains(&self.path) {
+            if self.archive.options.encrypted_policy() == EncryptedPolicy::Mark {
+                return Ok(Box::new(Cursor::new(Vec::new())));
+            }
+            let e = error::Error::PassphraseRequired("encrypted member".to_string()).into();
+            return Err(self.archive.context(Some(&self.path), e));
+        }
+        if self.original_type().is_some() {
+            return Ok(Box::new(Cursor::new(Vec::new())));
+        }
+        let key = self.shared_key();
+        if let Some(data) = key.as_ref().and_then(|key| self.shared(key)) {
+            return Ok(Box::new(Cursor::new(data)));
+        }
+        let reader = self
+            .archive
+            .open_member(&self.path)
+            .map_err(|e| self.archive.context(Some(&self.path), e))?;
+        match (key, self.archive.options.shared_cache()) {
+            (Some(key), Some(client)) => Ok(Box::new(Publisher::new(
+                reader,
+                client.clone(),
+                key,
+                self.attr.size,
+            ))),
+            _ => Ok(reader),
+        }
     }
 
     fn name(&self) -> &OsStr {
         self.path.file_name().unwrap()
     }
+
+    fn origin(&self) -> Option<&Path> {
+        self.archive.file.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.archive.member(&self.path)
+    }
+
+    fn nesting(&self) -> usize {
+        self.archive.file.nesting() + 1
+    }
+
+    // zip keeps the target as the contents, which other archives tell in the header.
+    fn readlink(&self) -> Result<PathBuf> {
+        if self.attr.kind != FileType::Symlink {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        }
+        if let Some(target) = self.archive.links.borrow().get(&self.path) {
+            return Ok(target.clone());
+        }
+        let mut target = Vec::new();
+        self.archive
+            .open_member(&self.path)
+            .and_then(|mut r| r.read_to_end(&mut target))
+            .map_err(|e| self.archive.context(Some(&self.path), e))?;
+        Ok(PathBuf::from(OsString::from_vec(target)))
+    }
+
+    // the members before it are read to find it, unless the archive can be seeked, which is
+    // not known until it is read.
+    fn reopen_cost(&self) -> u64 {
+        let positions = self.archive.positions.borrow();
+        let before = positions.get(&self.path).map_or(0, |&(_, before)| before);
+        self.archive.file.reopen_cost() + before
+    }
+
+    // the kernel reads past the size shown, which is 0, until the end.
+    fn direct_io(&self) -> bool {
+        self.size_unknown()
+    }
+
+    // members do not change but their sizes being found.
+    fn volatility(&self) -> fs::Volatility {
+        if self.size_unknown() {
+            fs::Volatility::Volatile
+        } else {
+            fs::Volatility::Immutable
+        }
+    }
+}
+
+// reads a member of unknown size, and records the size once it is read to the end.
+struct SizeFinder {
+    inner: Box<dyn fs::SeekableRead>,
+    file: Rc<ArchivedFile>,
+    pos: u64,
+    // false once seeked, after which the end of reads is not the size.
+    sequential: bool,
+}
+
+impl Read for SizeFinder {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        if n == 0 && !buf.is_empty() && self.sequential {
+            self.file.set_size(self.pos);
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for SizeFinder {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(0) => Some(self.pos),
+            _ => None,
+        };
+        // reads at offsets in order, like of the mount, seek to where it is.
+        if target == Some(self.pos) {
+            return Ok(self.pos);
+        }
+        self.sequential = false;
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
 }
 
+impl fs::SeekableRead for SizeFinder {}
+
+// members of at most this many bytes, which are found reading at most CHEAP_REOPEN_BYTES
+// before them, are read from the archive at each open instead of taking pages.
+const TINY_MEMBER_BYTES: u64 = 16 * 1024;
+const CHEAP_REOPEN_BYTES: u64 = 1024 * 1024;
+
 struct CacheFile {
-    cache: RefCell<reader::Cache>,
+    cache: Rc<RefCell<reader::Cache>>,
     file: Rc<ArchivedFile>,
 }
 
+// the progress of loading the file into the cache.
+const CACHED_BYTES_XATTR: &str = "user.showfs.cached_bytes";
+const TOTAL_BYTES_XATTR: &str = "user.showfs.total_bytes";
+// the name of the member in the header, before names are escaped or normalized.
+// names converted by hdrcharset are told as libarchive converted them.
+const RAW_NAME_XATTR: &str = "user.showfs.raw_name";
+
+fn raw_name(path: &Path) -> Vec<u8> {
+    path.file_name().unwrap_or_default().as_bytes().to_vec()
+}
+
+// the archive file on the disk which the entry is read from and the path of the member in it,
+// like /data/a.zip and b/c.jpg, separated by a NUL as both may have any other bytes.
+const ORIGIN_XATTR: &str = "user.showfs.origin";
+
+fn origin_value(origin: &Path, member: &Path) -> Vec<u8> {
+    let mut value = origin.as_os_str().as_bytes().to_vec();
+    value.push(0);
+    value.extend_from_slice(member.as_os_str().as_bytes());
+    value
+}
+
+// the state of reading the entries of the archive of a directory, and their number.
+const INDEX_STATE_XATTR: &str = "user.showfs.index_state";
+const ENTRY_COUNT_XATTR: &str = "user.showfs.entry_count";
+// the type of a special member shown as a regular file, like "fifo".
+const ORIGINAL_TYPE_XATTR: &str = "user.showfs.original_type";
+// "1" while the size of the member is not known, which is shown as 0.
+const SIZE_UNKNOWN_XATTR: &str = "user.showfs.size_unknown";
+
+// the name of the type of fifos, devices and sockets, None for other types.
+fn special_type_name(kind: FileType) -> Option<&'static str> {
+    match kind {
+        FileType::NamedPipe => Some("fifo"),
+        FileType::CharDevice => Some("char_device"),
+        FileType::BlockDevice => Some("block_device"),
+        FileType::Socket => Some("socket"),
+        _ => None,
+    }
+}
+
 impl CacheFile {
     fn new(file: ArchivedFile, page_manager: Rc<RefCell<page::PageManager>>) -> CacheFile {
+        let name = format!("{}/{}", file.archive.path().display(), file.path.display());
         let file = Rc::new(file);
+        let cache = reader::Cache::new(page_manager, file.clone(), name);
+        let cache = Rc::new(RefCell::new(cache));
+        if let Some(ref mut caches) = *file.archive.caches.borrow_mut() {
+            caches.push(Rc::downgrade(&cache));
+        }
         CacheFile {
-            cache: RefCell::new(reader::Cache::new(page_manager, file.clone())),
+            cache: cache,
             file: file,
         }
     }
@@ -104,159 +813,262 @@ impl fs::File for CacheFile {
     }
 
     fn open(&self) -> Result<Box<dyn fs::SeekableRead>> {
-        self.cache.borrow_mut().make_reader()
+        // the cache is allocated by the size, so the member is read uncached until it is found.
+        if self.file.size_unknown() {
+            return Ok(Box::new(SizeFinder {
+                inner: fs::File::open(&*self.file)?,
+                file: self.file.clone(),
+                pos: 0,
+                sequential: true,
+            }));
+        }
+        let size = self.file.getattr()?.size;
+        if size <= TINY_MEMBER_BYTES && self.reopen_cost() <= CHEAP_REOPEN_BYTES {
+            return fs::File::open(&*self.file);
+        }
+        let mut cache = self.cache.borrow_mut();
+        if !cache.has_key() {
+            cache.set_key(self.file.load_key());
+        }
+        cache.make_reader()
     }
 
     fn name(&self) -> &OsStr {
         self.file.name()
     }
-}
 
-struct DirEntry {
-    attr: FileAttr,
-    path: PathBuf,
+    fn origin(&self) -> Option<&Path> {
+        self.file.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        fs::File::member(&*self.file)
+    }
+
+    fn nesting(&self) -> usize {
+        self.file.nesting()
+    }
+
+    fn readlink(&self) -> Result<PathBuf> {
+        self.file.readlink()
+    }
+
+    fn reopen_cost(&self) -> u64 {
+        self.file.reopen_cost()
+    }
+
+    fn direct_io(&self) -> bool {
+        self.file.direct_io()
+    }
+
+    fn volatility(&self) -> fs::Volatility {
+        self.file.volatility()
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        let mut names = vec![
+            CACHED_BYTES_XATTR.into(),
+            TOTAL_BYTES_XATTR.into(),
+            RAW_NAME_XATTR.into(),
+        ];
+        if self.file.origin().is_some() {
+            names.push(ORIGIN_XATTR.into());
+        }
+        if self.file.original_type().is_some() {
+            names.push(ORIGINAL_TYPE_XATTR.into());
+        }
+        if self.file.size_unknown() {
+            names.push(SIZE_UNKNOWN_XATTR.into());
+        }
+        Ok(names)
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        if name == RAW_NAME_XATTR {
+            return Ok(raw_name(&self.file.path));
+        }
+        if name == ORIGIN_XATTR {
+            return self.file.archive.origin_xattr(&self.file.path);
+        }
+        if name == ORIGINAL_TYPE_XATTR {
+            let kind = self.file.original_type().and_then(special_type_name);
+            return kind
+                .map(|k| k.as_bytes().to_vec())
+                .ok_or_else(|| Error::from_raw_os_error(libc::ENODATA));
+        }
+        if name == SIZE_UNKNOWN_XATTR && self.file.size_unknown() {
+            return Ok(b"1".to_vec());
+        }
+        let bytes = if name == CACHED_BYTES_XATTR {
+            self.cache.borrow().cached_bytes() as u64
+        } else if name == TOTAL_BYTES_XATTR {
+            fs::File::getattr(&*self.file)?.size
+        } else {
+            return Err(Error::from_raw_os_error(libc::ENODATA));
+        };
+        Ok(bytes.to_string().into_bytes())
+    }
 }
 
+/// Dir is the directory in an archive, or the archive itself at the root.
 pub struct Dir {
-    archive: Rc<Box<dyn fs::File>>,
+    archive: Rc<Source>,
     path: PathBuf,
     attr: RefCell<Option<FileAttr>>,
-    dents: RefCell<Option<Rc<Vec<DirEntry>>>>,
     page_manager: Rc<RefCell<page::PageManager>>,
 }
 
 impl Dir {
-    pub fn new(f: Box<dyn fs::File>, page_manager: Rc<RefCell<page::PageManager>>) -> Self {
+    pub fn new(
+        f: Box<dyn fs::File>,
+        options: Rc<Options>,
+        page_manager: Rc<RefCell<page::PageManager>>,
+        indexer: Option<Rc<Indexer>>,
+    ) -> Self {
         Dir {
-            archive: Rc::new(f),
+            archive: Rc::new(Source::new(f, options, indexer)),
             path: PathBuf::new(),
             attr: RefCell::new(None),
-            dents: RefCell::new(None),
             page_manager: page_manager,
         }
     }
 
     fn from_parts(
-        f: Rc<Box<dyn fs::File>>,
+        f: Rc<Source>,
         path: PathBuf,
         attr: FileAttr,
-        dents: Rc<Vec<DirEntry>>,
         page_manager: Rc<RefCell<page::PageManager>>,
     ) -> Self {
         Dir {
             archive: f,
             path: path,
             attr: RefCell::new(Some(attr)),
-            dents: RefCell::new(Some(dents)),
             page_manager: page_manager,
         }
     }
-
-    fn update_cache(&self) -> Result<()> {
-        use crate::fs::Dir;
-        if self.dents.borrow().is_some() {
-            return Ok(());
-        }
-        let self_attr = self.getattr()?;
-        let mut archive = wrapper::Archive::new(self.archive.open()?);
-        let mut dents = Vec::new();
-        let mut dirs = HashSet::new();
-        loop {
-            match archive.next_entry() {
-                Some(Ok(ent)) => {
-                    let path = ent.pathname();
-                    let attr = to_fuse_file_attr(ent.size(), ent.filetype(), self_attr);
-                    {
-                        let mut parent = path.parent();
-                        while parent.is_some() {
-                            let path = parent.unwrap();
-                            if dirs.insert(PathBuf::from(path)) {
-                                dents.push(DirEntry {
-                                    attr: self_attr,
-                                    path: PathBuf::from(path),
-                                });
-                            }
-                            parent = path.parent();
-                        }
-                    }
-                    if attr.kind != FileType::Directory || dirs.insert(path.clone()) {
-                        dents.push(DirEntry {
-                            attr: attr,
-                            path: path,
-                        });
-                    }
-                }
-                Some(Err(e)) => return Err(e),
-                None => break,
-            }
-        }
-        *self.dents.borrow_mut() = Some(Rc::new(dents));
-        Ok(())
-    }
 }
 
 impl fs::Dir for Dir {
     fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
-        self.update_cache()?;
-        Ok(Box::new(DirHandler::open(self)))
+        let dents = self
+            .archive
+            .dents()
+            .map_err(|e| self.archive.context(None, e))?;
+        Ok(Box::new(DirHandler::open(self, dents)))
     }
 
     fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
-        self.update_cache()?;
-        let lookup_path = self.path.join(name);
-        for e in self.dents.borrow().as_ref().unwrap().iter() {
-            if e.path == lookup_path {
-                if e.attr.kind == FileType::Directory {
-                    return Ok(fs::Entry::Dir(Box::new(Dir::from_parts(
-                        self.archive.clone(),
-                        lookup_path.clone(),
-                        e.attr,
-                        self.dents.borrow().as_ref().unwrap().clone(),
-                        self.page_manager.clone(),
-                    ))));
-                } else {
-                    return Ok(fs::Entry::File(Box::new(CacheFile::new(
-                        ArchivedFile::new(self.archive.clone(), e.attr, lookup_path.clone()),
-                        self.page_manager.clone(),
-                    ))));
-                }
-            }
+        let attr = self
+            .archive
+            .lookup(&self.path, name)
+            .map_err(|e| self.archive.context(None, e))?;
+        match attr {
+            Some(attr) => Ok(entry(
+                &self.archive,
+                self.path.join(name),
+                attr,
+                &self.page_manager,
+            )),
+            None => Err(Error::from_raw_os_error(libc::ENOENT)),
         }
-        Err(Error::from_raw_os_error(libc::ENOENT))
     }
 
     fn getattr(&self) -> Result<FileAttr> {
         if self.attr.borrow().is_none() {
-            let mut attr = self.archive.getattr()?;
-            attr.kind = FileType::Directory;
-            *self.attr.borrow_mut() = Some(attr);
+            *self.attr.borrow_mut() = Some(self.archive.dir_attr()?);
         }
         Ok(self.attr.borrow().unwrap())
     }
 
     fn name(&self) -> &OsStr {
         if self.path.as_os_str().is_empty() {
-            self.archive.name()
+            self.archive.file.name()
         } else {
             self.path.file_name().unwrap()
         }
     }
+
+    fn origin(&self) -> Option<&Path> {
+        self.archive.file.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.archive.member(&self.path)
+    }
+
+    // the root is the archive file, which may be replaced.
+    fn volatility(&self) -> fs::Volatility {
+        if self.path.as_os_str().is_empty() {
+            self.archive.file.volatility()
+        } else {
+            fs::Volatility::Immutable
+        }
+    }
+
+    // the root shows the attributes of the archive file.
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        let mut names = if self.path.as_os_str().is_empty() {
+            self.archive.file.listxattr()?
+        } else if self.origin().is_some() {
+            vec![RAW_NAME_XATTR.into(), ORIGIN_XATTR.into()]
+        } else {
+            vec![RAW_NAME_XATTR.into()]
+        };
+        names.push(INDEX_STATE_XATTR.into());
+        names.push(ENTRY_COUNT_XATTR.into());
+        Ok(names)
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        if name == INDEX_STATE_XATTR {
+            Ok(self.archive.index_state().0.as_bytes().to_vec())
+        } else if name == ENTRY_COUNT_XATTR {
+            Ok(self.archive.index_state().1.to_string().into_bytes())
+        } else if self.path.as_os_str().is_empty() {
+            self.archive.file.getxattr(name)
+        } else if name == RAW_NAME_XATTR {
+            Ok(raw_name(&self.path))
+        } else if name == ORIGIN_XATTR {
+            self.archive.origin_xattr(&self.path)
+        } else {
+            Err(Error::from_raw_os_error(libc::ENODATA))
+        }
+    }
+}
+
+// the entry of the member at the path.
+fn entry(
+    archive: &Rc<Source>,
+    path: PathBuf,
+    attr: FileAttr,
+    page_manager: &Rc<RefCell<page::PageManager>>,
+) -> fs::Entry {
+    if attr.kind == FileType::Directory {
+        let dir = Dir::from_parts(archive.clone(), path, attr, page_manager.clone());
+        fs::Entry::Dir(Box::new(dir))
+    } else {
+        let file = CacheFile::new(
+            ArchivedFile::new(archive.clone(), attr, path),
+            page_manager.clone(),
+        );
+        fs::Entry::File(Box::new(file))
+    }
 }
 
 struct DirHandler {
-    archive: Rc<Box<dyn fs::File>>,
+    archive: Rc<Source>,
     path: PathBuf,
-    dents: Rc<Vec<DirEntry>>,
+    dents: Rc<Dents>,
     i: usize,
     page_manager: Rc<RefCell<page::PageManager>>,
 }
 
 impl DirHandler {
-    fn open(dir: &Dir) -> Self {
+    fn open(dir: &Dir, dents: Rc<Dents>) -> Self {
         DirHandler {
             archive: dir.archive.clone(),
             path: dir.path.clone(),
-            dents: dir.dents.borrow().as_ref().unwrap().clone(),
+            dents: dents,
             i: 0,
             page_manager: dir.page_manager.clone(),
         }
@@ -267,88 +1079,555 @@ impl Iterator for DirHandler {
     type Item = Result<fs::Entry>;
 
     fn next(&mut self) -> Option<Result<fs::Entry>> {
-        let dents = self.dents.as_ref();
-        while self.i < dents.len() {
-            let e = &dents[self.i];
-            self.i += 1;
-            match e.path.parent() {
-                Some(parent) if parent == self.path => {
-                    if e.attr.kind == FileType::Directory {
-                        let dir = Dir::from_parts(
-                            self.archive.clone(),
-                            e.path.clone(),
-                            e.attr,
-                            self.dents.clone(),
-                            self.page_manager.clone(),
-                        );
-                        return Some(Ok(fs::Entry::Dir(Box::new(dir))));
-                    } else {
-                        let file = CacheFile::new(
-                            ArchivedFile::new(self.archive.clone(), e.attr, e.path.clone()),
-                            self.page_manager.clone(),
-                        );
-                        return Some(Ok(fs::Entry::File(Box::new(file))));
-                    }
-                }
-                _ => continue,
-            }
+        let (name, attr) = self.dents.children(&self.path).get(self.i)?;
+        self.i += 1;
+        Some(Ok(entry(
+            &self.archive,
+            self.path.join(name),
+            *attr,
+            &self.page_manager,
+        )))
+    }
+}
+
+#[allow(unreachable_patterns)]
+pub fn capabilities(backend: Backend) -> Result<Capabilities> {
+    match backend {
+        #[cfg(feature = "libarchive")]
+        Backend::Libarchive => wrapper::capabilities(),
+        #[cfg(feature = "rust-backend")]
+        Backend::Rust => Ok(rust::capabilities()),
+        backend => {
+            let e = error::Error::UnsupportedFormat(format!("{:?} backend is not built", backend));
+            Err(e.into())
+        }
+    }
+}
+
+// warns of enabled formats and filters which the backend can not read.
+pub fn check_capabilities(options: &Options) {
+    let c = match capabilities(options.backend()) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("{}", e);
+            return;
+        }
+    };
+    for format in options.formats().iter() {
+        if !c.formats.contains(format) {
+            warn!("{} is not supported by {}", format.name(), c.version);
+        }
+    }
+    for filter in options.filters().iter() {
+        if c.external_filters.contains(filter) {
+            info!("{} is read by an external program", filter.name());
+        } else if !c.filters.contains(filter) {
+            warn!("{} is not supported by {}", filter.name(), c.version);
         }
-        None
     }
 }
 
+// the name of ArchiveViewer to map extensions to.
+const VIEWER_NAME: &str = "archive";
+
+/// ArchiveViewer shows zip and rar files, or ones of mapped extensions, as directories.
+/// the contents of read files are cached in memory up to max_bytes.
+/// the cache is owned by the viewer, with its own lru list, so other viewers do not evict it.
 pub struct ArchiveViewer {
+    options: Rc<Options>,
     page_manager: Rc<RefCell<page::PageManager>>,
+    indexer: Option<Rc<Indexer>>,
+    size_limit: SizeLimit,
+    // limits by lowercase extensions, which take precedence.
+    extension_size_limits: HashMap<String, SizeLimit>,
+    // if set, the root is read as an archive by these, whatever the name is.
+    root_options: Option<Rc<Options>>,
+    scrubber: Option<Rc<Scrubber>>,
 }
 
 impl ArchiveViewer {
-    pub fn new(max_bytes: usize) -> Result<ArchiveViewer> {
-        wrapper::initialize();
+    pub fn new(max_bytes: usize, options: Options) -> Result<ArchiveViewer> {
+        initialize();
         Ok(ArchiveViewer {
+            options: Rc::new(options),
             page_manager: Rc::new(RefCell::new(page::PageManager::new(max_bytes)?)),
+            indexer: None,
+            size_limit: SizeLimit::NONE,
+            extension_size_limits: HashMap::new(),
+            root_options: None,
+            scrubber: None,
         })
     }
+
+    // cached contents are encrypted by a key in memory, so that the temporary file behind
+    // the cache, which may be written back to the disk, does not keep plaintext.
+    pub fn set_encrypt_cache(&mut self) -> Result<()> {
+        let cipher = cipher::Cipher::ephemeral()?;
+        self.page_manager.borrow_mut().set_cipher(cipher);
+        Ok(())
+    }
+
+    // the last n members whose loads are not finished when their entries are dropped go on
+    // from where they stopped when they are opened again, instead of decoding them again.
+    pub fn set_warm_readers(&mut self, n: usize) {
+        self.page_manager.borrow_mut().set_max_kept_loads(n);
+    }
+
+    // the root is shown as an archive even without a known extension, like a zip named
+    // "backup". if the format is given, only it is read.
+    pub fn set_assume_archive(&mut self, format: Option<Format>) {
+        let mut options = (*self.options).clone();
+        if let Some(format) = format {
+            options.set_formats(vec![format]);
+        }
+        self.root_options = Some(Rc::new(options));
+    }
+
+    // archives out of the limit are shown as files, like huge ones on slow disks, or tiny
+    // ones which are likely broken.
+    pub fn set_size_limit(&mut self, limit: SizeLimit) {
+        self.size_limit = limit;
+    }
+
+    pub fn set_extension_size_limit(&mut self, ext: &str, limit: SizeLimit) {
+        self.extension_size_limits.insert(ext.to_lowercase(), limit);
+    }
+
+    fn in_size_limit(&self, f: &dyn fs::File) -> bool {
+        let ext = Path::new(f.name())
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        let limit = ext
+            .and_then(|ext| self.extension_size_limits.get(&ext))
+            .unwrap_or(&self.size_limit);
+        if *limit == SizeLimit::NONE {
+            return true;
+        }
+        match f.getattr() {
+            Ok(attr) if !limit.contains(attr.size) => {
+                debug!(size = attr.size, name = ?f.name(), "not shown as a directory");
+                false
+            }
+            // errors are told when the archive is read.
+            _ => true,
+        }
+    }
+
+    // archives in listed directories are indexed by the threads before they are accessed.
+    // the sandbox indexes archives on access only. the threads are confined like the mount if
+    // confinement is given.
+    pub fn set_index_threads(
+        &mut self,
+        threads: usize,
+        confinement: Option<&Confinement>,
+    ) -> Result<()> {
+        self.indexer = if threads > 0 && !self.options.sandbox() {
+            Some(Rc::new(Indexer::new(threads, &self.options, confinement)?))
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+impl ArchiveViewer {
+    // the cache of contents of archived files.
+    pub fn cache(&self) -> Rc<dyn control::Cache> {
+        self.page_manager.clone()
+    }
+
+    // the numbers of warnings of archives logged and suppressed as repeats.
+    pub fn warnings(&self) -> Arc<dyn stats::Counters> {
+        self.options.warnings().clone()
+    }
+
+    // archives shown after this are scrubbed by the returned scrubber, with results of the
+    // indexer if it is started before.
+    pub fn scrubber(&mut self) -> Rc<dyn control::Scrubber> {
+        let scrubber = Rc::new(Scrubber::new(self.indexer.clone()));
+        self.scrubber = Some(scrubber.clone());
+        scrubber
+    }
+}
+
+impl control::Cache for RefCell<page::PageManager> {
+    fn name(&self) -> &str {
+        "archive"
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.borrow().used_bytes()
+    }
+
+    fn max_bytes(&self) -> usize {
+        self.borrow().max_bytes()
+    }
+
+    fn drop_cache(&self) {
+        let kept = self.borrow_mut().take_kept_loads();
+        drop(kept);
+        self.borrow_mut().free_unused_pages()
+    }
+
+    fn loads(&self) -> Vec<(String, usize, usize)> {
+        self.borrow_mut().loads()
+    }
 }
 
 impl fs::Viewer for ArchiveViewer {
-    fn view(&self, e: fs::Entry) -> fs::Entry {
-        let is_archive = match e {
-            fs::Entry::File(ref f) => {
-                match Path::new(f.name()).extension().and_then(|ext| ext.to_str()) {
-                    Some(ext) => match ext.to_lowercase().as_str() {
-                        "zip" => true,
-                        "rar" => true,
-                        _ => false,
-                    },
-                    _ => false,
-                }
-            }
-            _ => false,
+    fn name(&self) -> &str {
+        VIEWER_NAME
+    }
+
+    fn extensions(&self) -> &[&str] {
+        match self.options.backend() {
+            Backend::Libarchive => &["zip", "rar"],
+            // tar is read, gzipped or not, but rar is not.
+            Backend::Rust => &["zip", "tar", "tar.gz", "tgz"],
+        }
+    }
+
+    fn view(&self, e: fs::Entry, ctx: &fs::ViewContext) -> fs::Entry {
+        let options = match (&self.root_options, ctx.depth()) {
+            (&Some(ref options), 0) => Some(options.clone()),
+            _ => match e {
+                fs::Entry::File(ref f) => match ctx.viewer_for(f.name()) {
+                    Some(ref viewer) if viewer == VIEWER_NAME && self.in_size_limit(&**f) => {
+                        Some(self.options.clone())
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
         };
-        if is_archive {
+        if let Some(options) = options {
             if let fs::Entry::File(f) = e {
-                return fs::Entry::Dir(Box::new(Dir::new(f, self.page_manager.clone())));
+                // queued unless the indexer has read the archive as it is.
+                if let (Some(indexer), Some(path)) = (&self.indexer, f.physical_path()) {
+                    if let Ok(attr) = f.getattr() {
+                        indexer.submit(path, (attr.mtime, attr.size));
+                    }
+                }
+                let dir = Dir::new(f, options, self.page_manager.clone(), self.indexer.clone());
+                if let Some(ref scrubber) = self.scrubber {
+                    scrubber.add(&dir.archive);
+                }
+                return fs::Entry::Dir(Box::new(dir));
             }
         }
         e
     }
 }
 
+#[test]
+fn test_size_limit() {
+    use crate::control::Control;
+    use crate::fs::Viewer;
+    use crate::physical;
+    use crate::stats::Stats;
+    use std::time::Duration;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.ZIP");
+    std::fs::write(&path, "broken").unwrap();
+    let ctx = Rc::new(physical::Context::new(Duration::from_secs(1)));
+    let control = Rc::new(Control::new(Rc::new(RefCell::new(Stats::new()))));
+    control.map_default_extension("zip", VIEWER_NAME);
+    let view_ctx = fs::ViewContext::new(PathBuf::from("/a.ZIP"), None, control.clone());
+    let mut viewer = ArchiveViewer::new(1024 * 1024, Options::default()).unwrap();
+    let is_dir = |viewer: &ArchiveViewer| {
+        let f = physical::File::new(path.clone(), ctx.clone());
+        match viewer.view(fs::Entry::File(Box::new(f)), &view_ctx) {
+            fs::Entry::Dir(_) => true,
+            fs::Entry::File(_) => false,
+        }
+    };
+    assert!(is_dir(&viewer));
+    viewer.set_size_limit("1024:".parse().unwrap());
+    assert!(!is_dir(&viewer));
+    viewer.set_extension_size_limit("Zip", ":1024".parse().unwrap());
+    assert!(is_dir(&viewer));
+    control.block_extension("zip");
+    assert!(!is_dir(&viewer));
+}
+
+#[test]
+fn test_assume_archive() {
+    use crate::control::Control;
+    use crate::fs::Viewer;
+    use crate::physical;
+    use crate::stats::Stats;
+    use std::time::Duration;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("backup");
+    std::fs::write(&path, "zip without suffix").unwrap();
+    let ctx = Rc::new(physical::Context::new(Duration::from_secs(1)));
+    let control = Rc::new(Control::new(Rc::new(RefCell::new(Stats::new()))));
+    let mut viewer = ArchiveViewer::new(1024 * 1024, Options::default()).unwrap();
+    let is_dir = |viewer: &ArchiveViewer, at: &str| {
+        let f = physical::File::new(path.clone(), ctx.clone());
+        let view_ctx = fs::ViewContext::new(PathBuf::from(at), None, control.clone());
+        match viewer.view(fs::Entry::File(Box::new(f)), &view_ctx) {
+            fs::Entry::Dir(_) => true,
+            fs::Entry::File(_) => false,
+        }
+    };
+    assert!(!is_dir(&viewer, "/"));
+    viewer.set_assume_archive(Some(Format::Zip));
+    assert!(is_dir(&viewer, "/"));
+    assert!(!is_dir(&viewer, "/backup"));
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_max_depth() {
+    use crate::fs::ShowFS;
+    use crate::testing::tar_bytes;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let inner = tar_bytes(&[("file", b"hello")]);
+    let outer = tar_bytes(&[("inner.tar", &inner)]);
+    std::fs::write(tmp.path().join("outer.tar"), outer).unwrap();
+    let tree = |depth| {
+        let mut fs = ShowFS::new(tmp.path());
+        fs.control().map_extension("tar", VIEWER_NAME);
+        fs.set_max_depth(depth);
+        let mut options = Options::default();
+        options.set_backend(Backend::Rust);
+        fs.register_viewer(ArchiveViewer::new(1024 * 1024, options).unwrap());
+        fs.into_tree()
+    };
+    let is_dir = |e: fs::Entry| match e {
+        fs::Entry::Dir(_) => true,
+        fs::Entry::File(_) => false,
+    };
+    let shallow = tree(1);
+    assert!(is_dir(shallow.resolve("/outer.tar").unwrap()));
+    assert!(!is_dir(shallow.resolve("/outer.tar/inner.tar").unwrap()));
+    let deep = tree(2);
+    assert!(is_dir(deep.resolve("/outer.tar/inner.tar").unwrap()));
+    assert!(deep.resolve("/outer.tar/inner.tar/file").is_ok());
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_partial_lookup() {
+    use crate::fs::Dir as FSDir;
+    use crate::testing::{archive_dir, tar_bytes};
+
+    // a member followed by a broken header.
+    let mut data = tar_bytes(&[("a/first", b"hello")]);
+    // in place of the end of the archive.
+    let end = data.len() - 1024;
+    data.truncate(end);
+    data.extend_from_slice(&[0xff; 512]);
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("broken.tar");
+    std::fs::write(&path, data).unwrap();
+
+    let dir = archive_dir(&path, Options::default());
+    let state = |dir: &Dir| {
+        let state = dir.getxattr(OsStr::new(INDEX_STATE_XATTR)).unwrap();
+        let count = dir.getxattr(OsStr::new(ENTRY_COUNT_XATTR)).unwrap();
+        (
+            String::from_utf8(state).unwrap(),
+            String::from_utf8(count).unwrap(),
+        )
+    };
+    assert_eq!(state(&dir), ("none".into(), "0".into()));
+    // found before the broken header is read.
+    let a = match dir.lookup(OsStr::new("a")).unwrap() {
+        fs::Entry::Dir(d) => d,
+        fs::Entry::File(_) => panic!("not a directory"),
+    };
+    assert!(a.lookup(OsStr::new("first")).is_ok());
+    assert_eq!(state(&dir), ("indexing".into(), "2".into()));
+    assert!(dir.lookup(OsStr::new("none")).is_err());
+    assert!(dir.open().is_err());
+    assert_eq!(state(&dir), ("failed".into(), "0".into()));
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_resume_scan() {
+    use crate::fs::Dir as FSDir;
+    use crate::testing::{archive_dir_of, tar_bytes, MemTree};
+
+    let tree = MemTree::new();
+    let data = tar_bytes(&[("a", b"x"), ("b", b"x"), ("c", b"x")]);
+    tree.file("a.tar", &data);
+    let file = match tree.root() {
+        fs::Entry::Dir(root) => match root.lookup(OsStr::new("a.tar")).unwrap() {
+            fs::Entry::File(f) => f,
+            fs::Entry::Dir(_) => panic!("not a file"),
+        },
+        fs::Entry::File(_) => panic!("not a directory"),
+    };
+    let dir = archive_dir_of(file, Options::default());
+
+    // each lookup reads the headers after the last one read.
+    assert!(dir.lookup(OsStr::new("a")).is_ok());
+    assert!(dir.lookup(OsStr::new("b")).is_ok());
+    assert!(dir.lookup(OsStr::new("c")).is_ok());
+    assert!(dir.lookup(OsStr::new("d")).is_err());
+    assert_eq!(dir.open().unwrap().count(), 3);
+    assert_eq!(tree.opens(), 1);
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_index_state() {
+    use crate::fs::Dir as FSDir;
+    use crate::physical;
+    use crate::testing::tar_bytes;
+    use std::time::Duration;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.tar");
+    std::fs::write(&path, tar_bytes(&[("a/file", b"hello")])).unwrap();
+
+    let ctx = Rc::new(physical::Context::new(Duration::from_secs(1)));
+    let mut options = Options::default();
+    options.set_backend(Backend::Rust);
+    let indexer = Rc::new(Indexer::new(1, &options, None).unwrap());
+    let m = std::fs::metadata(&path).unwrap();
+    let mtime = Timespec::new(m.mtime(), m.mtime_nsec() as i32);
+    indexer.submit(&path, (mtime, m.len()));
+    let page_manager = Rc::new(RefCell::new(page::PageManager::new(1024 * 1024).unwrap()));
+    let dir = Dir::new(
+        Box::new(physical::File::new(path, ctx)),
+        Rc::new(options),
+        page_manager,
+        Some(indexer),
+    );
+    assert!(dir.listxattr().unwrap().contains(&INDEX_STATE_XATTR.into()));
+    let state = || dir.getxattr(OsStr::new(INDEX_STATE_XATTR)).unwrap();
+    while state() == b"indexing" {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(state(), b"complete");
+    let count = dir.getxattr(OsStr::new(ENTRY_COUNT_XATTR)).unwrap();
+    assert_eq!(count, b"2");
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_large_member() {
+    use crate::fs::ShowFS;
+    use crate::testing::tar_bytes;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    // over 4 GiB, which is a hole of the sparse file.
+    const BIG: u64 = (4 << 30) + 1;
+    let tmp = tempfile::tempdir().unwrap();
+    let mut file = std::fs::File::create(tmp.path().join("large.tar")).unwrap();
+    let mut header = tar::Header::new_ustar();
+    header.set_path("big").unwrap();
+    header.set_size(BIG);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    file.write_all(header.as_bytes()).unwrap();
+    let end = 512 + (BIG + 511) / 512 * 512;
+    file.set_len(end).unwrap();
+    file.seek(SeekFrom::Start(end)).unwrap();
+    file.write_all(&tar_bytes(&[("small", b"hello")])).unwrap();
+
+    let mut fs = ShowFS::new(tmp.path());
+    fs.control().map_extension("tar", VIEWER_NAME);
+    let mut options = Options::default();
+    options.set_backend(Backend::Rust);
+    fs.register_viewer(ArchiveViewer::new(1024 * 1024, options).unwrap());
+    let tree = fs.into_tree();
+    let attr = tree.resolve("/large.tar/big").unwrap().getattr(0).unwrap();
+    assert_eq!(attr.size, BIG);
+    assert_eq!(attr.blocks, (BIG + 4095) / 4096);
+    let mut s = String::new();
+    tree.open("/large.tar/small")
+        .unwrap()
+        .read_to_string(&mut s)
+        .unwrap();
+    assert_eq!(s, "hello");
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_rust_backend() {
+    use crate::fs::{ShowFS, Viewer};
+    use crate::testing::tar_bytes;
+    use flate2::write::GzEncoder;
+    use std::io::{Read, Write};
+    use zip::write::FileOptions;
+    use zip::CompressionMethod;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(tmp.path().join("a.zip")).unwrap());
+    for &(name, method) in [
+        ("stored", CompressionMethod::Stored),
+        ("dir/deflated", CompressionMethod::Deflated),
+    ]
+    .iter()
+    {
+        let options = FileOptions::default().compression_method(method);
+        writer.start_file(name, options).unwrap();
+        writer.write_all(b"hello").unwrap();
+    }
+    writer.finish().unwrap();
+    let tar = tar_bytes(&[("file", b"hello")]);
+    std::fs::write(tmp.path().join("b.tar"), &tar).unwrap();
+    let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(&tar).unwrap();
+    let gz = gz.finish().unwrap();
+    std::fs::write(tmp.path().join("c.tar.gz"), &gz).unwrap();
+    std::fs::write(tmp.path().join("d.tgz"), &gz).unwrap();
+
+    // archives are shown by their extensions, without mappings.
+    let mut fs = ShowFS::new(tmp.path());
+    let mut options = Options::default();
+    options.set_backend(Backend::Rust);
+    let viewer = ArchiveViewer::new(1024 * 1024, options).unwrap();
+    assert!(!viewer.extensions().contains(&"rar"));
+    fs.register_viewer(viewer);
+    let tree = fs.into_tree();
+    for path in [
+        "/a.zip/stored",
+        "/a.zip/dir/deflated",
+        "/b.tar/file",
+        "/c.tar.gz/file",
+        "/d.tgz/file",
+    ]
+    .iter()
+    {
+        let mut s = String::new();
+        tree.open(path).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello");
+    }
+}
+
 #[test]
 fn test_iterate_dir() {
     use crate::fs::Dir as FSDir;
     use crate::physical;
+    use std::time::Duration;
 
     let page_manager = Rc::new(RefCell::new(
         page::PageManager::new(100 * 1024 * 1024).unwrap(),
     ));
     let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let zip = root.join("assets/test.zip");
-    let zip_dir = Dir::new(Box::new(physical::File::new(zip)), page_manager.clone());
+    let ctx = Rc::new(physical::Context::new(Duration::from_secs(1)));
+    let zip_dir = Dir::new(
+        Box::new(physical::File::new(zip, ctx)),
+        Rc::new(Options::default()),
+        page_manager.clone(),
+        None,
+    );
     let entries: Vec<_> = zip_dir.open().unwrap().map(|re| re.unwrap()).collect();
     assert!(entries
         .iter()
-        .all(|e| { e.file_type(0).unwrap() == FileType::RegularFile }));
+        .all(|e| { e.file_type().unwrap() == FileType::RegularFile }));
     let mut names: Vec<_> = entries.iter().map(|e| PathBuf::from(e.name())).collect();
     names.sort();
     let expect = vec![PathBuf::from("large"), PathBuf::from("small")];
@@ -356,17 +1635,20 @@ fn test_iterate_dir() {
 }
 
 #[test]
+#[cfg(feature = "libarchive")]
 fn test_file_read() {
     use crate::fs::File;
     use crate::physical;
     use std::fs as stdfs;
     use std::io::Read;
+    use std::time::Duration;
 
     let assets = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
     let zip = assets.join("test.zip");
-    let zip_file = physical::File::new(zip);
+    let ctx = Rc::new(physical::Context::new(Duration::from_secs(1)));
+    let zip_file = physical::File::new(zip, ctx);
     let read_archive = |name| {
-        let archive = wrapper::Archive::new(zip_file.open().unwrap());
+        let archive = wrapper::Archive::new(zip_file.open().unwrap(), &Options::default()).unwrap();
         let mut r = archive
             .find_open(|e| e.pathname() == PathBuf::from(name))
             .unwrap()
@@ -390,3 +1672,433 @@ fn test_file_read() {
     let large_expect = read_file("large");
     assert_eq!(large_actual, large_expect);
 }
+
+#[test]
+#[cfg(feature = "libarchive")]
+fn test_open_broken() {
+    use std::io::Cursor;
+
+    let broken = Cursor::new(b"not an archive".to_vec());
+    assert!(wrapper::Archive::new(broken, &Options::default()).is_err());
+}
+
+#[test]
+#[cfg(feature = "libarchive")]
+fn test_file_seek() {
+    use crate::fs::File;
+    use crate::physical;
+    use std::fs as stdfs;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::time::Duration;
+
+    let assets = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let zip = assets.join("test.zip");
+    let ctx = Rc::new(physical::Context::new(Duration::from_secs(1)));
+    let zip_file = physical::File::new(zip, ctx);
+    let archive = wrapper::Archive::new(zip_file.open().unwrap(), &Options::default()).unwrap();
+    let mut r = archive
+        .find_open(|e| e.pathname() == PathBuf::from("large"))
+        .unwrap()
+        .unwrap();
+    let mut expect = Vec::<u8>::new();
+    stdfs::File::open(assets.join("large"))
+        .unwrap()
+        .read_to_end(&mut expect)
+        .unwrap();
+    let len = expect.len();
+
+    let mut read_at = |pos| {
+        let mut v = vec![0; 100];
+        r.seek(SeekFrom::Start(pos as u64)).unwrap();
+        r.read_exact(&mut v).unwrap();
+        v
+    };
+    // forward
+    assert_eq!(read_at(len / 2), &expect[len / 2..len / 2 + 100]);
+    // backward
+    assert_eq!(read_at(10), &expect[10..110]);
+    assert_eq!(read_at(len - 100), &expect[len - 100..]);
+
+    assert_eq!(r.seek(SeekFrom::End(0)).unwrap(), len as u64);
+    let mut v = Vec::new();
+    assert_eq!(r.read_to_end(&mut v).unwrap(), 0);
+}
+
+#[test]
+#[cfg(feature = "libarchive")]
+fn test_open_in_memory() {
+    use std::fs as stdfs;
+    use std::io::Read;
+
+    let assets = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let mut zip = Vec::<u8>::new();
+    stdfs::File::open(assets.join("test.zip"))
+        .unwrap()
+        .read_to_end(&mut zip)
+        .unwrap();
+    let mut expect = Vec::<u8>::new();
+    stdfs::File::open(assets.join("large"))
+        .unwrap()
+        .read_to_end(&mut expect)
+        .unwrap();
+
+    fn read<R: Read>(mut r: R) -> Vec<u8> {
+        let mut v = Vec::<u8>::new();
+        r.read_to_end(&mut v).unwrap();
+        v
+    }
+
+    // from bytes.
+    let archive = wrapper::Archive::from_memory(&zip[..], &Options::default()).unwrap();
+    let r = archive
+        .find_open(|e| e.pathname() == PathBuf::from("large"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(read(r), expect);
+
+    // from cached pages.
+    let mut page_manager = page::PageManager::new(10 * 1024 * 1024).unwrap();
+    let mut page = page_manager.allocate(zip.len()).unwrap().upgrade().unwrap();
+    let mut n = 0;
+    for slice in page.get_slices_mut(0) {
+        let l = std::cmp::min(slice.len(), zip.len() - n);
+        slice[..l].copy_from_slice(&zip[n..n + l]);
+        n += l;
+    }
+    let archive = wrapper::Archive::from_pages(page, zip.len(), &Options::default()).unwrap();
+    let r = archive
+        .find_open(|e| e.pathname() == PathBuf::from("large"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(read(r), expect);
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_mmap() {
+    use crate::fs::Dir as FSDir;
+    use crate::testing::{archive_dir, tar_bytes};
+    use std::io::Read;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("mapped.tar");
+    std::fs::write(&path, tar_bytes(&[("file", b"hello")])).unwrap();
+
+    let mut options = Options::default();
+    options.set_mmap(true);
+    let dir = archive_dir(&path, options);
+    let mut s = String::new();
+    let file = dir.lookup(OsStr::new("file")).unwrap();
+    match file {
+        fs::Entry::File(ref f) => f.open().unwrap().read_to_string(&mut s).unwrap(),
+        fs::Entry::Dir(_) => panic!("not a file"),
+    };
+    assert_eq!(s, "hello");
+    assert_eq!(file.getxattr(OsStr::new(RAW_NAME_XATTR)).unwrap(), b"file");
+
+    std::fs::write(tmp.path().join("empty"), b"").unwrap();
+    assert!(physical::map(&tmp.path().join("empty")).unwrap().is_none());
+}
+
+#[test]
+fn test_encrypted_policy() {
+    use crate::fs::File;
+    use crate::testing::member;
+    use std::mem::zeroed;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.zip");
+    std::fs::write(&path, b"").unwrap();
+    let source = |policy| {
+        let mut options = Options::default();
+        options.set_encrypted_policy(policy);
+        Source::of_path(&path, options)
+    };
+    let member = Metadata {
+        encrypted: true,
+        ..member("dir/secret", libc::S_IFREG, 5)
+    };
+    let insert = |source: &Source| {
+        let mut dents = Dents::new(unsafe { zeroed::<FileAttr>() });
+        source.insert_member(&mut dents, &member);
+        dents
+            .children(Path::new("dir"))
+            .iter()
+            .map(|e| (e.0.clone(), e.1.size))
+            .collect::<Vec<_>>()
+    };
+    let open = |source: &Rc<Source>, name: &str| {
+        let attr = unsafe { zeroed::<FileAttr>() };
+        ArchivedFile::new(source.clone(), attr, PathBuf::from(name)).open()
+    };
+
+    assert!(insert(&source(EncryptedPolicy::Hide)).is_empty());
+
+    let deny = source(EncryptedPolicy::Deny);
+    assert_eq!(insert(&deny), vec![(OsString::from("secret"), 5)]);
+    let e = open(&deny, "dir/secret").err().unwrap();
+    assert_eq!(error::errno(&e), libc::EACCES);
+
+    let mark = source(EncryptedPolicy::Mark);
+    assert_eq!(insert(&mark), vec![(OsString::from("secret.ENCRYPTED"), 0)]);
+    assert!(open(&mark, "dir/secret.ENCRYPTED").is_ok());
+}
+
+#[test]
+fn test_special_files() {
+    use crate::fs::File;
+    use crate::testing::member;
+    use std::io::Read;
+    use std::mem::zeroed;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.tar");
+    std::fs::write(&path, b"").unwrap();
+    let source = |mode| {
+        let mut options = Options::default();
+        options.set_special_files(mode);
+        Source::of_path(&path, options)
+    };
+    let member = Metadata {
+        perm: 0o666,
+        ..member("dev/null", libc::S_IFCHR, 0)
+    };
+    let insert = |source: &Source| {
+        let mut dents = Dents::new(unsafe { zeroed::<FileAttr>() });
+        source.insert_member(&mut dents, &member);
+        dents.get(Path::new("dev"), OsStr::new("null"))
+    };
+
+    let keep = source(SpecialFiles::Keep);
+    assert_eq!(insert(&keep).unwrap().kind, FileType::CharDevice);
+    assert!(insert(&source(SpecialFiles::Hide)).is_none());
+
+    let regular = source(SpecialFiles::Regular);
+    let attr = insert(&regular).unwrap();
+    assert_eq!(attr.kind, FileType::RegularFile);
+    let file = ArchivedFile::new(regular.clone(), attr, PathBuf::from("dev/null"));
+    let mut contents = Vec::new();
+    file.open().unwrap().read_to_end(&mut contents).unwrap();
+    assert!(contents.is_empty());
+    let page_manager = Rc::new(RefCell::new(page::PageManager::new(1 << 20).unwrap()));
+    let file = CacheFile::new(file, page_manager);
+    assert!(file
+        .listxattr()
+        .unwrap()
+        .contains(&ORIGINAL_TYPE_XATTR.into()));
+    let kind = file.getxattr(OsStr::new(ORIGINAL_TYPE_XATTR)).unwrap();
+    assert_eq!(kind, b"char_device");
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_symlinks() {
+    use crate::fs::Dir as FSDir;
+    use crate::testing::archive_dir;
+    use zip::write::FileOptions;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    header.set_mode(0o777);
+    header.set_cksum();
+    builder
+        .append_link(&mut header, "link", "dir/target")
+        .unwrap();
+    header = tar::Header::new_ustar();
+    header.set_size(0);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, "file", &[][..]).unwrap();
+    std::fs::write(tmp.path().join("a.tar"), builder.into_inner().unwrap()).unwrap();
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(tmp.path().join("b.zip")).unwrap());
+    writer
+        .add_symlink("link", "dir/target", FileOptions::default())
+        .unwrap();
+    writer.finish().unwrap();
+
+    // the target is in the header of tar, and is the contents in zip.
+    for name in ["a.tar", "b.zip"].iter() {
+        let dir = archive_dir(tmp.path().join(name), Options::default());
+        let link = dir.lookup(OsStr::new("link")).unwrap();
+        assert_eq!(link.file_type().unwrap(), FileType::Symlink);
+        assert_eq!(link.readlink().unwrap(), Path::new("dir/target"));
+    }
+    let dir = archive_dir(tmp.path().join("a.tar"), Options::default());
+    let file = dir.lookup(OsStr::new("file")).unwrap();
+    let errno = file.readlink().err().and_then(|e| e.raw_os_error());
+    assert_eq!(errno, Some(libc::EINVAL));
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_unknown_size() {
+    use crate::fs::File;
+    use crate::testing::member;
+    use crate::testing::tar_bytes;
+    use std::io::Read;
+    use std::mem::zeroed;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.tar");
+    // larger than tiny members, which are not cached.
+    let contents = b"hello".repeat(4000);
+    std::fs::write(&path, tar_bytes(&[("a", &contents), ("b", &contents)])).unwrap();
+
+    let mut options = Options::default();
+    options.set_backend(Backend::Rust);
+    let source = Source::of_path(&path, options);
+    // like members streamed into archives without sizes in their headers.
+    let member = member("a", libc::S_IFREG, -1);
+    let mut dents = Dents::new(unsafe { zeroed::<FileAttr>() });
+    source.insert_member(&mut dents, &member);
+    let attr = dents.get(Path::new(""), OsStr::new("a")).unwrap();
+    assert_eq!(attr.size, 0);
+    let page_manager = Rc::new(RefCell::new(page::PageManager::new(1 << 20).unwrap()));
+    let file = CacheFile::new(
+        ArchivedFile::new(source.clone(), attr, PathBuf::from("a")),
+        page_manager.clone(),
+    );
+    assert!(file.direct_io());
+    assert_eq!(file.volatility(), fs::Volatility::Volatile);
+    let unknown = file.getxattr(OsStr::new(SIZE_UNKNOWN_XATTR)).unwrap();
+    assert_eq!(unknown, b"1");
+
+    // reads go past the size shown until the end, which tells the size.
+    let read = || {
+        let mut contents = Vec::new();
+        file.open().unwrap().read_to_end(&mut contents).unwrap();
+        contents
+    };
+    assert_eq!(read(), contents);
+    assert_eq!(file.getattr().unwrap().size, 20000);
+    assert!(!file.direct_io());
+    assert_eq!(file.volatility(), fs::Volatility::Immutable);
+    assert!(file.getxattr(OsStr::new(SIZE_UNKNOWN_XATTR)).is_err());
+    // then the member is cached.
+    assert_eq!(read(), contents);
+    let cached = file.getxattr(OsStr::new(CACHED_BYTES_XATTR)).unwrap();
+    assert_eq!(cached, b"20000");
+
+    // reads at offsets in order, as the mount reads, tell the size too.
+    let member = Metadata {
+        path: PathBuf::from("b"),
+        ..member
+    };
+    source.insert_member(&mut dents, &member);
+    let attr = dents.get(Path::new(""), OsStr::new("b")).unwrap();
+    let file = CacheFile::new(
+        ArchivedFile::new(source.clone(), attr, PathBuf::from("b")),
+        page_manager,
+    );
+    let mut reader = file.open().unwrap();
+    let mut read = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        let n = reader.read_at(read.len() as u64, &mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        read.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(read, contents);
+    assert_eq!(file.getattr().unwrap().size, 20000);
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_tiny_member() {
+    use crate::fs::Dir as FSDir;
+    use crate::testing::{archive_dir, tar_bytes};
+    use std::io::Read;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.tar");
+    let data = tar_bytes(&[("tiny", &[1; 5]), ("large", &[1; 20000])]);
+    std::fs::write(&path, data).unwrap();
+
+    let dir = archive_dir(&path, Options::default());
+    let cached_bytes = |name| {
+        let f = match dir.lookup(OsStr::new(name)).unwrap() {
+            fs::Entry::File(f) => f,
+            fs::Entry::Dir(_) => panic!("not a file"),
+        };
+        // loaded once read to the end and opened again.
+        for _ in 0..2 {
+            let mut contents = Vec::new();
+            f.open().unwrap().read_to_end(&mut contents).unwrap();
+        }
+        f.getxattr(OsStr::new(CACHED_BYTES_XATTR)).unwrap()
+    };
+    // the tiny one is read from the archive at each open.
+    assert_eq!(cached_bytes("tiny"), b"0");
+    assert_eq!(cached_bytes("large"), b"20000");
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_origin() {
+    use crate::fs::Dir as FSDir;
+    use crate::testing::{archive_dir, archive_dir_of, tar_bytes};
+
+    let inner = tar_bytes(&[("e", b"e")]);
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.tar");
+    std::fs::write(&path, tar_bytes(&[("b/c", b"c"), ("d.tar", &inner)])).unwrap();
+
+    let dir = archive_dir(&path, Options::default());
+    let origin = |path: &Path, member: &str| {
+        let mut value = path.as_os_str().as_bytes().to_vec();
+        value.push(0);
+        value.extend_from_slice(member.as_bytes());
+        value
+    };
+    assert_eq!(dir.member(), None);
+    let b = match dir.lookup(OsStr::new("b")).unwrap() {
+        fs::Entry::Dir(d) => d,
+        fs::Entry::File(_) => panic!("not a directory"),
+    };
+    let value = b.getxattr(OsStr::new(ORIGIN_XATTR)).unwrap();
+    assert_eq!(value, origin(&path, "b"));
+    let c = b.lookup(OsStr::new("c")).unwrap();
+    assert_eq!(c.member(), Some(PathBuf::from("b/c")));
+    let value = c.getxattr(OsStr::new(ORIGIN_XATTR)).unwrap();
+    assert_eq!(value, origin(&path, "b/c"));
+
+    // members of archives in archives are told by the paths through them.
+    let d = match dir.lookup(OsStr::new("d.tar")).unwrap() {
+        fs::Entry::File(f) => f,
+        fs::Entry::Dir(_) => panic!("not a file"),
+    };
+    let d = archive_dir_of(d, Options::default());
+    assert_eq!(d.member(), Some(PathBuf::from("d.tar")));
+    let e = d.lookup(OsStr::new("e")).unwrap();
+    assert_eq!(e.origin(), Some(path.as_path()));
+    let value = e.getxattr(OsStr::new(ORIGIN_XATTR)).unwrap();
+    assert_eq!(value, origin(&path, "d.tar/e"));
+}
+
+#[test]
+fn test_member_attr() {
+    use crate::testing::member;
+    use std::mem::zeroed;
+
+    let mut archive_attr = unsafe { zeroed::<FileAttr>() };
+    archive_attr.perm = 0o600;
+    archive_attr.uid = 1;
+    archive_attr.gid = 2;
+    let mut member = Metadata {
+        perm: 0o640,
+        owner: Some((1000, 100)),
+        ..member("file", libc::S_IFREG, 5)
+    };
+    let attr = member_attr(&member, archive_attr);
+    assert_eq!((attr.perm, attr.uid, attr.gid), (0o640, 1000, 100));
+    // members of archives which record no owners are owned like the archive.
+    member.perm = 0;
+    member.owner = None;
+    let attr = member_attr(&member, archive_attr);
+    assert_eq!((attr.perm, attr.uid, attr.gid), (0o600, 1, 2));
+}
@@ -0,0 +1,501 @@
+use super::page::PAGE_SIZE;
+use super::retry::RetryPolicy;
+use super::throttle::Throttle;
+use super::warnings::Warnings;
+use crate::shared_cache;
+use std::cmp::max;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    SevenZip,
+    Ar,
+    Cab,
+    Cpio,
+    Iso9660,
+    Lha,
+    Rar,
+    Rar5,
+    Tar,
+    Xar,
+    Zip,
+}
+
+impl Format {
+    pub const ALL: [Format; 11] = [
+        Format::SevenZip,
+        Format::Ar,
+        Format::Cab,
+        Format::Cpio,
+        Format::Iso9660,
+        Format::Lha,
+        Format::Rar,
+        Format::Rar5,
+        Format::Tar,
+        Format::Xar,
+        Format::Zip,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::SevenZip => "7zip",
+            Format::Ar => "ar",
+            Format::Cab => "cab",
+            Format::Cpio => "cpio",
+            Format::Iso9660 => "iso9660",
+            Format::Lha => "lha",
+            Format::Rar => "rar",
+            Format::Rar5 => "rar5",
+            Format::Tar => "tar",
+            Format::Xar => "xar",
+            Format::Zip => "zip",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Format, String> {
+        match Format::ALL.iter().find(|f| f.name() == s) {
+            Some(f) => Ok(*f),
+            None => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    Bzip2,
+    Compress,
+    Gzip,
+    Lz4,
+    Lzma,
+    Xz,
+    Zstd,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 7] = [
+        Filter::Bzip2,
+        Filter::Compress,
+        Filter::Gzip,
+        Filter::Lz4,
+        Filter::Lzma,
+        Filter::Xz,
+        Filter::Zstd,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Filter::Bzip2 => "bzip2",
+            Filter::Compress => "compress",
+            Filter::Gzip => "gzip",
+            Filter::Lz4 => "lz4",
+            Filter::Lzma => "lzma",
+            Filter::Xz => "xz",
+            Filter::Zstd => "zstd",
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Filter, String> {
+        match Filter::ALL.iter().find(|f| f.name() == s) {
+            Some(f) => Ok(*f),
+            None => Err(format!("unknown filter: {}", s)),
+        }
+    }
+}
+
+/// EncryptedPolicy tells how members which are encrypted are shown without passphrases.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EncryptedPolicy {
+    // shown as they are, reads fail by the backend.
+    Read,
+    Hide,
+    // shown, but opens fail by EACCES.
+    Deny,
+    // shown as empty files named like a.txt.ENCRYPTED.
+    Mark,
+}
+
+impl EncryptedPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            EncryptedPolicy::Read => "read",
+            EncryptedPolicy::Hide => "hide",
+            EncryptedPolicy::Deny => "deny",
+            EncryptedPolicy::Mark => "mark",
+        }
+    }
+}
+
+impl FromStr for EncryptedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<EncryptedPolicy, String> {
+        match s {
+            "read" => Ok(EncryptedPolicy::Read),
+            "hide" => Ok(EncryptedPolicy::Hide),
+            "deny" => Ok(EncryptedPolicy::Deny),
+            "mark" => Ok(EncryptedPolicy::Mark),
+            _ => Err(format!("unknown policy: {}", s)),
+        }
+    }
+}
+
+/// SpecialFiles tells how fifos, devices and sockets in archives are shown.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpecialFiles {
+    // shown as they are, which can not be opened.
+    Keep,
+    Hide,
+    // shown as empty regular files, with the original types in an xattr.
+    Regular,
+}
+
+impl SpecialFiles {
+    pub fn name(self) -> &'static str {
+        match self {
+            SpecialFiles::Keep => "keep",
+            SpecialFiles::Hide => "hide",
+            SpecialFiles::Regular => "regular",
+        }
+    }
+}
+
+impl FromStr for SpecialFiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<SpecialFiles, String> {
+        match s {
+            "keep" => Ok(SpecialFiles::Keep),
+            "hide" => Ok(SpecialFiles::Hide),
+            "regular" => Ok(SpecialFiles::Regular),
+            _ => Err(format!("unknown mode: {}", s)),
+        }
+    }
+}
+
+/// Capabilities tell what a backend can read.
+pub struct Capabilities {
+    pub version: String,
+    pub formats: Vec<Format>,
+    pub filters: Vec<Filter>,
+    // filters done by external programs, which may not be installed.
+    pub external_filters: Vec<Filter>,
+}
+
+/// Backend is the implementation to read archives.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    Libarchive,
+    // zip, tar and gzip only.
+    Rust,
+}
+
+impl Default for Backend {
+    // libarchive is preferred when it is built in.
+    fn default() -> Backend {
+        if cfg!(feature = "libarchive") {
+            Backend::Libarchive
+        } else {
+            Backend::Rust
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Backend, String> {
+        match s {
+            "libarchive" => Ok(Backend::Libarchive),
+            "rust" => Ok(Backend::Rust),
+            _ => Err(format!("unknown backend: {}", s)),
+        }
+    }
+}
+
+// the default size of reads from the archive file.
+const DEFAULT_BLOCK_SIZE: usize = 256 * 1024;
+
+/// Options describe how archives are read.
+#[derive(Clone)]
+pub struct Options {
+    formats: Vec<Format>,
+    filters: Vec<Filter>,
+    block_size: usize,
+    // options of libarchive like hdrcharset=CP932.
+    read_options: Vec<String>,
+    passphrases: Vec<String>,
+    // asked for more passphrases until it returns None.
+    passphrase_callback: Option<Rc<dyn Fn() -> Option<String>>>,
+    backend: Backend,
+    // decodes archives in a confined helper process.
+    sandbox: bool,
+    encrypted_policy: EncryptedPolicy,
+    special_files: SpecialFiles,
+    // helpers in the sandbox are killed when they send nothing for this long.
+    decode_timeout: Option<Duration>,
+    // reads archive files on the disk by mapping them into memory.
+    mmap: bool,
+    // asked for contents of members before they are decoded.
+    shared_cache: Option<Arc<shared_cache::Client>>,
+    // limits reads from archive files, shared by clones of the options.
+    throttle: Option<Arc<Throttle>>,
+    // retries failed reads from archive files.
+    retry_policy: Option<RetryPolicy>,
+    // limits logs of warnings, shared by clones of the options.
+    warnings: Arc<Warnings>,
+}
+
+impl Options {
+    // the size is aligned to pages of the cache.
+    pub fn set_block_size(&mut self, size: usize) {
+        let pages = max(1, (size + PAGE_SIZE - 1) / PAGE_SIZE);
+        self.block_size = pages * PAGE_SIZE;
+    }
+
+    pub fn set_formats(&mut self, formats: Vec<Format>) {
+        self.formats = formats;
+    }
+
+    pub fn set_filters(&mut self, filters: Vec<Filter>) {
+        self.filters = filters;
+    }
+
+    // the option is in the form of archive_read_set_options.
+    pub fn add_read_option(&mut self, option: String) {
+        self.read_options.push(option);
+    }
+
+    // passphrases are tried in the order of addition.
+    pub fn add_passphrase(&mut self, passphrase: String) {
+        self.passphrases.push(passphrase);
+    }
+
+    // asked by libarchive after the added passphrases, until it returns None.
+    pub fn set_passphrase_callback<F>(&mut self, f: F)
+    where
+        F: Fn() -> Option<String> + 'static,
+    {
+        self.passphrase_callback = Some(Rc::new(f));
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn set_sandbox(&mut self, sandbox: bool) {
+        self.sandbox = sandbox;
+    }
+
+    // applied to encrypted members only when no passphrase is given.
+    pub fn set_encrypted_policy(&mut self, policy: EncryptedPolicy) {
+        self.encrypted_policy = policy;
+    }
+
+    pub fn set_special_files(&mut self, mode: SpecialFiles) {
+        self.special_files = mode;
+    }
+
+    // sandboxed decoding fails by EIO when it makes no progress for the ceiling.
+    pub fn set_decode_timeout(&mut self, ceiling: Duration) {
+        self.decode_timeout = Some(ceiling);
+    }
+
+    // archive files on the disk are mapped into memory, not read by read(2).
+    pub fn set_mmap(&mut self, mmap: bool) {
+        self.mmap = mmap;
+    }
+
+    // members of archive files on the disk are shared with other mounts by the server.
+    pub fn set_shared_cache(&mut self, client: shared_cache::Client) {
+        self.shared_cache = Some(Arc::new(client));
+    }
+
+    // archive files are read at most the bytes per second altogether.
+    pub fn set_read_rate(&mut self, rate: u64) {
+        self.throttle = Some(Arc::new(Throttle::new(rate)));
+    }
+
+    pub fn formats(&self) -> &[Format] {
+        &self.formats
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn read_options(&self) -> &[String] {
+        &self.read_options
+    }
+
+    pub fn passphrases(&self) -> &[String] {
+        &self.passphrases
+    }
+
+    #[cfg_attr(not(feature = "libarchive"), allow(dead_code))]
+    pub fn passphrase_callback(&self) -> Option<Rc<dyn Fn() -> Option<String>>> {
+        self.passphrase_callback.clone()
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn sandbox(&self) -> bool {
+        self.sandbox
+    }
+
+    pub fn encrypted_policy(&self) -> EncryptedPolicy {
+        self.encrypted_policy
+    }
+
+    pub fn special_files(&self) -> SpecialFiles {
+        self.special_files
+    }
+
+    // whether encrypted members may be read by passphrases.
+    pub fn has_passphrase(&self) -> bool {
+        !self.passphrases.is_empty() || self.passphrase_callback.is_some()
+    }
+
+    pub fn decode_timeout(&self) -> Option<Duration> {
+        self.decode_timeout
+    }
+
+    pub fn mmap(&self) -> bool {
+        self.mmap
+    }
+
+    pub fn shared_cache(&self) -> Option<&Arc<shared_cache::Client>> {
+        self.shared_cache.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = Some(policy);
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_ref()
+    }
+
+    pub fn read_rate(&self) -> Option<u64> {
+        self.throttle.as_ref().map(|t| t.rate())
+    }
+
+    pub(crate) fn set_throttle(&mut self, throttle: Option<Arc<Throttle>>) {
+        self.throttle = throttle;
+    }
+
+    pub(crate) fn throttle(&self) -> Option<Arc<Throttle>> {
+        self.throttle.clone()
+    }
+
+    pub(crate) fn set_warnings(&mut self, warnings: Arc<Warnings>) {
+        self.warnings = warnings;
+    }
+
+    pub(crate) fn warnings(&self) -> &Arc<Warnings> {
+        &self.warnings
+    }
+}
+
+/// SizeLimit is the range of sizes of archive files which are shown as directories.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SizeLimit {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl SizeLimit {
+    pub const NONE: SizeLimit = SizeLimit {
+        min: 0,
+        max: std::u64::MAX,
+    };
+
+    pub fn contains(&self, size: u64) -> bool {
+        self.min <= size && size <= self.max
+    }
+}
+
+impl FromStr for SizeLimit {
+    type Err = String;
+
+    // parses MIN:MAX in bytes, either of which can be empty.
+    fn from_str(s: &str) -> std::result::Result<SizeLimit, String> {
+        let mut parts = s.splitn(2, ':');
+        let parse = |part: Option<&str>, default| match part {
+            Some("") => Ok(default),
+            Some(n) => n.parse().map_err(|_| format!("invalid size: {}", n)),
+            None => Err(format!("invalid size limit: {}", s)),
+        };
+        let min = parse(parts.next(), SizeLimit::NONE.min)?;
+        let max = parse(parts.next(), SizeLimit::NONE.max)?;
+        Ok(SizeLimit { min: min, max: max })
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            formats: vec![Format::Zip, Format::Rar, Format::Rar5, Format::Tar],
+            filters: vec![Filter::Gzip, Filter::Xz, Filter::Zstd],
+            block_size: DEFAULT_BLOCK_SIZE,
+            read_options: Vec::new(),
+            passphrases: Vec::new(),
+            passphrase_callback: None,
+            backend: Backend::default(),
+            sandbox: false,
+            encrypted_policy: EncryptedPolicy::Read,
+            special_files: SpecialFiles::Keep,
+            decode_timeout: None,
+            mmap: false,
+            shared_cache: None,
+            throttle: None,
+            retry_policy: None,
+            warnings: Arc::new(Warnings::default()),
+        }
+    }
+}
+
+#[test]
+fn test_names() {
+    for format in Format::ALL.iter() {
+        assert_eq!(format.name().parse::<Format>(), Ok(*format));
+    }
+    for filter in Filter::ALL.iter() {
+        assert_eq!(filter.name().parse::<Filter>(), Ok(*filter));
+    }
+    assert!("rar4".parse::<Format>().is_err());
+    for policy in ["read", "hide", "deny", "mark"].iter() {
+        assert_eq!(policy.parse::<EncryptedPolicy>().unwrap().name(), *policy);
+    }
+    for mode in ["keep", "hide", "regular"].iter() {
+        assert_eq!(mode.parse::<SpecialFiles>().unwrap().name(), *mode);
+    }
+}
+
+#[test]
+fn test_size_limit() {
+    let limit = "1024:".parse::<SizeLimit>().unwrap();
+    assert_eq!(limit.min, 1024);
+    assert_eq!(limit.max, SizeLimit::NONE.max);
+    assert!(!limit.contains(1023));
+    assert!(limit.contains(1024));
+    assert_eq!(":10".parse::<SizeLimit>().unwrap().max, 10);
+    assert!("10".parse::<SizeLimit>().is_err());
+    assert!("a:b".parse::<SizeLimit>().is_err());
+}
@@ -1,14 +1,17 @@
 use super::buffer::Buffer;
+use super::cipher::Cipher;
 use super::link;
+use std::any::Any;
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::io::Result;
 use std::marker::PhantomData;
 use std::mem;
 use std::ptr;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::slice;
 
-const PAGE_SIZE: usize = 4096;
+pub const PAGE_SIZE: usize = 4096;
 const PAGE_MAP_LEN: usize = PAGE_SIZE / 4;
 
 trait Allocator {
@@ -65,6 +68,11 @@ struct AllocatedPage {
     base: PagePtr,
     data_pages: u32,
     use_count: u32,
+    // the contents are encrypted by the cipher with the id, which is unique among allocations.
+    id: u64,
+    cipher: Option<Rc<Cipher>>,
+    // freed after others when pages run short.
+    costly: bool,
 }
 
 impl AllocatedPage {
@@ -114,6 +122,9 @@ impl AllocatedPage {
         bytes: usize,
         lru_head: &mut link::LinkHead<AllocatedPage>,
         allocator: &mut A,
+        id: u64,
+        cipher: Option<Rc<Cipher>>,
+        costly: bool,
     ) -> WeakRefPage {
         // if allocator can not allocate memory, this panics.
         let (data_pages, rel_map_pages) = AllocatedPage::calc_page_count(bytes);
@@ -135,6 +146,9 @@ impl AllocatedPage {
                 base: allocator.base(),
                 data_pages: data_pages as u32,
                 use_count: 0,
+                id: id,
+                cipher: cipher,
+                costly: costly,
             },
         ));
         lru_head.push_front(header.lru());
@@ -382,21 +396,129 @@ impl Allocator for PageAllocator {
     }
 }
 
+/// LoadProgress is a file being loaded into pages.
+pub trait LoadProgress {
+    // the cached and the total bytes, None if the load is finished.
+    fn progress(&self) -> Option<(usize, usize)>;
+}
+
 pub struct PageManager {
+    // loads of files which nobody refers to, by keys of the files, so that opening them again
+    // goes on from where they stopped. they keep their pages, which are freed when pages run
+    // short. this is dropped first, while the pages are still there.
+    kept_loads: VecDeque<(String, Rc<dyn Any>)>,
+    max_kept_loads: usize,
     use_page_lru: link::LinkHead<AllocatedPage>,
     allocator: PageAllocator,
+    max_pages: usize,
+    loads: Vec<(String, Weak<dyn LoadProgress>)>,
+    cipher: Option<Rc<Cipher>>,
+    // the id of the next allocation.
+    next_id: u64,
 }
 
 impl PageManager {
     pub fn new(max_bytes: usize) -> Result<PageManager> {
         let max_pages = (max_bytes + PAGE_SIZE - 1) / PAGE_SIZE;
         Ok(PageManager {
+            kept_loads: VecDeque::new(),
+            max_kept_loads: 0,
             use_page_lru: link::LinkHead::new(),
             allocator: PageAllocator::new(max_pages)?,
+            max_pages: max_pages,
+            loads: Vec::new(),
+            cipher: None,
+            next_id: 0,
         })
     }
 
+    // contents of allocations after this are encrypted by the cipher.
+    pub fn set_cipher(&mut self, cipher: Cipher) {
+        self.cipher = Some(Rc::new(cipher));
+    }
+
+    // shows the progress of the load by the name until it is finished.
+    pub fn track_load(&mut self, name: String, load: Weak<dyn LoadProgress>) {
+        self.forget_finished_loads();
+        self.loads.push((name, load));
+    }
+
+    // the names, the cached and the total bytes of files being loaded.
+    pub fn loads(&mut self) -> Vec<(String, usize, usize)> {
+        self.forget_finished_loads();
+        self.loads
+            .iter()
+            .filter_map(|&(ref name, ref load)| {
+                let (cached, total) = load.upgrade()?.progress()?;
+                Some((name.clone(), cached, total))
+            })
+            .collect()
+    }
+
+    fn forget_finished_loads(&mut self) {
+        self.loads
+            .retain(|&(_, ref load)| load.upgrade().and_then(|l| l.progress()).is_some());
+    }
+
+    // keeps at most n loads which nobody refers to, or none by 0.
+    pub fn set_max_kept_loads(&mut self, n: usize) {
+        self.max_kept_loads = n;
+    }
+
+    // keeps the load by the key, returning the oldest one if it is over the limit, which the
+    // caller drops after the manager is borrowed, as dropping readers may drop other caches.
+    pub fn keep_load(&mut self, key: String, load: Rc<dyn Any>) -> Option<Rc<dyn Any>> {
+        if self.max_kept_loads == 0 {
+            return Some(load);
+        }
+        self.kept_loads.retain(|&(ref k, _)| *k != key);
+        self.kept_loads.push_back((key, load));
+        if self.kept_loads.len() > self.max_kept_loads {
+            return self.kept_loads.pop_front().map(|(_, load)| load);
+        }
+        None
+    }
+
+    pub fn take_load(&mut self, key: &str) -> Option<Rc<dyn Any>> {
+        let i = self.kept_loads.iter().position(|&(ref k, _)| k == key)?;
+        self.kept_loads.remove(i).map(|(_, load)| load)
+    }
+
+    // all kept loads, to be dropped so that their pages can be freed.
+    pub fn take_kept_loads(&mut self) -> Vec<Rc<dyn Any>> {
+        self.kept_loads.drain(..).map(|(_, load)| load).collect()
+    }
+
+    pub fn kept_loads(&self) -> usize {
+        self.kept_loads.len()
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_pages * PAGE_SIZE
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        (self.max_pages - self.allocator.free_pages()) * PAGE_SIZE
+    }
+
+    // frees pages which no reader uses now.
+    pub fn free_unused_pages(&mut self) {
+        for page in self.use_page_lru.iter_reverse_mut() {
+            if !page.is_used() {
+                unsafe {
+                    AllocatedPage::deallocate(page, &mut self.allocator);
+                }
+            }
+        }
+    }
+
     pub fn allocate(&mut self, bytes: usize) -> Option<WeakRefPage> {
+        self.allocate_costly(bytes, false)
+    }
+
+    // costly allocations, like contents which take long to read again, are freed after
+    // the others when pages run short.
+    pub fn allocate_costly(&mut self, bytes: usize, costly: bool) -> Option<WeakRefPage> {
         let need_pages = AllocatedPage::need_pages(bytes);
         if need_pages > self.allocator.free_pages() {
             let lwm_pages = need_pages - self.allocator.free_pages();
@@ -405,29 +527,37 @@ impl PageManager {
                 return None;
             }
         }
+        let id = self.next_id;
+        self.next_id += 1;
         unsafe {
             Some(AllocatedPage::allocate(
                 bytes,
                 &mut self.use_page_lru,
                 &mut self.allocator,
+                id,
+                self.cipher.clone(),
+                costly,
             ))
         }
     }
 
     fn free_old_pages(&mut self, mut lwm_pages: usize) -> bool {
         assert!(lwm_pages > 0);
-        for page in self.use_page_lru.iter_reverse_mut() {
-            if page.is_used() {
-                continue;
-            }
-            let pages = page.all_pages();
-            unsafe {
-                AllocatedPage::deallocate(page, &mut self.allocator);
-            }
-            if pages >= lwm_pages {
-                return true;
+        // cheap ones first, then costly ones, in the order of least recent use.
+        for &costly in [false, true].iter() {
+            for page in self.use_page_lru.iter_reverse_mut() {
+                if page.is_used() || page.costly != costly {
+                    continue;
+                }
+                let pages = page.all_pages();
+                unsafe {
+                    AllocatedPage::deallocate(page, &mut self.allocator);
+                }
+                if pages >= lwm_pages {
+                    return true;
+                }
+                lwm_pages -= pages;
             }
-            lwm_pages -= pages;
         }
         false
     }
@@ -466,11 +596,32 @@ impl RefPage {
         WeakRefPage::new(self.page.clone())
     }
 
+    // the keystream of the contents if they are encrypted.
+    pub fn keystream(&self) -> Option<Keystream> {
+        let page = unsafe { self.page.borrow().as_ref().unwrap() };
+        let cipher = page.cipher.clone()?;
+        Some(Keystream {
+            cipher: cipher,
+            id: page.id,
+        })
+    }
+
+    // the id of the allocation, which differs among allocations.
+    pub fn id(&self) -> u64 {
+        unsafe { self.page.borrow().as_ref().unwrap().id }
+    }
+
     pub fn get_slices(&self, from: usize) -> SliceIter<'_> {
         let page = *self.page.borrow_mut();
         unsafe {
             page.as_mut().unwrap().update_lru();
         }
+        self.peek_slices(from)
+    }
+
+    // like get_slices, without marking the pages recently used, for scrubbing.
+    pub fn peek_slices(&self, from: usize) -> SliceIter<'_> {
+        let page = *self.page.borrow_mut();
         SliceIter {
             page: page,
             n: from / PAGE_SIZE,
@@ -501,6 +652,18 @@ impl Drop for RefPage {
     }
 }
 
+/// Keystream encrypts and decrypts contents of an allocation, at offsets from its start.
+pub struct Keystream {
+    cipher: Rc<Cipher>,
+    id: u64,
+}
+
+impl Keystream {
+    pub fn apply(&self, offset: usize, data: &mut [u8]) {
+        self.cipher.apply(self.id, offset, data)
+    }
+}
+
 pub struct SliceIter<'a>
 where
     RefPage: 'a,
@@ -618,3 +781,36 @@ fn test_ref_page() {
         }
     }
 }
+
+#[test]
+fn test_free_unused_pages() {
+    let mut m = PageManager::new(10 * PAGE_SIZE).unwrap();
+    assert_eq!(m.used_bytes(), 0);
+    let p1 = m.allocate(PAGE_SIZE).unwrap();
+    let p2 = m.allocate(PAGE_SIZE).unwrap();
+    assert_eq!(m.used_bytes(), 4 * PAGE_SIZE);
+    let used = p1.upgrade().unwrap();
+    m.free_unused_pages();
+    assert_eq!(m.used_bytes(), 2 * PAGE_SIZE);
+    assert!(p2.upgrade().is_none());
+    drop(used);
+    m.free_unused_pages();
+    assert_eq!(m.used_bytes(), 0);
+    assert_eq!(m.max_bytes(), 10 * PAGE_SIZE);
+}
+
+#[test]
+fn test_free_costly_pages_last() {
+    let mut m = PageManager::new(10 * PAGE_SIZE).unwrap();
+    let costly = m.allocate_costly(PAGE_SIZE, true).unwrap();
+    let p1 = m.allocate(PAGE_SIZE).unwrap();
+    let p2 = m.allocate(PAGE_SIZE).unwrap();
+    // the costly one is the least recently used, but the cheap one is freed first.
+    assert!(m.allocate(4 * PAGE_SIZE).is_some());
+    assert!(costly.upgrade().is_some());
+    assert!(p1.upgrade().is_none());
+    assert!(p2.upgrade().is_some());
+    // the costly one is freed when the cheap ones are not enough.
+    assert!(m.allocate(8 * PAGE_SIZE).is_some());
+    assert!(costly.upgrade().is_none());
+}
@@ -1,52 +1,158 @@
 use libc;
-use super::page::{PageManager, RefPage, SliceIter, WeakRefPage};
+use super::page::{Keystream, LoadProgress, PageManager, RefPage, SliceIter, WeakRefPage};
+use crate::error;
 use crate::fs::{File, SeekableRead};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::cmp::min;
-use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::convert::TryFrom;
+use std::io::{Error, IoSliceMut, Read, Result, Seek, SeekFrom};
+use std::mem;
 use std::rc::Rc;
 
+type Load = Rc<RefCell<LoadingState<Box<dyn SeekableRead>>>>;
+
+// reads of cached files of at most this many bytes are served from a window of this many
+// bytes staged at once, so that tiny reads nearby, like 512 bytes each by media scanners, do
+// not walk the pages and decrypt them one by one.
+const TINY_READ_BYTES: usize = 4096;
+const STAGE_BYTES: usize = 64 * 1024;
+
 enum CacheState {
     Empty,
-    Loading(Rc<RefCell<LoadingState<Box<dyn SeekableRead>>>>),
-    Loaded(WeakRefPage, usize),
+    Loading(Load),
+    // the checksum of the contents is sealed by the first scrub.
+    Loaded(WeakRefPage, usize, Cell<Option<u32>>),
 }
 
 pub struct Cache {
     page_manager: Rc<RefCell<PageManager>>,
     size: Option<usize>,
     file: Rc<dyn File>,
+    // the name of the file in the progress of loads.
+    name: String,
+    // the key of the file among caches of the page manager, by which the load going on is
+    // kept when the cache is dropped. None not to keep it.
+    key: Option<String>,
     state: CacheState,
 }
 
 impl Cache {
-    pub fn new(page_manager: Rc<RefCell<PageManager>>, file: Rc<dyn File>) -> Cache {
+    pub fn new(page_manager: Rc<RefCell<PageManager>>, file: Rc<dyn File>, name: String) -> Cache {
         Cache {
             page_manager: page_manager,
             size: None,
             file: file,
+            name: name,
+            key: None,
             state: CacheState::Empty,
         }
     }
 
+    pub fn has_key(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn set_key(&mut self, key: Option<String>) {
+        self.key = key;
+    }
+
+    // the load of the file which a dropped cache kept, if any.
+    fn take_kept_load(&self) -> Option<Load> {
+        let load = self
+            .page_manager
+            .borrow_mut()
+            .take_load(self.key.as_ref()?)?;
+        load.downcast().ok()
+    }
+
+    // allocates pages for the file, dropping kept loads if pages run short. files costing
+    // more than their sizes to read again are kept longer.
+    fn allocate(&self, size: usize) -> Option<WeakRefPage> {
+        let costly = self.file.reopen_cost() > size as u64;
+        if let Some(weak) = self.page_manager.borrow_mut().allocate_costly(size, costly) {
+            return Some(weak);
+        }
+        let kept = self.page_manager.borrow_mut().take_kept_loads();
+        if kept.is_empty() {
+            return None;
+        }
+        drop(kept);
+        self.page_manager.borrow_mut().allocate_costly(size, costly)
+    }
+
+    // the bytes of the file in pages now.
+    pub fn cached_bytes(&self) -> usize {
+        match self.state {
+            CacheState::Empty => 0,
+            CacheState::Loading(ref state) => state.borrow().cached_size,
+            CacheState::Loaded(ref page, size, _) => page.upgrade().map_or(0, |_| size),
+        }
+    }
+
+    // the pages and the size of the contents, if they are loaded.
+    pub fn loaded(&self) -> Option<(RefPage, usize)> {
+        match self.state {
+            CacheState::Loaded(ref page, size, _) => Some((page.upgrade()?, size)),
+            _ => None,
+        }
+    }
+
+    // seals the checksum of the loaded pages of the id at the first call, and tells whether
+    // it is the same at later ones. pages loaded again are sealed again.
+    pub fn seal(&self, id: u64, checksum: u32) -> bool {
+        let (page, sealed) = match self.state {
+            CacheState::Loaded(ref page, _, ref sealed) => (page, sealed),
+            _ => return true,
+        };
+        if page.upgrade().map(|p| p.id()) != Some(id) {
+            return true;
+        }
+        match sealed.get() {
+            Some(c) => c == checksum,
+            None => {
+                sealed.set(Some(checksum));
+                true
+            }
+        }
+    }
+
+    // forgets the contents, which are loaded again by the next reader.
+    pub fn evict(&mut self) {
+        self.state = CacheState::Empty;
+        self.size = None;
+    }
+
     pub fn make_reader(&mut self) -> Result<Box<dyn SeekableRead>> {
         match self.state {
             CacheState::Empty => {
+                if let Some(load) = self.take_kept_load() {
+                    self.size = Some(load.borrow().size);
+                    self.state = CacheState::Loading(load);
+                    return self.make_reader();
+                }
                 if self.size.is_none() {
-                    self.size = Some(self.file.getattr()?.size as usize);
+                    // the whole file is mapped, which can not be beyond the address space.
+                    let size = usize::try_from(self.file.getattr()?.size)
+                        .map_err(|_| Error::from_raw_os_error(libc::EFBIG))?;
+                    self.size = Some(size);
                 }
-                let weak = self
-                    .page_manager
-                    .borrow_mut()
-                    .allocate(self.size.unwrap())
-                    .ok_or(Error::new(ErrorKind::Other, "oom"))?;
+                let weak = self.allocate(self.size.unwrap()).ok_or_else(|| {
+                    let s = format!("no pages for {} bytes", self.size.unwrap());
+                    Error::from(error::Error::Cache(s))
+                })?;
                 let page = weak.upgrade().unwrap();
                 let reader = self.file.open()?;
                 let loading_state = Rc::new(RefCell::new(LoadingState {
                     reader: Some(reader),
+                    size: self.size.unwrap(),
                     cached_size: 0,
+                    keystream: page.keystream(),
                     page: page,
                 }));
+                let load: Rc<dyn LoadProgress> = loading_state.clone();
+                self.page_manager
+                    .borrow_mut()
+                    .track_load(self.name.clone(), Rc::downgrade(&load));
                 self.state = CacheState::Loading(loading_state);
             }
             CacheState::Loading(_) => {
@@ -61,17 +167,19 @@ impl Cache {
                     }
                     let cache_size = loading_state.borrow().cached_size;
                     let weak = loading_state.borrow().page.downgrade();
-                    state = CacheState::Loaded(weak, cache_size)
+                    state = CacheState::Loaded(weak, cache_size, Cell::new(None))
                 }
                 self.state = state;
             }
-            CacheState::Loaded(_, _) => {
-                if let CacheState::Loaded(ref page, cache_size) = self.state {
+            CacheState::Loaded(..) => {
+                if let CacheState::Loaded(ref page, cache_size, _) = self.state {
                     if let Some(page) = page.upgrade() {
                         return Ok(Box::new(CacheReader {
                             size: cache_size,
                             pos: 0,
+                            keystream: page.keystream(),
                             page: page,
+                            stage: Stage::default(),
                         }));
                     }
                 }
@@ -82,6 +190,32 @@ impl Cache {
     }
 }
 
+// the load going on is kept for the cache of the same file made later, like when the entry is
+// looked up again after the kernel forgot it.
+impl Drop for Cache {
+    fn drop(&mut self) {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return,
+        };
+        let load = match mem::replace(&mut self.state, CacheState::Empty) {
+            CacheState::Loading(load) => load,
+            _ => return,
+        };
+        let dropped = self.page_manager.borrow_mut().keep_load(key, load);
+        drop(dropped);
+    }
+}
+
+// the position moved from base, which is beyond the end if it does not fit in usize.
+fn moved(base: usize, i: i64) -> Result<usize> {
+    let pos = base as i128 + i as i128;
+    if pos < 0 {
+        return Err(Error::from_raw_os_error(libc::EINVAL));
+    }
+    Ok(usize::try_from(pos).unwrap_or(usize::MAX))
+}
+
 macro_rules! impl_seek {
     ($struct_: ident) => { impl_seek!{$struct_[ ]} };
     ($struct_: ident < $($v: ident),* >) => {
@@ -91,21 +225,9 @@ macro_rules! impl_seek {
         impl<$($v)*> Seek for $struct_<$($v)*> {
             fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
                 match pos {
-                    SeekFrom::Start(n) => self.pos = n as usize,
-                    SeekFrom::End(i) => {
-                        if i < 0 && self.size < -i as usize {
-                            return Err(Error::from_raw_os_error(libc::EINVAL));
-                        } else {
-                            self.pos = self.size + i as usize;
-                        }
-                    }
-                    SeekFrom::Current(i) => {
-                        if i < 0 && self.pos < -i as usize {
-                            return Err(Error::from_raw_os_error(libc::EINVAL));
-                        } else {
-                            self.pos += i as usize;
-                        }
-                    }
+                    SeekFrom::Start(n) => self.pos = usize::try_from(n).unwrap_or(usize::MAX),
+                    SeekFrom::End(i) => self.pos = moved(self.size, i)?,
+                    SeekFrom::Current(i) => self.pos = moved(self.pos, i)?,
                 }
                 Ok(self.pos as u64)
             }
@@ -113,38 +235,157 @@ macro_rules! impl_seek {
     };
 }
 
+// copies at most max bytes from the pages to the buffers in order, returns the bytes copied.
+fn copy_slices<'a, I>(slices: I, max: usize, bufs: &mut [IoSliceMut<'_>]) -> usize
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let mut bufs = bufs.iter_mut().filter(|b| !b.is_empty());
+    let mut buf = match bufs.next() {
+        Some(buf) => buf,
+        None => return 0,
+    };
+    let mut buf_pos = 0;
+    let mut read = 0;
+    for mut slice in slices {
+        while !slice.is_empty() && read < max {
+            let l = min(min(slice.len(), buf.len() - buf_pos), max - read);
+            buf[buf_pos..buf_pos + l].copy_from_slice(&slice[..l]);
+            slice = &slice[l..];
+            buf_pos += l;
+            read += l;
+            if buf_pos == buf.len() {
+                buf = match bufs.next() {
+                    Some(buf) => buf,
+                    None => return read,
+                };
+                buf_pos = 0;
+            }
+        }
+        if read >= max {
+            break;
+        }
+    }
+    read
+}
+
+// decrypts the bytes copied to the buffers from the offset of the pages.
+fn decrypt(keystream: Option<&Keystream>, offset: usize, bufs: &mut [IoSliceMut<'_>], len: usize) {
+    let keystream = match keystream {
+        Some(keystream) => keystream,
+        None => return,
+    };
+    let mut done = 0;
+    for buf in bufs.iter_mut() {
+        let n = min(buf.len(), len - done);
+        keystream.apply(offset + done, &mut buf[..n]);
+        done += n;
+    }
+}
+
+fn total_len(bufs: &[IoSliceMut<'_>]) -> usize {
+    bufs.iter().map(|b| b.len()).sum()
+}
+
+// the decrypted bytes of the pages from the offset.
+#[derive(Default)]
+struct Stage {
+    offset: usize,
+    data: Vec<u8>,
+}
+
+impl Stage {
+    // the staged bytes from pos, if all of them are.
+    fn get(&self, pos: usize, len: usize) -> Option<&[u8]> {
+        let start = pos.checked_sub(self.offset)?;
+        self.data.get(start..start.checked_add(len)?)
+    }
+}
+
 struct CacheReader {
     size: usize,
     pos: usize,
     page: RefPage,
+    keystream: Option<Keystream>,
+    stage: Stage,
+}
+
+impl CacheReader {
+    // stages the window from the position, reusing the buffer.
+    fn fill_stage(&mut self, pos: usize) {
+        let len = min(STAGE_BYTES, self.size - pos);
+        let mut data = mem::replace(&mut self.stage.data, Vec::new());
+        data.resize(len, 0);
+        let read = copy_slices(
+            self.page.get_slices(pos),
+            len,
+            &mut [IoSliceMut::new(&mut data)],
+        );
+        data.truncate(read);
+        if let Some(ref keystream) = self.keystream {
+            keystream.apply(pos, &mut data);
+        }
+        self.stage = Stage {
+            offset: pos,
+            data: data,
+        };
+    }
+
+    // fills the buffers from several pages at once, from the position given, not the one of
+    // the reader.
+    fn read_vectored_at(&mut self, pos: usize, bufs: &mut [IoSliceMut<'_>]) -> usize {
+        if pos >= self.size {
+            return 0;
+        }
+        let max = min(self.size - pos, total_len(bufs));
+        if max <= TINY_READ_BYTES && self.stage.get(pos, max).is_none() {
+            self.fill_stage(pos);
+        }
+        if let Some(staged) = self.stage.get(pos, max) {
+            return copy_slices(std::iter::once(staged), max, bufs);
+        }
+        let read = copy_slices(self.page.get_slices(pos), max, bufs);
+        decrypt(self.keystream.as_ref(), pos, bufs, read);
+        read
+    }
 }
 
 impl_seek!(CacheReader);
 
 impl Read for CacheReader {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if self.pos >= self.size {
-            return Ok(0);
-        }
-        let max = min(self.size - self.pos, buf.len());
-        let mut read = 0;
-        for slice in self.page.get_slices(self.pos) {
-            if read >= max {
-                break;
-            }
-            let l = min(slice.len(), max - read);
-            &mut buf[read..read + l].copy_from_slice(&slice[..l]);
-            read += l;
-        }
+        self.read_vectored(&mut [IoSliceMut::new(buf)])
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        let read = self.read_vectored_at(self.pos, bufs);
         self.pos += read;
         Ok(read)
     }
 }
 
+impl SeekableRead for CacheReader {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let pos = usize::try_from(offset).unwrap_or(usize::MAX);
+        Ok(self.read_vectored_at(pos, &mut [IoSliceMut::new(buf)]))
+    }
+}
+
 struct LoadingState<R> {
     reader: Option<R>,
+    size: usize,
     cached_size: usize,
     page: RefPage,
+    // contents are encrypted as they are read into the pages.
+    keystream: Option<Keystream>,
+}
+
+impl<R> LoadProgress for RefCell<LoadingState<R>> {
+    fn progress(&self) -> Option<(usize, usize)> {
+        let state = self.borrow();
+        state.reader.as_ref()?;
+        Some((state.cached_size, state.size))
+    }
 }
 
 impl<R: Read> LoadingState<R> {
@@ -178,6 +419,9 @@ impl<R: Read> LoadingState<R> {
                     self.reader = None;
                     return Ok(self.cached_size);
                 }
+                if let Some(ref keystream) = self.keystream {
+                    keystream.apply(self.cached_size, &mut slice[n..n + nn]);
+                }
                 n += nn;
                 self.cached_size += nn;
             }
@@ -194,58 +438,71 @@ struct LoadingReader<R> {
 
 impl_seek!(LoadingReader<R>);
 
-impl<R: Read> Read for LoadingReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let cached_size = self
-            .state
-            .borrow_mut()
-            .read_to_at_least(self.pos + buf.len())?;
-        if self.pos >= cached_size {
+impl<R: Read> LoadingReader<R> {
+    // loads the file up to the end of the buffers from the position given, not the one of the
+    // reader, and fills them.
+    fn read_vectored_at(&self, pos: usize, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        let len = total_len(bufs);
+        let read_to = pos.saturating_add(len);
+        let cached_size = self.state.borrow_mut().read_to_at_least(read_to)?;
+        if pos >= cached_size {
             return Ok(0);
         }
-        let max = min(cached_size - self.pos, buf.len());
-        let mut read = 0;
-        for slice in self.state.borrow().get_slices(self.pos) {
-            if read >= max {
-                break;
-            }
-            let l = min(slice.len(), max - read);
-            &mut buf[read..read + l].copy_from_slice(&slice[..l]);
-            read += l;
-        }
+        let max = min(cached_size - pos, len);
+        let state = self.state.borrow();
+        let read = copy_slices(state.get_slices(pos), max, bufs);
+        decrypt(state.keystream.as_ref(), pos, bufs, read);
+        Ok(read)
+    }
+}
+
+impl<R: Read> Read for LoadingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_vectored(&mut [IoSliceMut::new(buf)])
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        let read = self.read_vectored_at(self.pos, bufs)?;
         self.pos += read;
         Ok(read)
     }
 }
 
-#[test]
-fn test_read() {
-    use libc;
-    use fuse::FileAttr;
-    use std::ffi::OsStr;
-    use std::io::Cursor;
-    use std::mem::zeroed;
-    struct VecFile {
-        v: Vec<u8>,
-        open_count: Rc<RefCell<u8>>,
-    }
-    impl File for VecFile {
-        fn getattr(&self) -> Result<FileAttr> {
-            let mut a = unsafe { zeroed::<FileAttr>() };
-            a.size = self.v.len() as u64;
-            Ok(a)
-        }
+impl<R: Read> SeekableRead for LoadingReader<R> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let pos = usize::try_from(offset).unwrap_or(usize::MAX);
+        self.read_vectored_at(pos, &mut [IoSliceMut::new(buf)])
+    }
+}
 
-        fn open(&self) -> Result<Box<dyn SeekableRead>> {
-            *self.open_count.borrow_mut() += 1;
-            Ok(Box::new(Cursor::new(self.v.clone())))
-        }
+#[cfg(test)]
+struct VecFile {
+    v: Vec<u8>,
+    open_count: Rc<RefCell<u8>>,
+}
 
-        fn name(&self) -> &OsStr {
-            unimplemented!();
-        }
+#[cfg(test)]
+impl File for VecFile {
+    fn getattr(&self) -> Result<fuse::FileAttr> {
+        let mut a = unsafe { std::mem::zeroed::<fuse::FileAttr>() };
+        a.size = self.v.len() as u64;
+        Ok(a)
     }
 
+    fn open(&self) -> Result<Box<dyn SeekableRead>> {
+        *self.open_count.borrow_mut() += 1;
+        Ok(Box::new(std::io::Cursor::new(self.v.clone())))
+    }
+
+    fn name(&self) -> &std::ffi::OsStr {
+        unimplemented!();
+    }
+}
+
+#[test]
+fn test_read() {
+    use libc;
+
     let page_manager = Rc::new(RefCell::new(PageManager::new(10 * 1024 * 1024).unwrap()));
     let mut v = vec![0; 2 * 1024 * 1024];
     for e in v.iter_mut() {
@@ -256,7 +513,8 @@ fn test_read() {
         v: v.clone(),
         open_count: open_count.clone(),
     });
-    let mut cache = Cache::new(page_manager.clone(), file);
+    let mut cache = Cache::new(page_manager.clone(), file.clone(), "file".to_string());
+    assert_eq!(cache.cached_bytes(), 0);
 
     // first read.
     {
@@ -265,6 +523,7 @@ fn test_read() {
         assert_eq!(r.read_to_end(&mut out).unwrap(), 2 * 1024 * 1024);
         assert_eq!(v, out);
     }
+    assert_eq!(cache.cached_bytes(), 2 * 1024 * 1024);
     // second read.
     {
         let mut r = cache.make_reader().unwrap();
@@ -273,4 +532,173 @@ fn test_read() {
         assert_eq!(v, out);
         assert_eq!(*open_count.borrow(), 1);
     }
+    // loads in progress tell the bytes loaded.
+    {
+        let mut partial = Cache::new(page_manager.clone(), file, "partial".to_string());
+        let mut r = partial.make_reader().unwrap();
+        let mut buf = vec![0; 4096];
+        r.read_exact(&mut buf).unwrap();
+        let loads = page_manager.borrow_mut().loads();
+        assert_eq!(loads, vec![("partial".to_string(), 4096, 2 * 1024 * 1024)]);
+        assert_eq!(partial.cached_bytes(), 4096);
+        let mut out = Vec::<u8>::new();
+        assert_eq!(r.read_to_end(&mut out).unwrap(), 2 * 1024 * 1024 - 4096);
+        assert_eq!(v[4096..], out[..]);
+        assert!(page_manager.borrow_mut().loads().is_empty());
+    }
+    // vectored read across pages.
+    {
+        let mut r = cache.make_reader().unwrap();
+        r.seek(SeekFrom::Start(4000)).unwrap();
+        let (mut a, mut b) = (vec![0; 100], vec![0; 8192]);
+        let n = r
+            .read_vectored(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)])
+            .unwrap();
+        assert_eq!(n, 8292);
+        assert_eq!(a[..], v[4000..4100]);
+        assert_eq!(b[..], v[4100..12292]);
+    }
+    // positions beyond 4 GiB are past the end, on 32-bit targets too.
+    {
+        let mut r = cache.make_reader().unwrap();
+        r.seek(SeekFrom::Start(5 << 30)).unwrap();
+        assert_eq!(r.read(&mut [0; 16]).unwrap(), 0);
+        assert!(r.seek(SeekFrom::Current(i64::MIN)).is_err());
+        let end = r.seek(SeekFrom::End(-1)).unwrap();
+        assert_eq!(end, 2 * 1024 * 1024 - 1);
+        assert_eq!(moved(usize::MAX, i64::MAX).unwrap(), usize::MAX);
+    }
+}
+
+#[test]
+fn test_kept_load() {
+    let page_manager = Rc::new(RefCell::new(PageManager::new(3 * 1024 * 1024).unwrap()));
+    page_manager.borrow_mut().set_max_kept_loads(1);
+    let v: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let open_count = Rc::new(RefCell::new(0));
+    let new_cache = |key: &str| {
+        let file = Rc::new(VecFile {
+            v: v.clone(),
+            open_count: open_count.clone(),
+        });
+        let mut cache = Cache::new(page_manager.clone(), file, key.to_string());
+        cache.set_key(Some(key.to_string()));
+        cache
+    };
+
+    // the load goes on from where the dropped cache stopped.
+    let mut cache = new_cache("a");
+    let mut buf = vec![0; 4096];
+    cache.make_reader().unwrap().read_exact(&mut buf).unwrap();
+    drop(cache);
+    assert_eq!(page_manager.borrow().kept_loads(), 1);
+    let mut cache = new_cache("a");
+    let mut out = Vec::new();
+    cache.make_reader().unwrap().read_to_end(&mut out).unwrap();
+    assert_eq!(out, v);
+    assert_eq!(*open_count.borrow(), 1);
+    assert_eq!(page_manager.borrow().kept_loads(), 0);
+    drop(cache);
+
+    // kept loads are dropped when pages run short.
+    let mut cache = new_cache("b");
+    cache.make_reader().unwrap().read_exact(&mut buf).unwrap();
+    drop(cache);
+    let mut cache = new_cache("c");
+    cache.make_reader().unwrap().read_to_end(&mut out).unwrap();
+    assert_eq!(page_manager.borrow().kept_loads(), 0);
+    assert_eq!(*open_count.borrow(), 3);
+}
+
+#[test]
+fn test_copy_slices() {
+    let slices: Vec<&[u8]> = vec![b"abc", b"defg", b"h"];
+    let (mut a, mut b, mut c) = ([0; 2], [0; 0], [0; 10]);
+    let mut bufs = [
+        IoSliceMut::new(&mut a),
+        IoSliceMut::new(&mut b),
+        IoSliceMut::new(&mut c),
+    ];
+    assert_eq!(copy_slices(slices.iter().cloned(), 6, &mut bufs), 6);
+    assert_eq!(&a, b"ab");
+    assert_eq!(&c[..4], b"cdef");
+}
+
+#[test]
+fn test_encrypted_read() {
+    use super::cipher::Cipher;
+
+    let mut page_manager = PageManager::new(1024 * 1024).unwrap();
+    page_manager.set_cipher(Cipher::ephemeral().unwrap());
+    let page_manager = Rc::new(RefCell::new(page_manager));
+    let v: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    let file = Rc::new(VecFile {
+        v: v.clone(),
+        open_count: Rc::new(RefCell::new(0)),
+    });
+    let mut cache = Cache::new(page_manager, file, "file".to_string());
+    for _ in 0..2 {
+        let mut r = cache.make_reader().unwrap();
+        r.seek(SeekFrom::Start(3)).unwrap();
+        let (mut a, mut b) = (vec![0; 100], vec![0; 5000]);
+        r.read_vectored(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)])
+            .unwrap();
+        assert_eq!(a[..], v[3..103]);
+        assert_eq!(b[..], v[103..5103]);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out[..], v[5103..]);
+    }
+    // tiny reads are served from the window, across its end and after seeks back.
+    let mut r = cache.make_reader().unwrap();
+    let mut out = Vec::new();
+    let mut buf = [0; 512];
+    for _ in 0..(v.len() + 511) / 512 {
+        let n = r.read(&mut buf).unwrap();
+        out.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(out, v);
+    r.seek(SeekFrom::Start(STAGE_BYTES as u64 - 100)).unwrap();
+    assert_eq!(r.read(&mut buf).unwrap(), 512);
+    assert_eq!(buf[..], v[STAGE_BYTES - 100..STAGE_BYTES + 412]);
+    r.seek(SeekFrom::Start(1)).unwrap();
+    r.read_exact(&mut buf).unwrap();
+    assert_eq!(buf[..], v[1..513]);
+
+    // the pages do not have the plaintext.
+    let page = match cache.state {
+        CacheState::Loaded(ref page, _, _) => page.upgrade().unwrap(),
+        _ => panic!("not loaded"),
+    };
+    let first = page.get_slices(0).next().unwrap();
+    assert_ne!(first[..100], v[..100]);
+}
+
+#[test]
+fn test_read_at() {
+    let page_manager = Rc::new(RefCell::new(PageManager::new(1024 * 1024).unwrap()));
+    let v: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    let file = Rc::new(VecFile {
+        v: v.clone(),
+        open_count: Rc::new(RefCell::new(0)),
+    });
+    let mut cache = Cache::new(page_manager, file, "file".to_string());
+    // while loading, and once loaded.
+    for _ in 0..2 {
+        let mut r = cache.make_reader().unwrap();
+        let mut buf = [0; 1000];
+        r.read_exact(&mut buf[..10]).unwrap();
+        // reads at offsets interleaved with reads in order do not move the position.
+        assert_eq!(r.read_at(50_000, &mut buf).unwrap(), 1000);
+        assert_eq!(buf[..], v[50_000..51_000]);
+        assert_eq!(r.read_at(20, &mut buf[..100]).unwrap(), 100);
+        assert_eq!(buf[..100], v[20..120]);
+        assert_eq!(r.read_at(99_900, &mut buf).unwrap(), 100);
+        assert_eq!(r.read_at(5 << 30, &mut buf).unwrap(), 0);
+        r.read_exact(&mut buf[..10]).unwrap();
+        assert_eq!(buf[..10], v[10..20]);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out[..], v[20..]);
+    }
 }
@@ -0,0 +1,215 @@
+use libc;
+
+use std::fmt;
+use std::io::{Error, Read, Result, Seek, SeekFrom};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use crate::fs::SeekableRead;
+
+// errnos which can be retried by names, which network filesystems and removable media
+// return while they reconnect.
+const ERRNOS: [(&str, i32); 6] = [
+    ("EIO", libc::EIO),
+    ("EAGAIN", libc::EAGAIN),
+    ("ETIMEDOUT", libc::ETIMEDOUT),
+    ("ENOTCONN", libc::ENOTCONN),
+    ("ECONNRESET", libc::ECONNRESET),
+    ("ESTALE", libc::ESTALE),
+];
+
+/// RetryPolicy tells how failed reads from archive files are retried.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RetryPolicy {
+    // the number of reads including the first one.
+    pub attempts: u32,
+    // the wait before the first retry, which doubles for each retry.
+    pub backoff: Duration,
+    pub errnos: Vec<i32>,
+}
+
+impl RetryPolicy {
+    fn retryable(&self, e: &Error) -> bool {
+        match e.raw_os_error() {
+            Some(errno) => self.errnos.contains(&errno),
+            None => false,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    // retries EIO, ETIMEDOUT and EAGAIN twice.
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(100),
+            errnos: vec![libc::EIO, libc::ETIMEDOUT, libc::EAGAIN],
+        }
+    }
+}
+
+impl FromStr for RetryPolicy {
+    type Err = String;
+
+    // parses ATTEMPTS[:BACKOFF_MS[:ERRNO,...]], like 5:200:EIO,ESTALE.
+    fn from_str(s: &str) -> std::result::Result<RetryPolicy, String> {
+        let mut policy = RetryPolicy::default();
+        let mut parts = s.splitn(3, ':');
+        let attempts = parts.next().unwrap_or("");
+        policy.attempts = match attempts.parse() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("invalid attempts: {}", attempts)),
+        };
+        if let Some(ms) = parts.next() {
+            let ms = ms.parse().map_err(|_| format!("invalid backoff: {}", ms))?;
+            policy.backoff = Duration::from_millis(ms);
+        }
+        if let Some(names) = parts.next() {
+            policy.errnos = names
+                .split(',')
+                .map(|name| match ERRNOS.iter().find(|e| e.0 == name) {
+                    Some(e) => Ok(e.1),
+                    None => Err(format!("unknown errno: {}", name)),
+                })
+                .collect::<std::result::Result<_, _>>()?;
+        }
+        Ok(policy)
+    }
+}
+
+// the form parsed, like 5:200:EIO,ESTALE.
+impl fmt::Display for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self
+            .errnos
+            .iter()
+            .filter_map(|errno| ERRNOS.iter().find(|e| e.1 == *errno))
+            .map(|e| e.0)
+            .collect();
+        let backoff = self.backoff.as_millis();
+        write!(f, "{}:{}:{}", self.attempts, backoff, names.join(","))
+    }
+}
+
+/// Retrying reads again at the same position when the inner reader fails by the policy.
+pub struct Retrying<R> {
+    inner: R,
+    policy: RetryPolicy,
+    // the position after the last success, which is restored before retries.
+    pos: u64,
+}
+
+impl<R: Seek> Retrying<R> {
+    pub fn new(inner: R, policy: RetryPolicy) -> Retrying<R> {
+        Retrying {
+            inner: inner,
+            policy: policy,
+            pos: 0,
+        }
+    }
+
+    fn retry<T, F>(&mut self, mut f: F) -> Result<T>
+    where
+        F: FnMut(&mut R) -> Result<T>,
+    {
+        let mut backoff = self.policy.backoff;
+        let mut attempt = 1;
+        loop {
+            let e = match f(&mut self.inner) {
+                Ok(v) => return Ok(v),
+                Err(e) => e,
+            };
+            if attempt >= self.policy.attempts || !self.policy.retryable(&e) {
+                return Err(e);
+            }
+            warn!(error = ?e, attempt = attempt, "retrying a read of an archive file");
+            thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+            // the failed call may have moved the position.
+            let pos = self.pos;
+            let _ = self.inner.seek(SeekFrom::Start(pos));
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for Retrying<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.retry(|r| r.read(buf))?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Retrying<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = self.retry(|r| r.seek(pos))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+// reads at offsets are retried at the offset, leaving the position as it is.
+impl<R: SeekableRead> SeekableRead for Retrying<R> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.retry(|r| r.read_at(offset, buf))
+    }
+}
+
+#[test]
+fn test_policy() {
+    let policy: RetryPolicy = "5:20:EIO,ESTALE".parse().unwrap();
+    assert_eq!(policy.attempts, 5);
+    assert_eq!(policy.backoff, Duration::from_millis(20));
+    assert_eq!(policy.errnos, vec![libc::EIO, libc::ESTALE]);
+    assert_eq!(policy.to_string(), "5:20:EIO,ESTALE");
+    assert_eq!(policy.to_string().parse::<RetryPolicy>().unwrap(), policy);
+    assert_eq!("2".parse::<RetryPolicy>().unwrap().attempts, 2);
+    assert!("0".parse::<RetryPolicy>().is_err());
+    assert!("2:10:ENOENT".parse::<RetryPolicy>().is_err());
+}
+
+#[test]
+fn test_retrying() {
+    use std::io::Cursor;
+
+    // fails every other read, after reading a byte.
+    struct Flaky {
+        inner: Cursor<Vec<u8>>,
+        fail: bool,
+        errno: i32,
+    }
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.fail = !self.fail;
+            if self.fail {
+                self.inner.read(&mut buf[..1])?;
+                return Err(Error::from_raw_os_error(self.errno));
+            }
+            self.inner.read(&mut buf[..2])
+        }
+    }
+    impl Seek for Flaky {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+    let policy = RetryPolicy {
+        attempts: 2,
+        backoff: Duration::from_millis(1),
+        errnos: vec![libc::EIO],
+    };
+    let flaky = |errno| Flaky {
+        inner: Cursor::new(b"abcde".to_vec()),
+        fail: false,
+        errno: errno,
+    };
+    let mut r = Retrying::new(flaky(libc::EIO), policy.clone());
+    let mut s = String::new();
+    r.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "abcde");
+
+    let mut r = Retrying::new(flaky(libc::ENOENT), policy);
+    assert!(r.read_to_string(&mut String::new()).is_err());
+}
@@ -0,0 +1,406 @@
+use flate2;
+use libc;
+use tar;
+use time;
+use zip;
+
+use self::flate2::read::GzDecoder;
+use self::time::Timespec;
+use super::backend::{ArchiveBackend, Metadata};
+use super::options::{Capabilities, Filter, Format, Options};
+use crate::error;
+use crate::fs::SeekableRead;
+use std::cmp::min;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::io::{self, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::iter;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// opens zip, tar, or gzipped tar by the magic number.
+pub fn open(mut r: Box<dyn SeekableRead>, options: &Options) -> Result<Box<dyn ArchiveBackend>> {
+    let mut magic = [0; 512];
+    let mut n = 0;
+    while n < magic.len() {
+        match r.read(&mut magic[n..])? {
+            0 => break,
+            nn => n += nn,
+        }
+    }
+    r.seek(SeekFrom::Start(0))?;
+    let magic = &magic[..n];
+    let enabled = |format| options.formats().contains(&format);
+    if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+        if enabled(Format::Zip) {
+            let archive = zip::ZipArchive::new(r).map_err(zip_error)?;
+            return Ok(Box::new(Zip {
+                archive: archive,
+                next: 0,
+            }));
+        }
+    } else if magic.starts_with(b"\x1f\x8b") {
+        // assumes a tar is compressed.
+        if enabled(Format::Tar) && options.filters().contains(&Filter::Gzip) {
+            return Ok(Box::new(Tar::new(Box::new(GzDecoder::new(r)), true)));
+        }
+    } else if magic.len() >= 262 && &magic[257..262] == b"ustar" {
+        if enabled(Format::Tar) {
+            return Ok(Box::new(Tar::new(Box::new(r), false)));
+        }
+    }
+    Err(error::Error::UnsupportedFormat("unrecognized archive format".to_string()).into())
+}
+
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: format!("showfs {} rust backend", env!("CARGO_PKG_VERSION")),
+        formats: vec![Format::Tar, Format::Zip],
+        filters: vec![Filter::Gzip],
+        external_filters: Vec::new(),
+    }
+}
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    use self::zip::result::ZipError;
+    let e = match e {
+        ZipError::Io(e) => error::Error::Io(e),
+        ZipError::InvalidArchive(s) => error::Error::Io(Error::new(ErrorKind::InvalidData, s)),
+        ZipError::UnsupportedArchive(s) if s == ZipError::PASSWORD_REQUIRED => {
+            error::Error::PassphraseRequired(s.to_string())
+        }
+        ZipError::UnsupportedArchive(s) => error::Error::UnsupportedFormat(s.to_string()),
+        ZipError::FileNotFound => error::Error::Io(Error::from_raw_os_error(libc::ENOENT)),
+    };
+    e.into()
+}
+
+// days since the epoch, in the proleptic gregorian calendar.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+struct Zip {
+    archive: zip::ZipArchive<Box<dyn SeekableRead>>,
+    next: usize,
+}
+
+// sizes in headers may lie, buffers grow from this as they are read.
+const MAX_INITIAL_BUFFER: usize = 64 * 1024 * 1024;
+
+// a buffer of the entry, which is extracted into memory. entries beyond the address space,
+// like ones over 4 GiB on 32-bit targets, can not be.
+fn buffer_for(size: u64) -> Result<Vec<u8>> {
+    match usize::try_from(size) {
+        Ok(size) if size <= isize::MAX as usize => {
+            Ok(Vec::with_capacity(min(size, MAX_INITIAL_BUFFER)))
+        }
+        _ => Err(Error::from_raw_os_error(libc::EFBIG)),
+    }
+}
+
+// the name is kept in bytes like libarchive.
+fn zip_path<'a>(f: &'a zip::read::ZipFile<'_>) -> &'a Path {
+    Path::new(OsStr::from_bytes(f.name_raw()))
+}
+
+fn zip_metadata(f: &zip::read::ZipFile<'_>) -> Metadata {
+    let mode = f.unix_mode().unwrap_or(0) as libc::mode_t;
+    let filetype = if mode & libc::S_IFMT != 0 {
+        mode & libc::S_IFMT
+    } else if f.is_dir() {
+        libc::S_IFDIR
+    } else {
+        libc::S_IFREG
+    };
+    // dos time has no time zone, it is taken as utc.
+    let t = f.last_modified();
+    let days = days_from_civil(t.year() as i64, t.month() as i64, t.day() as i64);
+    let sec = days * 86400 + t.hour() as i64 * 3600 + t.minute() as i64 * 60 + t.second() as i64;
+    Metadata {
+        path: zip_path(f).to_path_buf(),
+        size: f.size() as i64,
+        filetype: filetype,
+        perm: mode & 0o7777,
+        owner: None,
+        atime: None,
+        mtime: Some(Timespec::new(sec, 0)),
+        ctime: None,
+        birthtime: None,
+        // the target is the content.
+        symlink: None,
+        // the zip crate does not tell, reads fail instead.
+        encrypted: false,
+    }
+}
+
+impl ArchiveBackend for Zip {
+    fn entries(&mut self) -> Box<dyn Iterator<Item = Result<Metadata>> + '_> {
+        Box::new(iter::from_fn(move || {
+            if self.next >= self.archive.len() {
+                return None;
+            }
+            self.next += 1;
+            let f = self.archive.by_index_raw(self.next - 1);
+            Some(f.map(|f| zip_metadata(&f)).map_err(zip_error))
+        }))
+    }
+
+    // the entry is extracted into memory, the cache holds it anyway.
+    fn open_entry(mut self: Box<Self>, path: &Path) -> Option<Result<Box<dyn SeekableRead>>> {
+        for i in self.next..self.archive.len() {
+            match self.archive.by_index_raw(i) {
+                Ok(ref f) if zip_path(f) == path => {}
+                Ok(_) => continue,
+                Err(e) => return Some(Err(zip_error(e))),
+            }
+            let mut f = match self.archive.by_index(i) {
+                Ok(f) => f,
+                Err(e) => return Some(Err(zip_error(e))),
+            };
+            let mut v = match buffer_for(f.size()) {
+                Ok(v) => v,
+                Err(e) => return Some(Err(e)),
+            };
+            return Some(
+                f.read_to_end(&mut v)
+                    .map(|_| Box::new(Cursor::new(v)) as Box<dyn SeekableRead>),
+            );
+        }
+        None
+    }
+}
+
+// counts bytes read from the stream.
+struct Counting<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+const TAR_BLOCK_SIZE: u64 = 512;
+
+// the tar is read an entry at a time, so that the stream can be kept between entries.
+struct Tar {
+    // at the header of the next entry.
+    stream: Counting<Box<dyn Read>>,
+    compressed: bool,
+}
+
+impl Tar {
+    fn new(r: Box<dyn Read>, compressed: bool) -> Tar {
+        Tar {
+            stream: Counting { inner: r, count: 0 },
+            compressed: compressed,
+        }
+    }
+
+    // reads the next entry by f, and leaves the stream at the header after it.
+    fn next_entry<T, F>(&mut self, f: F) -> Option<Result<T>>
+    where
+        F: FnOnce(&mut tar::Entry<'_, &mut Counting<Box<dyn Read>>>) -> Result<T>,
+    {
+        let result = {
+            let mut archive = tar::Archive::new(&mut self.stream);
+            let mut entries = match archive.entries() {
+                Ok(entries) => entries,
+                Err(e) => return Some(Err(e)),
+            };
+            let next = match entries.next() {
+                Some(Ok(mut e)) => f(&mut e).and_then(|v| {
+                    io::copy(&mut e, &mut io::sink())?;
+                    Ok(Some(v))
+                }),
+                Some(Err(e)) => Err(e),
+                None => Ok(None),
+            };
+            next
+        };
+        // entries are padded to blocks, whose rest the tar crate skips before the next header.
+        let padding = (TAR_BLOCK_SIZE - self.stream.count % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+        let skipped = io::copy(&mut (&mut self.stream).take(padding), &mut io::sink());
+        match (result, skipped) {
+            (Ok(v), Ok(_)) => v.map(Ok),
+            (Err(e), _) | (Ok(_), Err(e)) => Some(Err(e)),
+        }
+    }
+}
+
+fn tar_metadata<R: Read>(e: &tar::Entry<'_, R>) -> Result<Metadata> {
+    use self::tar::EntryType;
+    let header = e.header();
+    let kind = header.entry_type();
+    let filetype = match kind {
+        EntryType::Directory => libc::S_IFDIR,
+        EntryType::Symlink => libc::S_IFLNK,
+        EntryType::Char => libc::S_IFCHR,
+        EntryType::Block => libc::S_IFBLK,
+        EntryType::Fifo => libc::S_IFIFO,
+        _ => libc::S_IFREG,
+    };
+    let symlink = match kind {
+        EntryType::Symlink => e.link_name()?.map(|p| p.into_owned()),
+        _ => None,
+    };
+    // writers may leave the fields empty.
+    let owner = match (header.uid(), header.gid()) {
+        (Ok(uid), Ok(gid)) => Some((uid as u32, gid as u32)),
+        _ => None,
+    };
+    Ok(Metadata {
+        path: e.path()?.into_owned(),
+        size: e.size() as i64,
+        filetype: filetype,
+        perm: header.mode()? & 0o7777,
+        owner: owner,
+        atime: None,
+        mtime: header.mtime().ok().map(|t| Timespec::new(t as i64, 0)),
+        ctime: None,
+        birthtime: None,
+        symlink: symlink,
+        encrypted: false,
+    })
+}
+
+impl ArchiveBackend for Tar {
+    fn entries(&mut self) -> Box<dyn Iterator<Item = Result<Metadata>> + '_> {
+        Box::new(iter::from_fn(move || self.next_entry(|e| tar_metadata(e))))
+    }
+
+    fn open_entry(mut self: Box<Self>, path: &Path) -> Option<Result<Box<dyn SeekableRead>>> {
+        self.open_next(path)
+    }
+
+    // gzip streams are decompressed from the beginning to be read again.
+    fn resumable(&self) -> bool {
+        self.compressed
+    }
+
+    fn open_next(&mut self, path: &Path) -> Option<Result<Box<dyn SeekableRead>>> {
+        loop {
+            let data = self.next_entry(|e| {
+                if e.path()? != path {
+                    return Ok(None);
+                }
+                let mut v = buffer_for(e.size())?;
+                e.read_to_end(&mut v)?;
+                Ok(Some(v))
+            });
+            match data {
+                Some(Ok(Some(v))) => return Some(Ok(Box::new(Cursor::new(v)))),
+                Some(Ok(None)) => continue,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return None,
+            }
+        }
+    }
+}
+
+#[test]
+fn test_tar() {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_ustar();
+    header.set_path("dir/file").unwrap();
+    header.set_size(5);
+    header.set_mode(0o640);
+    header.set_uid(1000);
+    header.set_gid(100);
+    header.set_mtime(1234567890);
+    header.set_cksum();
+    builder.append(&header, &b"hello"[..]).unwrap();
+    let data = builder.into_inner().unwrap();
+
+    let open_tar = || open(Box::new(Cursor::new(data.clone())), &Options::default()).unwrap();
+    assert!(!open_tar().resumable());
+    let entries: Vec<_> = open_tar().entries().map(|m| m.unwrap()).collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].path, Path::new("dir/file"));
+    assert_eq!(entries[0].size, 5);
+    assert_eq!(entries[0].filetype, libc::S_IFREG);
+    assert_eq!(entries[0].perm, 0o640);
+    assert_eq!(entries[0].owner, Some((1000, 100)));
+    assert_eq!(entries[0].mtime, Some(Timespec::new(1234567890, 0)));
+
+    let mut v = Vec::new();
+    let mut r = open_tar()
+        .open_entry(Path::new("dir/file"))
+        .unwrap()
+        .unwrap();
+    r.read_to_end(&mut v).unwrap();
+    assert_eq!(v, b"hello");
+    assert!(open_tar().open_entry(Path::new("none")).is_none());
+
+    let mut options = Options::default();
+    options.set_formats(vec![Format::Zip]);
+    assert!(open(Box::new(Cursor::new(data)), &options).is_err());
+}
+
+#[test]
+fn test_tar_gz_open_next() {
+    use self::flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for &(name, data) in [("a", &b"first"[..]), ("b", &b"x"[..]), ("c", &b"third"[..])].iter() {
+        let mut header = tar::Header::new_ustar();
+        header.set_path(name).unwrap();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+    let data = encoder.finish().unwrap();
+
+    let mut archive = open(Box::new(Cursor::new(data)), &Options::default()).unwrap();
+    assert!(archive.resumable());
+    let mut read = |path: &str| {
+        let mut v = Vec::new();
+        let mut r = archive.open_next(Path::new(path))?.unwrap();
+        r.read_to_end(&mut v).unwrap();
+        Some(v)
+    };
+    // members after the last opened one are read on from it.
+    assert_eq!(read("a").unwrap(), b"first");
+    assert_eq!(read("c").unwrap(), b"third");
+    assert!(read("b").is_none());
+}
+
+#[test]
+fn test_non_utf8_name() {
+    // "\x82\xa0" is a hiragana in shift_jis.
+    let name = OsStr::from_bytes(b"\x82\xa0.txt");
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_ustar();
+    header.set_path(name).unwrap();
+    header.set_size(1);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, &b"a"[..]).unwrap();
+    let data = builder.into_inner().unwrap();
+
+    let open_tar = || open(Box::new(Cursor::new(data.clone())), &Options::default()).unwrap();
+    let entries: Vec<_> = open_tar().entries().map(|m| m.unwrap()).collect();
+    assert_eq!(entries[0].path, Path::new(name));
+    assert!(open_tar().open_entry(Path::new(name)).is_some());
+}
+
+#[test]
+fn test_days_from_civil() {
+    assert_eq!(days_from_civil(1970, 1, 1), 0);
+    assert_eq!(days_from_civil(2000, 3, 1), 11017);
+    assert_eq!(days_from_civil(1969, 12, 31), -1);
+}
@@ -0,0 +1,828 @@
+use libc;
+use tempfile;
+use time;
+
+use self::time::Timespec;
+use super::backend::{ArchiveBackend, Metadata};
+use super::options::{Backend, Options};
+use crate::confine;
+use crate::error;
+use crate::fs::SeekableRead;
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsString;
+use std::fs as stdfs;
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::mem;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdout, Command, Stdio};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+// the first argument to run the helper.
+pub const HELPER_ARG: &str = "--sandbox-helper";
+// passphrases are sent by a pipe at the fd of the helper, which other processes can not read
+// unlike the environment or arguments.
+const PASSPHRASES_FD: libc::c_int = 3;
+
+// messages from the helper.
+const ENTRY: u8 = b'E';
+const DATA: u8 = b'D';
+const ERROR: u8 = b'X';
+const END: u8 = b'Z';
+
+const DATA_SIZE: usize = 64 * 1024;
+
+/// Sandbox decodes an archive in a confined helper process.
+/// the helper reads the archive file by the fd passed as stdin, or a private copy of an archive
+/// in another archive, and sends back results over a pipe.
+pub struct Sandbox {
+    copy: RefCell<Option<Rc<stdfs::File>>>,
+}
+
+impl Sandbox {
+    pub fn new() -> Sandbox {
+        Sandbox {
+            copy: RefCell::new(None),
+        }
+    }
+
+    // archives on the disk are opened by helpers, others are read by open only for the first
+    // time.
+    pub fn open<F>(
+        &self,
+        path: Option<&Path>,
+        open: F,
+        options: &Options,
+    ) -> Result<Box<dyn ArchiveBackend>>
+    where
+        F: FnOnce() -> Result<Box<dyn SeekableRead>>,
+    {
+        let input = match path {
+            Some(path) => Input::Path(path.to_path_buf()),
+            None => {
+                if self.copy.borrow().is_none() {
+                    let mut copy = tempfile::tempfile()?;
+                    io::copy(&mut open()?, &mut copy)?;
+                    *self.copy.borrow_mut() = Some(Rc::new(copy));
+                }
+                Input::Copy(self.copy.borrow().as_ref().unwrap().clone())
+            }
+        };
+        Ok(Box::new(Remote {
+            input: input,
+            options: options.clone(),
+            listing: None,
+        }))
+    }
+}
+
+// the archive which helpers read.
+enum Input {
+    Path(PathBuf),
+    Copy(Rc<stdfs::File>),
+}
+
+impl Input {
+    // opens again not to share the offset with other helpers.
+    fn open(&self) -> Result<stdfs::File> {
+        match *self {
+            Input::Path(ref path) => stdfs::File::open(path),
+            Input::Copy(ref copy) => {
+                stdfs::File::open(format!("/proc/self/fd/{}", copy.as_raw_fd()))
+            }
+        }
+    }
+}
+
+// a pipe of the read and the write ends, which are closed on exec.
+fn pipe() -> Result<(stdfs::File, stdfs::File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    unsafe {
+        Ok((
+            stdfs::File::from_raw_fd(fds[0]),
+            stdfs::File::from_raw_fd(fds[1]),
+        ))
+    }
+}
+
+struct Remote {
+    input: Input,
+    options: Options,
+    // the helper listing entries, kept for entries to continue after the last listed one.
+    listing: Option<Helper>,
+}
+
+impl Remote {
+    fn spawn(&self, command: &str, path: Option<&Path>) -> Result<Helper> {
+        let input = self.input.open()?;
+        let formats: Vec<_> = self.options.formats().iter().map(|f| f.name()).collect();
+        let filters: Vec<_> = self.options.filters().iter().map(|f| f.name()).collect();
+        let mut cmd = Command::new(env::current_exe()?);
+        cmd.arg(HELPER_ARG)
+            .arg(command)
+            .arg(match self.options.backend() {
+                Backend::Libarchive => "libarchive",
+                Backend::Rust => "rust",
+            })
+            .arg(formats.join(","))
+            .arg(filters.join(","))
+            .arg(self.options.block_size().to_string());
+        for option in self.options.read_options().iter() {
+            cmd.arg("--read-option").arg(option);
+        }
+        if let Some(policy) = self.options.retry_policy() {
+            cmd.arg("--retry").arg(policy.to_string());
+        }
+        if let Some(path) = path {
+            cmd.arg("--path").arg(path);
+        }
+        let (passphrases, mut writer) = pipe()?;
+        let fd = passphrases.as_raw_fd();
+        unsafe {
+            cmd.pre_exec(move || {
+                // the pipe is left open at the fd in the helper.
+                let r = if fd == PASSPHRASES_FD {
+                    libc::fcntl(fd, libc::F_SETFD, 0)
+                } else {
+                    libc::dup2(fd, PASSPHRASES_FD)
+                };
+                if r < 0 {
+                    return Err(Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut child = cmd
+            .stdin(Stdio::from(input))
+            .stdout(Stdio::piped())
+            .spawn()?;
+        drop(passphrases);
+        let out = Watched {
+            out: child.stdout.take().unwrap(),
+            ceiling: self.options.decode_timeout(),
+        };
+        let helper = Helper {
+            child: child,
+            out: BufReader::new(out),
+            done: false,
+        };
+        for p in self.options.passphrases().iter() {
+            write_bytes(&mut writer, p.as_bytes())?;
+        }
+        Ok(helper)
+    }
+}
+
+impl ArchiveBackend for Remote {
+    fn entries(&mut self) -> Box<dyn Iterator<Item = Result<Metadata>> + '_> {
+        if self.listing.is_none() {
+            match self.spawn("list", None) {
+                Ok(helper) => self.listing = Some(helper),
+                Err(e) => return Box::new(Some(Err(e)).into_iter()),
+            }
+        }
+        Box::new(self.listing.as_mut().unwrap())
+    }
+
+    fn open_entry(self: Box<Self>, path: &Path) -> Option<Result<Box<dyn SeekableRead>>> {
+        let helper = match self.spawn("read", Some(path)) {
+            Ok(helper) => helper,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut r = RemoteReader {
+            remote: *self,
+            path: path.to_path_buf(),
+            helper: helper,
+            buf: Vec::new(),
+            begin: 0,
+            pos: 0,
+        };
+        // a missing entry is told before any data.
+        match r.fill() {
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => None,
+            Err(e) => Some(Err(e)),
+            Ok(_) => Some(Ok(Box::new(r))),
+        }
+    }
+}
+
+// the output of the helper, whose reads fail by TimedOut if nothing comes within the ceiling.
+struct Watched {
+    out: ChildStdout,
+    ceiling: Option<Duration>,
+}
+
+impl Read for Watched {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if let Some(ceiling) = self.ceiling {
+            let mut fd = libc::pollfd {
+                fd: self.out.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ms = ceiling.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            loop {
+                match unsafe { libc::poll(&mut fd, 1, ms) } {
+                    0 => return Err(Error::from(ErrorKind::TimedOut)),
+                    n if n > 0 => break,
+                    _ => {
+                        let e = Error::last_os_error();
+                        if e.kind() != ErrorKind::Interrupted {
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        self.out.read(buf)
+    }
+}
+
+struct Helper {
+    child: Child,
+    out: BufReader<Watched>,
+    done: bool,
+}
+
+impl Helper {
+    // returns None at the end.
+    fn next_message(&mut self) -> Result<Option<Message>> {
+        if self.done {
+            return Ok(None);
+        }
+        match read_message(&mut self.out) {
+            Ok(Message::End) => {
+                self.done = true;
+                Ok(None)
+            }
+            Ok(Message::Error(e)) => {
+                self.done = true;
+                Err(e)
+            }
+            Ok(m) => Ok(Some(m)),
+            Err(ref e) if e.kind() == ErrorKind::TimedOut => {
+                self.done = true;
+                // the decoder may spin or block forever on broken archives.
+                let _ = self.child.kill();
+                error!(
+                    pid = self.child.id(),
+                    ceiling = ?self.out.get_ref().ceiling,
+                    "sandbox helper is stuck, killed"
+                );
+                Err(Error::from_raw_os_error(libc::EIO))
+            }
+            Err(e) => {
+                self.done = true;
+                // the helper may be killed by the sandbox.
+                let status = self.child.wait()?;
+                Err(Error::new(
+                    ErrorKind::Other,
+                    format!("sandbox helper failed: {}, {}", status, e),
+                ))
+            }
+        }
+    }
+}
+
+impl Iterator for Helper {
+    type Item = Result<Metadata>;
+
+    fn next(&mut self) -> Option<Result<Metadata>> {
+        match self.next_message() {
+            Ok(Some(Message::Entry(m))) => Some(Ok(m)),
+            Ok(Some(_)) => Some(Err(Error::new(
+                ErrorKind::InvalidData,
+                "unexpected message from sandbox helper",
+            ))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// streams an entry from the helper.
+struct RemoteReader {
+    remote: Remote,
+    path: PathBuf,
+    helper: Helper,
+    // buf holds data from begin.
+    buf: Vec<u8>,
+    begin: u64,
+    pos: u64,
+}
+
+impl RemoteReader {
+    // receives the next data, returns false at the end.
+    fn fill(&mut self) -> Result<bool> {
+        self.begin += self.buf.len() as u64;
+        self.buf.clear();
+        match self.helper.next_message()? {
+            Some(Message::Data(data)) => {
+                self.buf = data;
+                Ok(true)
+            }
+            Some(_) => Err(Error::new(
+                ErrorKind::InvalidData,
+                "unexpected message from sandbox helper",
+            )),
+            None => Ok(false),
+        }
+    }
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pos < self.begin {
+            // data can not be read backward, read from the beginning.
+            self.helper = self.remote.spawn("read", Some(&self.path))?;
+            self.buf.clear();
+            self.begin = 0;
+        }
+        while self.pos >= self.begin + self.buf.len() as u64 {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let offset = (self.pos - self.begin) as usize;
+        let n = buf.len().min(self.buf.len() - offset);
+        buf[..n].copy_from_slice(&self.buf[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for RemoteReader {
+    // seeks are done on the next read.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+            SeekFrom::End(_) => {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "seek from end of sandboxed entry",
+                ))
+            }
+        };
+        if pos < 0 {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl SeekableRead for RemoteReader {}
+
+enum Message {
+    Entry(Metadata),
+    Data(Vec<u8>),
+    Error(Error),
+    End,
+}
+
+fn write_bytes<W: Write>(w: &mut W, b: &[u8]) -> Result<()> {
+    w.write_all(&(b.len() as u32).to_le_bytes())?;
+    w.write_all(b)
+}
+
+fn write_path<W: Write>(w: &mut W, p: &Option<PathBuf>) -> Result<()> {
+    match *p {
+        Some(ref p) => {
+            w.write_all(&[1])?;
+            write_bytes(w, p.as_os_str().as_bytes())
+        }
+        None => w.write_all(&[0]),
+    }
+}
+
+fn write_time<W: Write>(w: &mut W, t: Option<Timespec>) -> Result<()> {
+    match t {
+        Some(t) => {
+            w.write_all(&[1])?;
+            w.write_all(&t.sec.to_le_bytes())?;
+            w.write_all(&t.nsec.to_le_bytes())
+        }
+        None => w.write_all(&[0]),
+    }
+}
+
+fn write_entry<W: Write>(w: &mut W, m: &Metadata) -> Result<()> {
+    w.write_all(&[ENTRY])?;
+    write_bytes(w, m.path.as_os_str().as_bytes())?;
+    w.write_all(&m.size.to_le_bytes())?;
+    w.write_all(&(m.filetype as u32).to_le_bytes())?;
+    w.write_all(&(m.perm as u32).to_le_bytes())?;
+    match m.owner {
+        Some((uid, gid)) => {
+            w.write_all(&[1])?;
+            w.write_all(&uid.to_le_bytes())?;
+            w.write_all(&gid.to_le_bytes())?;
+        }
+        None => w.write_all(&[0])?,
+    }
+    for t in [m.atime, m.mtime, m.ctime, m.birthtime].iter() {
+        write_time(w, *t)?;
+    }
+    write_path(w, &m.symlink)?;
+    w.write_all(&[m.encrypted as u8])
+}
+
+fn write_error<W: Write>(w: &mut W, e: &Error) -> Result<()> {
+    let (class, s) = match e.get_ref().and_then(|e| e.downcast_ref::<error::Error>()) {
+        Some(&error::Error::UnsupportedFormat(ref s)) => (1, s.clone()),
+        Some(&error::Error::PassphraseRequired(ref s)) => (2, s.clone()),
+        Some(&error::Error::WrongPassphrase(ref s)) => (3, s.clone()),
+        Some(&error::Error::Truncated(ref s)) => (4, s.clone()),
+        _ => (0, e.to_string()),
+    };
+    w.write_all(&[ERROR, class])?;
+    w.write_all(&e.raw_os_error().unwrap_or(0).to_le_bytes())?;
+    write_bytes(w, s.as_bytes())
+}
+
+fn read_array<R: Read, T: Default + AsMut<[u8]>>(r: &mut R) -> Result<T> {
+    let mut a = T::default();
+    r.read_exact(a.as_mut())?;
+    Ok(a)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> Result<Vec<u8>> {
+    let len = u32::from_le_bytes(read_array(r)?) as usize;
+    let mut v = vec![0; len];
+    r.read_exact(&mut v)?;
+    Ok(v)
+}
+
+fn read_path<R: Read>(r: &mut R) -> Result<Option<PathBuf>> {
+    match read_array::<R, [u8; 1]>(r)?[0] {
+        0 => Ok(None),
+        _ => Ok(Some(PathBuf::from(OsString::from_vec(read_bytes(r)?)))),
+    }
+}
+
+fn read_time<R: Read>(r: &mut R) -> Result<Option<Timespec>> {
+    match read_array::<R, [u8; 1]>(r)?[0] {
+        0 => Ok(None),
+        _ => {
+            let sec = i64::from_le_bytes(read_array(r)?);
+            let nsec = i32::from_le_bytes(read_array(r)?);
+            Ok(Some(Timespec::new(sec, nsec)))
+        }
+    }
+}
+
+fn read_owner<R: Read>(r: &mut R) -> Result<Option<(u32, u32)>> {
+    match read_array::<R, [u8; 1]>(r)?[0] {
+        0 => Ok(None),
+        _ => {
+            let uid = u32::from_le_bytes(read_array(r)?);
+            Ok(Some((uid, u32::from_le_bytes(read_array(r)?))))
+        }
+    }
+}
+
+fn read_message<R: Read>(r: &mut R) -> Result<Message> {
+    match read_array::<R, [u8; 1]>(r)?[0] {
+        ENTRY => Ok(Message::Entry(Metadata {
+            path: PathBuf::from(OsString::from_vec(read_bytes(r)?)),
+            size: i64::from_le_bytes(read_array(r)?),
+            filetype: u32::from_le_bytes(read_array(r)?) as libc::mode_t,
+            perm: u32::from_le_bytes(read_array(r)?) as libc::mode_t,
+            owner: read_owner(r)?,
+            atime: read_time(r)?,
+            mtime: read_time(r)?,
+            ctime: read_time(r)?,
+            birthtime: read_time(r)?,
+            symlink: read_path(r)?,
+            encrypted: read_array::<R, [u8; 1]>(r)?[0] != 0,
+        })),
+        DATA => Ok(Message::Data(read_bytes(r)?)),
+        ERROR => {
+            let class = read_array::<R, [u8; 1]>(r)?[0];
+            let errno = i32::from_le_bytes(read_array(r)?);
+            let s = String::from_utf8_lossy(&read_bytes(r)?).into_owned();
+            let e = match class {
+                1 => error::Error::UnsupportedFormat(s),
+                2 => error::Error::PassphraseRequired(s),
+                3 => error::Error::WrongPassphrase(s),
+                4 => error::Error::Truncated(s),
+                _ if errno != 0 => error::Error::Io(Error::from_raw_os_error(errno)),
+                _ => error::Error::Io(Error::new(ErrorKind::Other, s)),
+            };
+            Ok(Message::Error(e.into()))
+        }
+        END => Ok(Message::End),
+        _ => Err(Error::new(ErrorKind::InvalidData, "unknown message")),
+    }
+}
+
+// sends entries or data of an entry, an error ends messages.
+fn serve<W: Write>(
+    backend: Result<Box<dyn ArchiveBackend>>,
+    path: Option<&Path>,
+    w: &mut W,
+) -> Result<()> {
+    let result = backend.and_then(|mut backend| match path {
+        None => {
+            for m in backend.entries() {
+                write_entry(w, &m?)?;
+            }
+            Ok(())
+        }
+        Some(path) => {
+            let mut r = backend
+                .open_entry(path)
+                .unwrap_or(Err(Error::from_raw_os_error(libc::ENOENT)))?;
+            let mut buf = vec![0; DATA_SIZE];
+            loop {
+                let n = r.read(&mut buf)?;
+                if n == 0 {
+                    return Ok(());
+                }
+                w.write_all(&[DATA])?;
+                write_bytes(w, &buf[..n])?;
+            }
+        }
+    });
+    if let Err(e) = result {
+        write_error(w, &e)?;
+    }
+    w.write_all(&[END])?;
+    w.flush()
+}
+
+// system calls to decode from stdin to stdout, and to manage memory.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_pread64,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_close,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_futex,
+    libc::SYS_getrandom,
+    libc::SYS_clock_gettime,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+// confines the process with seccomp, other system calls fail with EPERM. no path can be opened
+// by landlock either, if the kernel has it.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn confine() -> Result<()> {
+    confine::deny_paths()?;
+    confine::seccomp(ALLOWED_SYSCALLS, true)
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn confine() -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Other,
+        "sandbox is not supported on this architecture",
+    ))
+}
+
+fn parse_list<T: FromStr>(s: &str) -> Option<Vec<T>> {
+    s.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().ok())
+        .collect()
+}
+
+fn parse_helper_args(args: Vec<OsString>) -> Option<(Option<PathBuf>, Options)> {
+    let mut args = args.into_iter();
+    let command = args.next()?;
+    let mut options = Options::default();
+    let mut next_str = || args.next().and_then(|a| a.into_string().ok());
+    options.set_backend(next_str()?.parse().ok()?);
+    options.set_formats(parse_list(&next_str()?)?);
+    options.set_filters(parse_list(&next_str()?)?);
+    options.set_block_size(next_str()?.parse().ok()?);
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--read-option") => options.add_read_option(args.next()?.into_string().ok()?),
+            Some("--retry") => options.set_retry_policy(args.next()?.to_str()?.parse().ok()?),
+            Some("--path") => path = Some(PathBuf::from(args.next()?)),
+            _ => return None,
+        }
+    }
+    match command.to_str() {
+        Some("list") if path.is_none() => Some((None, options)),
+        Some("read") if path.is_some() => Some((path, options)),
+        _ => None,
+    }
+}
+
+// reads passphrases sent by the mount until the end of the pipe.
+fn read_passphrases<R: Read>(r: &mut R, options: &mut Options) -> Result<()> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    let mut r = &data[..];
+    while !r.is_empty() {
+        let p = String::from_utf8(read_bytes(&mut r)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        options.add_passphrase(p);
+    }
+    Ok(())
+}
+
+// the entry point of the helper, args follow HELPER_ARG.
+pub fn helper_main(args: Vec<OsString>) -> ! {
+    let (path, mut options) = match parse_helper_args(args) {
+        Some(parsed) => parsed,
+        None => {
+            eprintln!("showfs: bad arguments for the sandbox helper");
+            process::exit(2);
+        }
+    };
+    let mut passphrases = unsafe { stdfs::File::from_raw_fd(PASSPHRASES_FD) };
+    if let Err(e) = read_passphrases(&mut passphrases, &mut options) {
+        eprintln!("showfs: failed to read passphrases: {}", e);
+        process::exit(2);
+    }
+    drop(passphrases);
+    super::initialize();
+    // stdin is the archive, and stdout is the pipe to the parent.
+    let input = unsafe { stdfs::File::from_raw_fd(0) };
+    let mut w = BufWriter::new(unsafe { stdfs::File::from_raw_fd(1) });
+    if let Err(e) = confine() {
+        eprintln!("showfs: failed to confine the sandbox helper: {}", e);
+        process::exit(1);
+    }
+    let name = path.clone().unwrap_or_default();
+    // reads of the archive are retried like in the mount.
+    let input = super::read_source(Box::new(input), &options);
+    let backend = super::open_backend(input, &options, &name);
+    let code = match serve(backend, path.as_ref().map(|p| p.as_path()), &mut w) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    };
+    // not to close stdout twice.
+    mem::forget(w.into_inner());
+    process::exit(code)
+}
+
+#[test]
+fn test_helper_args() {
+    let args = [
+        "read", "rust", "tar", "", "10240", "--retry", "5:20:EIO", "--path", "a",
+    ];
+    let args = args.iter().map(OsString::from).collect();
+    let (path, options) = parse_helper_args(args).unwrap();
+    assert_eq!(path, Some(PathBuf::from("a")));
+    let policy = "5:20:EIO".parse().unwrap();
+    assert_eq!(options.retry_policy(), Some(&policy));
+    let args = vec![OsString::from("list"), OsString::from("rust")];
+    assert!(parse_helper_args(args).is_none());
+}
+
+#[test]
+fn test_messages() {
+    use std::io::Cursor;
+
+    let m = Metadata {
+        owner: Some((1000, 100)),
+        mtime: Some(Timespec::new(1234567890, 5)),
+        symlink: Some(PathBuf::from("target")),
+        encrypted: true,
+        ..crate::testing::member("dir/file", libc::S_IFLNK, 5)
+    };
+    let mut v = Vec::new();
+    write_entry(&mut v, &m).unwrap();
+    let e = error::Error::WrongPassphrase("zip".to_string()).into();
+    write_error(&mut v, &e).unwrap();
+    write_error(&mut v, &Error::from_raw_os_error(libc::ENOENT)).unwrap();
+    let mut r = Cursor::new(v);
+    match read_message(&mut r).unwrap() {
+        Message::Entry(e) => {
+            assert_eq!(e.path, m.path);
+            assert_eq!(e.filetype, m.filetype);
+            assert_eq!(e.mtime, m.mtime);
+            assert_eq!(e.owner, m.owner);
+            assert_eq!(e.symlink, m.symlink);
+            assert!(e.encrypted);
+        }
+        _ => panic!("not an entry"),
+    }
+    match read_message(&mut r).unwrap() {
+        Message::Error(e) => assert_eq!(error::errno(&e), libc::EACCES),
+        _ => panic!("not an error"),
+    }
+    match read_message(&mut r).unwrap() {
+        Message::Error(e) => assert_eq!(e.raw_os_error(), Some(libc::ENOENT)),
+        _ => panic!("not an error"),
+    }
+
+    // passphrases may have any characters.
+    let mut v = Vec::new();
+    write_bytes(&mut v, b"a\nb").unwrap();
+    write_bytes(&mut v, b"c").unwrap();
+    let mut options = Options::default();
+    read_passphrases(&mut Cursor::new(v), &mut options).unwrap();
+    assert_eq!(options.passphrases(), ["a\nb", "c"]);
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_serve() {
+    use super::options::Backend;
+    use std::io::Cursor;
+    use tar;
+
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_ustar();
+    header.set_path("file").unwrap();
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, &b"hello"[..]).unwrap();
+    let data = builder.into_inner().unwrap();
+    let mut options = Options::default();
+    options.set_backend(Backend::Rust);
+
+    let serve_tar = |path: Option<&Path>| {
+        let mut v = Vec::new();
+        let input = Box::new(Cursor::new(data.clone()));
+        let backend = super::open_backend(input, &options, Path::new(""));
+        serve(backend, path, &mut v).unwrap();
+        Cursor::new(v)
+    };
+    let mut r = serve_tar(None);
+    match read_message(&mut r).unwrap() {
+        Message::Entry(e) => assert_eq!(e.path, Path::new("file")),
+        _ => panic!("not an entry"),
+    }
+    assert!(match read_message(&mut r).unwrap() {
+        Message::End => true,
+        _ => false,
+    });
+
+    let mut r = serve_tar(Some(Path::new("file")));
+    match read_message(&mut r).unwrap() {
+        Message::Data(d) => assert_eq!(d, b"hello"),
+        _ => panic!("not data"),
+    }
+
+    let mut r = serve_tar(Some(Path::new("none")));
+    match read_message(&mut r).unwrap() {
+        Message::Error(e) => assert_eq!(e.raw_os_error(), Some(libc::ENOENT)),
+        _ => panic!("not an error"),
+    }
+}
+
+#[test]
+fn test_stuck_helper() {
+    use std::time::Instant;
+
+    let mut child = Command::new("sleep")
+        .arg("10")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let out = Watched {
+        out: child.stdout.take().unwrap(),
+        ceiling: Some(Duration::from_millis(50)),
+    };
+    let mut helper = Helper {
+        child: child,
+        out: BufReader::new(out),
+        done: false,
+    };
+    let start = Instant::now();
+    let e = helper.next_message().err().unwrap();
+    assert_eq!(e.raw_os_error(), Some(libc::EIO));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    // killed, not exited.
+    assert!(!helper.child.wait().unwrap().success());
+}
@@ -0,0 +1,232 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::min;
+use std::rc::{Rc, Weak};
+
+use super::indexer::Indexer;
+use super::reader;
+use super::Source;
+use crate::control;
+use crate::stats::ScrubStats;
+
+// bytes of a cache checksummed by a step, so that a large member takes several.
+const CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+// crc-32 of ieee 802.3 continued by the data. bitwise, as scrubbing is not in a hurry.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+// what a step checks.
+enum Item {
+    // results of the indexer which are not taken.
+    Indexed,
+    Source(Weak<Source>),
+    // the id of the pages, the offset and the checksum so far, if it is being checksummed.
+    Cache(Weak<RefCell<reader::Cache>>, Option<(u64, usize, u32)>),
+}
+
+/// Scrubber checks archives whose entries are read against the archive files on the disk,
+/// and the contents of members in the cache against their checksums, for mounts running for
+/// weeks. archives modified or gone since are forgotten with their cached members, and
+/// members whose cached contents differ from the first checksums are dropped from the cache.
+pub struct Scrubber {
+    indexer: Option<Rc<Indexer>>,
+    sources: RefCell<Vec<Weak<Source>>>,
+    // the items left in the pass, the next at the end.
+    pass: RefCell<Vec<Item>>,
+    started: Cell<bool>,
+}
+
+impl Scrubber {
+    pub(super) fn new(indexer: Option<Rc<Indexer>>) -> Scrubber {
+        Scrubber {
+            indexer: indexer,
+            sources: RefCell::new(Vec::new()),
+            pass: RefCell::new(Vec::new()),
+            started: Cell::new(false),
+        }
+    }
+
+    // the archive and the caches of its members are scrubbed from the next pass.
+    pub(super) fn add(&self, source: &Rc<Source>) {
+        *source.caches.borrow_mut() = Some(Vec::new());
+        self.sources.borrow_mut().push(Rc::downgrade(source));
+    }
+
+    fn start_pass(&self) {
+        let mut pass = Vec::new();
+        if self.indexer.is_some() {
+            pass.push(Item::Indexed);
+        }
+        let mut sources = self.sources.borrow_mut();
+        sources.retain(|source| source.strong_count() > 0);
+        for weak in sources.iter() {
+            let source = match weak.upgrade() {
+                Some(source) => source,
+                None => continue,
+            };
+            pass.push(Item::Source(weak.clone()));
+            let mut caches = source.caches.borrow_mut();
+            if let Some(ref mut caches) = *caches {
+                caches.retain(|cache| cache.strong_count() > 0);
+                pass.extend(caches.iter().map(|cache| Item::Cache(cache.clone(), None)));
+            }
+        }
+        pass.reverse();
+        *self.pass.borrow_mut() = pass;
+    }
+
+    fn check(&self, item: Item, stats: &mut ScrubStats) {
+        match item {
+            Item::Indexed => {
+                if let Some(ref indexer) = self.indexer {
+                    stats.stale += indexer.forget_stale() as u64;
+                }
+                stats.checked += 1;
+            }
+            Item::Source(weak) => {
+                if let Some(source) = weak.upgrade() {
+                    if source.is_stale() {
+                        warn!(path = %source.path().display(), "the archive is modified");
+                        source.evict();
+                        stats.stale += 1;
+                    }
+                    stats.checked += 1;
+                }
+            }
+            Item::Cache(weak, cursor) => {
+                let cache = match weak.upgrade() {
+                    Some(cache) => cache,
+                    None => return,
+                };
+                let (page, size) = match cache.borrow().loaded() {
+                    Some(loaded) => loaded,
+                    None => return,
+                };
+                let id = page.id();
+                // pages loaded again are checked from the start.
+                let (offset, crc) = match cursor {
+                    Some((i, offset, crc)) if i == id => (offset, crc),
+                    _ => (0, 0),
+                };
+                let end = min(size, offset + CHUNK_BYTES);
+                let mut crc = crc;
+                let mut pos = offset;
+                for slice in page.peek_slices(offset) {
+                    let n = min(slice.len(), end - pos);
+                    crc = crc32(crc, &slice[..n]);
+                    pos += n;
+                    if pos == end {
+                        break;
+                    }
+                }
+                drop(page);
+                if end < size {
+                    let item = Item::Cache(weak, Some((id, end, crc)));
+                    self.pass.borrow_mut().push(item);
+                    return;
+                }
+                stats.checked += 1;
+                if !cache.borrow().seal(id, crc) {
+                    error!("cached contents are corrupt, which are dropped to be read again");
+                    cache.borrow_mut().evict();
+                    stats.corrupt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl control::Scrubber for Scrubber {
+    fn scrub_next(&self, stats: &mut ScrubStats) -> bool {
+        if !self.started.replace(true) {
+            self.start_pass();
+        }
+        let item = self.pass.borrow_mut().pop();
+        match item {
+            Some(item) => {
+                self.check(item, stats);
+                true
+            }
+            None => {
+                self.started.set(false);
+                false
+            }
+        }
+    }
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    assert_eq!(crc32(0, b""), 0);
+}
+
+#[test]
+#[cfg(feature = "rust-backend")]
+fn test_scrub() {
+    use super::options::Options;
+    use crate::control::Scrubber as _;
+    use crate::fs::{self, Dir as _};
+    use crate::testing::{archive_dir, tar_bytes};
+    use std::ffi::OsStr;
+    use std::io::Read;
+
+    let write_tar = |path: &std::path::Path, contents: &[u8]| {
+        std::fs::write(path, tar_bytes(&[("a", contents)])).unwrap();
+    };
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.tar");
+    // larger than tiny members, which are not cached.
+    write_tar(&path, &[1; 20000]);
+
+    let dir = archive_dir(&path, Options::default());
+    let scrubber = Scrubber::new(None);
+    scrubber.add(&dir.archive);
+    let read = || {
+        let f = match dir.lookup(OsStr::new("a")).unwrap() {
+            fs::Entry::File(f) => f,
+            fs::Entry::Dir(_) => panic!("not a file"),
+        };
+        let mut contents = Vec::new();
+        f.open().unwrap().read_to_end(&mut contents).unwrap();
+        // loaded once read to the end and opened again.
+        f.open().unwrap();
+        (f, contents)
+    };
+    let pass = || {
+        let mut stats = ScrubStats::default();
+        while scrubber.scrub_next(&mut stats) {}
+        stats
+    };
+    let (f, _) = read();
+    // the first pass seals the checksum of the cached member.
+    let stats = pass();
+    assert_eq!((stats.checked, stats.stale, stats.corrupt), (2, 0, 0));
+    assert_eq!(pass().corrupt, 0);
+
+    // corrupt contents are dropped from the cache.
+    let cache = dir.archive.caches.borrow().as_ref().unwrap()[0]
+        .upgrade()
+        .unwrap();
+    let (mut page, _) = cache.borrow().loaded().unwrap();
+    page.get_slices_mut(0).next().unwrap()[0] ^= 1;
+    drop(page);
+    assert_eq!(pass().corrupt, 1);
+    assert!(cache.borrow().loaded().is_none());
+    drop(f);
+
+    // entries of modified archives are read again.
+    write_tar(&path, &[2; 30000]);
+    assert_eq!(pass().stale, 1);
+    assert_eq!(read().1, vec![2; 30000]);
+    assert_eq!(pass().stale, 0);
+}
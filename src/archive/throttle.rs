@@ -0,0 +1,109 @@
+use std::io::{Read, Result, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::fs::SeekableRead;
+
+// tokens are bytes, which are refilled at the rate up to a second of it.
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// Throttle limits the rate of reads from archive files, which is shared by the mount and
+/// indexers, so that reading archives on network filesystems does not saturate the link.
+pub struct Throttle {
+    // bytes per second.
+    rate: u64,
+    bucket: Mutex<Bucket>,
+}
+
+impl Throttle {
+    pub fn new(rate: u64) -> Throttle {
+        Throttle {
+            rate: rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    // takes the bytes which are read, and waits until the bucket is not in debt.
+    fn take(&self, n: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last).as_secs_f64() * self.rate as f64;
+            bucket.tokens = (bucket.tokens + refill).min(self.rate as f64);
+            bucket.last = now;
+            bucket.tokens -= n as f64;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.rate as f64)
+            } else {
+                Duration::from_secs(0)
+            }
+        };
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Throttled reads the inner reader at the rate of the throttle.
+pub struct Throttled<R> {
+    inner: R,
+    throttle: Arc<Throttle>,
+}
+
+impl<R> Throttled<R> {
+    pub fn new(inner: R, throttle: Arc<Throttle>) -> Throttled<R> {
+        Throttled {
+            inner: inner,
+            throttle: throttle,
+        }
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.throttle.take(n);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Throttled<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<R: SeekableRead> SeekableRead for Throttled<R> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read_at(offset, buf)?;
+        self.throttle.take(n);
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_throttle() {
+    use std::io::Cursor;
+
+    let throttle = Arc::new(Throttle::new(1000));
+    let mut r = Throttled::new(Cursor::new(vec![0; 1500]), throttle.clone());
+    let start = Instant::now();
+    let mut buf = Vec::new();
+    r.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf.len(), 1500);
+    // a second of the rate is read at once, and the rest waits.
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
@@ -0,0 +1,147 @@
+#[cfg(feature = "libarchive")]
+use std::collections::HashMap;
+#[cfg(feature = "libarchive")]
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(feature = "libarchive")]
+use std::time::Instant;
+
+use crate::stats;
+
+// a warning is logged once in the interval, with the number of repeats suppressed since.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+// kinds of warnings remembered, over which ones not logged in the interval are forgotten.
+// if all of them are logged in the interval, new kinds are suppressed.
+#[cfg(feature = "libarchive")]
+const MAX_KINDS: usize = 1024;
+
+#[cfg(feature = "libarchive")]
+struct Seen {
+    logged_at: Instant,
+    suppressed: u64,
+}
+
+#[derive(Default)]
+struct State {
+    #[cfg(feature = "libarchive")]
+    seen: HashMap<(PathBuf, String), Seen>,
+    logged: u64,
+    suppressed: u64,
+}
+
+/// Warnings limits logs of warnings of libarchive, which a broken archive repeats at each
+/// operation, like when a file manager browses it. a warning of an archive and a message is
+/// logged once in the interval, with the number of the ones suppressed since.
+/// it is shared by clones of the options, and by indexing threads.
+pub struct Warnings {
+    #[cfg_attr(not(feature = "libarchive"), allow(dead_code))]
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+impl Warnings {
+    pub fn new(interval: Duration) -> Warnings {
+        Warnings {
+            interval: interval,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Some with the number of repeats suppressed since the last log if the warning of the
+    /// archive should be logged, or None. the rust backend has no warnings.
+    #[cfg(feature = "libarchive")]
+    pub fn admit(&self, archive: &Path, message: &str) -> Option<u64> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let key = (archive.to_path_buf(), message.to_string());
+        let suppressed = match state.seen.get_mut(&key) {
+            Some(seen) if now.duration_since(seen.logged_at) < self.interval => {
+                seen.suppressed += 1;
+                None
+            }
+            Some(seen) => {
+                seen.logged_at = now;
+                Some(std::mem::replace(&mut seen.suppressed, 0))
+            }
+            None => {
+                if state.seen.len() >= MAX_KINDS {
+                    let interval = self.interval;
+                    state
+                        .seen
+                        .retain(|_, seen| now.duration_since(seen.logged_at) < interval);
+                }
+                if state.seen.len() >= MAX_KINDS {
+                    None
+                } else {
+                    let seen = Seen {
+                        logged_at: now,
+                        suppressed: 0,
+                    };
+                    state.seen.insert(key, seen);
+                    Some(0)
+                }
+            }
+        };
+        match suppressed {
+            Some(_) => state.logged += 1,
+            None => state.suppressed += 1,
+        }
+        suppressed
+    }
+
+    /// the numbers of warnings logged and suppressed so far.
+    pub fn counts(&self) -> (u64, u64) {
+        let state = self.state.lock().unwrap();
+        (state.logged, state.suppressed)
+    }
+}
+
+impl stats::Counters for Warnings {
+    fn name(&self) -> &str {
+        "archive_warnings"
+    }
+
+    fn counts(&self) -> Vec<(&'static str, u64)> {
+        let (logged, suppressed) = self.counts();
+        vec![("logged", logged), ("suppressed", suppressed)]
+    }
+}
+
+impl Default for Warnings {
+    fn default() -> Warnings {
+        Warnings::new(DEFAULT_INTERVAL)
+    }
+}
+
+#[test]
+#[cfg(feature = "libarchive")]
+fn test_admit() {
+    let warnings = Warnings::new(Duration::from_millis(100));
+    let a = Path::new("/a.zip");
+    assert_eq!(warnings.admit(a, "bad header"), Some(0));
+    assert_eq!(warnings.admit(a, "bad header"), None);
+    assert_eq!(warnings.admit(a, "bad header"), None);
+    // other messages and archives are logged.
+    assert_eq!(warnings.admit(a, "bad crc"), Some(0));
+    assert_eq!(warnings.admit(Path::new("/b.zip"), "bad header"), Some(0));
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(warnings.admit(a, "bad header"), Some(2));
+    assert_eq!(warnings.counts(), (4, 2));
+}
+
+#[test]
+#[cfg(feature = "libarchive")]
+fn test_admit_full() {
+    let warnings = Warnings::new(Duration::from_millis(100));
+    let a = Path::new("/a.zip");
+    for i in 0..MAX_KINDS {
+        assert_eq!(warnings.admit(a, &i.to_string()), Some(0));
+    }
+    assert_eq!(warnings.admit(a, "bad crc"), None);
+    assert_eq!(warnings.admit(a, "0"), None);
+    // kinds not logged in the interval make room.
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(warnings.admit(a, "bad crc"), Some(0));
+    assert_eq!(warnings.counts(), (MAX_KINDS as u64 + 1, 2));
+}
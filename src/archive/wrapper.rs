@@ -1,39 +1,173 @@
 use libarchive3_sys;
 use libc;
+use time;
 
 use self::libarchive3_sys::ffi;
+use self::time::Timespec;
+use super::backend::{ArchiveBackend, Metadata};
+use super::options::{Capabilities, Filter, Format, Options};
+use super::page::RefPage;
+use crate::error;
 use crate::fs::SeekableRead;
-use std::cmp::min;
-use std::error::Error as STDError;
-use std::ffi::{CStr, CString};
+use crate::isolate;
+use std::cmp::{max, min};
+use std::ffi::{CStr, CString, OsStr};
 use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
-use std::marker;
-use std::path::PathBuf;
+use std::os::unix::ffi::OsStrExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::rc::Rc;
 
 pub fn initialize() {
     unsafe { libc::setlocale(libc::LC_ALL, CString::new("").unwrap().as_ptr()) };
 }
 
-struct Proxy<R: SeekableRead> {
+unsafe fn support_format(raw: *mut ffi::Struct_archive, format: Format) -> libc::c_int {
+    match format {
+        Format::SevenZip => ffi::archive_read_support_format_7zip(raw),
+        Format::Ar => ffi::archive_read_support_format_ar(raw),
+        Format::Cab => ffi::archive_read_support_format_cab(raw),
+        Format::Cpio => ffi::archive_read_support_format_cpio(raw),
+        Format::Iso9660 => ffi::archive_read_support_format_iso9660(raw),
+        Format::Lha => ffi::archive_read_support_format_lha(raw),
+        Format::Rar => ffi::archive_read_support_format_rar(raw),
+        Format::Rar5 => ffi::archive_read_support_format_rar5(raw),
+        Format::Tar => ffi::archive_read_support_format_tar(raw),
+        Format::Xar => ffi::archive_read_support_format_xar(raw),
+        Format::Zip => ffi::archive_read_support_format_zip(raw),
+    }
+}
+
+// warn means an external program is used instead of the library.
+unsafe fn support_filter(raw: *mut ffi::Struct_archive, filter: Filter) -> libc::c_int {
+    match filter {
+        Filter::Bzip2 => ffi::archive_read_support_filter_bzip2(raw),
+        Filter::Compress => ffi::archive_read_support_filter_compress(raw),
+        Filter::Gzip => ffi::archive_read_support_filter_gzip(raw),
+        Filter::Lz4 => ffi::archive_read_support_filter_lz4(raw),
+        Filter::Lzma => ffi::archive_read_support_filter_lzma(raw),
+        Filter::Xz => ffi::archive_read_support_filter_xz(raw),
+        Filter::Zstd => ffi::archive_read_support_filter_zstd(raw),
+    }
+}
+
+// enables only the chosen parsers, to keep away from misdetection and bugs of the others.
+unsafe fn enable_support(options: &Options, raw: *mut ffi::Struct_archive) -> Result<()> {
+    for format in options.formats().iter() {
+        if support_format(raw, *format) != ffi::ARCHIVE_OK {
+            return Err(archive_error(raw, &format!("support format {:?}", format)));
+        }
+    }
+    for filter in options.filters().iter() {
+        let ret = support_filter(raw, *filter);
+        if ret != ffi::ARCHIVE_OK && ret != ffi::ARCHIVE_WARN {
+            return Err(archive_error(raw, &format!("support filter {:?}", filter)));
+        }
+    }
+    Ok(())
+}
+
+// probes the linked libarchive, which may lack libraries of some filters.
+pub fn capabilities() -> Result<Capabilities> {
+    unsafe {
+        let raw = ffi::archive_read_new();
+        if raw.is_null() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "archive_read_new: out of memory",
+            ));
+        }
+        let mut c = Capabilities {
+            version: CStr::from_ptr(ffi::archive_version_details())
+                .to_string_lossy()
+                .into_owned(),
+            formats: Vec::new(),
+            filters: Vec::new(),
+            external_filters: Vec::new(),
+        };
+        for format in Format::ALL.iter() {
+            if support_format(raw, *format) == ffi::ARCHIVE_OK {
+                c.formats.push(*format);
+            }
+        }
+        for filter in Filter::ALL.iter() {
+            match support_filter(raw, *filter) {
+                ffi::ARCHIVE_OK => c.filters.push(*filter),
+                ffi::ARCHIVE_WARN => c.external_filters.push(*filter),
+                _ => {}
+            }
+        }
+        ffi::archive_read_free(raw);
+        Ok(c)
+    }
+}
+
+/// BlockSource lends archive data to libarchive block by block.
+/// a returned block must be valid until the next call.
+pub trait BlockSource {
+    fn read_block(&mut self) -> Result<&[u8]>;
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+
+    // returns the number of bytes skipped.
+    fn skip(&mut self, n: u64) -> Result<u64> {
+        let from = self.seek(SeekFrom::Current(0))?;
+        let to = self.seek(SeekFrom::Current(n as i64))?;
+        Ok(to - from)
+    }
+
+    // while set, only headers are read, and data between them are skipped.
+    fn set_headers_only(&mut self, _headers_only: bool) {}
+}
+
+// the size of blocks read while only headers are read. libarchive skips data beyond
+// the buffered block by the skip callback, so smaller blocks read fewer bytes of data.
+const HEADER_BLOCK_SIZE: usize = 16 * 1024;
+
+// calculates a new position like Seek.
+fn seek_position(pos: SeekFrom, current: usize, size: usize) -> Result<usize> {
+    let n = match pos {
+        SeekFrom::Start(n) => n as i64,
+        SeekFrom::End(n) => size as i64 + n,
+        SeekFrom::Current(n) => current as i64 + n,
+    };
+    if n < 0 {
+        return Err(Error::from_raw_os_error(libc::EINVAL));
+    }
+    Ok(n as usize)
+}
+
+/// Proxy copies data from a reader to the temporary buffer.
+pub struct Proxy<R: SeekableRead> {
     r: R,
     buf: Vec<u8>,
     pos: u64,
+    headers_only: bool,
 }
 
 impl<R: SeekableRead> Proxy<R> {
-    fn new(r: R) -> Proxy<R> {
+    fn new(r: R, block_size: usize) -> Proxy<R> {
         let mut v = Vec::new();
-        v.resize(4096, 0);
+        v.resize(block_size, 0);
         Proxy {
             r: r,
             buf: v,
             pos: 0,
+            headers_only: false,
         }
     }
+}
 
-    fn read(&mut self) -> Result<&[u8]> {
-        let n = self.r.read(&mut self.buf[..])?;
+impl<R: SeekableRead> BlockSource for Proxy<R> {
+    fn read_block(&mut self) -> Result<&[u8]> {
+        let r = &mut self.r;
+        let size = if self.headers_only {
+            min(HEADER_BLOCK_SIZE, self.buf.len())
+        } else {
+            self.buf.len()
+        };
+        let buf = &mut self.buf[..size];
+        let n = retry_interrupted(|| r.read(buf))?;
         self.pos += n as u64;
         Ok(&self.buf[..n])
     }
@@ -42,103 +176,386 @@ impl<R: SeekableRead> Proxy<R> {
         self.pos = self.r.seek(pos)?;
         Ok(self.pos)
     }
+
+    fn set_headers_only(&mut self, headers_only: bool) {
+        self.headers_only = headers_only;
+    }
+}
+
+/// Memory lends bytes in memory as a single block.
+pub struct Memory<T: AsRef<[u8]>> {
+    data: T,
+    pos: usize,
+}
+
+impl<T: AsRef<[u8]>> BlockSource for Memory<T> {
+    fn read_block(&mut self) -> Result<&[u8]> {
+        let data = self.data.as_ref();
+        let begin = min(self.pos, data.len());
+        self.pos = data.len();
+        Ok(&data[begin..])
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.data.as_ref().len())?;
+        Ok(self.pos as u64)
+    }
+}
+
+/// Pages lends cached pages one by one.
+pub struct Pages {
+    page: RefPage,
+    size: usize,
+    pos: usize,
+}
+
+impl BlockSource for Pages {
+    fn read_block(&mut self) -> Result<&[u8]> {
+        if self.pos >= self.size {
+            return Ok(&[]);
+        }
+        let rest = self.size - self.pos;
+        match self.page.get_slices(self.pos).next() {
+            Some(slice) => {
+                let l = min(slice.len(), rest);
+                self.pos += l;
+                Ok(&slice[..l])
+            }
+            None => Ok(&[]),
+        }
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.size)?;
+        Ok(self.pos as u64)
+    }
 }
 
-pub struct Archive<R: SeekableRead> {
+pub struct Archive<S: BlockSource> {
     raw: *mut ffi::Struct_archive,
     eof: bool,
-    _proxy: Box<Proxy<R>>,
+    // the number of headers read.
+    index: usize,
+    options: Options,
+    // boxed to pass a stable pointer to callbacks.
+    source: Box<S>,
+    passphrase: Box<Passphrase>,
+    // the archive file, which warnings are told of.
+    name: PathBuf,
 }
 
 unsafe fn set_error(raw: *mut ffi::Struct_archive, e: Error) {
-    let desc = CString::new(e.description()).unwrap();
+    // the message is a format string.
+    let desc = e.to_string().replace('\0', "").replace('%', "%%");
+    let desc = CString::new(desc).unwrap();
     ffi::archive_set_error(raw, e.raw_os_error().unwrap_or(libc::EIO), desc.as_ptr());
 }
 
+// calls interrupted by signals are repeated. other transient errors are retried by the retry
+// policy of the options, under the source.
+fn retry_interrupted<T, F: FnMut() -> Result<T>>(mut f: F) -> Result<T> {
+    loop {
+        match f() {
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            r => return r,
+        }
+    }
+}
+
 unsafe fn error_string(raw: *mut ffi::Struct_archive) -> String {
     let p = ffi::archive_error_string(raw);
     if p.is_null() {
         "unknown".to_string()
     } else {
-        CStr::from_ptr(p).to_str().unwrap().to_string()
+        CStr::from_ptr(p).to_string_lossy().into_owned()
     }
 }
 
-unsafe extern "C" fn read_callback<R: SeekableRead>(
+// classifies the last error of libarchive.
+// most of them are told only by the message.
+unsafe fn archive_error(raw: *mut ffi::Struct_archive, context: &str) -> Error {
+    let message = error_string(raw);
+    let lower = message.to_lowercase();
+    let errno = ffi::archive_errno(raw);
+    let s = format!("{}: {}", context, message);
+    let e = if lower.contains("passphrase required") {
+        error::Error::PassphraseRequired(s)
+    } else if lower.contains("passphrase") {
+        error::Error::WrongPassphrase(s)
+    } else if lower.contains("unrecognized archive format")
+        || lower.contains("unsupported")
+        || lower.contains("unavailable")
+    {
+        error::Error::UnsupportedFormat(s)
+    } else if lower.contains("truncated") || lower.contains("premature end") {
+        error::Error::Truncated(s)
+    } else if errno > 0 && errno != libc::EILSEQ && errno != libc::EINVAL {
+        // a system error, EILSEQ and EINVAL are used for bad data and misuse.
+        let e = Error::from_raw_os_error(errno);
+        error::Error::Io(Error::new(e.kind(), format!("{} ({})", s, e)))
+    } else {
+        error::Error::Io(Error::new(ErrorKind::Other, s))
+    };
+    e.into()
+}
+
+// holds a passphrase from the callback until libarchive copies it.
+struct Passphrase {
+    callback: Option<Rc<dyn Fn() -> Option<String>>>,
+    last: Option<CString>,
+}
+
+// runs the body of a callback, which must not unwind into libarchive, as that aborts the
+// process. a panic is an error of the archive then, which fails the operation.
+fn guarded<T, F: FnOnce() -> Result<T>>(f: F) -> Result<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = isolate::panic_message(&*payload);
+            error!(panic = %message, "panicked in a callback of libarchive");
+            let message = format!("panicked reading the archive: {}", message);
+            Err(Error::new(ErrorKind::Other, message))
+        }
+    }
+}
+
+// no passphrase is given if the callback panics.
+unsafe extern "C" fn passphrase_callback(
+    _raw: *mut ffi::Struct_archive,
+    client_data: *mut libc::c_void,
+) -> *const libc::c_char {
+    let last = guarded(|| {
+        let passphrase = (client_data as *mut Passphrase).as_mut().unwrap();
+        passphrase.last = match passphrase.callback {
+            Some(ref f) => f().and_then(|p| CString::new(p).ok()),
+            None => None,
+        };
+        Ok(passphrase.last.as_ref().map_or(ptr::null(), |p| p.as_ptr()))
+    });
+    last.unwrap_or(ptr::null())
+}
+
+unsafe extern "C" fn read_callback<S: BlockSource>(
     raw: *mut ffi::Struct_archive,
     client_data: *mut libc::c_void,
     buffer: *mut *const libc::c_void,
 ) -> libc::ssize_t {
-    let proxy = (client_data as *mut Proxy<R>).as_mut().unwrap();
-    let err;
-    match proxy.read() {
-        Ok(data) => {
-            *buffer = data.as_ptr() as *const libc::c_void;
-            return data.len() as libc::ssize_t;
-        }
-        Err(e) => err = e,
-    };
-    set_error(raw, err);
-    -1
+    let block = guarded(|| {
+        let source = (client_data as *mut S).as_mut().unwrap();
+        let data = source.read_block()?;
+        Ok((data.as_ptr() as *const libc::c_void, data.len()))
+    });
+    match block {
+        Ok((data, len)) => {
+            *buffer = data;
+            len as libc::ssize_t
+        }
+        Err(e) => {
+            set_error(raw, e);
+            ffi::ARCHIVE_FATAL as libc::ssize_t
+        }
+    }
 }
 
-unsafe extern "C" fn seek_callback<R: SeekableRead>(
+unsafe extern "C" fn skip_callback<S: BlockSource>(
+    raw: *mut ffi::Struct_archive,
+    client_data: *mut libc::c_void,
+    request: i64,
+) -> i64 {
+    // libarchive never skips backward.
+    if request <= 0 {
+        return 0;
+    }
+    let skipped = guarded(|| {
+        let source = (client_data as *mut S).as_mut().unwrap();
+        retry_interrupted(|| source.skip(request as u64))
+    });
+    match skipped {
+        Ok(n) => n as i64,
+        Err(e) => {
+            set_error(raw, e);
+            ffi::ARCHIVE_FATAL as i64
+        }
+    }
+}
+
+unsafe extern "C" fn seek_callback<S: BlockSource>(
     raw: *mut ffi::Struct_archive,
     client_data: *mut libc::c_void,
     offset: i64,
     whence: libc::c_int,
 ) -> i64 {
-    let proxy = (client_data as *mut Proxy<R>).as_mut().unwrap();
     let pos = match whence {
         libc::SEEK_SET => SeekFrom::Start(offset as u64),
         libc::SEEK_CUR => SeekFrom::Current(offset),
         libc::SEEK_END => SeekFrom::End(offset),
-        _ => unreachable!(),
+        _ => {
+            set_error(raw, Error::from_raw_os_error(libc::EINVAL));
+            return ffi::ARCHIVE_FATAL as i64;
+        }
     };
-    match proxy.seek(pos) {
+    let seeked = guarded(|| {
+        let source = (client_data as *mut S).as_mut().unwrap();
+        retry_interrupted(|| source.seek(pos))
+    });
+    match seeked {
         Ok(n) => n as i64,
         Err(e) => {
-            // correct?
             set_error(raw, e);
-            -1
+            ffi::ARCHIVE_FATAL as i64
         }
     }
 }
 
-impl<R: SeekableRead> Archive<R> {
-    pub fn new(r: R) -> Self {
+impl<R: SeekableRead> Archive<Proxy<R>> {
+    pub fn new(r: R, options: &Options) -> Result<Self> {
+        Archive::from_source(Proxy::new(r, options.block_size()), options)
+    }
+}
+
+impl<T: AsRef<[u8]>> Archive<Memory<T>> {
+    /// opens an archive in memory.
+    pub fn from_memory(data: T, options: &Options) -> Result<Self> {
+        Archive::from_source(Memory { data: data, pos: 0 }, options)
+    }
+}
+
+impl Archive<Pages> {
+    /// opens an archive of size bytes in cached pages.
+    #[allow(dead_code)]
+    pub fn from_pages(page: RefPage, size: usize, options: &Options) -> Result<Self> {
+        let source = Pages {
+            page: page,
+            size: size,
+            pos: 0,
+        };
+        Archive::from_source(source, options)
+    }
+}
+
+impl<S: BlockSource> Archive<S> {
+    fn from_source(source: S, options: &Options) -> Result<Self> {
+        // raw and source are released by drop on failures.
+        let mut archive = Archive {
+            raw: ptr::null_mut(),
+            eof: false,
+            index: 0,
+            options: options.clone(),
+            source: Box::new(source),
+            passphrase: Box::new(Passphrase {
+                callback: options.passphrase_callback(),
+                last: None,
+            }),
+            name: PathBuf::new(),
+        };
+        archive.open()?;
+        Ok(archive)
+    }
+
+    pub fn named(mut self, name: &Path) -> Self {
+        self.name = name.to_path_buf();
+        self
+    }
+
+    // logs the last warning of libarchive, unless the archive repeats it.
+    fn warn(&self, context: &str) {
+        let message = format!("{}: {}", context, unsafe { error_string(self.raw) });
+        match self.options.warnings().admit(&self.name, &message) {
+            Some(0) => warn!(archive = %self.name.display(), "{}", message),
+            Some(n) => warn!(
+                archive = %self.name.display(),
+                "{} (repeated {} times since)",
+                message,
+                n
+            ),
+            None => {}
+        }
+    }
+
+    // (re)opens the archive from the current position of the source.
+    fn open(&mut self) -> Result<()> {
         unsafe {
-            let raw = ffi::archive_read_new();
+            if !self.raw.is_null() {
+                ffi::archive_read_free(self.raw);
+            }
+            self.eof = false;
+            self.index = 0;
+            self.raw = ffi::archive_read_new();
+            let raw = self.raw;
             if raw.is_null() {
-                panic!("oom");
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "archive_read_new: out of memory",
+                ));
+            }
+            enable_support(&self.options, raw)?;
+            if !self.options.read_options().is_empty() {
+                let opts = CString::new(self.options.read_options().join(","))
+                    .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+                // options are applied to the enabled formats only.
+                let ret = ffi::archive_read_set_options(raw, opts.as_ptr());
+                if ret != ffi::ARCHIVE_OK && ret != ffi::ARCHIVE_WARN {
+                    return Err(archive_error(raw, "archive_read_set_options"));
+                }
             }
-            if ffi::archive_read_support_format_all(raw) != ffi::ARCHIVE_OK {
-                panic!("not support format");
+            for p in self.options.passphrases().iter() {
+                let p =
+                    CString::new(p.as_str()).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+                if ffi::archive_read_add_passphrase(raw, p.as_ptr()) != ffi::ARCHIVE_OK {
+                    return Err(archive_error(raw, "archive_read_add_passphrase"));
+                }
             }
-            if ffi::archive_read_support_filter_all(raw) != ffi::ARCHIVE_OK {
-                panic!("not support filter");
+            if self.passphrase.callback.is_some() {
+                let passphrase: *mut Passphrase = &mut *self.passphrase;
+                if ffi::archive_read_set_passphrase_callback(
+                    raw,
+                    passphrase as *mut libc::c_void,
+                    Some(passphrase_callback),
+                ) != ffi::ARCHIVE_OK
+                {
+                    return Err(archive_error(raw, "archive_read_set_passphrase_callback"));
+                }
             }
-            if ffi::archive_read_set_seek_callback(raw, Some(seek_callback::<R>)) != ffi::ARCHIVE_OK
+            if ffi::archive_read_set_seek_callback(raw, Some(seek_callback::<S>)) != ffi::ARCHIVE_OK
             {
-                panic!("failed to set seek");
+                return Err(archive_error(raw, "archive_read_set_seek_callback"));
             }
-            let proxy = Box::into_raw(Box::new(Proxy::new(r)));
+            if ffi::archive_read_set_skip_callback(raw, Some(skip_callback::<S>)) != ffi::ARCHIVE_OK
+            {
+                return Err(archive_error(raw, "archive_read_set_skip_callback"));
+            }
+            let source: *mut S = &mut *self.source;
             if ffi::archive_read_open(
                 raw,
-                proxy as *mut libc::c_void,
+                source as *mut libc::c_void,
                 None,
-                Some(read_callback::<R>),
+                Some(read_callback::<S>),
                 None,
             ) != ffi::ARCHIVE_OK
             {
-                panic!("failed to open");
+                return Err(archive_error(raw, "archive_read_open"));
             }
-            Archive {
-                raw: raw,
-                eof: false,
-                _proxy: Box::from_raw(proxy),
+            Ok(())
+        }
+    }
+
+    // reopens the archive, and moves to the index-th entry.
+    fn reopen_at(&mut self, index: usize) -> Result<()> {
+        self.source.seek(SeekFrom::Start(0))?;
+        self.open()?;
+        while self.index < index {
+            match self.next_entry_raw() {
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        "entry is not found in reopened archive",
+                    ))
+                }
             }
         }
+        Ok(())
     }
 
     fn next_entry_raw(&mut self) -> Option<Result<Entry>> {
@@ -151,9 +568,7 @@ impl<R: SeekableRead> Archive<R> {
             match unsafe { ffi::archive_read_next_header(self.raw, &mut entry) } {
                 ffi::ARCHIVE_OK => break,
                 ffi::ARCHIVE_WARN => {
-                    warn!("archive_read_next_header: {}", unsafe {
-                        error_string(self.raw)
-                    });
+                    self.warn("archive_read_next_header");
                     break;
                 }
                 ffi::ARCHIVE_EOF => {
@@ -162,34 +577,43 @@ impl<R: SeekableRead> Archive<R> {
                 }
                 ffi::ARCHIVE_RETRY => {
                     // failed but retryable.
-                    warn!("archive_read_next_header: {}, retry.", unsafe {
-                        error_string(self.raw)
-                    });
+                    self.warn("archive_read_next_header, retry");
                     continue;
                 }
-                ffi::ARCHIVE_FATAL => {
-                    return Some(Err(Error::new(ErrorKind::Other, unsafe {
-                        error_string(self.raw)
-                    })));
+                ffi::ARCHIVE_FATAL | ffi::ARCHIVE_FAILED => {
+                    return Some(Err(unsafe {
+                        archive_error(self.raw, "archive_read_next_header")
+                    }));
+                }
+                n => {
+                    return Some(Err(Error::new(
+                        ErrorKind::Other,
+                        format!("unknown error {} from libarchive", n),
+                    )));
                 }
-                _ => unreachable!(),
             }
         }
+        self.index += 1;
         Some(Ok(Entry::new(entry)))
     }
 
-    pub fn next_entry<'a>(&'a mut self) -> Option<Result<RefEntry<'a, R>>> {
-        self.next_entry_raw().map(|r| r.map(|e| RefEntry::new(e)))
+    // iterates metadata of the rest of entries.
+    pub fn entries(&mut self) -> Entries<'_, S> {
+        self.source.set_headers_only(true);
+        Entries { a: self }
     }
 
-    pub fn find_open<P>(mut self, p: P) -> Option<Result<Reader<R>>>
+    pub fn find_open<P>(mut self, p: P) -> Option<Result<Reader<S>>>
     where
         P: Fn(&Entry) -> bool,
     {
+        let size;
+        self.source.set_headers_only(true);
         loop {
             match self.next_entry_raw() {
                 Some(Ok(e)) => {
                     if p(&e) {
+                        size = e.size_if_set();
                         break;
                     }
                 }
@@ -197,44 +621,110 @@ impl<R: SeekableRead> Archive<R> {
                 None => return None,
             }
         }
-        Some(Ok(Reader::new(self)))
+        self.source.set_headers_only(false);
+        Some(Ok(Reader::new(self, size)))
     }
 }
 
-impl<R: SeekableRead> Drop for Archive<R> {
+impl<S: BlockSource + 'static> ArchiveBackend for Archive<S> {
+    fn entries(&mut self) -> Box<dyn Iterator<Item = Result<Metadata>> + '_> {
+        Box::new(Archive::entries(self))
+    }
+
+    fn open_entry(self: Box<Self>, path: &Path) -> Option<Result<Box<dyn SeekableRead>>> {
+        self.find_open(|e| e.pathname() == path)
+            .map(|r| r.map(|r| Box::new(r) as Box<dyn SeekableRead>))
+    }
+}
+
+impl<S: BlockSource> Drop for Archive<S> {
     fn drop(&mut self) {
-        unsafe { ffi::archive_read_free(self.raw) };
+        if !self.raw.is_null() {
+            unsafe { ffi::archive_read_free(self.raw) };
+        }
     }
 }
 
-pub struct Reader<R: SeekableRead> {
-    a: Archive<R>,
+pub struct Reader<S: BlockSource> {
+    a: Archive<S>,
+    // the index of the entry in the archive.
+    index: usize,
+    size: Option<u64>,
     buf: *const libc::c_void,
-    read_pos: usize,
+    // positions are 64 bits even on 32-bit targets, for entries over 4 GiB.
+    read_pos: u64,
     buf_size: libc::size_t,
-    offset: libc::off_t,
+    // la_int64_t of libarchive, which off_t is not on 32-bit targets.
+    offset: i64,
+    // the end of the previous block, data before this is gone.
+    // between this and offset is a hole of a sparse file.
+    hole_start: u64,
     eof: bool,
 }
 
-impl<R: SeekableRead> Reader<R> {
-    fn new(a: Archive<R>) -> Reader<R> {
+impl<S: BlockSource> Reader<S> {
+    fn new(a: Archive<S>, size: Option<u64>) -> Reader<S> {
         Reader {
+            index: a.index,
             a: a,
+            size: size,
             buf: ptr::null(),
             read_pos: 0,
             buf_size: 0,
             offset: 0,
+            hole_start: 0,
             eof: false,
         }
     }
 
+    // data can not be read backward, so read the entry from the beginning.
+    fn rewind(&mut self) -> Result<()> {
+        self.a.reopen_at(self.index)?;
+        self.buf = ptr::null();
+        self.buf_size = 0;
+        self.offset = 0;
+        self.hole_start = 0;
+        self.eof = false;
+        Ok(())
+    }
+
+    fn set_eof(&mut self) {
+        self.eof = true;
+        self.buf = ptr::null();
+        self.buf_size = 0;
+        // the tail of a sparse file may be a hole.
+        let end = max(self.hole_start, self.size.unwrap_or(0));
+        self.hole_start = end;
+        self.offset = end as i64;
+    }
+
+    // skips the rest of the entry.
+    fn skip(&mut self) -> Result<()> {
+        if self.eof {
+            return Ok(());
+        }
+        match unsafe { ffi::archive_read_data_skip(self.a.raw) } {
+            ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => {
+                self.hole_start = self.block_end();
+                self.set_eof();
+                Ok(())
+            }
+            _ => Err(unsafe { archive_error(self.a.raw, "archive_read_data_skip") }),
+        }
+    }
+
+    // the position after the current block.
+    fn block_end(&self) -> u64 {
+        self.offset as u64 + self.buf_size as u64
+    }
+
     fn fill_gap(&mut self, buf: &mut [u8]) -> usize {
-        if self.read_pos < self.offset as usize {
-            let l = min(buf.len(), (self.offset as usize) - self.read_pos);
+        if self.read_pos < self.offset as u64 {
+            let l = min(buf.len() as u64, self.offset as u64 - self.read_pos) as usize;
             for x in &mut buf[..l] {
                 *x = 0;
             }
-            self.read_pos += l;
+            self.read_pos += l as u64;
             return l;
         }
         0
@@ -245,7 +735,8 @@ impl<R: SeekableRead> Reader<R> {
             return Ok(());
         }
 
-        while self.offset as usize + self.buf_size as usize <= self.read_pos {
+        while self.block_end() <= self.read_pos {
+            self.hole_start = self.block_end();
             match unsafe {
                 ffi::archive_read_data_block(
                     self.a.raw,
@@ -256,26 +747,20 @@ impl<R: SeekableRead> Reader<R> {
             } {
                 ffi::ARCHIVE_OK => continue,
                 ffi::ARCHIVE_WARN => {
-                    warn!("archive_read_data_block: {}", unsafe {
-                        error_string(self.a.raw)
-                    });
+                    self.a.warn("archive_read_data_block");
                     continue;
                 }
                 ffi::ARCHIVE_EOF => {
-                    self.eof = true;
+                    self.set_eof();
                     return Ok(());
                 }
                 ffi::ARCHIVE_RETRY => {
                     // failed but retryable.
-                    warn!("archive_read_data_block: {}, retry", unsafe {
-                        error_string(self.a.raw)
-                    });
+                    self.a.warn("archive_read_data_block, retry");
                     continue;
                 }
-                ffi::ARCHIVE_FATAL => {
-                    return Err(Error::new(ErrorKind::Other, unsafe {
-                        error_string(self.a.raw)
-                    }));
+                ffi::ARCHIVE_FATAL | ffi::ARCHIVE_FAILED => {
+                    return Err(unsafe { archive_error(self.a.raw, "archive_read_data_block") });
                 }
                 n if n < 0 => {
                     return Err(Error::new(
@@ -290,32 +775,73 @@ impl<R: SeekableRead> Reader<R> {
     }
 }
 
-impl<R: SeekableRead> Read for Reader<R> {
+impl<S: BlockSource> Read for Reader<S> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         self.read_data_block()?;
         let n = self.fill_gap(buf);
         if n > 0 {
             return Ok(n);
         }
-        let begin = self.read_pos - self.offset as usize;
+        let begin = self.read_pos - self.offset as u64;
+        if begin >= self.buf_size as u64 {
+            // eof
+            return Ok(0);
+        }
+        // in the block, which is in memory.
+        let begin = begin as usize;
         let l = min(buf.len(), self.buf_size - begin);
         unsafe {
             let p = (self.buf as *const u8).offset(begin as isize);
             ptr::copy_nonoverlapping(p, buf.as_mut_ptr(), l);
         }
-        self.read_pos += l;
+        self.read_pos += l as u64;
         Ok(l)
     }
 }
 
-impl<R: SeekableRead> Seek for Reader<R> {
+impl<S: BlockSource> Seek for Reader<S> {
+    // forward seeks are done by consuming data blocks on the next read.
     fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        match pos {
-            SeekFrom::Start(n) => self.read_pos = n as usize,
-            SeekFrom::End(_) => unimplemented!(),
-            SeekFrom::Current(n) => self.read_pos += n as usize,
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => match self.size {
+                Some(size) => size as i64 + n,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        "seek from end of entry of unknown size",
+                    ))
+                }
+            },
+            SeekFrom::Current(n) => self.read_pos as i64 + n,
+        };
+        if pos < 0 {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
         }
-        Ok(self.read_pos as u64)
+        let pos = pos as u64;
+        if pos < self.hole_start {
+            self.rewind()?;
+        }
+        if self.size.map_or(false, |size| pos >= size) {
+            self.skip()?;
+        }
+        self.read_pos = pos;
+        Ok(pos)
+    }
+}
+
+// members are read in order by libarchive, so reads at offsets seek.
+impl<S: BlockSource> SeekableRead for Reader<S> {}
+
+pub struct Entries<'a, S: BlockSource> {
+    a: &'a mut Archive<S>,
+}
+
+impl<'a, S: BlockSource> Iterator for Entries<'a, S> {
+    type Item = Result<Metadata>;
+
+    fn next(&mut self) -> Option<Result<Metadata>> {
+        self.a.next_entry_raw().map(|r| r.map(|e| e.metadata()))
     }
 }
 
@@ -328,42 +854,234 @@ impl Entry {
         Entry { entry: entry }
     }
 
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            path: self.pathname(),
+            size: self.size_if_set().map_or(-1, |size| size as i64),
+            filetype: self.filetype(),
+            perm: self.perm(),
+            owner: self.owner(),
+            atime: self.atime(),
+            mtime: self.mtime(),
+            ctime: self.ctime(),
+            birthtime: self.birthtime(),
+            symlink: self.symlink(),
+            encrypted: self.is_encrypted(),
+        }
+    }
+
+    // the name is kept in bytes, names not in utf-8 can be opened too.
+    // it is null if it can not be converted to the locale, then utf-8 is tried.
     pub fn pathname(&self) -> PathBuf {
-        let c_str = unsafe { CStr::from_ptr(ffi::archive_entry_pathname(self.entry)) };
-        PathBuf::from(c_str.to_string_lossy().as_ref())
+        unsafe {
+            self.path(ffi::archive_entry_pathname(self.entry))
+                .or_else(|| self.path(ffi::archive_entry_pathname_utf8(self.entry)))
+                .unwrap_or_default()
+        }
     }
 
-    pub fn size(&self) -> i64 {
-        unsafe { ffi::archive_entry_size(self.entry) }
+    // some formats do not record the size in headers.
+    pub fn size_if_set(&self) -> Option<u64> {
+        unsafe {
+            if ffi::archive_entry_size_is_set(self.entry) == 0 {
+                None
+            } else {
+                Some(ffi::archive_entry_size(self.entry) as u64)
+            }
+        }
     }
 
     pub fn filetype(&self) -> libc::mode_t {
         unsafe { ffi::archive_entry_filetype(self.entry) }
     }
-}
 
-pub struct RefEntry<'a, R: SeekableRead> {
-    e: Entry,
-    _m: marker::PhantomData<&'a R>,
-}
+    pub fn perm(&self) -> libc::mode_t {
+        unsafe { ffi::archive_entry_perm(self.entry) }
+    }
 
-impl<'a, R: SeekableRead> RefEntry<'a, R> {
-    fn new(e: Entry) -> RefEntry<'a, R> {
-        RefEntry {
-            e: e,
-            _m: marker::PhantomData,
+    pub fn uid(&self) -> i64 {
+        unsafe { ffi::archive_entry_uid(self.entry) }
+    }
+
+    pub fn gid(&self) -> i64 {
+        unsafe { ffi::archive_entry_gid(self.entry) }
+    }
+
+    // formats which record no owners, like zip, leave ids zero and names unset, unlike root.
+    pub fn owner(&self) -> Option<(u32, u32)> {
+        let (uid, gid) = (self.uid(), self.gid());
+        if uid == 0 && gid == 0 && self.uname().is_none() {
+            None
+        } else {
+            Some((uid as u32, gid as u32))
         }
     }
 
-    pub fn pathname(&self) -> PathBuf {
-        self.e.pathname()
+    fn string(&self, p: *const libc::c_char) -> Option<String> {
+        if p.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(p) }.to_string_lossy().into_owned())
+        }
+    }
+
+    fn path(&self, p: *const libc::c_char) -> Option<PathBuf> {
+        if p.is_null() {
+            None
+        } else {
+            let bytes = unsafe { CStr::from_ptr(p) }.to_bytes();
+            Some(PathBuf::from(OsStr::from_bytes(bytes)))
+        }
     }
 
-    pub fn size(&self) -> i64 {
-        self.e.size()
+    pub fn uname(&self) -> Option<String> {
+        self.string(unsafe { ffi::archive_entry_uname(self.entry) })
     }
 
-    pub fn filetype(&self) -> libc::mode_t {
-        self.e.filetype()
+    #[allow(dead_code)]
+    pub fn gname(&self) -> Option<String> {
+        self.string(unsafe { ffi::archive_entry_gname(self.entry) })
+    }
+
+    // target of the symbolic link.
+    pub fn symlink(&self) -> Option<PathBuf> {
+        self.path(unsafe { ffi::archive_entry_symlink(self.entry) })
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        unsafe { ffi::archive_entry_is_encrypted(self.entry) != 0 }
+    }
+
+    fn time(
+        &self,
+        is_set: unsafe extern "C" fn(*mut ffi::Struct_archive_entry) -> libc::c_int,
+        sec: unsafe extern "C" fn(*mut ffi::Struct_archive_entry) -> libc::time_t,
+        nsec: unsafe extern "C" fn(*mut ffi::Struct_archive_entry) -> libc::c_long,
+    ) -> Option<Timespec> {
+        unsafe {
+            if is_set(self.entry) == 0 {
+                return None;
+            }
+            Some(Timespec {
+                sec: sec(self.entry) as i64,
+                nsec: nsec(self.entry) as i32,
+            })
+        }
+    }
+
+    pub fn atime(&self) -> Option<Timespec> {
+        self.time(
+            ffi::archive_entry_atime_is_set,
+            ffi::archive_entry_atime,
+            ffi::archive_entry_atime_nsec,
+        )
     }
+
+    pub fn mtime(&self) -> Option<Timespec> {
+        self.time(
+            ffi::archive_entry_mtime_is_set,
+            ffi::archive_entry_mtime,
+            ffi::archive_entry_mtime_nsec,
+        )
+    }
+
+    pub fn ctime(&self) -> Option<Timespec> {
+        self.time(
+            ffi::archive_entry_ctime_is_set,
+            ffi::archive_entry_ctime,
+            ffi::archive_entry_ctime_nsec,
+        )
+    }
+
+    pub fn birthtime(&self) -> Option<Timespec> {
+        self.time(
+            ffi::archive_entry_birthtime_is_set,
+            ffi::archive_entry_birthtime,
+            ffi::archive_entry_birthtime_nsec,
+        )
+    }
+}
+
+#[test]
+fn test_retry_interrupted() {
+    let mut n = 0;
+    let r = retry_interrupted(|| {
+        n += 1;
+        if n < 3 {
+            Err(Error::from(ErrorKind::Interrupted))
+        } else {
+            Ok(n)
+        }
+    });
+    assert_eq!(r.unwrap(), 3);
+
+    // left to the retry policy.
+    n = 0;
+    let r: Result<()> = retry_interrupted(|| {
+        n += 1;
+        Err(Error::from(ErrorKind::TimedOut))
+    });
+    assert!(r.is_err());
+    assert_eq!(n, 1);
+}
+
+#[test]
+fn test_header_scan() {
+    use std::cell::Cell;
+    use std::io::Cursor;
+
+    // counts bytes read from the inner reader.
+    struct Counting {
+        inner: Cursor<Vec<u8>>,
+        read: Rc<Cell<u64>>,
+    }
+    impl Read for Counting {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read.set(self.read.get() + n as u64);
+            Ok(n)
+        }
+    }
+    impl Seek for Counting {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+    impl SeekableRead for Counting {}
+
+    // 16 members of 4 MiB.
+    let data = vec![0x5a; 4 << 20];
+    let names: Vec<_> = (0..16).map(|i| format!("member{}", i)).collect();
+    let files: Vec<_> = names.iter().map(|n| (n.as_str(), &data[..])).collect();
+    let tar = crate::testing::tar_bytes(&files);
+    let size = tar.len() as u64;
+    let read = Rc::new(Cell::new(0));
+    let r = Counting {
+        inner: Cursor::new(tar),
+        read: read.clone(),
+    };
+    let mut archive = Archive::new(r, &Options::default()).unwrap();
+    assert_eq!(archive.entries().count(), 16);
+    // headers are read without the data, which is skipped.
+    assert!(read.get() < size / 16, "{} of {}", read.get(), size);
+}
+
+#[test]
+fn test_panic_in_callback() {
+    struct Panicking;
+    impl Read for Panicking {
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize> {
+            panic!("broken reader");
+        }
+    }
+    impl Seek for Panicking {
+        fn seek(&mut self, _pos: SeekFrom) -> Result<u64> {
+            panic!("broken reader");
+        }
+    }
+    impl SeekableRead for Panicking {}
+
+    // fails the archive instead of aborting the process.
+    let e = Archive::new(Panicking, &Options::default()).err().unwrap();
+    assert!(e.to_string().contains("broken reader"), "{}", e);
 }
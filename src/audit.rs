@@ -0,0 +1,62 @@
+use time;
+
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{Result, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// AuditLog records which files are opened by whom, and how many bytes are read.
+pub struct AuditLog {
+    file: RefCell<File>,
+}
+
+impl AuditLog {
+    // appends to the file, which only the owner can read.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AuditLog> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(AuditLog {
+            file: RefCell::new(file),
+        })
+    }
+
+    // writes a line like `2006-01-02T15:04:05Z open uid=1000 pid=42 fh=3 path="/a.zip/b"`.
+    // read is the bytes read by the handle, known when it is closed.
+    pub fn record(&self, event: &str, uid: u32, pid: u32, fh: u64, path: &Path, read: Option<u64>) {
+        let mut line = format!(
+            "{} {} uid={} pid={} fh={} path={:?}",
+            time::now_utc().rfc3339(),
+            event,
+            uid,
+            pid,
+            fh,
+            path
+        );
+        if let Some(read) = read {
+            line += &format!(" read={}", read);
+        }
+        line.push('\n');
+        // a line is written at once, so that lines are not mixed with other writers.
+        if let Err(e) = self.file.borrow_mut().write_all(line.as_bytes()) {
+            error!(error = ?e, "failed to write audit log");
+        }
+    }
+}
+
+#[test]
+fn test_audit_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.log");
+    let audit = AuditLog::open(&path).unwrap();
+    audit.record("open", 1000, 42, 3, Path::new("/a.zip/b c"), None);
+    audit.record("close", 1000, 42, 3, Path::new("/a.zip/b c"), Some(10));
+    let log = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with(" open uid=1000 pid=42 fh=3 path=\"/a.zip/b c\""));
+    assert!(lines[1].ends_with(" close uid=1000 pid=42 fh=3 path=\"/a.zip/b c\" read=10"));
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::io::{Read, Result, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::fs::Entry;
+use crate::stats::Stats;
+use crate::tree::VirtualTree;
+
+// the size of sequential reads, like readahead of the kernel.
+const READ_SIZE: usize = 128 * 1024;
+// the size and the number of random reads of each file, like pages faulted by programs.
+const RANDOM_READ_SIZE: usize = 4096;
+const RANDOM_READS: usize = 16;
+
+// the amount of work of a phase and the time taken.
+struct Phase {
+    name: &'static str,
+    count: u64,
+    unit: &'static str,
+    elapsed: Duration,
+}
+
+/// Report tells the throughput and latencies of each phase of the benchmark.
+pub struct Report {
+    phases: Vec<Phase>,
+    stats: Stats,
+}
+
+// one line per phase, like "cold_read bytes=1048576 secs=0.010 rate=104857600/s",
+// followed by latencies of operations in the format of stats.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for phase in self.phases.iter() {
+            let secs = phase.elapsed.as_secs_f64();
+            let rate = if secs > 0.0 {
+                (phase.count as f64 / secs) as u64
+            } else {
+                0
+            };
+            writeln!(
+                f,
+                "{} {}={} secs={:.3} rate={}/s",
+                phase.name, phase.unit, phase.count, secs, rate
+            )?;
+        }
+        write!(f, "{}", self.stats)
+    }
+}
+
+// a deterministic sequence of offsets, so that runs are compared by the same reads.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+struct Bench<'a> {
+    tree: &'a VirtualTree,
+    stats: Stats,
+    phases: Vec<Phase>,
+}
+
+impl<'a> Bench<'a> {
+    // lists every directory, which reads headers of archives.
+    fn index(&mut self) -> Result<Vec<(PathBuf, u64)>> {
+        let mut files = Vec::new();
+        let start = Instant::now();
+        let mut last = start;
+        let stats = &mut self.stats;
+        self.tree.walk("/", |path, entry| {
+            let now = Instant::now();
+            stats.record("index", now - last);
+            last = now;
+            if let Entry::File(ref f) = *entry {
+                files.push((path.to_path_buf(), f.getattr()?.size));
+            }
+            Ok(())
+        })?;
+        self.phases.push(Phase {
+            name: "index",
+            count: files.len() as u64,
+            unit: "files",
+            elapsed: start.elapsed(),
+        });
+        Ok(files)
+    }
+
+    // reads the files from the start to the end.
+    fn sequential(&mut self, name: &'static str, files: &[(PathBuf, u64)]) -> Result<()> {
+        let mut buf = vec![0; READ_SIZE];
+        let mut bytes = 0;
+        let start = Instant::now();
+        for &(ref path, _) in files.iter() {
+            let mut r = self.tree.open(path)?;
+            loop {
+                let t = Instant::now();
+                let n = r.read(&mut buf)?;
+                self.stats.record(name, t.elapsed());
+                if n == 0 {
+                    break;
+                }
+                bytes += n as u64;
+            }
+        }
+        self.phases.push(Phase {
+            name: name,
+            count: bytes,
+            unit: "bytes",
+            elapsed: start.elapsed(),
+        });
+        Ok(())
+    }
+
+    // reads pieces of the files at random offsets.
+    fn random(&mut self, files: &[(PathBuf, u64)]) -> Result<()> {
+        let mut buf = vec![0; RANDOM_READ_SIZE];
+        let mut offsets = XorShift(0x2545_f491_4f6c_dd1d);
+        let mut bytes = 0;
+        let start = Instant::now();
+        for &(ref path, size) in files.iter().filter(|f| f.1 > 0) {
+            let mut r = self.tree.open(path)?;
+            for _ in 0..RANDOM_READS {
+                let t = Instant::now();
+                r.seek(SeekFrom::Start(offsets.next() % size))?;
+                let n = r.read(&mut buf)?;
+                self.stats.record("random_read", t.elapsed());
+                bytes += n as u64;
+            }
+        }
+        self.phases.push(Phase {
+            name: "random_read",
+            count: bytes,
+            unit: "bytes",
+            elapsed: start.elapsed(),
+        });
+        Ok(())
+    }
+}
+
+/// runs the benchmark on the tree: lists it, reads every file twice, first decoding them
+/// into the cache and then from the cache, and reads pieces of them at random offsets.
+pub fn run(tree: &VirtualTree) -> Result<Report> {
+    let mut bench = Bench {
+        tree: tree,
+        stats: Stats::new(),
+        phases: Vec::new(),
+    };
+    let files = bench.index()?;
+    bench.sequential("cold_read", &files)?;
+    bench.sequential("warm_read", &files)?;
+    bench.random(&files)?;
+    Ok(Report {
+        phases: bench.phases,
+        stats: bench.stats,
+    })
+}
+
+#[test]
+fn test_run() {
+    use crate::fs::ShowFS;
+
+    let tmp = tempfile::tempdir().unwrap();
+    std::fs::create_dir(tmp.path().join("dir")).unwrap();
+    std::fs::write(tmp.path().join("dir/a"), vec![1; 300 * 1024]).unwrap();
+    std::fs::write(tmp.path().join("b"), "").unwrap();
+    let tree = ShowFS::new(tmp.path()).into_tree();
+    let report = run(&tree).unwrap();
+
+    let line = |name: &str| {
+        let report = report.to_string();
+        let prefix = format!("{} ", name);
+        report
+            .lines()
+            .find(|l| l.starts_with(&prefix))
+            .unwrap()
+            .to_string()
+    };
+    assert!(line("index").starts_with("index files=2 "));
+    assert!(line("cold_read").starts_with("cold_read bytes=307200 "));
+    assert!(line("warm_read").starts_with("warm_read bytes=307200 "));
+    assert!(line("random_read").starts_with("random_read bytes="));
+    // 3 reads of a and the ends of both.
+    assert_eq!(report.stats.get("cold_read").unwrap().count(), 5);
+    assert_eq!(report.stats.get("random_read").unwrap().count(), 16);
+    assert_eq!(report.stats.get("index").unwrap().count(), 4);
+}
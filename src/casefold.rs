@@ -0,0 +1,89 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+// the name compared by case insensitive clients.
+fn fold(name: &OsStr) -> OsString {
+    match name.to_str() {
+        Some(s) => OsString::from(s.to_lowercase()),
+        None => OsString::from_vec(name.as_bytes().to_ascii_lowercase()),
+    }
+}
+
+// like "Readme (2).txt".
+pub(crate) fn suffixed(name: &OsStr, n: usize) -> OsString {
+    let bytes = name.as_bytes();
+    // the extension is kept, but of dot files.
+    let dot = match bytes.iter().rposition(|&b| b == b'.') {
+        Some(0) | None => bytes.len(),
+        Some(i) => i,
+    };
+    let mut s = bytes[..dot].to_vec();
+    s.extend_from_slice(format!(" ({})", n).as_bytes());
+    s.extend_from_slice(&bytes[dot..]);
+    OsString::from_vec(s)
+}
+
+// the shown names of the original names, which do not collide by case.
+// the smallest of colliding names is kept, so the result does not depend on the order.
+pub(crate) fn disambiguate(mut names: Vec<OsString>) -> HashMap<OsString, OsString> {
+    names.sort();
+    let folded: HashSet<_> = names.iter().map(|n| fold(n)).collect();
+    let mut taken = HashSet::new();
+    let mut shown = HashMap::new();
+    for name in names.into_iter() {
+        let mut s = name.clone();
+        if !taken.insert(fold(&name)) {
+            for n in 2.. {
+                s = suffixed(&name, n);
+                let f = fold(&s);
+                if !folded.contains(&f) && taken.insert(f) {
+                    break;
+                }
+            }
+        }
+        shown.insert(name, s);
+    }
+    shown
+}
+
+#[test]
+fn test_disambiguate() {
+    let names = vec![
+        "Readme",
+        "README",
+        "readme (2)",
+        "a.TXT",
+        "A.txt",
+        ".x",
+        ".X",
+    ];
+    let shown = disambiguate(names.iter().map(OsString::from).collect());
+    let get = |name: &str| shown[OsStr::new(name)].to_str().unwrap().to_string();
+    assert_eq!(get("README"), "README");
+    assert_eq!(get("Readme"), "Readme (3)");
+    assert_eq!(get("readme (2)"), "readme (2)");
+    assert_eq!(get("A.txt"), "A.txt");
+    assert_eq!(get("a.TXT"), "a (2).TXT");
+    assert_eq!(get(".X"), ".X");
+    assert_eq!(get(".x"), ".x (2)");
+}
+
+#[test]
+fn test_lookup_disambiguated() {
+    use crate::names::{Codec, NameViewer};
+    use crate::testing::{MemTree, Viewed};
+
+    let tree = MemTree::new();
+    tree.file("/README", b"upper").file("/readme", b"lower");
+    let viewer = NameViewer::new(Codec::CaseFold);
+    let viewed = Viewed::new(vec![&viewer]);
+    assert_eq!(
+        viewed.walk(tree.root()),
+        vec!["/", "/README 5", "/readme (2) 5"]
+    );
+    // looked up without listing.
+    assert_eq!(viewed.read(tree.root(), "/readme (2)").unwrap(), b"lower");
+    assert_eq!(viewed.read(tree.root(), "/README").unwrap(), b"upper");
+    assert!(viewed.resolve(tree.root(), "/readme").is_err());
+}
@@ -0,0 +1,166 @@
+use fuse;
+use libc;
+use time;
+
+use self::fuse::{FileAttr, FileType};
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, Error, Result};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::casefold;
+use crate::fs;
+use crate::physical;
+
+/// reads paths, one per line. empty lines and lines starting with # are skipped.
+pub fn read_list<R: BufRead>(r: R) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for line in r.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        paths.push(PathBuf::from(line));
+    }
+    Ok(paths)
+}
+
+/// CollectionDir is a root which is not on the disk, whose entries are the listed files,
+/// typically archives spread across disks. the entries are named after the files, with
+/// suffixes like "a (2).zip" if the names are taken.
+pub struct CollectionDir {
+    // the names and the paths, in the order of the list.
+    entries: Vec<(OsString, PathBuf)>,
+    attr: FileAttr,
+    ctx: Rc<physical::Context>,
+}
+
+impl CollectionDir {
+    pub fn new(paths: &[PathBuf], ctx: Rc<physical::Context>) -> CollectionDir {
+        let mut taken = HashSet::new();
+        let mut entries = Vec::new();
+        for path in paths.iter() {
+            let name = match path.file_name() {
+                Some(name) => name,
+                None => {
+                    warn!(path = %path.display(), "not a file to collect");
+                    continue;
+                }
+            };
+            let mut shown = name.to_os_string();
+            let mut n = 2;
+            while !taken.insert(shown.clone()) {
+                shown = casefold::suffixed(name, n);
+                n += 1;
+            }
+            entries.push((shown, path.clone()));
+        }
+        let now = time::get_time();
+        let attr = FileAttr {
+            ino: 0, // dummy
+            size: 0,
+            blocks: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind: FileType::Directory,
+            perm: 0o555,
+            nlink: 2,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            rdev: 0,
+            flags: 0,
+        };
+        CollectionDir {
+            entries: entries,
+            attr: attr,
+            ctx: ctx,
+        }
+    }
+
+    fn entry(&self, name: &OsStr, path: &Path) -> fs::Entry {
+        let (name, path) = (name.to_os_string(), path.to_path_buf());
+        if path.is_dir() {
+            fs::Entry::Dir(Box::new(physical::Dir::named(path, name, self.ctx.clone())))
+        } else {
+            fs::Entry::File(Box::new(physical::File::named(
+                path,
+                name,
+                self.ctx.clone(),
+            )))
+        }
+    }
+}
+
+impl fs::Dir for CollectionDir {
+    // listed files which are gone are not shown.
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        let entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|&&(_, ref path)| match path.symlink_metadata() {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!(path = %path.display(), error = ?e, "listed file is not found");
+                    false
+                }
+            })
+            .map(|&(ref name, ref path)| Ok(self.entry(name, path)))
+            .collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
+        match self.entries.iter().find(|e| e.0 == name) {
+            Some(&(ref name, ref path)) if path.symlink_metadata().is_ok() => {
+                Ok(self.entry(name, path))
+            }
+            _ => Err(Error::from_raw_os_error(libc::ENOENT)),
+        }
+    }
+
+    fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr)
+    }
+
+    fn name(&self) -> &OsStr {
+        OsStr::new("")
+    }
+}
+
+#[test]
+fn test_collection() {
+    use crate::fs::Dir;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    let tmp = tempfile::tempdir().unwrap();
+    for dir in ["a", "b"].iter() {
+        std::fs::create_dir(tmp.path().join(dir)).unwrap();
+        std::fs::write(tmp.path().join(dir).join("x.zip"), dir).unwrap();
+    }
+    let list = format!(
+        "# comics\n{}\n\n{}\n{}\n",
+        tmp.path().join("a/x.zip").display(),
+        tmp.path().join("b/x.zip").display(),
+        tmp.path().join("gone.zip").display()
+    );
+    let paths = read_list(Cursor::new(list)).unwrap();
+    assert_eq!(paths.len(), 3);
+    let ctx = Rc::new(physical::Context::new(Duration::from_secs(1)));
+    let dir = CollectionDir::new(&paths, ctx);
+    let names: Vec<_> = dir
+        .open()
+        .unwrap()
+        .map(|e| e.unwrap().name().to_os_string())
+        .collect();
+    assert_eq!(names, vec!["x.zip", "x (2).zip"]);
+    match dir.lookup(OsStr::new("x (2).zip")).unwrap() {
+        fs::Entry::File(f) => assert_eq!(f.physical_path(), Some(&*tmp.path().join("b/x.zip"))),
+        fs::Entry::Dir(_) => panic!("not a file"),
+    }
+    assert!(dir.lookup(OsStr::new("gone.zip")).is_err());
+}
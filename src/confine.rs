@@ -0,0 +1,366 @@
+use libc;
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+// x32 calls have the same arch in seccomp, with numbers of this bit set.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// system calls which the mount never needs once it is serving, but attacks would.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_personality,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_quotactl,
+];
+
+/// filters system calls of the thread and threads created by it with seccomp.
+/// if allow is true, only the listed calls are allowed, else the listed ones are denied.
+/// denied calls fail with EPERM.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub(crate) fn seccomp(syscalls: &[libc::c_long], allow: bool) -> Result<()> {
+    fn stmt(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k: k,
+        }
+    }
+    fn jeq(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+            jt: jt,
+            jf: jf,
+            k: k,
+        }
+    }
+    #[cfg(target_arch = "x86_64")]
+    fn jge(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+            jt: jt,
+            jf: jf,
+            k: k,
+        }
+    }
+    let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+    let ret = libc::BPF_RET | libc::BPF_K;
+    let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let (listed, others) = if allow {
+        (libc::SECCOMP_RET_ALLOW, deny)
+    } else {
+        (deny, libc::SECCOMP_RET_ALLOW)
+    };
+    // offsets in struct seccomp_data.
+    let mut filter = vec![
+        stmt(load, 4),
+        jeq(AUDIT_ARCH, 1, 0),
+        stmt(ret, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(load, 0),
+    ];
+    // x32 numbers of the listed calls would pass a denylist, they are denied altogether.
+    #[cfg(target_arch = "x86_64")]
+    filter.extend_from_slice(&[jge(X32_SYSCALL_BIT, 0, 1), stmt(ret, deny)]);
+    for nr in syscalls.iter() {
+        filter.push(jeq(*nr as u32, 0, 1));
+        filter.push(stmt(ret, listed));
+    }
+    filter.push(stmt(ret, others));
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+            || libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const libc::sock_fprog,
+            ) != 0
+        {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub(crate) fn seccomp(_syscalls: &[libc::c_long], _allow: bool) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Other,
+        "seccomp is not supported on this architecture",
+    ))
+}
+
+// accesses of the first ABI of landlock, which are handled, so denied unless allowed by rules.
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_ALL: u64 = (1 << 13) - 1;
+// accesses which apply to files, others apply to directories only.
+const ACCESS_FILE: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE;
+const ACCESS_READ: u64 = ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+const ACCESS_WRITE: u64 =
+    ACCESS_READ | ACCESS_FS_WRITE_FILE | ACCESS_FS_REMOVE_FILE | ACCESS_FS_MAKE_REG;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+// a ruleset handling all accesses, None if the kernel does not have landlock.
+fn landlock_ruleset() -> Result<Option<File>> {
+    let attr = RulesetAttr {
+        handled_access_fs: ACCESS_FS_ALL,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if fd < 0 {
+        let e = Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => Ok(None),
+            _ => Err(e),
+        };
+    }
+    Ok(Some(unsafe { File::from_raw_fd(fd as libc::c_int) }))
+}
+
+fn landlock_restrict(ruleset: &File) -> Result<()> {
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+            || libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) != 0
+        {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// denies the calling thread, and threads created by it, any access to files by paths with
+/// landlock, while opened files can be used still. returns false if the kernel does not have
+/// landlock.
+pub(crate) fn deny_paths() -> Result<bool> {
+    match landlock_ruleset()? {
+        Some(ruleset) => landlock_restrict(&ruleset).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+// allows the access under the path, which is skipped if it does not exist.
+fn landlock_allow(ruleset: &File, path: &Path, access: u64) -> Result<()> {
+    let file = match std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let access = if file.metadata()?.is_dir() {
+        access
+    } else {
+        access & ACCESS_FILE
+    };
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: file.as_raw_fd(),
+    };
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    if r != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Confinement is what the thread serving the mount may access once it is confined:
+/// the paths allowed by landlock, and system calls but ones which only attacks would need.
+/// threads which are running already, like the control socket, are not confined.
+#[derive(Clone, Default)]
+pub struct Confinement {
+    readable: Vec<PathBuf>,
+    writable: Vec<PathBuf>,
+}
+
+impl Confinement {
+    pub fn new() -> Confinement {
+        Confinement::default()
+    }
+
+    // files and directories under the path can be read.
+    pub fn allow_read<P: AsRef<Path>>(&mut self, path: P) {
+        self.readable.push(path.as_ref().to_path_buf());
+    }
+
+    // files under the directory can be read, written, created and removed.
+    pub fn allow_write<P: AsRef<Path>>(&mut self, path: P) {
+        self.writable.push(path.as_ref().to_path_buf());
+    }
+
+    /// confines the calling thread and threads created by it, which is never undone.
+    pub fn apply(&self) -> Result<()> {
+        let ruleset = landlock_ruleset()?
+            .ok_or_else(|| Error::new(ErrorKind::Other, "landlock is not enabled in the kernel"))?;
+        for path in self.readable.iter() {
+            landlock_allow(&ruleset, path, ACCESS_READ)?;
+        }
+        for path in self.writable.iter() {
+            landlock_allow(&ruleset, path, ACCESS_WRITE)?;
+        }
+        landlock_restrict(&ruleset)?;
+        seccomp(DENIED_SYSCALLS, false)
+    }
+}
+
+#[test]
+fn test_confinement() {
+    use std::process::Command;
+    use std::thread;
+
+    let readable = tempfile::tempdir().unwrap();
+    let writable = tempfile::tempdir().unwrap();
+    let other = tempfile::tempdir().unwrap();
+    std::fs::write(readable.path().join("a"), "a").unwrap();
+    std::fs::write(other.path().join("b"), "b").unwrap();
+    let mut confinement = Confinement::new();
+    confinement.allow_read(readable.path());
+    confinement.allow_write(writable.path());
+    confinement.allow_read("/nonexistent");
+    let (r, w, o) = (
+        readable.path().to_path_buf(),
+        writable.path().to_path_buf(),
+        other.path().to_path_buf(),
+    );
+    // confinement applies to the thread only, not to the test runner.
+    let confined = thread::spawn(move || {
+        if let Err(e) = confinement.apply() {
+            // the kernel may not have landlock.
+            eprintln!("not confined: {}", e);
+            return false;
+        }
+        assert_eq!(std::fs::read(r.join("a")).unwrap(), b"a");
+        assert!(std::fs::write(r.join("c"), "c").is_err());
+        std::fs::write(w.join("c"), "c").unwrap();
+        assert!(std::fs::read(o.join("b")).is_err());
+        assert!(Command::new("/bin/true").status().is_err());
+        true
+    })
+    .join()
+    .unwrap();
+    if confined {
+        assert!(writable.path().join("c").exists());
+    }
+    assert_eq!(std::fs::read(other.path().join("b")).unwrap(), b"b");
+}
+
+#[test]
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn test_seccomp() {
+    use std::thread;
+
+    // the filter applies to the thread only, not to the test runner.
+    thread::spawn(|| {
+        seccomp(DENIED_SYSCALLS, false).unwrap();
+        let query = 0xffff_ffff as libc::c_ulong;
+        let personality = unsafe { libc::syscall(libc::SYS_personality, query) };
+        assert_eq!(personality, -1);
+        assert_eq!(Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+        #[cfg(target_arch = "x86_64")]
+        {
+            let x32 = libc::SYS_personality | X32_SYSCALL_BIT as libc::c_long;
+            assert_eq!(unsafe { libc::syscall(x32, query) }, -1);
+            assert_eq!(Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+        }
+        // others are allowed.
+        assert!(unsafe { libc::getpid() } > 0);
+    })
+    .join()
+    .unwrap();
+    assert_ne!(unsafe { libc::personality(0xffff_ffff) }, -1);
+}
+
+#[test]
+fn test_deny_paths() {
+    use std::io::Read;
+    use std::thread;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a");
+    std::fs::write(&path, "a").unwrap();
+    let mut opened = File::open(&path).unwrap();
+    let denied = thread::spawn(move || {
+        if !deny_paths().unwrap() {
+            // the kernel may not have landlock.
+            return false;
+        }
+        assert!(File::open(&path).is_err());
+        let mut s = String::new();
+        opened.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "a");
+        true
+    })
+    .join()
+    .unwrap();
+    if denied {
+        assert!(File::open(tmp.path().join("a")).is_ok());
+    }
+}
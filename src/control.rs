@@ -0,0 +1,742 @@
+use fuse;
+use libc;
+
+use self::fuse::{FileAttr, FileType};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+use std::io::{Cursor, Error, Result};
+use std::iter;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::audit::AuditLog;
+use crate::fs;
+use crate::stats::{Counters, ScrubStats, Stats};
+
+/// the name of the control directory at the mount root.
+pub const DIR_NAME: &str = ".showfs";
+
+/// Cache is a cache which is shown and dropped by the control directory.
+pub trait Cache {
+    fn name(&self) -> &str;
+    fn used_bytes(&self) -> usize;
+    fn max_bytes(&self) -> usize;
+    // frees what is not in use.
+    fn drop_cache(&self);
+    // the names, the cached and the total bytes of files being loaded.
+    fn loads(&self) -> Vec<(String, usize, usize)> {
+        Vec::new()
+    }
+}
+
+/// Scrubber checks what is kept, like entries and contents of archives, against their
+/// sources a step at a time, so that scrubbing does not keep operations waiting.
+pub trait Scrubber {
+    // checks the next of the kept entries, evicting stale or corrupt ones and counting them
+    // in the stats. false once the pass is over, after which the next call starts another.
+    fn scrub_next(&self, stats: &mut ScrubStats) -> bool;
+}
+
+// a step of scrubbing is taken at most this often, along with operations.
+const SCRUB_STEP_INTERVAL: Duration = Duration::from_millis(100);
+
+// passes of the scrubbers, which start the interval after the last one is over.
+struct ScrubSchedule {
+    interval: Duration,
+    due: Instant,
+    // the scrubber taking steps in this pass.
+    current: usize,
+}
+
+/// HandleKind tells what a handle is opened for.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HandleKind {
+    File,
+    Dir,
+}
+
+// an open handle, which may pin the cache.
+struct Handle {
+    kind: HandleKind,
+    ino: u64,
+    path: PathBuf,
+    uid: u32,
+    pid: u32,
+    // the position after the last read.
+    offset: u64,
+    read_bytes: u64,
+}
+
+/// Control is the state of the mount shown in the control directory.
+pub struct Control {
+    stats: Rc<RefCell<Stats>>,
+    config: RefCell<Vec<(String, String)>>,
+    caches: RefCell<Vec<Rc<dyn Cache>>>,
+    handles: RefCell<BTreeMap<u64, Handle>>,
+    log_level: RefCell<Option<Box<dyn Fn(&str) -> Result<()>>>>,
+    audit: Option<AuditLog>,
+    // lowercase extensions to the names of viewers, and ones never viewed.
+    extensions: RefCell<HashMap<String, String>>,
+    blocked_extensions: RefCell<HashSet<String>>,
+    // set by the reindex command or a signal, and taken by the filesystem.
+    reindex: Arc<AtomicBool>,
+    scrubbers: RefCell<Vec<Rc<dyn Scrubber>>>,
+    scrub: RefCell<Option<ScrubSchedule>>,
+}
+
+impl Control {
+    pub fn new(stats: Rc<RefCell<Stats>>) -> Control {
+        Control {
+            stats: stats,
+            config: RefCell::new(Vec::new()),
+            caches: RefCell::new(Vec::new()),
+            handles: RefCell::new(BTreeMap::new()),
+            log_level: RefCell::new(None),
+            audit: None,
+            extensions: RefCell::new(HashMap::new()),
+            blocked_extensions: RefCell::new(HashSet::new()),
+            reindex: Arc::new(AtomicBool::new(false)),
+            scrubbers: RefCell::new(Vec::new()),
+            scrub: RefCell::new(None),
+        }
+    }
+
+    // if set, opens and closes of files are recorded.
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        self.audit = Some(audit);
+    }
+
+    pub fn add_config(&self, key: &str, value: String) {
+        self.config.borrow_mut().push((key.to_string(), value));
+    }
+
+    // the last value added for the key.
+    pub fn config(&self, key: &str) -> Option<String> {
+        let config = self.config.borrow();
+        config
+            .iter()
+            .rev()
+            .find(|e| e.0 == key)
+            .map(|e| e.1.clone())
+    }
+
+    // files of the extension are shown by the viewer of the name.
+    pub fn map_extension(&self, ext: &str, viewer: &str) {
+        self.extensions
+            .borrow_mut()
+            .insert(ext.to_lowercase(), viewer.to_string());
+    }
+
+    // a default of the viewer, which does not override the mapping by users.
+    pub fn map_default_extension(&self, ext: &str, viewer: &str) {
+        self.extensions
+            .borrow_mut()
+            .entry(ext.to_lowercase())
+            .or_insert_with(|| viewer.to_string());
+    }
+
+    // files of the extension are shown as they are by any viewer.
+    pub fn block_extension(&self, ext: &str) {
+        self.blocked_extensions
+            .borrow_mut()
+            .insert(ext.to_lowercase());
+    }
+
+    // the name of the viewer which shows the file of the name. compound extensions like
+    // tar.gz are mapped before the last ones, and blocking either blocks the file.
+    pub fn viewer_for(&self, name: &OsStr) -> Option<String> {
+        let path = Path::new(name);
+        let last = path.extension()?.to_str()?.to_lowercase();
+        let compound = Path::new(path.file_stem()?)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| format!("{}.{}", e.to_lowercase(), last));
+        let exts: Vec<_> = compound.into_iter().chain(Some(last)).collect();
+        let blocked = self.blocked_extensions.borrow();
+        if exts.iter().any(|ext| blocked.contains(ext)) {
+            return None;
+        }
+        let extensions = self.extensions.borrow();
+        exts.iter().find_map(|ext| extensions.get(ext)).cloned()
+    }
+
+    pub fn register_cache(&self, cache: Rc<dyn Cache>) {
+        self.caches.borrow_mut().push(cache);
+    }
+
+    // the bytes used by the registered caches.
+    pub fn caches_used_bytes(&self) -> usize {
+        self.caches.borrow().iter().map(|c| c.used_bytes()).sum()
+    }
+
+    // the bytes which the registered caches can use at most.
+    pub fn caches_max_bytes(&self) -> usize {
+        self.caches.borrow().iter().map(|c| c.max_bytes()).sum()
+    }
+
+    pub fn drop_caches(&self) {
+        for cache in self.caches.borrow().iter() {
+            cache.drop_cache();
+        }
+    }
+
+    // drops the caches and forgets them, so that their memory is released with the mount.
+    pub fn release_caches(&self) {
+        let caches: Vec<_> = self.caches.borrow_mut().drain(..).collect();
+        for cache in caches.iter() {
+            cache.drop_cache();
+        }
+    }
+
+    // the counts are shown in the stats.
+    pub fn register_counters(&self, counters: Arc<dyn Counters>) {
+        self.stats.borrow_mut().add_counters(counters);
+    }
+
+    pub fn register_scrubber(&self, scrubber: Rc<dyn Scrubber>) {
+        self.scrubbers.borrow_mut().push(scrubber);
+    }
+
+    // the registered scrubbers pass over what is kept every interval, starting now.
+    pub fn schedule_scrub(&self, interval: Duration) {
+        self.stats.borrow_mut().scrub_mut();
+        *self.scrub.borrow_mut() = Some(ScrubSchedule {
+            interval: interval,
+            due: Instant::now(),
+            current: 0,
+        });
+    }
+
+    // takes a step of scrubbing if it is due.
+    pub fn scrub_if_due(&self) {
+        let mut schedule = self.scrub.borrow_mut();
+        let schedule = match *schedule {
+            Some(ref mut schedule) => schedule,
+            None => return,
+        };
+        let now = Instant::now();
+        if now < schedule.due {
+            return;
+        }
+        let scrubbers = self.scrubbers.borrow();
+        let mut stats = self.stats.borrow_mut();
+        let scrub = stats.scrub_mut();
+        schedule.due = now + SCRUB_STEP_INTERVAL;
+        match scrubbers.get(schedule.current) {
+            Some(scrubber) => {
+                if !scrubber.scrub_next(scrub) {
+                    schedule.current += 1;
+                }
+            }
+            None => {
+                info!(checked = scrub.checked, "a scrub pass is over");
+                scrub.passes += 1;
+                scrub.checked = 0;
+                schedule.current = 0;
+                schedule.due = now + schedule.interval;
+            }
+        }
+    }
+
+    // the flag of reindex, which other threads may set.
+    pub fn reindex_request(&self) -> Arc<AtomicBool> {
+        self.reindex.clone()
+    }
+
+    // true once after reindex is requested.
+    pub fn take_reindex(&self) -> bool {
+        self.reindex.swap(false, Ordering::SeqCst)
+    }
+
+    // f takes a filter like "debug" or "showfs=trace".
+    pub fn set_log_level_handler<F>(&self, f: F)
+    where
+        F: Fn(&str) -> Result<()> + 'static,
+    {
+        *self.log_level.borrow_mut() = Some(Box::new(f));
+    }
+
+    pub fn open_handle(
+        &self,
+        fh: u64,
+        kind: HandleKind,
+        ino: u64,
+        path: PathBuf,
+        uid: u32,
+        pid: u32,
+    ) {
+        if let (Some(audit), HandleKind::File) = (&self.audit, kind) {
+            audit.record("open", uid, pid, fh, &path, None);
+        }
+        self.handles.borrow_mut().insert(
+            fh,
+            Handle {
+                kind: kind,
+                ino: ino,
+                path: path,
+                uid: uid,
+                pid: pid,
+                offset: 0,
+                read_bytes: 0,
+            },
+        );
+    }
+
+    pub fn read_handle(&self, fh: u64, offset: u64, size: usize) {
+        if let Some(h) = self.handles.borrow_mut().get_mut(&fh) {
+            h.offset = offset + size as u64;
+            h.read_bytes += size as u64;
+        }
+    }
+
+    pub fn close_handle(&self, fh: u64) {
+        let h = match self.handles.borrow_mut().remove(&fh) {
+            Some(h) => h,
+            None => return,
+        };
+        if let (Some(audit), HandleKind::File) = (&self.audit, h.kind) {
+            audit.record("close", h.uid, h.pid, fh, &h.path, Some(h.read_bytes));
+        }
+    }
+
+    // runs commands, one per line.
+    fn command(&self, commands: &str) -> Result<()> {
+        for line in commands.lines() {
+            let args: Vec<_> = line.split_whitespace().collect();
+            match args.as_slice() {
+                [] => {}
+                ["drop_cache"] => {
+                    self.drop_caches();
+                    info!("caches are dropped");
+                }
+                ["reindex"] => {
+                    self.reindex.store(true, Ordering::SeqCst);
+                    info!("reindex is requested");
+                }
+                ["loglevel", level] => match *self.log_level.borrow() {
+                    Some(ref f) => f(level)?,
+                    None => return Err(Error::from_raw_os_error(libc::ENOTSUP)),
+                },
+                _ => {
+                    warn!("unknown command: {}", line);
+                    return Err(Error::from_raw_os_error(libc::EINVAL));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn contents(&self, kind: Kind) -> String {
+        let mut s = String::new();
+        match kind {
+            Kind::Version => writeln!(s, "showfs {}", env!("CARGO_PKG_VERSION")).unwrap(),
+            Kind::Config => {
+                for &(ref key, ref value) in self.config.borrow().iter() {
+                    writeln!(s, "{} = {}", key, value).unwrap();
+                }
+            }
+            Kind::Stats => write!(s, "{}", self.stats.borrow()).unwrap(),
+            Kind::Cache => {
+                for cache in self.caches.borrow().iter() {
+                    writeln!(
+                        s,
+                        "{} used={} max={}",
+                        cache.name(),
+                        cache.used_bytes(),
+                        cache.max_bytes()
+                    )
+                    .unwrap();
+                    for (name, cached, total) in cache.loads() {
+                        writeln!(s, "  loading cached={} total={} {}", cached, total, name)
+                            .unwrap();
+                    }
+                }
+            }
+            Kind::OpenFiles => {
+                writeln!(s, "fh kind ino pid offset read path").unwrap();
+                for (fh, h) in self.handles.borrow().iter() {
+                    let kind = match h.kind {
+                        HandleKind::File => "file",
+                        HandleKind::Dir => "dir",
+                    };
+                    writeln!(
+                        s,
+                        "{} {} {} {} {} {} {}",
+                        fh,
+                        kind,
+                        h.ino,
+                        h.pid,
+                        h.offset,
+                        h.read_bytes,
+                        h.path.display()
+                    )
+                    .unwrap();
+                }
+            }
+            Kind::Control => {}
+        }
+        s
+    }
+}
+
+/// requests reindex by SIGUSR1, like the reindex command.
+/// this must be called before other threads start, so that they block the signal.
+pub fn reindex_on_sigusr1(request: Arc<AtomicBool>) -> Result<()> {
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGUSR1);
+        let ret = libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        if ret != 0 {
+            return Err(Error::from_raw_os_error(ret));
+        }
+        set
+    };
+    thread::Builder::new()
+        .name("sigusr1".to_string())
+        .spawn(move || loop {
+            let mut sig = 0;
+            if unsafe { libc::sigwait(&set, &mut sig) } != 0 {
+                continue;
+            }
+            request.store(true, Ordering::SeqCst);
+            info!("reindex is requested by SIGUSR1");
+        })?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {
+    Version,
+    Config,
+    Stats,
+    Cache,
+    OpenFiles,
+    Control,
+}
+
+impl Kind {
+    const ALL: [Kind; 6] = [
+        Kind::Version,
+        Kind::Config,
+        Kind::Stats,
+        Kind::Cache,
+        Kind::OpenFiles,
+        Kind::Control,
+    ];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Kind::Version => "version",
+            Kind::Config => "config",
+            Kind::Stats => "stats",
+            Kind::Cache => "cache",
+            Kind::OpenFiles => "open_files",
+            Kind::Control => "control",
+        }
+    }
+}
+
+struct File {
+    kind: Kind,
+    attr: FileAttr,
+    control: Rc<Control>,
+}
+
+impl fs::File for File {
+    fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr)
+    }
+
+    // the contents are made at open.
+    fn open(&self) -> Result<Box<dyn fs::SeekableRead>> {
+        Ok(Box::new(Cursor::new(self.control.contents(self.kind))))
+    }
+
+    fn name(&self) -> &OsStr {
+        OsStr::new(self.kind.name())
+    }
+
+    fn direct_io(&self) -> bool {
+        true
+    }
+
+    fn write(&self, data: &[u8]) -> Result<()> {
+        if self.kind != Kind::Control {
+            return Err(Error::from_raw_os_error(libc::EROFS));
+        }
+        match std::str::from_utf8(data) {
+            Ok(s) => self.control.command(s),
+            Err(_) => Err(Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
+
+    // allows `echo drop_cache > control`, which truncates.
+    fn truncate(&self, size: u64) -> Result<()> {
+        if self.kind != Kind::Control {
+            return Err(Error::from_raw_os_error(libc::EROFS));
+        }
+        match size {
+            0 => Ok(()),
+            _ => Err(Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
+}
+
+struct Dir {
+    attr: FileAttr,
+    control: Rc<Control>,
+}
+
+impl Dir {
+    fn file(&self, kind: Kind) -> fs::Entry {
+        let mut attr = self.attr;
+        attr.kind = FileType::RegularFile;
+        attr.perm = if kind == Kind::Control { 0o200 } else { 0o444 };
+        attr.size = 0;
+        attr.blocks = 0;
+        attr.nlink = 1;
+        fs::Entry::File(Box::new(File {
+            kind: kind,
+            attr: attr,
+            control: self.control.clone(),
+        }))
+    }
+}
+
+impl fs::Dir for Dir {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        let entries: Vec<_> = Kind::ALL.iter().map(|k| Ok(self.file(*k))).collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
+        match Kind::ALL.iter().find(|k| OsStr::new(k.name()) == name) {
+            Some(k) => Ok(self.file(*k)),
+            None => Err(Error::from_raw_os_error(libc::ENOENT)),
+        }
+    }
+
+    fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr)
+    }
+
+    fn name(&self) -> &OsStr {
+        OsStr::new(DIR_NAME)
+    }
+}
+
+/// RootDir adds the control directory to the root of the mount.
+pub struct RootDir {
+    inner: Box<dyn fs::Dir>,
+    control: Rc<Control>,
+}
+
+impl RootDir {
+    pub fn new(inner: Box<dyn fs::Dir>, control: Rc<Control>) -> RootDir {
+        RootDir {
+            inner: inner,
+            control: control,
+        }
+    }
+
+    fn control_dir(&self) -> Result<fs::Entry> {
+        let mut attr = self.inner.getattr()?;
+        attr.perm = 0o555;
+        Ok(fs::Entry::Dir(Box::new(Dir {
+            attr: attr,
+            control: self.control.clone(),
+        })))
+    }
+}
+
+impl fs::Dir for RootDir {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        let control_dir = self.control_dir();
+        // the control directory hides an entry of the same name.
+        let entries = self.inner.open()?.filter(|e| match *e {
+            Ok(ref e) => e.name() != DIR_NAME,
+            Err(_) => true,
+        });
+        Ok(Box::new(entries.chain(iter::once(control_dir))))
+    }
+
+    fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
+        if name == DIR_NAME {
+            self.control_dir()
+        } else {
+            self.inner.lookup(name)
+        }
+    }
+
+    fn getattr(&self) -> Result<FileAttr> {
+        self.inner.getattr()
+    }
+
+    fn name(&self) -> &OsStr {
+        self.inner.name()
+    }
+
+    fn origin(&self) -> Option<&Path> {
+        self.inner.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.inner.member()
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.inner.listxattr()
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.inner.getxattr(name)
+    }
+}
+
+#[test]
+fn test_control() {
+    use crate::fs::Dir as FSDir;
+    use std::io::Read;
+    use std::mem::zeroed;
+
+    struct CountCache(RefCell<u32>);
+    impl Cache for CountCache {
+        fn name(&self) -> &str {
+            "count"
+        }
+        fn used_bytes(&self) -> usize {
+            1
+        }
+        fn max_bytes(&self) -> usize {
+            2
+        }
+        fn drop_cache(&self) {
+            *self.0.borrow_mut() += 1;
+        }
+    }
+
+    let control = Rc::new(Control::new(Rc::new(RefCell::new(Stats::new()))));
+    let cache = Rc::new(CountCache(RefCell::new(0)));
+    control.register_cache(cache.clone());
+    control.open_handle(3, HandleKind::File, 10, PathBuf::from("/a.zip"), 1000, 100);
+    control.read_handle(3, 4096, 10);
+    let dir = Dir {
+        attr: unsafe { zeroed::<FileAttr>() },
+        control: control.clone(),
+    };
+    assert_eq!(dir.open().unwrap().count(), Kind::ALL.len());
+
+    let read = |name| match dir.lookup(OsStr::new(name)).unwrap() {
+        fs::Entry::File(f) => {
+            let mut s = String::new();
+            f.open().unwrap().read_to_string(&mut s).unwrap();
+            s
+        }
+        _ => panic!("not a file"),
+    };
+    assert_eq!(read("cache"), "count used=1 max=2\n");
+    assert_eq!(control.caches_max_bytes() - control.caches_used_bytes(), 1);
+    assert_eq!(
+        read("open_files"),
+        "fh kind ino pid offset read path\n3 file 10 100 4106 10 /a.zip\n"
+    );
+
+    let write = |data: &[u8]| match dir.lookup(OsStr::new("control")).unwrap() {
+        fs::Entry::File(f) => f.write(data),
+        _ => panic!("not a file"),
+    };
+    write(b"drop_cache\n").unwrap();
+    assert_eq!(*cache.0.borrow(), 1);
+    assert_eq!(
+        write(b"unknown\n").unwrap_err().raw_os_error(),
+        Some(libc::EINVAL)
+    );
+    assert!(write(b"loglevel debug\n").is_err());
+    control.set_log_level_handler(|level| {
+        assert_eq!(level, "debug");
+        Ok(())
+    });
+    write(b"loglevel debug\n").unwrap();
+    assert!(!control.take_reindex());
+    write(b"reindex\n").unwrap();
+    assert!(control.take_reindex());
+    assert!(!control.take_reindex());
+
+    // released caches are dropped and forgotten.
+    control.release_caches();
+    assert_eq!(*cache.0.borrow(), 2);
+    assert_eq!(Rc::strong_count(&cache), 1);
+    assert_eq!(control.caches_max_bytes(), 0);
+}
+
+#[test]
+fn test_extensions() {
+    let control = Control::new(Rc::new(RefCell::new(Stats::new())));
+    control.map_extension("CBZ", "archive");
+    control.map_default_extension("cbz", "other");
+    control.map_default_extension("zip", "archive");
+    control.block_extension("jar");
+    control.map_default_extension("jar", "archive");
+    let archive = Some("archive".to_string());
+    assert_eq!(control.viewer_for(OsStr::new("a.cbz")), archive);
+    assert_eq!(control.viewer_for(OsStr::new("a.Zip")), archive);
+    assert_eq!(control.viewer_for(OsStr::new("a.jar")), None);
+    assert_eq!(control.viewer_for(OsStr::new("zip")), None);
+    control.map_default_extension("tar.gz", "archive");
+    control.map_default_extension("gz", "other");
+    assert_eq!(control.viewer_for(OsStr::new("a.TAR.gz")), archive);
+    let other = Some("other".to_string());
+    assert_eq!(control.viewer_for(OsStr::new("a.log.gz")), other);
+    control.block_extension("gz");
+    assert_eq!(control.viewer_for(OsStr::new("a.tar.gz")), None);
+}
+
+#[test]
+fn test_scrub_schedule() {
+    use std::cell::Cell;
+
+    // checks two entries per pass.
+    struct Counting(Cell<u64>);
+    impl Scrubber for Counting {
+        fn scrub_next(&self, stats: &mut ScrubStats) -> bool {
+            self.0.set(self.0.get() + 1);
+            if self.0.get() % 3 == 0 {
+                return false;
+            }
+            stats.checked += 1;
+            true
+        }
+    }
+
+    let stats = Rc::new(RefCell::new(Stats::new()));
+    let control = Control::new(stats.clone());
+    let scrubber = Rc::new(Counting(Cell::new(0)));
+    control.register_scrubber(scrubber.clone());
+    // nothing is scrubbed until scheduled.
+    control.scrub_if_due();
+    assert_eq!(scrubber.0.get(), 0);
+    control.schedule_scrub(Duration::from_secs(3600));
+    let step = || {
+        control.scrub.borrow_mut().as_mut().unwrap().due = Instant::now();
+        control.scrub_if_due();
+    };
+    for _ in 0..4 {
+        step();
+    }
+    let scrub = *stats.borrow_mut().scrub_mut();
+    assert_eq!((scrub.passes, scrub.checked), (1, 0));
+    // the next pass waits for the interval.
+    control.scrub_if_due();
+    assert_eq!(scrubber.0.get(), 3);
+    step();
+    assert_eq!(stats.borrow_mut().scrub_mut().checked, 1);
+}
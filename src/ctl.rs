@@ -0,0 +1,295 @@
+use libc;
+use serde_json;
+
+use self::serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use crate::control;
+use crate::du;
+use crate::fs::unmount;
+use crate::locate;
+
+// the directory of sockets, which only the user can enter.
+// it is made if missing, and refused if another user could enter or replace it, like
+// /tmp/showfs-UID made by someone else.
+fn socket_dir() -> Result<PathBuf> {
+    let uid = unsafe { libc::geteuid() };
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Path::new(&dir).join("showfs"),
+        None => PathBuf::from(format!("/tmp/showfs-{}", uid)),
+    };
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    let m = fs::symlink_metadata(&dir)?;
+    if !m.is_dir() || m.uid() != uid || m.mode() & 0o077 != 0 {
+        let message = format!(
+            "{} is not a directory only the user can access",
+            dir.display()
+        );
+        return Err(Error::new(ErrorKind::PermissionDenied, message));
+    }
+    Ok(dir)
+}
+
+/// the socket of the mount, like $XDG_RUNTIME_DIR/showfs/%2Fmnt%2Fa.sock for /mnt/a.
+pub fn socket_path<P: AsRef<Path>>(mountpoint: P) -> Result<PathBuf> {
+    let mountpoint = fs::canonicalize(mountpoint)?;
+    let mountpoint = mountpoint
+        .to_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "mountpoint is not utf-8"))?;
+    let name = mountpoint.replace('%', "%25").replace('/', "%2F");
+    Ok(socket_dir()?.join(name + ".sock"))
+}
+
+// files kept open by pin, by their paths in the mount, so that their contents stay in use
+// and are not freed by drop-cache.
+type Pins = HashMap<PathBuf, fs::File>;
+
+// the path in the mount, refusing ones going out of it.
+fn mounted_path(mountpoint: &Path, path: &str) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for c in Path::new(path).components() {
+        match c {
+            Component::RootDir | Component::CurDir => continue,
+            Component::Normal(name) => relative.push(name),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "invalid path")),
+        }
+    }
+    Ok(mountpoint.join(relative))
+}
+
+fn pin(mountpoint: &Path, pins: &mut Pins, path: &str) -> Result<String> {
+    let path = mounted_path(mountpoint, path)?;
+    let file = fs::File::open(&path)?;
+    if !file.metadata()?.is_file() {
+        return Err(Error::new(ErrorKind::InvalidInput, "not a file"));
+    }
+    pins.insert(path, file);
+    Ok(String::new())
+}
+
+fn unpin(mountpoint: &Path, pins: &mut Pins, path: &str) -> Result<String> {
+    match pins.remove(&mounted_path(mountpoint, path)?) {
+        Some(_) => Ok(String::new()),
+        None => Err(Error::new(ErrorKind::NotFound, "not pinned")),
+    }
+}
+
+// runs the request on the control directory of the mount.
+// the fuse loop serves it like other processes, so that the state is not shared among threads.
+fn handle(
+    mountpoint: &Path,
+    index: Option<&Arc<locate::Index>>,
+    pins: &mut Pins,
+    request: &Value,
+) -> Result<String> {
+    let dir = mountpoint.join(control::DIR_NAME);
+    let command = |s: String| fs::write(dir.join("control"), s).map(|_| String::new());
+    let cmd = request["cmd"].as_str().unwrap_or("");
+    let args: Vec<_> = match request["args"].as_array() {
+        Some(args) => args.iter().filter_map(|a| a.as_str()).collect(),
+        None => Vec::new(),
+    };
+    match (cmd, args.as_slice()) {
+        ("version", []) | ("config", []) | ("stats", []) | ("cache", []) => {
+            fs::read_to_string(dir.join(cmd))
+        }
+        ("open-files", []) => fs::read_to_string(dir.join("open_files")),
+        ("drop-cache", []) => command("drop_cache\n".to_string()),
+        ("reindex", []) => {
+            let output = command("reindex\n".to_string())?;
+            if let Some(index) = index {
+                locate::Index::rebuild(index.clone())?;
+            }
+            Ok(output)
+        }
+        ("find", [pattern]) => {
+            let index = index.ok_or_else(|| {
+                Error::new(ErrorKind::InvalidInput, "the locate index is not enabled")
+            })?;
+            let mut output = String::new();
+            for path in index.find(pattern)? {
+                output.push_str(&path.to_string_lossy());
+                output.push('\n');
+            }
+            Ok(output)
+        }
+        ("du", []) => du::run_mounted(mountpoint, Path::new("/")).map(|u| u.to_string()),
+        ("du", [path]) => du::run_mounted(mountpoint, Path::new(path)).map(|u| u.to_string()),
+        ("pin", [path]) => pin(mountpoint, pins, path),
+        ("unpin", [path]) => unpin(mountpoint, pins, path),
+        ("set-loglevel", [level]) => command(format!("loglevel {}\n", level)),
+        ("unmount", []) => {
+            // pinned files would keep the mount busy.
+            pins.clear();
+            unmount(mountpoint).map(|_| String::new())
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown command: {}", request),
+        )),
+    }
+}
+
+// reads requests, one object per line, and writes responses likewise.
+fn serve_connection(
+    mountpoint: &Path,
+    index: Option<&Arc<locate::Index>>,
+    pins: &mut Pins,
+    stream: UnixStream,
+) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let response = match serde_json::from_str::<Value>(&line?) {
+            Ok(request) => match handle(mountpoint, index, pins, &request) {
+                Ok(output) => json!({"ok": true, "output": output}),
+                Err(e) => json!({"ok": false, "error": e.to_string()}),
+            },
+            Err(e) => json!({"ok": false, "error": e.to_string()}),
+        };
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+/// Server removes the socket when dropped.
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve_at(
+    path: PathBuf,
+    mountpoint: PathBuf,
+    index: Option<Arc<locate::Index>>,
+) -> Result<Server> {
+    // a socket left by a crashed mount refuses connections.
+    if path.exists() && UnixStream::connect(&path).is_err() {
+        fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    thread::Builder::new()
+        .name("ctl".to_string())
+        .spawn(move || {
+            let mut pins = Pins::new();
+            for stream in listener.incoming() {
+                let result = stream
+                    .and_then(|s| serve_connection(&mountpoint, index.as_ref(), &mut pins, s));
+                if let Err(e) = result {
+                    warn!("control socket: {}", e);
+                }
+            }
+        })?;
+    Ok(Server { path: path })
+}
+
+/// listens to the control socket of the mount, which answers find by the index if any.
+pub fn serve<P: AsRef<Path>>(mountpoint: P, index: Option<Arc<locate::Index>>) -> Result<Server> {
+    let path = socket_path(mountpoint.as_ref())?;
+    serve_at(path, fs::canonicalize(mountpoint)?, index)
+}
+
+fn request(path: &Path, request: &Value) -> Result<Value> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{}", request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// runs `showfs ctl MOUNTPOINT COMMAND [ARG...]`.
+pub fn client_main(args: &[String]) -> ! {
+    let (mountpoint, cmd, args) = match args {
+        [mountpoint, cmd, args @ ..] => (mountpoint, cmd, args),
+        _ => {
+            eprintln!("usage: showfs ctl MOUNTPOINT COMMAND [ARG...]");
+            process::exit(2);
+        }
+    };
+    let req = json!({"cmd": cmd, "args": args});
+    let response = socket_path(mountpoint).and_then(|path| request(&path, &req));
+    match response {
+        Ok(ref r) if r["ok"] == true => {
+            print!("{}", r["output"].as_str().unwrap_or(""));
+            process::exit(0);
+        }
+        Ok(r) => eprintln!("{}", r["error"].as_str().unwrap_or("invalid response")),
+        Err(e) => eprintln!("{}", e),
+    }
+    process::exit(1);
+}
+
+#[test]
+fn test_serve() {
+    let mount = tempfile::tempdir().unwrap();
+    let dir = mount.path().join(control::DIR_NAME);
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("stats"), "read count=1\n").unwrap();
+    let sockets = tempfile::tempdir().unwrap();
+    let path = sockets.path().join("ctl.sock");
+    let server = serve_at(path.clone(), mount.path().to_path_buf(), None).unwrap();
+
+    let r = request(&path, &json!({"cmd": "stats"})).unwrap();
+    assert_eq!(r, json!({"ok": true, "output": "read count=1\n"}));
+    let r = request(&path, &json!({"cmd": "set-loglevel", "args": ["debug"]})).unwrap();
+    assert_eq!(r["ok"], true);
+    assert_eq!(
+        fs::read_to_string(dir.join("control")).unwrap(),
+        "loglevel debug\n"
+    );
+    let r = request(&path, &json!({"cmd": "pin"})).unwrap();
+    assert_eq!(r["ok"], false);
+
+    // pinned files are kept open until unpinned.
+    fs::write(mount.path().join("a.txt"), "a").unwrap();
+    let r = request(&path, &json!({"cmd": "pin", "args": ["/a.txt"]})).unwrap();
+    assert_eq!(r, json!({"ok": true, "output": ""}));
+    let r = request(&path, &json!({"cmd": "unpin", "args": ["a.txt"]})).unwrap();
+    assert_eq!(r["ok"], true);
+    let r = request(&path, &json!({"cmd": "unpin", "args": ["a.txt"]})).unwrap();
+    assert_eq!(r["ok"], false);
+    for args in [["missing.txt"], ["../a.txt"], ["/"]].iter() {
+        let r = request(&path, &json!({"cmd": "pin", "args": args})).unwrap();
+        assert_eq!(r["ok"], false);
+    }
+    let r = request(&path, &json!({"cmd": "find", "args": ["*.txt"]})).unwrap();
+    assert_eq!(r["ok"], false);
+
+    drop(server);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_socket_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let runtime = tempfile::tempdir().unwrap();
+    let saved = env::var_os("XDG_RUNTIME_DIR");
+    env::set_var("XDG_RUNTIME_DIR", runtime.path());
+    let dir = socket_dir().unwrap();
+    assert_eq!(dir, runtime.path().join("showfs"));
+    assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+    // others can enter.
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(socket_dir().is_err());
+    match saved {
+        Some(saved) => env::set_var("XDG_RUNTIME_DIR", saved),
+        None => env::remove_var("XDG_RUNTIME_DIR"),
+    }
+}
@@ -0,0 +1,303 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Algorithm is a hash which sidecar files like a.iso.sha256 tell.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Algorithm {
+    Sha256,
+    Md5,
+}
+
+impl Algorithm {
+    // in the order sidecars are looked for.
+    pub const ALL: [Algorithm; 2] = [Algorithm::Sha256, Algorithm::Md5];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Md5 => "md5",
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 32,
+            Algorithm::Md5 => 16,
+        }
+    }
+
+    // the sidecar of the file, like a.iso.sha256 for a.iso.
+    pub fn sidecar(self, path: &Path) -> PathBuf {
+        let mut s = OsString::from(path.as_os_str());
+        s.push(".");
+        s.push(self.extension());
+        PathBuf::from(s)
+    }
+
+    // reads the hash from the output of sha256sum or md5sum, like "HEX  a.iso".
+    pub fn parse(self, s: &str) -> Option<Vec<u8>> {
+        let hex = s.split_whitespace().next()?;
+        if hex.len() != self.len() * 2 {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+        }
+    }
+}
+
+/// Hasher computes the hash of the algorithm.
+pub enum Hasher {
+    Sha256(Sha256),
+    Md5(Md5),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Sha256(ref mut h) => h.update(data),
+            Hasher::Md5(ref mut h) => h.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => h.finish(),
+            Hasher::Md5(h) => h.finish(),
+        }
+    }
+}
+
+// buffers input into 64 byte blocks, and pads the last one with the length in bits.
+struct Blocks {
+    buf: [u8; 64],
+    len: usize,
+    total: u64,
+}
+
+impl Blocks {
+    fn new() -> Blocks {
+        Blocks {
+            buf: [0; 64],
+            len: 0,
+            total: 0,
+        }
+    }
+
+    fn update<F: FnMut(&[u8; 64])>(&mut self, mut data: &[u8], mut f: F) {
+        self.total = self.total.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let n = (64 - self.len).min(data.len());
+            self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+            if self.len == 64 {
+                f(&self.buf);
+                self.len = 0;
+            }
+        }
+    }
+
+    fn pad<F: FnMut(&[u8; 64])>(&mut self, length: [u8; 8], mut f: F) {
+        let total = self.total;
+        self.update(&[0x80], &mut f);
+        while self.len != 56 {
+            self.update(&[0], &mut f);
+        }
+        self.update(&length, &mut f);
+        self.total = total;
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+pub struct Sha256 {
+    state: [u32; 8],
+    blocks: Blocks,
+}
+
+impl Sha256 {
+    fn new() -> Sha256 {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            blocks: Blocks::new(),
+        }
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                block[i * 4],
+                block[i * 4 + 1],
+                block[i * 4 + 2],
+                block[i * 4 + 3],
+            ]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let mut v = *state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [
+                t1.wrapping_add(t2),
+                v[0],
+                v[1],
+                v[2],
+                v[3].wrapping_add(t1),
+                v[4],
+                v[5],
+                v[6],
+            ];
+        }
+        for (s, v) in state.iter_mut().zip(v.iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |b| Sha256::compress(state, b));
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let bits = self.blocks.total.wrapping_mul(8).to_be_bytes();
+        let state = &mut self.state;
+        self.blocks.pad(bits, |b| Sha256::compress(state, b));
+        self.state.iter().flat_map(|s| s.to_be_bytes()).collect()
+    }
+}
+
+const MD5_S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+pub struct Md5 {
+    state: [u32; 4],
+    blocks: Blocks,
+}
+
+impl Md5 {
+    fn new() -> Md5 {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            blocks: Blocks::new(),
+        }
+    }
+
+    fn compress(state: &mut [u32; 4], block: &[u8; 64]) {
+        let mut m = [0u32; 16];
+        for i in 0..16 {
+            m[i] = u32::from_le_bytes([
+                block[i * 4],
+                block[i * 4 + 1],
+                block[i * 4 + 2],
+                block[i * 4 + 3],
+            ]);
+        }
+        let [mut a, mut b, mut c, mut d] = *state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            // the constants are floor(abs(sin(i + 1)) * 2^32).
+            let k = ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32;
+            let f = f.wrapping_add(a).wrapping_add(k).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(MD5_S[i]));
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |b| Md5::compress(state, b));
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let bits = self.blocks.total.wrapping_mul(8).to_le_bytes();
+        let state = &mut self.state;
+        self.blocks.pad(bits, |b| Md5::compress(state, b));
+        self.state.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+}
+
+#[test]
+fn test_hashes() {
+    let hex = |algorithm: Algorithm, data: &[u8]| {
+        let mut h = algorithm.hasher();
+        // fed in pieces across blocks.
+        for chunk in data.chunks(7) {
+            h.update(chunk);
+        }
+        h.finish()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    assert_eq!(
+        hex(Algorithm::Sha256, b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        hex(Algorithm::Sha256, &[b'a'; 1000]),
+        "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+    );
+    assert_eq!(hex(Algorithm::Md5, b""), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(
+        hex(
+            Algorithm::Md5,
+            b"The quick brown fox jumps over the lazy dog"
+        ),
+        "9e107d9d372bb6826bd81d3542a419d6"
+    );
+    let parsed = Algorithm::Md5.parse("9e107d9d372bb6826bd81d3542a419d6  fox.txt\n");
+    assert_eq!(parsed.unwrap()[..2], [0x9e, 0x10]);
+    assert!(Algorithm::Sha256
+        .parse("9e107d9d372bb6826bd81d3542a419d6")
+        .is_none());
+}
@@ -0,0 +1,163 @@
+use fuse;
+
+use self::fuse::FileType;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+
+use crate::control;
+use crate::flat;
+use crate::tree::VirtualTree;
+
+/// Usage is the logical sizes of files under each directory, like du, and the numbers and
+/// the sizes of files by their extensions, to tell what is in a collection of archives.
+/// sizes are of the contents, not of blocks, as archives are shown as directories.
+pub struct Usage {
+    // the files and the bytes under each directory, by the path.
+    dirs: BTreeMap<PathBuf, (u64, u64)>,
+    // the files and the bytes by the extension in lower case, "" for none.
+    extensions: HashMap<String, (u64, u64)>,
+}
+
+impl Usage {
+    fn new() -> Usage {
+        Usage {
+            dirs: BTreeMap::new(),
+            extensions: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, path: &Path, kind: FileType, size: u64) {
+        if kind == FileType::Directory {
+            self.dirs.entry(path.to_path_buf()).or_insert((0, 0));
+            return;
+        }
+        // the parents are added before their entries.
+        for dir in path.ancestors().skip(1) {
+            match self.dirs.get_mut(dir) {
+                Some(usage) => {
+                    usage.0 += 1;
+                    usage.1 += size;
+                }
+                None => break,
+            }
+        }
+        let ext = path
+            .extension()
+            .map_or(String::new(), |e| e.to_string_lossy().to_lowercase());
+        let usage = self.extensions.entry(ext).or_insert((0, 0));
+        usage.0 += 1;
+        usage.1 += size;
+    }
+}
+
+// one line per directory in the order of paths, like "dir files=2 bytes=1048576 /a.zip",
+// followed by one per extension, larger first, like "ext files=2 bytes=1048576 jpg".
+// files without extensions are counted as "-".
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, &(files, bytes)) in self.dirs.iter() {
+            writeln!(f, "dir files={} bytes={} {}", files, bytes, path.display())?;
+        }
+        let mut extensions: Vec<_> = self.extensions.iter().collect();
+        extensions.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(b.0)));
+        for (ext, &(files, bytes)) in extensions {
+            let ext = if ext.is_empty() { "-" } else { ext };
+            writeln!(f, "ext files={} bytes={} {}", files, bytes, ext)?;
+        }
+        Ok(())
+    }
+}
+
+/// the usage of the path in the tree, walked without fuse. entries which fail are skipped
+/// with warnings.
+pub fn run(tree: &VirtualTree, path: &Path) -> Result<Usage> {
+    let mut usage = Usage::new();
+    tree.walk(path, |path, entry| {
+        match entry.getattr(0) {
+            Ok(attr) => usage.add(path, attr.kind, attr.size),
+            Err(e) => warn!(error = ?e, "failed to get attributes of {}", path.display()),
+        }
+        Ok(())
+    })?;
+    Ok(usage)
+}
+
+/// the usage of the path in the mount, walked through the mountpoint like find, so that it
+/// is not run by the thread which serves the mount. the control directory and the directory
+/// of all files are skipped.
+pub fn run_mounted(mountpoint: &Path, path: &Path) -> Result<Usage> {
+    let mut relative = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::RootDir | Component::CurDir => continue,
+            Component::Normal(name) => relative.push(name),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "invalid path")),
+        }
+    }
+    let mut usage = Usage::new();
+    let path = Path::new("/").join(&relative);
+    let m = fs::symlink_metadata(mountpoint.join(&relative))?;
+    walk(mountpoint, &path, &m, &mut usage);
+    Ok(usage)
+}
+
+fn walk(mountpoint: &Path, path: &Path, m: &fs::Metadata, usage: &mut Usage) {
+    if !m.is_dir() {
+        usage.add(path, FileType::RegularFile, m.len());
+        return;
+    }
+    usage.add(path, FileType::Directory, 0);
+    let dir = mountpoint.join(path.strip_prefix("/").unwrap());
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!(error = ?e, "failed to list {}", path.display());
+            return;
+        }
+    };
+    for entry in entries {
+        let (name, m) = match entry.and_then(|e| Ok((e.file_name(), e.metadata()?))) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!(error = ?e, "failed to list {}", path.display());
+                continue;
+            }
+        };
+        if path == Path::new("/") && (name == control::DIR_NAME || name == flat::DIR_NAME) {
+            continue;
+        }
+        walk(mountpoint, &path.join(&name), &m, usage);
+    }
+}
+
+#[test]
+fn test_run() {
+    use crate::fs::ShowFS;
+
+    let tmp = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(tmp.path().join("a/b")).unwrap();
+    std::fs::write(tmp.path().join("a/b/c.JPG"), vec![1; 300]).unwrap();
+    std::fs::write(tmp.path().join("a/d.jpg"), vec![1; 200]).unwrap();
+    std::fs::write(tmp.path().join("e.txt"), "hello").unwrap();
+    std::fs::write(tmp.path().join("f"), "").unwrap();
+    let expected = "dir files=4 bytes=505 /
+dir files=2 bytes=500 /a
+dir files=1 bytes=300 /a/b
+ext files=2 bytes=500 jpg
+ext files=1 bytes=5 txt
+ext files=1 bytes=0 -
+";
+    let tree = ShowFS::new(tmp.path()).into_tree();
+    let usage = run(&tree, Path::new("/")).unwrap();
+    assert_eq!(usage.to_string(), expected);
+    // the same through the mountpoint, which is the directory here.
+    std::fs::create_dir(tmp.path().join(control::DIR_NAME)).unwrap();
+    let usage = run_mounted(tmp.path(), Path::new("/")).unwrap();
+    assert_eq!(usage.to_string(), expected);
+    let usage = run_mounted(tmp.path(), Path::new("a/b")).unwrap();
+    assert_eq!(usage.dirs.len(), 1);
+    assert!(run_mounted(tmp.path(), Path::new("/a/../..")).is_err());
+}
@@ -0,0 +1,164 @@
+use libc;
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Error describes why an archive could not be read, or what else failed.
+/// it travels inside io::Error, and is turned into an errno for fuse.
+/// the first variants are errors of backends decoding archives.
+#[derive(Debug)]
+pub enum Error {
+    UnsupportedFormat(String),
+    PassphraseRequired(String),
+    WrongPassphrase(String),
+    Truncated(String),
+    Io(io::Error),
+    /// the error reading the archive, or the member if any.
+    Archive {
+        archive: PathBuf,
+        member: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// the page cache can not hold a file.
+    Cache(String),
+    /// mounting or unmounting failed.
+    Fuse(String),
+    /// reading the entry panicked, with the message.
+    Panicked(String),
+    /// the entry panicked recently and is not read until the cooldown passes.
+    Quarantined(String),
+    /// the mount lost the kernel while serving, like by an aborted connection.
+    Disconnected(String),
+}
+
+impl Error {
+    /// adds the archive and the member to the error, unless it has them already.
+    pub fn archive(archive: &Path, member: Option<&Path>, e: io::Error) -> io::Error {
+        if let Some(&Error::Archive { .. }) = e.get_ref().and_then(|e| e.downcast_ref()) {
+            return e;
+        }
+        Error::Archive {
+            archive: archive.to_path_buf(),
+            member: member.map(Path::to_path_buf),
+            source: e,
+        }
+        .into()
+    }
+
+    pub fn errno(&self) -> libc::c_int {
+        match *self {
+            Error::UnsupportedFormat(_) => libc::ENOTSUP,
+            Error::PassphraseRequired(_) | Error::WrongPassphrase(_) => libc::EACCES,
+            Error::Truncated(_)
+            | Error::Cache(_)
+            | Error::Fuse(_)
+            | Error::Panicked(_)
+            | Error::Quarantined(_) => libc::EIO,
+            Error::Disconnected(_) => libc::ENOTCONN,
+            Error::Io(ref e) => e.raw_os_error().unwrap_or(libc::EIO),
+            Error::Archive { ref source, .. } => errno(source),
+        }
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match *self {
+            Error::UnsupportedFormat(_) => io::ErrorKind::InvalidData,
+            Error::PassphraseRequired(_) | Error::WrongPassphrase(_) => {
+                io::ErrorKind::PermissionDenied
+            }
+            Error::Truncated(_) => io::ErrorKind::UnexpectedEof,
+            Error::Io(ref e) => e.kind(),
+            Error::Archive { ref source, .. } => source.kind(),
+            Error::Disconnected(_) => io::ErrorKind::NotConnected,
+            Error::Cache(_) | Error::Fuse(_) | Error::Panicked(_) | Error::Quarantined(_) => {
+                io::ErrorKind::Other
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::UnsupportedFormat(ref s) => write!(f, "unsupported format: {}", s),
+            Error::PassphraseRequired(ref s) => write!(f, "passphrase required: {}", s),
+            Error::WrongPassphrase(ref s) => write!(f, "wrong passphrase: {}", s),
+            Error::Truncated(ref s) => write!(f, "truncated: {}", s),
+            Error::Io(ref e) => e.fmt(f),
+            Error::Archive {
+                ref archive,
+                ref member,
+                ref source,
+            } => match *member {
+                Some(ref m) => write!(f, "{}: {}: {}", archive.display(), m.display(), source),
+                None => write!(f, "{}: {}", archive.display(), source),
+            },
+            Error::Cache(ref s) => write!(f, "cache: {}", s),
+            Error::Fuse(ref s) => write!(f, "fuse: {}", s),
+            Error::Panicked(ref s) => write!(f, "panicked: {}", s),
+            Error::Quarantined(ref s) => write!(f, "quarantined: {}", s),
+            Error::Disconnected(ref s) => write!(f, "disconnected: {}", s),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Archive { ref source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
+
+// returns the errno for the error, EIO if unknown.
+// errors with context have the errno of their sources.
+pub fn errno(e: &io::Error) -> libc::c_int {
+    if let Some(raw) = e.raw_os_error() {
+        return raw;
+    }
+    if let Some(e) = e.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+        return e.errno();
+    }
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => libc::EACCES,
+        _ => libc::EIO,
+    }
+}
+
+#[test]
+fn test_errno() {
+    let e: io::Error = Error::UnsupportedFormat("zip".to_string()).into();
+    assert_eq!(errno(&e), libc::ENOTSUP);
+    let e: io::Error = Error::WrongPassphrase("zip".to_string()).into();
+    assert_eq!(errno(&e), libc::EACCES);
+    assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    let e: io::Error = Error::Io(io::Error::from_raw_os_error(libc::ENOENT)).into();
+    assert_eq!(errno(&e), libc::ENOENT);
+    assert_eq!(errno(&io::Error::new(io::ErrorKind::Other, "x")), libc::EIO);
+    let e: io::Error = Error::Disconnected("aborted".to_string()).into();
+    assert_eq!(errno(&e), libc::ENOTCONN);
+
+    let source: io::Error = Error::Truncated("eof".to_string()).into();
+    let e = Error::archive(Path::new("/a.zip"), Some(Path::new("b/c")), source);
+    assert_eq!(errno(&e), libc::EIO);
+    assert_eq!(e.to_string(), "/a.zip: b/c: truncated: eof");
+    // the context added first is kept.
+    let e = Error::archive(Path::new("/x.zip"), None, e);
+    assert_eq!(e.to_string(), "/a.zip: b/c: truncated: eof");
+    let source = io::Error::from_raw_os_error(libc::ENOENT);
+    let e = Error::archive(Path::new("/a.zip"), None, source);
+    assert_eq!(errno(&e), libc::ENOENT);
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+}
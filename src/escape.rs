@@ -0,0 +1,92 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::str;
+
+/// Mode tells how undisplayable names, of invalid UTF-8 or with control characters, are shown.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    // invalid bytes and control characters are replaced by U+FFFD.
+    Transliterate,
+    // invalid bytes and control characters are encoded like %1B.
+    Percent,
+}
+
+fn displayable(name: &OsStr) -> bool {
+    match name.to_str() {
+        Some(s) => !s.chars().any(char::is_control),
+        None => false,
+    }
+}
+
+pub(crate) fn escape(mode: Mode, name: &OsStr) -> OsString {
+    if displayable(name) {
+        return name.to_os_string();
+    }
+    let mut escaped = String::new();
+    let mut bytes = name.as_bytes();
+    while !bytes.is_empty() {
+        // the valid prefix, and the length of the invalid sequence after it.
+        let (valid, invalid) = match str::from_utf8(bytes) {
+            Ok(s) => (s, 0),
+            Err(e) => {
+                let s = str::from_utf8(&bytes[..e.valid_up_to()]).unwrap();
+                (s, e.error_len().unwrap_or(bytes.len() - e.valid_up_to()))
+            }
+        };
+        for c in valid.chars() {
+            if !c.is_control() {
+                escaped.push(c);
+                continue;
+            }
+            match mode {
+                Mode::Transliterate => escaped.push('\u{fffd}'),
+                Mode::Percent => {
+                    let mut buf = [0; 4];
+                    for b in c.encode_utf8(&mut buf).bytes() {
+                        write!(escaped, "%{:02X}", b).unwrap();
+                    }
+                }
+            }
+        }
+        let rest = &bytes[valid.len()..];
+        for b in rest[..invalid].iter() {
+            match mode {
+                Mode::Transliterate => escaped.push('\u{fffd}'),
+                Mode::Percent => write!(escaped, "%{:02X}", b).unwrap(),
+            }
+        }
+        bytes = &rest[invalid..];
+    }
+    OsString::from(escaped)
+}
+
+#[test]
+fn test_escape() {
+    let name = OsStr::from_bytes(b"a\x1b[0m\xff.txt");
+    assert_eq!(escape(Mode::Percent, name), "a%1B[0m%FF.txt");
+    assert_eq!(
+        escape(Mode::Transliterate, name),
+        "a\u{fffd}[0m\u{fffd}.txt"
+    );
+    assert_eq!(escape(Mode::Percent, OsStr::new("100%.txt")), "100%.txt");
+    assert_eq!(
+        escape(Mode::Percent, OsStr::from_bytes(b"\xe3\x81")),
+        "%E3%81"
+    );
+}
+
+#[test]
+fn test_lookup_escaped() {
+    use crate::names::{Codec, NameViewer};
+    use crate::testing::{MemTree, Viewed};
+
+    let original = OsStr::from_bytes(b"/a\nb\xff");
+    let tree = MemTree::new();
+    tree.file(original, b"a");
+    let viewer = NameViewer::new(Codec::Escape(Mode::Percent));
+    let viewed = Viewed::new(vec![&viewer]);
+    assert_eq!(viewed.walk(tree.root()), vec!["/", "/a%0Ab%FF 1"]);
+    assert_eq!(viewed.read(tree.root(), "/a%0Ab%FF").unwrap(), b"a");
+    assert!(viewed.resolve(tree.root(), original).is_err());
+}
@@ -0,0 +1,239 @@
+//! the C API declared in include/showfs.h, built with the ffi feature.
+//!
+//! handles are not thread safe, each tree and its files must be used by one thread.
+//! functions returning pointers set errno and return NULL on errors, the others return
+//! negative errnos.
+
+// pointers must be the ones returned by this API, as include/showfs.h describes.
+#![allow(clippy::missing_safety_doc)]
+
+use fuse;
+use libc;
+
+use self::fuse::FileType;
+use self::libc::{c_char, c_int, c_void, size_t, ssize_t};
+use std::ffi::{CStr, CString, OsStr};
+use std::io::Result;
+use std::os::unix::ffi::OsStrExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use crate::archive::{ArchiveViewer, Options};
+use crate::error;
+use crate::fs::{SeekableRead, ShowFS};
+use crate::tree::VirtualTree;
+
+/// Tree is showfs_tree, the tree shown with the archive viewer.
+pub struct Tree {
+    tree: VirtualTree,
+}
+
+/// File is showfs_file, an opened file of a tree.
+pub struct File {
+    reader: Box<dyn SeekableRead>,
+}
+
+/// DirEntry is showfs_entry, an entry of a listed directory.
+#[repr(C)]
+pub struct DirEntry {
+    name: *mut c_char,
+    // the type and the permissions, like st_mode.
+    mode: u32,
+    size: u64,
+    mtime: i64,
+}
+
+// calls f, and turns errors and panics into errnos, panics must not unwind into C.
+fn call<T, F: FnOnce() -> Result<T>>(f: F) -> std::result::Result<T, c_int> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(v)) => Ok(v),
+        Ok(Err(e)) => Err(error::errno(&e)),
+        Err(_) => Err(libc::EIO),
+    }
+}
+
+fn set_errno(errno: c_int) {
+    unsafe { *libc::__errno_location() = errno }
+}
+
+unsafe fn to_path<'a>(p: *const c_char) -> &'a Path {
+    Path::new(OsStr::from_bytes(CStr::from_ptr(p).to_bytes()))
+}
+
+fn to_mode(kind: FileType, perm: u16) -> u32 {
+    let t = match kind {
+        FileType::RegularFile => libc::S_IFREG,
+        FileType::Directory => libc::S_IFDIR,
+        FileType::Symlink => libc::S_IFLNK,
+        FileType::NamedPipe => libc::S_IFIFO,
+        FileType::CharDevice => libc::S_IFCHR,
+        FileType::BlockDevice => libc::S_IFBLK,
+        FileType::Socket => libc::S_IFSOCK,
+    };
+    t | perm as u32
+}
+
+/// opens the tree of the target, a file or a directory, caching up to max_cache bytes of
+/// archived files.
+#[no_mangle]
+pub unsafe extern "C" fn showfs_tree_open(target: *const c_char, max_cache: u64) -> *mut Tree {
+    let target = to_path(target);
+    let result = call(|| {
+        let mut fs = ShowFS::new(target);
+        fs.register_viewer(ArchiveViewer::new(max_cache as usize, Options::default())?);
+        Ok(Box::new(Tree {
+            tree: fs.into_tree(),
+        }))
+    });
+    match result {
+        Ok(tree) => Box::into_raw(tree),
+        Err(errno) => {
+            set_errno(errno);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn showfs_tree_free(tree: *mut Tree) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+/// lists the directory at the path, entries are freed by showfs_entries_free.
+#[no_mangle]
+pub unsafe extern "C" fn showfs_list_dir(
+    tree: *mut Tree,
+    path: *const c_char,
+    entries: *mut *mut DirEntry,
+    len: *mut size_t,
+) -> c_int {
+    let tree = &(*tree).tree;
+    let path = to_path(path);
+    let result = call(|| {
+        let mut list = Vec::new();
+        for e in tree.list(path)? {
+            let attr = e.getattr(0)?;
+            let name = CString::new(e.name().as_bytes())?;
+            list.push(DirEntry {
+                name: name.into_raw(),
+                mode: to_mode(attr.kind, attr.perm),
+                size: attr.size,
+                mtime: attr.mtime.sec,
+            });
+        }
+        Ok(list.into_boxed_slice())
+    });
+    match result {
+        Ok(list) => {
+            *len = list.len();
+            *entries = Box::into_raw(list) as *mut DirEntry;
+            0
+        }
+        Err(errno) => -errno,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn showfs_entries_free(entries: *mut DirEntry, len: size_t) {
+    if entries.is_null() {
+        return;
+    }
+    let list = Box::from_raw(ptr::slice_from_raw_parts_mut(entries, len));
+    for e in list.iter() {
+        drop(CString::from_raw(e.name));
+    }
+}
+
+/// opens the file at the path, it is closed by showfs_file_close.
+#[no_mangle]
+pub unsafe extern "C" fn showfs_file_open(tree: *mut Tree, path: *const c_char) -> *mut File {
+    let tree = &(*tree).tree;
+    let path = to_path(path);
+    match call(|| tree.open(path)) {
+        Ok(reader) => Box::into_raw(Box::new(File { reader: reader })),
+        Err(errno) => {
+            set_errno(errno);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// reads up to len bytes at the offset, returns the bytes read, less than len only at the end.
+#[no_mangle]
+pub unsafe extern "C" fn showfs_file_read(
+    file: *mut File,
+    offset: u64,
+    buf: *mut c_void,
+    len: size_t,
+) -> ssize_t {
+    let reader = &mut (*file).reader;
+    let buf = slice::from_raw_parts_mut(buf as *mut u8, len);
+    let result = call(|| {
+        let mut read = 0;
+        while read < buf.len() {
+            match reader.read_at(offset + read as u64, &mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    });
+    match result {
+        Ok(read) => read as ssize_t,
+        Err(errno) => -errno as ssize_t,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn showfs_file_close(file: *mut File) {
+    if !file.is_null() {
+        drop(Box::from_raw(file));
+    }
+}
+
+#[test]
+fn test_ffi() {
+    let tmp = tempfile::tempdir().unwrap();
+    std::fs::write(tmp.path().join("file"), "hello").unwrap();
+    let target = CString::new(tmp.path().as_os_str().as_bytes()).unwrap();
+    unsafe {
+        let tree = showfs_tree_open(target.as_ptr(), 1024 * 1024);
+        assert!(!tree.is_null());
+
+        let (mut entries, mut len) = (ptr::null_mut(), 0);
+        let root = CString::new("/").unwrap();
+        assert_eq!(
+            showfs_list_dir(tree, root.as_ptr(), &mut entries, &mut len),
+            0
+        );
+        assert_eq!(len, 1);
+        let e = &*entries;
+        assert_eq!(CStr::from_ptr(e.name).to_bytes(), b"file");
+        assert_eq!(e.mode & libc::S_IFMT, libc::S_IFREG);
+        assert_eq!(e.size, 5);
+        showfs_entries_free(entries, len);
+
+        let path = CString::new("/file").unwrap();
+        let file = showfs_file_open(tree, path.as_ptr());
+        assert!(!file.is_null());
+        let mut buf = [0u8; 10];
+        assert_eq!(
+            showfs_file_read(file, 1, buf.as_mut_ptr() as *mut c_void, 10),
+            4
+        );
+        assert_eq!(&buf[..4], b"ello");
+        showfs_file_close(file);
+
+        let none = CString::new("/none").unwrap();
+        assert!(showfs_file_open(tree, none.as_ptr()).is_null());
+        assert_eq!(
+            showfs_list_dir(tree, path.as_ptr(), &mut entries, &mut len),
+            -libc::ENOTDIR
+        );
+        showfs_tree_free(tree);
+    }
+}
@@ -0,0 +1,336 @@
+use fuse;
+use libc;
+
+use self::fuse::{FileAttr, FileType};
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+use std::io::{Error, Result};
+use std::iter;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::fs;
+use crate::locate;
+use crate::tree::VirtualTree;
+
+/// the name of the directory of all files at the root.
+pub const DIR_NAME: &str = ".all";
+
+// names longer than this can not be looked up by the kernel.
+const NAME_MAX: usize = 255;
+
+// the name of the file at the path in the directory of all files, like a.zip%2Fb%2Fc.jpg for
+// /a.zip/b/c.jpg. % is encoded too, so that names are decoded back to the paths.
+fn encode(path: &Path) -> OsString {
+    let mut name = Vec::new();
+    let path = path.strip_prefix("/").unwrap_or(path);
+    for &b in path.as_os_str().as_bytes() {
+        match b {
+            b'/' => name.extend_from_slice(b"%2F"),
+            b'%' => name.extend_from_slice(b"%25"),
+            b => name.push(b),
+        }
+    }
+    OsString::from_vec(name)
+}
+
+fn decode(name: &OsStr) -> Option<PathBuf> {
+    let mut path = b"/".to_vec();
+    let mut bytes = name.as_bytes();
+    while let Some((&b, rest)) = bytes.split_first() {
+        if b == b'/' {
+            return None;
+        }
+        if b != b'%' {
+            path.push(b);
+            bytes = rest;
+            continue;
+        }
+        match rest.get(..2) {
+            Some(b"2F") => path.push(b'/'),
+            Some(b"25") => path.push(b'%'),
+            _ => return None,
+        }
+        bytes = &rest[2..];
+    }
+    Some(PathBuf::from(OsString::from_vec(path)))
+}
+
+// resolves files in the directory of all files, keeping the directory of the last one, as
+// files are mostly opened in the order of the listing.
+struct Files {
+    tree: VirtualTree,
+    index: Arc<locate::Index>,
+    last: RefCell<Option<(PathBuf, Box<dyn fs::Dir>)>>,
+}
+
+impl Files {
+    fn resolve(&self, path: &Path) -> Result<Box<dyn fs::File>> {
+        let parent = path
+            .parent()
+            .ok_or_else(|| Error::from_raw_os_error(libc::ENOENT))?;
+        let mut last = self.last.borrow_mut();
+        let cached = match *last {
+            Some((ref p, _)) => p == parent,
+            None => false,
+        };
+        if !cached {
+            *last = match self.tree.resolve(parent)? {
+                fs::Entry::Dir(d) => Some((parent.to_path_buf(), d)),
+                fs::Entry::File(_) => return Err(Error::from_raw_os_error(libc::ENOTDIR)),
+            };
+        }
+        let dir = &last.as_ref().unwrap().1;
+        match self.tree.lookup(&**dir, path)? {
+            fs::Entry::File(f) => Ok(f),
+            fs::Entry::Dir(_) => Err(Error::from_raw_os_error(libc::ENOENT)),
+        }
+    }
+}
+
+// a file in the directory of all files, which is resolved at the first use, so that listing
+// the directory does not read every archive.
+struct File {
+    files: Rc<Files>,
+    path: PathBuf,
+    name: OsString,
+    inner: RefCell<Option<Box<dyn fs::File>>>,
+}
+
+impl File {
+    fn new(files: Rc<Files>, path: PathBuf) -> File {
+        File {
+            name: encode(&path),
+            files: files,
+            path: path,
+            inner: RefCell::new(None),
+        }
+    }
+
+    fn with<T, F: FnOnce(&dyn fs::File) -> Result<T>>(&self, f: F) -> Result<T> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.is_none() {
+            *inner = Some(self.files.resolve(&self.path)?);
+        }
+        f(&**inner.as_ref().unwrap())
+    }
+}
+
+impl fs::File for File {
+    fn getattr(&self) -> Result<FileAttr> {
+        self.with(|f| f.getattr())
+    }
+
+    fn open(&self) -> Result<Box<dyn fs::SeekableRead>> {
+        self.with(|f| f.open())
+    }
+
+    fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    // only regular files are listed.
+    fn file_type(&self) -> Result<FileType> {
+        Ok(FileType::RegularFile)
+    }
+
+    fn nesting(&self) -> usize {
+        self.with(|f| Ok(f.nesting())).unwrap_or(0)
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.with(|f| f.listxattr())
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.with(|f| f.getxattr(name))
+    }
+
+    fn direct_io(&self) -> bool {
+        self.with(|f| Ok(f.direct_io())).unwrap_or(false)
+    }
+
+    fn volatility(&self) -> fs::Volatility {
+        let volatility = self.with(|f| Ok(f.volatility()));
+        volatility.unwrap_or(fs::Volatility::Volatile)
+    }
+
+    fn reopen_cost(&self) -> u64 {
+        self.with(|f| Ok(f.reopen_cost())).unwrap_or(0)
+    }
+}
+
+// the directory of all files, listed from the last build of the locate index.
+struct Dir {
+    attr: FileAttr,
+    files: Rc<Files>,
+}
+
+impl fs::Dir for Dir {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        let files = self.files.clone();
+        let paths = self.files.index.files().map_err(|e| {
+            debug!(error = ?e, "all files are not listed yet");
+            Error::from_raw_os_error(libc::EAGAIN)
+        })?;
+        let entries = paths.into_iter().filter_map(move |path| {
+            let file = File::new(files.clone(), path);
+            if file.name.len() > NAME_MAX {
+                return None;
+            }
+            Some(Ok(fs::Entry::File(Box::new(file))))
+        });
+        Ok(Box::new(entries))
+    }
+
+    fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
+        let path = decode(name).ok_or_else(|| Error::from_raw_os_error(libc::ENOENT))?;
+        let file = File::new(self.files.clone(), path);
+        // fails here if the file is gone.
+        file.with(|_| Ok(()))?;
+        Ok(fs::Entry::File(Box::new(file)))
+    }
+
+    // the listing changes with each build of the index.
+    fn getattr(&self) -> Result<FileAttr> {
+        let mut attr = self.attr;
+        if let Some(at) = self.files.index.built_at() {
+            attr.mtime = at;
+            attr.ctime = at;
+        }
+        Ok(attr)
+    }
+
+    fn name(&self) -> &OsStr {
+        OsStr::new(DIR_NAME)
+    }
+}
+
+/// RootDir adds the directory of all files to the root of the mount, which lists every
+/// regular file in the mount by names encoding their paths, like a.zip%2Fb%2Fc.jpg, for media
+/// players which do not look into directories. the files are the ones found by the last build
+/// of the locate index.
+pub struct RootDir {
+    inner: Box<dyn fs::Dir>,
+    files: Rc<Files>,
+}
+
+impl RootDir {
+    pub fn new(inner: Box<dyn fs::Dir>, tree: VirtualTree, index: Arc<locate::Index>) -> RootDir {
+        RootDir {
+            inner: inner,
+            files: Rc::new(Files {
+                tree: tree,
+                index: index,
+                last: RefCell::new(None),
+            }),
+        }
+    }
+
+    fn all_dir(&self) -> Result<fs::Entry> {
+        let mut attr = self.inner.getattr()?;
+        attr.perm = 0o555;
+        Ok(fs::Entry::Dir(Box::new(Dir {
+            attr: attr,
+            files: self.files.clone(),
+        })))
+    }
+}
+
+impl fs::Dir for RootDir {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        let all_dir = self.all_dir();
+        // the directory of all files hides an entry of the same name.
+        let entries = self.inner.open()?.filter(|e| match *e {
+            Ok(ref e) => e.name() != DIR_NAME,
+            Err(_) => true,
+        });
+        Ok(Box::new(entries.chain(iter::once(all_dir))))
+    }
+
+    fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
+        if name == DIR_NAME {
+            self.all_dir()
+        } else {
+            self.inner.lookup(name)
+        }
+    }
+
+    fn getattr(&self) -> Result<FileAttr> {
+        self.inner.getattr()
+    }
+
+    fn name(&self) -> &OsStr {
+        self.inner.name()
+    }
+
+    fn origin(&self) -> Option<&Path> {
+        self.inner.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.inner.member()
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.inner.listxattr()
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.inner.getxattr(name)
+    }
+}
+
+#[test]
+fn test_encode() {
+    let name = encode(Path::new("/a.zip/b%c/d.jpg"));
+    assert_eq!(name, "a.zip%2Fb%25c%2Fd.jpg");
+    assert_eq!(decode(&name), Some(PathBuf::from("/a.zip/b%c/d.jpg")));
+    assert_eq!(decode(OsStr::new("a%2")), None);
+    assert_eq!(decode(OsStr::new("a%41")), None);
+}
+
+#[test]
+fn test_all_files() {
+    use crate::fs::{Dir as _, ShowFS};
+    use std::io::Read;
+
+    let tmp = tempfile::tempdir().unwrap();
+    std::fs::create_dir(tmp.path().join("a")).unwrap();
+    std::fs::write(tmp.path().join("a/b.jpg"), "b").unwrap();
+    std::fs::write(tmp.path().join("c%d.jpg"), "c").unwrap();
+    let index = locate::Index::new(tmp.path());
+    let tree = ShowFS::new(tmp.path()).into_tree();
+    let root = match tree.resolve("/").unwrap() {
+        fs::Entry::Dir(d) => d,
+        fs::Entry::File(_) => panic!("not a directory"),
+    };
+    let root = RootDir::new(root, ShowFS::new(tmp.path()).into_tree(), index.clone());
+    let all = match root.lookup(OsStr::new(DIR_NAME)).unwrap() {
+        fs::Entry::Dir(d) => d,
+        fs::Entry::File(_) => panic!("not a directory"),
+    };
+    // not listed until the index is built.
+    assert!(all.open().is_err());
+    index.build();
+    let names: Vec<_> = all
+        .open()
+        .unwrap()
+        .map(|e| e.unwrap().name().to_owned())
+        .collect();
+    assert_eq!(names, vec!["a%2Fb.jpg", "c%25d.jpg"]);
+
+    let f = match all.lookup(OsStr::new("a%2Fb.jpg")).unwrap() {
+        fs::Entry::File(f) => f,
+        fs::Entry::Dir(_) => panic!("not a file"),
+    };
+    assert_eq!(f.name(), "a%2Fb.jpg");
+    let mut contents = String::new();
+    f.open().unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "b");
+    // directories and files which are gone are not found.
+    assert!(all.lookup(OsStr::new("a")).is_err());
+    assert!(all.lookup(OsStr::new("a%2Fnone.jpg")).is_err());
+}
@@ -1,32 +1,73 @@
 use fuse;
 use libc;
 use time;
+use tracing;
 
 use self::fuse::{
     FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
-    ReplyOpen, Request,
+    ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr, Request,
 };
 use self::time::Timespec;
-use std::collections::HashMap;
+use self::tracing::{field, Span};
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::AsRef;
 use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Cursor, Error, ErrorKind, Result};
 use std::io::{Read, Seek, SeekFrom};
 use std::iter;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::rc::Rc;
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use std::vec::Vec;
 
+use crate::access::AccessPolicy;
+use crate::audit::AuditLog;
+use crate::collection;
+use crate::confine::Confinement;
+use crate::control::{self, Control, HandleKind};
+use crate::error;
+use crate::flat;
+use crate::inodes::InodeMap;
+use crate::locate;
 use crate::physical;
+use crate::stats::{Activity, Stats, Timer};
+use crate::tree::VirtualTree;
 
+// the operation, inode and path are fields of the event, as spans are logged only at debug.
 macro_rules! error_with_log {
-    ($reply:expr, $e:expr) => {{
+    ($reply:expr, $e:expr, $op:expr, $ino:expr, $path:expr) => {{
         let cerr = to_cerr(&$e);
+        let path = $path;
         if cerr == libc::ENOENT {
-            warn!("{}:{}: {:?}", file!(), line!(), $e);
+            warn!(
+                op = $op,
+                ino = $ino,
+                path = %path.display(),
+                errno = cerr,
+                error = ?$e,
+                "{}:{}",
+                file!(),
+                line!()
+            );
         } else {
-            error!("{}:{}: {:?}", file!(), line!(), $e);
+            error!(
+                op = $op,
+                ino = $ino,
+                path = %path.display(),
+                errno = cerr,
+                error = ?$e,
+                "{}:{}",
+                file!(),
+                line!()
+            );
         }
         $reply.error(cerr)
     }};
@@ -34,10 +75,76 @@ macro_rules! error_with_log {
 
 // TODO: configurable?
 const TTL: Timespec = Timespec { sec: 1, nsec: 0 };
+// the ttl of immutable entries, long enough to be forever as the kernel forgets them sooner.
+const IMMUTABLE_TTL: Timespec = Timespec {
+    sec: 24 * 60 * 60,
+    nsec: 0,
+};
+
+// assigned inodes are saved at this many, besides at unmount.
+const INODE_SAVE_INTERVAL: usize = 1024;
+
+// flags of open replies.
+const FOPEN_DIRECT_IO: u32 = 1;
+const FOPEN_KEEP_CACHE: u32 = 2;
+// capabilities which the fuse crate asks the kernel for at init. it does not tell the
+// connection, so they are not negotiated by options, and others like readdirplus and parallel
+// dirops are not available.
+const INIT_CAPABILITIES: &str = "async_read";
+
+// rough bytes of an entry with its names and tables, and of a listed entry, for the budget.
+const ENTRY_BYTES: usize = 512;
+const LISTED_BYTES: usize = 64;
+// parents and names of evicted entries kept, over which the ones evicted first are dropped.
+// they are few bytes each, and not counted for the budget.
+const MAX_EVICTED: usize = 65536;
+
+// the block size of statfs replies, which fuse replies by default.
+const STATFS_BLOCK_SIZE: u32 = 512;
+
+/// SeekableRead is the contents of an opened file.
+pub trait SeekableRead: Seek + Read {
+    /// reads from the offset, like pread. readers which can serve it without their position
+    /// do so, so that reads at several offsets do not race on it. the others seek and read.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read(buf)
+    }
+}
+
+impl<R: SeekableRead + ?Sized> SeekableRead for Box<R> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        (**self).read_at(offset, buf)
+    }
+}
+
+impl SeekableRead for fs::File {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        FileExt::read_at(self, buf, offset)
+    }
+}
 
-pub trait SeekableRead: Seek + Read {}
-impl<T: Seek + Read> SeekableRead for T {}
+impl<T: AsRef<[u8]>> SeekableRead for Cursor<T> {}
 
+/// Volatility tells how long the kernel may keep attributes of an entry and its name.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Volatility {
+    // may change any time, like files on the disk.
+    Volatile,
+    // never changes while mounted, like members of archives.
+    Immutable,
+}
+
+impl Volatility {
+    fn ttl(self) -> Timespec {
+        match self {
+            Volatility::Volatile => TTL,
+            Volatility::Immutable => IMMUTABLE_TTL,
+        }
+    }
+}
+
+/// Entry is a file or a directory in the tree.
 pub enum Entry {
     File(Box<dyn File>),
     Dir(Box<dyn Dir>),
@@ -60,31 +167,159 @@ impl Entry {
             &Entry::Dir(ref d) => d.name(),
         }
     }
-    pub fn file_type(&self, ino: u64) -> Result<FileType> {
-        self.getattr(ino).map(|a| a.kind)
+    // directories are known by the variant, without getattr.
+    pub fn file_type(&self) -> Result<FileType> {
+        match self {
+            &Entry::File(ref f) => f.file_type(),
+            &Entry::Dir(_) => Ok(FileType::Directory),
+        }
+    }
+    pub fn origin(&self) -> Option<&Path> {
+        match self {
+            &Entry::File(ref f) => f.origin(),
+            &Entry::Dir(ref d) => d.origin(),
+        }
+    }
+    pub fn member(&self) -> Option<PathBuf> {
+        match self {
+            &Entry::File(ref f) => f.member(),
+            &Entry::Dir(ref d) => d.member(),
+        }
+    }
+    pub fn hardlink_key(&self) -> Option<(u64, u64)> {
+        match self {
+            &Entry::File(ref f) => f.hardlink_key(),
+            &Entry::Dir(_) => None,
+        }
+    }
+    pub fn volatility(&self) -> Volatility {
+        match self {
+            &Entry::File(ref f) => f.volatility(),
+            &Entry::Dir(ref d) => d.volatility(),
+        }
+    }
+    pub fn listxattr(&self) -> Result<Vec<OsString>> {
+        match self {
+            &Entry::File(ref f) => f.listxattr(),
+            &Entry::Dir(ref d) => d.listxattr(),
+        }
+    }
+    pub fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        match self {
+            &Entry::File(ref f) => f.getxattr(name),
+            &Entry::Dir(ref d) => d.getxattr(name),
+        }
+    }
+    pub fn readlink(&self) -> Result<PathBuf> {
+        match self {
+            &Entry::File(ref f) => f.readlink(),
+            &Entry::Dir(_) => Err(Error::from_raw_os_error(libc::EINVAL)),
+        }
     }
 }
 
+/// File is a regular file, or any non directory entry.
 pub trait File {
     fn getattr(&self) -> Result<FileAttr>;
     fn open(&self) -> Result<Box<dyn SeekableRead>>;
     fn name(&self) -> &OsStr;
+    // readdir only needs the type, implementors may know it without getattr.
+    fn file_type(&self) -> Result<FileType> {
+        self.getattr().map(|a| a.kind)
+    }
+    // files sharing the key, typically (dev, ino) of the source, are given the same inode.
+    fn hardlink_key(&self) -> Option<(u64, u64)> {
+        None
+    }
+    // the path on the disk, if the file is not virtual.
+    fn physical_path(&self) -> Option<&Path> {
+        None
+    }
+    // the file on the disk which the contents are read from, like the archive of archived files.
+    fn origin(&self) -> Option<&Path> {
+        self.physical_path()
+    }
+    // the path of the file in the origin, like b/c.jpg for /a.zip/b/c.jpg, through archives in
+    // archives, like b.zip/c.jpg for /a.zip/b.zip/c.jpg. None if the file is the origin.
+    fn member(&self) -> Option<PathBuf> {
+        None
+    }
+    // the number of archives, or other views, which the file is in.
+    fn nesting(&self) -> usize {
+        0
+    }
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        Ok(Vec::new())
+    }
+    fn getxattr(&self, _name: &OsStr) -> Result<Vec<u8>> {
+        Err(Error::from_raw_os_error(libc::ENODATA))
+    }
+    // the target of a symbolic link, EINVAL like readlink(2) for other files.
+    fn readlink(&self) -> Result<PathBuf> {
+        Err(Error::from_raw_os_error(libc::EINVAL))
+    }
+    // if true, reads are not limited by the size, which is unknown until open.
+    fn direct_io(&self) -> bool {
+        false
+    }
+    // picks the ttl of replies, entries are volatile unless they tell.
+    fn volatility(&self) -> Volatility {
+        Volatility::Volatile
+    }
+    // a hint of the bytes read besides the contents to read the file again, like members
+    // before it in a compressed archive, by which caches keep costly files longer.
+    fn reopen_cost(&self) -> u64 {
+        0
+    }
+    // files are read only but virtual ones.
+    fn write(&self, _data: &[u8]) -> Result<()> {
+        Err(Error::from_raw_os_error(libc::EROFS))
+    }
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(Error::from_raw_os_error(libc::EROFS))
+    }
 }
 
+/// Dir is a directory, which lists and looks up its entries.
 pub trait Dir {
     fn open(&self) -> Result<Box<dyn Iterator<Item = Result<Entry>>>>;
     fn lookup(&self, name: &OsStr) -> Result<Entry>;
     fn getattr(&self) -> Result<FileAttr>;
     fn name(&self) -> &OsStr;
+    // the directory or the file on the disk which the entries are read from.
+    fn origin(&self) -> Option<&Path> {
+        None
+    }
+    // the path of the directory in the origin, like File::member.
+    fn member(&self) -> Option<PathBuf> {
+        None
+    }
+    fn volatility(&self) -> Volatility {
+        Volatility::Volatile
+    }
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        Ok(Vec::new())
+    }
+    fn getxattr(&self, _name: &OsStr) -> Result<Vec<u8>> {
+        Err(Error::from_raw_os_error(libc::ENODATA))
+    }
 }
 
-fn to_cerr(e: &Error) -> libc::c_int {
-    match e.raw_os_error() {
-        Some(raw) => raw,
-        None => libc::EIO,
+// replies the value, or its size if the kernel asks.
+fn reply_xattr(reply: ReplyXattr, size: u32, value: &[u8]) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if (size as usize) < value.len() {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(value);
     }
 }
 
+fn to_cerr(e: &Error) -> libc::c_int {
+    error::errno(e)
+}
+
 struct InodeReserver {
     inode: u64,
 }
@@ -95,10 +330,26 @@ impl InodeReserver {
     }
 }
 
+// the tables of entries and handlers are owned by the fuse loop, which serves requests one by
+// one in a thread. entries are not Send for their Rc, so the tables have no locks to shard.
+// they should be sharded when requests are served by threads.
 struct EntryHolder {
     inode: u64,
     inode_to_entry: HashMap<u64, Entry>,
     path_to_inode: HashMap<(u64, OsString), u64>,
+    inode_to_parent: HashMap<u64, (u64, OsString)>,
+    hardlink_to_inode: HashMap<(u64, u64), u64>,
+    // lookups replied to the kernel and not forgotten yet.
+    lookups: HashMap<u64, u64>,
+    // inodes of paths kept across mounts.
+    inode_map: Option<InodeMap>,
+    // parents and names of evicted entries, to make them again if the kernel uses them, like
+    // by handles of nfs exports.
+    evicted: HashMap<u64, (u64, OsString)>,
+    // evicted inodes, the first evicted at the front.
+    evicted_order: VecDeque<u64>,
+    // how many times inodes are given to other files, which is added to their generations.
+    reuses: HashMap<u64, u64>,
 }
 
 impl EntryHolder {
@@ -107,8 +358,33 @@ impl EntryHolder {
             inode: 0,
             inode_to_entry: HashMap::new(),
             path_to_inode: HashMap::new(),
+            inode_to_parent: HashMap::new(),
+            hardlink_to_inode: HashMap::new(),
+            lookups: HashMap::new(),
+            inode_map: None,
+            evicted: HashMap::new(),
+            evicted_order: VecDeque::new(),
+            reuses: HashMap::new(),
         }
     }
+    // the path from the mount root, the first registered one for hardlinks.
+    fn path(&self, mut ino: u64) -> PathBuf {
+        let mut names = Vec::new();
+        while let Some(&(parent, ref name)) = self
+            .inode_to_parent
+            .get(&ino)
+            .or_else(|| self.evicted.get(&ino))
+        {
+            if parent == 0 {
+                break;
+            }
+            names.push(name);
+            ino = parent;
+        }
+        let mut path = PathBuf::from("/");
+        path.extend(names.iter().rev());
+        path
+    }
     fn get_by_path(&self, parent: u64, name: &OsStr) -> Option<(u64, &Entry)> {
         self.path_to_inode
             .get(&(parent, name.to_os_string()))
@@ -119,25 +395,214 @@ impl EntryHolder {
         self.inode += 1;
         InodeReserver { inode: i }
     }
+    // reuses the inode of a registered hardlink if any, or the one in the inode map.
+    fn reserve_inode_for(&mut self, parent: u64, ent: &Entry) -> InodeReserver {
+        let linked = ent
+            .hardlink_key()
+            .and_then(|key| self.hardlink_to_inode.get(&key));
+        if let Some(&ino) = linked {
+            return InodeReserver { inode: ino };
+        }
+        if self.inode_map.is_none() {
+            return self.reserve_inode();
+        }
+        let path = self.path(parent).join(ent.name());
+        let mapped = self.inode_map.as_ref().and_then(|m| m.get(&path));
+        match mapped {
+            Some((ino, _)) => InodeReserver { inode: ino },
+            None => {
+                let ir = self.reserve_inode();
+                let map = self.inode_map.as_mut().unwrap();
+                map.insert(path, ir.inode());
+                if map.unsaved() >= INODE_SAVE_INTERVAL {
+                    if let Err(e) = map.save() {
+                        error!(error = ?e, "failed to save inodes");
+                    }
+                }
+                ir
+            }
+        }
+    }
+    fn set_inode_map(&mut self, map: InodeMap) {
+        self.inode = self.inode.max(map.max_inode() + 1);
+        self.inode_map = Some(map);
+    }
+    // the generation of the inode, which changes if the inode may be of another file.
+    fn generation(&self, ino: u64) -> u64 {
+        let reuses = self.reuses.get(&ino).cloned().unwrap_or(0);
+        let map = match self.inode_map {
+            Some(ref map) => map,
+            None => return reuses,
+        };
+        match map.get(&self.path(ino)) {
+            Some((mapped, generation)) if mapped == ino => generation + reuses,
+            _ => map.generation() + reuses,
+        }
+    }
+    fn save_inodes(&mut self) {
+        if let Some(ref mut map) = self.inode_map {
+            if let Err(e) = map.save() {
+                error!(error = ?e, "failed to save inodes");
+            }
+        }
+    }
     fn register_with(&mut self, parent: u64, ent: Entry, ir: InodeReserver) {
-        debug!("register {:?} with {}", ent.name(), ir.inode);
+        debug!(ino = ir.inode, name = ?ent.name(), "register");
+        if let Some(key) = ent.hardlink_key() {
+            self.hardlink_to_inode.entry(key).or_insert(ir.inode);
+        }
         self.path_to_inode
             .insert((parent, ent.name().to_os_string()), ir.inode);
-        self.inode_to_entry.insert(ir.inode, ent);
+        self.inode_to_parent
+            .entry(ir.inode)
+            .or_insert((parent, ent.name().to_os_string()));
+        // the first registered one of hardlinks represents the inode.
+        self.inode_to_entry.entry(ir.inode).or_insert(ent);
     }
     fn register_root(&mut self, root: Entry) {
-        self.inode = 2; // next to root (1)
+        self.inode = self.inode.max(2); // next to root (1)
         self.register_with(0, root, InodeReserver { inode: 1 })
     }
     fn get_by_inode(&self, ino: u64) -> Option<&Entry> {
         self.inode_to_entry.get(&ino)
     }
+    fn add_lookup(&mut self, ino: u64) {
+        *self.lookups.entry(ino).or_insert(0) += 1;
+    }
+    fn forget(&mut self, ino: u64, nlookup: u64) {
+        if let Some(n) = self.lookups.get_mut(&ino) {
+            *n = n.saturating_sub(nlookup);
+            if *n == 0 {
+                self.lookups.remove(&ino);
+            }
+        }
+    }
+    fn used_bytes(&self) -> usize {
+        self.inode_to_entry.len() * ENTRY_BYTES
+    }
+    // removes entries which the kernel does not refer to, but the root.
+    // children keep their parents referred, so paths of the rest are kept.
+    fn evict_forgotten(&mut self) -> usize {
+        let lookups = &self.lookups;
+        let evicted: HashSet<u64> = self
+            .inode_to_entry
+            .keys()
+            .filter(|&&ino| ino != 1 && !lookups.contains_key(&ino))
+            .cloned()
+            .collect();
+        let mut parents: Vec<_> = evicted
+            .iter()
+            .filter_map(|ino| self.inode_to_parent.get(ino).map(|p| (*ino, p.clone())))
+            .collect();
+        // parents, which have smaller inodes, are dropped first.
+        parents.sort();
+        self.remove(&evicted);
+        for (ino, parent) in parents {
+            self.evicted.insert(ino, parent);
+            self.evicted_order.push_back(ino);
+        }
+        while self.evicted_order.len() > MAX_EVICTED {
+            if let Some(ino) = self.evicted_order.pop_front() {
+                self.evicted.remove(&ino);
+            }
+        }
+        evicted.len()
+    }
+    // the parent and the name of the evicted inode.
+    fn evicted_parent(&self, ino: u64) -> Option<(u64, OsString)> {
+        self.evicted.get(&ino).cloned()
+    }
+    // registers the entry made again for the evicted inode. the path keeps its inode if it is
+    // looked up again since.
+    fn restore(&mut self, ino: u64, parent: u64, ent: Entry) {
+        let (_, name) = self.evicted.remove(&ino).unwrap();
+        self.evicted_order.retain(|&i| i != ino);
+        self.path_to_inode
+            .entry((parent, name.clone()))
+            .or_insert(ino);
+        self.inode_to_parent.insert(ino, (parent, name));
+        self.inode_to_entry.insert(ino, ent);
+    }
+    fn remove(&mut self, inodes: &HashSet<u64>) {
+        self.inode_to_entry.retain(|ino, _| !inodes.contains(ino));
+        self.inode_to_parent.retain(|ino, _| !inodes.contains(ino));
+        self.path_to_inode.retain(|_, ino| !inodes.contains(ino));
+        self.hardlink_to_inode
+            .retain(|_, ino| !inodes.contains(ino));
+        self.evicted.retain(|ino, _| !inodes.contains(ino));
+        self.evicted_order.retain(|ino| !inodes.contains(ino));
+    }
+    // the entry of the inode is made again, like by reindex.
+    fn replace(&mut self, ino: u64, ent: Entry) {
+        self.inode_to_entry.insert(ino, ent);
+    }
+    // replaces the entry of the inode by the one of the same path, which may be another file
+    // if it changed. then the generation is bumped, so that handles of the old file are stale.
+    fn renew(&mut self, ino: u64, ent: Entry) {
+        let reused = match self.inode_to_entry.get(&ino) {
+            Some(old) => !same_file(old, &ent),
+            None => false,
+        };
+        if reused {
+            debug!(ino = ino, "the inode is reused by another file");
+            *self.reuses.entry(ino).or_insert(0) += 1;
+        }
+        self.replace(ino, ent);
+    }
+    // inodes but the root, parents first.
+    fn inodes_by_depth(&self) -> Vec<u64> {
+        let mut inodes: Vec<_> = self
+            .inode_to_entry
+            .keys()
+            .filter(|&&ino| ino != 1)
+            .map(|&ino| (self.path(ino).components().count(), ino))
+            .collect();
+        inodes.sort();
+        inodes.into_iter().map(|(_, ino)| ino).collect()
+    }
+}
+
+// tells whether the entries are of the same file, by the device and the inode on the disk,
+// or by the size and the mtime of virtual ones, like files in archives. directories of the
+// same path are the same even if their entries changed.
+fn same_file(a: &Entry, b: &Entry) -> bool {
+    if let (Some(a), Some(b)) = (a.hardlink_key(), b.hardlink_key()) {
+        return a == b;
+    }
+    match (a.getattr(0), b.getattr(0)) {
+        (Ok(a), Ok(b)) if a.kind == FileType::Directory => b.kind == FileType::Directory,
+        (Ok(a), Ok(b)) => a.kind == b.kind && a.size == b.size && a.mtime == b.mtime,
+        _ => false,
+    }
+}
+
+// an entry of a listed directory.
+struct Listed {
+    ino: u64,
+    name: OsString,
+    kind: FileType,
+}
+
+// a listing of a directory and the mtime of the directory, which changes with the entries.
+struct Listing {
+    mtime: Timespec,
+    entries: Rc<Vec<Listed>>,
+}
+
+enum DirHandler {
+    // reads the directory, and remembers the entries if the mtime is known.
+    Reading(
+        iter::Peekable<Box<dyn Iterator<Item = Result<Entry>>>>,
+        Option<(Timespec, Vec<Listed>)>,
+    ),
+    // replays the listing remembered before.
+    Listed(Rc<Vec<Listed>>),
 }
 
 struct HandlerHolder {
     fh: u64, // fh counter
     file_handlers: HashMap<u64, Box<dyn SeekableRead>>,
-    dir_handlers: HashMap<u64, iter::Peekable<Box<dyn Iterator<Item = Result<Entry>>>>>,
+    dir_handlers: HashMap<u64, DirHandler>,
 }
 
 impl HandlerHolder {
@@ -154,14 +619,10 @@ impl HandlerHolder {
         self.file_handlers.insert(fh, r);
         return fh;
     }
-    fn register_dir<I>(&mut self, iter: I) -> u64
-    where
-        I: Iterator<Item = Result<Entry>> + 'static,
-    {
+    fn register_dir(&mut self, handler: DirHandler) -> u64 {
         let fh = self.fh;
         self.fh += 1;
-        let iter: Box<dyn Iterator<Item = Result<Entry>>> = Box::new(iter);
-        self.dir_handlers.insert(fh, iter.peekable());
+        self.dir_handlers.insert(fh, handler);
         return fh;
     }
     fn get_file(&self, fh: u64) -> Option<&Box<dyn SeekableRead>> {
@@ -170,10 +631,7 @@ impl HandlerHolder {
     fn get_file_mut(&mut self, fh: u64) -> Option<&mut Box<dyn SeekableRead>> {
         self.file_handlers.get_mut(&fh)
     }
-    fn get_dir_mut(
-        &mut self,
-        fh: u64,
-    ) -> Option<&mut iter::Peekable<Box<dyn Iterator<Item = Result<Entry>>>>> {
+    fn get_dir_mut(&mut self, fh: u64) -> Option<&mut DirHandler> {
         self.dir_handlers.get_mut(&fh)
     }
     fn release_file(&mut self, fh: u64) {
@@ -183,20 +641,84 @@ impl HandlerHolder {
     fn release_dir(&mut self, fh: u64) -> bool {
         self.dir_handlers.remove(&fh).is_some()
     }
+    // releases handlers of files and directories, returning their handles.
+    fn release_all(&mut self) -> Vec<u64> {
+        let mut fhs: Vec<_> = self.file_handlers.drain().map(|(fh, _)| fh).collect();
+        fhs.extend(self.dir_handlers.drain().map(|(fh, _)| fh));
+        fhs
+    }
 }
 
+/// ViewContext tells viewers where an entry is shown.
+pub struct ViewContext {
+    path: PathBuf,
+    parent_attr: Option<FileAttr>,
+    control: Rc<Control>,
+}
+
+impl ViewContext {
+    pub fn new(path: PathBuf, parent_attr: Option<FileAttr>, control: Rc<Control>) -> ViewContext {
+        ViewContext {
+            path: path,
+            parent_attr: parent_attr,
+            control: control,
+        }
+    }
+
+    // the path of the entry from the mount root, like /comics/a.zip.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // the number of directories above the entry, 0 for the root.
+    pub fn depth(&self) -> usize {
+        self.path.components().count().saturating_sub(1)
+    }
+
+    // the attributes of the directory which has the entry, None for the root.
+    pub fn parent_attr(&self) -> Option<&FileAttr> {
+        self.parent_attr.as_ref()
+    }
+
+    // the option of the mount shown in .showfs/config, like "sort".
+    pub fn config(&self, key: &str) -> Option<String> {
+        self.control.config(key)
+    }
+
+    // the name of the viewer which the extension of the name is mapped to, None if it is
+    // not mapped or blocked.
+    pub fn viewer_for(&self, name: &OsStr) -> Option<String> {
+        self.control.viewer_for(name)
+    }
+}
+
+/// Viewer changes how an entry is shown, like an archive file as a directory.
+/// viewers are applied to every listed or looked up entry, in the order of registration.
+/// viewers of files by extensions have names, which extensions are mapped to.
 pub trait Viewer {
-    fn view(&self, e: Entry) -> Entry;
+    fn view(&self, e: Entry, ctx: &ViewContext) -> Entry;
+    fn name(&self) -> &str {
+        ""
+    }
+    // extensions mapped to the viewer unless users map them to others.
+    fn extensions(&self) -> &[&str] {
+        &[]
+    }
 }
 
-struct CompositeViewer {
+// files are not shown by named viewers in as many archives, against crafted nests.
+const DEFAULT_MAX_DEPTH: usize = 4;
+
+pub(crate) struct CompositeViewer {
     viewers: Vec<Box<dyn Viewer>>,
+    max_depth: usize,
 }
 
 impl CompositeViewer {
     fn new() -> CompositeViewer {
         CompositeViewer {
             viewers: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
         }
     }
 
@@ -204,21 +726,129 @@ impl CompositeViewer {
         self.viewers.push(Box::new(v))
     }
 
-    fn view(&self, e: Entry) -> Entry {
+    pub(crate) fn view(&self, e: Entry, ctx: &ViewContext) -> Entry {
+        let pruned = match e {
+            Entry::File(ref f) => f.nesting() >= self.max_depth,
+            Entry::Dir(_) => false,
+        };
+        if pruned && ctx.viewer_for(e.name()).is_some() {
+            info!(
+                path = %ctx.path().display(),
+                "not viewed in {} nested archives",
+                self.max_depth
+            );
+        }
         let mut e = e;
         for viewer in self.viewers.iter() {
-            e = viewer.view(e);
+            // viewers without names, like normalize, do not nest.
+            if pruned && !viewer.name().is_empty() {
+                continue;
+            }
+            e = viewer.view(e, ctx);
         }
         e
     }
 }
 
+// what is shown at the root.
+#[derive(Clone)]
+pub(crate) enum Origin {
+    // a file or a directory.
+    Path(PathBuf),
+    // files listed, shown in a directory.
+    List(Rc<Vec<PathBuf>>),
+}
+
+impl Origin {
+    fn describe(&self) -> String {
+        match *self {
+            Origin::Path(ref path) => path.display().to_string(),
+            Origin::List(ref paths) => format!("list of {} files", paths.len()),
+        }
+    }
+}
+
+// the root of the tree, the origin through the viewers.
+pub(crate) fn view_origin(
+    origin: &Origin,
+    physical: &Rc<physical::Context>,
+    viewers: &CompositeViewer,
+    control: &Rc<Control>,
+) -> Result<Entry> {
+    let root = match *origin {
+        Origin::Path(ref path) if fs::metadata(path)?.is_dir() => Entry::Dir(Box::new(
+            physical::Dir::new(path.to_path_buf(), physical.clone()),
+        )),
+        Origin::Path(ref path) => Entry::File(Box::new(physical::File::new(
+            path.to_path_buf(),
+            physical.clone(),
+        ))),
+        Origin::List(ref paths) => Entry::Dir(Box::new(collection::CollectionDir::new(
+            paths,
+            physical.clone(),
+        ))),
+    };
+    let ctx = ViewContext::new(PathBuf::from("/"), None, control.clone());
+    Ok(viewers.view(root, &ctx))
+}
+
+// the root of a lazy mount until the origin is viewed, or while it can not be.
+struct UnloadedRoot;
+
+impl Dir for UnloadedRoot {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<Entry>>>> {
+        Err(Error::from_raw_os_error(libc::EIO))
+    }
+    fn lookup(&self, _name: &OsStr) -> Result<Entry> {
+        Err(Error::from_raw_os_error(libc::EIO))
+    }
+    fn getattr(&self) -> Result<FileAttr> {
+        Err(Error::from_raw_os_error(libc::EIO))
+    }
+    fn name(&self) -> &OsStr {
+        OsStr::new("")
+    }
+}
+
+/// ShowFS is the filesystem which shows the origin through viewers.
 pub struct ShowFS {
-    origin: PathBuf,
+    origin: Origin,
     entries: EntryHolder,
     handlers: HandlerHolder,
     viewers: Rc<CompositeViewer>,
+    physical: Rc<physical::Context>,
+    noatime: bool,
     buf: Vec<u8>,
+    stats: Rc<RefCell<Stats>>,
+    control: Rc<Control>,
+    control_dir: bool,
+    mounted: Option<Box<dyn FnOnce()>>,
+    // listings of directories by the inode.
+    listings: HashMap<u64, Listing>,
+    memory_budget: Option<usize>,
+    // the estimate of used bytes after the last relief, which is not retried until it grows.
+    relieved_bytes: usize,
+    lazy: bool,
+    statfs_cache: bool,
+    auto_cache: bool,
+    // the mtime and the size of files by the inode at the last open, for auto_cache.
+    opened_attrs: HashMap<u64, (Timespec, u64)>,
+    // the uid and the gid to switch to once /dev/fuse is opened.
+    privileges: Option<(u32, u32)>,
+    // applied to the thread serving the mount once /dev/fuse is opened.
+    confinement: Option<Confinement>,
+    // lets users other than the one who mounted access the mount.
+    allow_other: bool,
+    // which processes may open files and directories.
+    access_policy: Option<AccessPolicy>,
+    // false until the origin is viewed as the root.
+    root_loaded: bool,
+    // the mountpoint is a file, on which the origin is shown as a file.
+    file_root: bool,
+    // true once the mount is torn down.
+    shut_down: bool,
+    // lists regular files in the directory of all files at the root.
+    all_files: Option<Arc<locate::Index>>,
 }
 
 impl ShowFS {
@@ -226,57 +856,593 @@ impl ShowFS {
     where
         P: AsRef<Path>,
     {
+        ShowFS::with_origin(Origin::Path(origin.as_ref().to_path_buf()))
+    }
+
+    /// shows the listed files, typically archives, in the root directory.
+    /// files of the same name are shown with suffixes like "a (2).zip".
+    pub fn from_list(paths: Vec<PathBuf>) -> ShowFS {
+        ShowFS::with_origin(Origin::List(Rc::new(paths)))
+    }
+
+    fn with_origin(origin: Origin) -> ShowFS {
+        let stats = Rc::new(RefCell::new(Stats::new()));
         ShowFS {
-            origin: origin.as_ref().to_path_buf(),
+            origin: origin,
             entries: EntryHolder::new(),
             handlers: HandlerHolder::new(),
             viewers: Rc::new(CompositeViewer::new()),
+            // the kernel keeps attributes for TTL, so caching stats as long is enough.
+            physical: Rc::new(physical::Context::new(Duration::from_secs(TTL.sec as u64))),
+            noatime: false,
             buf: Vec::new(),
+            stats: stats.clone(),
+            control: Rc::new(Control::new(stats)),
+            control_dir: true,
+            mounted: None,
+            listings: HashMap::new(),
+            memory_budget: None,
+            relieved_bytes: 0,
+            lazy: false,
+            statfs_cache: false,
+            auto_cache: false,
+            opened_attrs: HashMap::new(),
+            privileges: None,
+            confinement: None,
+            allow_other: false,
+            access_policy: None,
+            root_loaded: false,
+            file_root: false,
+            shut_down: false,
+            all_files: None,
+        }
+    }
+
+    // if set, the mount is reported as noatime.
+    pub fn set_noatime(&mut self, noatime: bool) {
+        self.noatime = noatime;
+    }
+
+    // order of entries listed from physical directories.
+    pub fn set_sort_order(&mut self, order: physical::SortOrder) {
+        Rc::get_mut(&mut self.physical)
+            .unwrap()
+            .set_sort_order(order)
+    }
+
+    // if set, files are verified by their sidecars, like a.iso.sha256, when read to the end.
+    pub fn set_verify_sidecars(&mut self, verify: bool) {
+        Rc::get_mut(&mut self.physical)
+            .unwrap()
+            .set_verify_sidecars(verify)
+    }
+
+    // if set, the .showfs directory is shown at the root.
+    pub fn set_control_dir(&mut self, enabled: bool) {
+        self.control_dir = enabled;
+    }
+
+    // if set, every regular file found by the locate index is shown in /.all by a name
+    // encoding its path, like a.zip%2Fb.jpg, for media players which do not look into
+    // directories.
+    pub fn set_all_files(&mut self, index: Arc<locate::Index>) {
+        self.all_files = Some(index);
+    }
+
+    // if set, the origin is viewed at the first operation, not before mounting.
+    // operations on the root fail with EIO until the origin can be viewed.
+    pub fn set_lazy(&mut self, lazy: bool) {
+        self.lazy = lazy;
+    }
+
+    // if set, statfs reports the capacity of caches as the size and the unused as the free,
+    // so that df based checks can alarm on cache pressure.
+    pub fn set_statfs_cache(&mut self, enabled: bool) {
+        self.statfs_cache = enabled;
+    }
+
+    // if set, the kernel keeps cached pages of files across opens unless the mtime or the size
+    // changed since the last open, like auto_cache of libfuse. contents of archives never change,
+    // so they are read from showfs once as long as the kernel keeps them.
+    pub fn set_auto_cache(&mut self, enabled: bool) {
+        self.auto_cache = enabled;
+    }
+
+    // if set, the process drops to the user and the group once it is mounted, so that archives
+    // are not read as root. the lazy mount reads the origin after it too.
+    pub fn set_drop_privileges(&mut self, uid: u32, gid: u32) {
+        self.privileges = Some((uid, gid));
+    }
+
+    // if set, the thread serving the mount is confined once it is mounted, after privileges
+    // are dropped, so that bugs in parsers can not reach other files.
+    pub fn set_confinement(&mut self, confinement: Confinement) {
+        self.confinement = Some(confinement);
+    }
+
+    // if set, other users can access the mount, which needs user_allow_other in
+    // /etc/fuse.conf unless mounted by root.
+    pub fn set_allow_other(&mut self, allow_other: bool) {
+        self.allow_other = allow_other;
+    }
+
+    // opens of files and directories by processes which the policy does not permit fail with
+    // EACCES, so that the mount can be shared with some users or applications only.
+    pub fn set_access_policy(&mut self, policy: AccessPolicy) {
+        self.access_policy = Some(policy);
+    }
+
+    // whether the process of the request may open files and directories.
+    fn permits(&self, req: &Request<'_>) -> bool {
+        let policy = match self.access_policy {
+            Some(ref policy) => policy,
+            None => return true,
+        };
+        let permitted = policy.permits(req.uid(), req.pid());
+        if !permitted {
+            debug!(
+                uid = req.uid(),
+                pid = req.pid(),
+                "denied by the access policy"
+            );
+        }
+        permitted
+    }
+
+    // limits the bytes of caches, entries and listings altogether. over the budget, unused
+    // caches are dropped, then listings and entries which the kernel forgot are evicted.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = Some(bytes);
+    }
+
+    // an estimate of the bytes used by caches, entries and listings.
+    fn used_bytes(&self) -> usize {
+        let listed: usize = self.listings.values().map(|l| l.entries.len()).sum();
+        self.control.caches_used_bytes() + self.entries.used_bytes() + listed * LISTED_BYTES
+    }
+
+    fn relieve_memory(&mut self) {
+        let max = match self.memory_budget {
+            Some(max) => max,
+            None => return,
+        };
+        let used = self.used_bytes();
+        // nothing more is freed until more is used.
+        if used <= max || used <= self.relieved_bytes {
+            self.relieved_bytes = min(self.relieved_bytes, used);
+            return;
+        }
+        self.control.drop_caches();
+        if self.used_bytes() > max {
+            self.listings.clear();
+            // files opened next are read again, which is safe.
+            self.opened_attrs.clear();
+            let evicted = self.entries.evict_forgotten();
+            debug!(evicted = evicted, "evicted forgotten entries");
+        }
+        self.relieved_bytes = self.used_bytes();
+        debug!(
+            before = used,
+            after = self.relieved_bytes,
+            "relieved memory"
+        );
+    }
+
+    // inodes are kept in the map across mounts, which is saved at unmount.
+    pub fn set_inode_map(&mut self, map: InodeMap) {
+        self.entries.set_inode_map(map);
+    }
+
+    // records opens and closes of files, before the control is shared.
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        Rc::get_mut(&mut self.control).unwrap().set_audit_log(audit)
+    }
+
+    pub fn control(&self) -> Rc<Control> {
+        self.control.clone()
+    }
+
+    // tells other threads whether the filesystem is in an operation.
+    pub fn activity(&self) -> Arc<Activity> {
+        self.stats.borrow().activity()
+    }
+
+    // called when the filesystem is mounted, before serving requests.
+    pub fn set_mounted_callback<F: FnOnce() + 'static>(&mut self, f: F) {
+        self.mounted = Some(Box::new(f));
+    }
+
+    // records the path of the inode, only if the span is logged.
+    fn record_path(&self, span: &Span, ino: u64) {
+        if !span.is_disabled() {
+            span.record("path", &field::display(self.entries.path(ino).display()));
         }
     }
 
+    // files in as many archives are shown as they are. (default: 4)
+    pub fn set_max_depth(&mut self, depth: usize) {
+        Rc::get_mut(&mut self.viewers).unwrap().max_depth = depth;
+    }
+
     pub fn register_viewer<V: Viewer + 'static>(&mut self, v: V) {
+        for ext in v.extensions().iter() {
+            self.control.map_default_extension(ext, v.name());
+        }
         Rc::get_mut(&mut self.viewers).unwrap().add(v)
     }
 
+    /// the tree which would be mounted, to walk it without fuse.
+    pub fn into_tree(self) -> VirtualTree {
+        VirtualTree::new(self.origin, self.physical, self.viewers, self.control)
+    }
+
+    // the viewed origin with the control directory, which is a directory to be mounted.
+    fn root_entry(&self) -> Result<Entry> {
+        let root = view_origin(&self.origin, &self.physical, &self.viewers, &self.control)?;
+        let root = match (root, &self.all_files) {
+            (Entry::Dir(d), &Some(ref index)) if !self.file_root => {
+                let tree = VirtualTree::new(
+                    self.origin.clone(),
+                    self.physical.clone(),
+                    self.viewers.clone(),
+                    self.control.clone(),
+                );
+                Entry::Dir(Box::new(flat::RootDir::new(d, tree, index.clone())))
+            }
+            (root, _) => root,
+        };
+        match (root, self.file_root) {
+            (Entry::Dir(d), false) if self.control_dir => Ok(Entry::Dir(Box::new(
+                control::RootDir::new(d, self.control.clone()),
+            ))),
+            (Entry::Dir(d), false) => Ok(Entry::Dir(d)),
+            (Entry::File(f), true) => Ok(Entry::File(f)),
+            (Entry::File(_), false) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the origin is shown as a file, which needs a file mountpoint",
+            )),
+            (Entry::Dir(_), true) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the origin is shown as a directory, which needs a directory mountpoint",
+            )),
+        }
+    }
+
+    // drops listings, stats and cached contents, and views the entries known to the kernel
+    // again from the origin, keeping their inodes. entries which are gone are removed.
+    // dentries of the kernel can not be invalidated by fuse 0.3, they expire in their ttls.
+    fn reindex(&mut self) {
+        info!("reindexing");
+        self.listings.clear();
+        self.physical.clear_stat_cache();
+        self.control.drop_caches();
+        self.entries.evict_forgotten();
+        match self.root_entry() {
+            Ok(root) => {
+                self.entries.replace(1, root);
+                self.root_loaded = true;
+            }
+            Err(e) => {
+                error!(error = ?e, "failed to reindex the root");
+                return;
+            }
+        }
+        let mut gone = HashSet::new();
+        for ino in self.entries.inodes_by_depth() {
+            let (parent, name) = match self.entries.inode_to_parent.get(&ino) {
+                Some(&(parent, ref name)) => (parent, name.clone()),
+                None => continue,
+            };
+            let viewed = match self.entries.get_by_inode(parent) {
+                Some(&Entry::Dir(ref p)) if !gone.contains(&parent) => {
+                    let parent_attr = p.getattr().ok();
+                    p.lookup(&name).map(|ent| {
+                        let path = self.entries.path(ino);
+                        let ctx = ViewContext::new(path, parent_attr, self.control.clone());
+                        self.viewers.view(ent, &ctx)
+                    })
+                }
+                _ => Err(Error::from_raw_os_error(libc::ENOENT)),
+            };
+            match viewed {
+                Ok(ent) => self.entries.renew(ino, ent),
+                Err(e) => {
+                    debug!(ino = ino, error = ?e, "gone by reindex");
+                    gone.insert(ino);
+                }
+            }
+        }
+        self.entries.remove(&gone);
+        info!(gone = gone.len(), "reindexed");
+    }
+
+    // views the origin as the root of a lazy mount, which is retried until it succeeds.
+    fn load_root(&mut self) {
+        if self.root_loaded {
+            return;
+        }
+        match self.root_entry() {
+            Ok(root) => {
+                info!("the origin is loaded");
+                self.entries.replace(1, root);
+                self.root_loaded = true;
+            }
+            Err(e) => error!(error = ?e, "failed to load the origin"),
+        }
+    }
+
+    // makes the entry of the evicted inode again by looking it up from the nearest parent which
+    // is held, so that inodes which the kernel still uses do not fail with ENOENT.
+    fn restore(&mut self, ino: u64) {
+        if self.entries.get_by_inode(ino).is_some() {
+            return;
+        }
+        let (parent, name) = match self.entries.evicted_parent(ino) {
+            Some(parent) => parent,
+            None => return,
+        };
+        self.restore(parent);
+        let viewed = match self.entries.get_by_inode(parent) {
+            Some(&Entry::Dir(ref p)) => {
+                let parent_attr = p.getattr().ok();
+                p.lookup(&name).map(|ent| {
+                    let path = self.entries.path(ino);
+                    let ctx = ViewContext::new(path, parent_attr, self.control.clone());
+                    self.viewers.view(ent, &ctx)
+                })
+            }
+            _ => return,
+        };
+        match viewed {
+            Ok(ent) => {
+                debug!(ino = ino, "restored an evicted entry");
+                self.entries.restore(ino, parent, ent);
+            }
+            Err(e) => debug!(ino = ino, error = ?e, "failed to restore an evicted entry"),
+        }
+    }
+
+    fn reindex_if_requested(&mut self) {
+        if self.control.take_reindex() {
+            self.reindex();
+        }
+    }
+
     pub fn mount<P>(mut self, target: P) -> Result<()>
     where
         P: AsRef<Path>,
     {
-        let root = if fs::metadata(self.origin.clone())?.is_dir() {
-            Entry::Dir(Box::new(physical::Dir::new(self.origin.clone())))
+        // a file is mounted on by the origin shown as a file, like a.log for a.log.gz.
+        let metadata = fs::metadata(target.as_ref())?;
+        if !metadata.is_dir() && !metadata.is_file() {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid mountpoint"));
+        }
+        self.file_root = metadata.is_file();
+        if self.file_root && self.lazy {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "lazy mounts need a directory mountpoint",
+            ));
+        }
+        let viewed_root = if self.lazy {
+            Entry::Dir(Box::new(UnloadedRoot))
         } else {
-            Entry::File(Box::new(physical::File::new(self.origin.clone())))
+            self.root_loaded = true;
+            self.root_entry()?
         };
-        let viewed_root = self.viewers.view(root);
-        match viewed_root {
-            Entry::Dir(_) if fs::metadata(target.as_ref())?.is_dir() => {
-                // fallthrough
+        self.control.add_config("origin", self.origin.describe());
+        self.control
+            .add_config("mountpoint", target.as_ref().display().to_string());
+        self.entries.register_root(viewed_root);
+        // the generation is saved before inodes of it are replied.
+        self.entries.save_inodes();
+        let mut options = Vec::new();
+        if self.noatime {
+            options.push(OsStr::new("-o"));
+            options.push(OsStr::new("noatime"));
+        }
+        if self.allow_other {
+            options.push(OsStr::new("-o"));
+            options.push(OsStr::new("allow_other"));
+        }
+        let mounted = self.mounted.take();
+        let privileges = self.privileges;
+        let confinement = self.confinement.take();
+        let mut session = fuse::Session::new(self, target.as_ref(), &options)?;
+        if let Some((uid, gid)) = privileges {
+            drop_privileges(uid, gid)?;
+            info!(uid = uid, gid = gid, "dropped privileges");
+        }
+        if let Some(confinement) = confinement {
+            confinement.apply()?;
+            info!("confined");
+        }
+        if let Some(f) = mounted {
+            f();
+        }
+        // the session ends by ENODEV when the mount is unmounted, or by lazy unmounts once
+        // the last file is closed. the kernel sends no destroy to fuse but fuseblk mounts.
+        let result = session.run();
+        session.filesystem.shutdown();
+        // entries and viewers, with the cache, are released before returning.
+        drop(session);
+        match result {
+            Ok(()) => {
+                info!("unmounted");
+                Ok(())
             }
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "invalid origin or mountpoint",
-                ));
+            Err(e) => Err(error::Error::Disconnected(e.to_string()).into()),
+        }
+    }
+
+    // closes handles left open, like by forced unmounts, in the audit log too, and releases
+    // the caches. background work of viewers stops when they are dropped.
+    fn shutdown(&mut self) {
+        if self.shut_down {
+            return;
+        }
+        self.shut_down = true;
+        for fh in self.handlers.release_all() {
+            self.control.close_handle(fh);
+        }
+        self.listings.clear();
+        self.control.release_caches();
+        self.entries.save_inodes();
+        info!("latencies of operations:\n{}", self.stats.borrow());
+    }
+}
+
+// tells whether pages of the file which the kernel cached at the last open are still valid,
+// recording the mtime and the size of this open.
+fn keep_cache(opened_attrs: &mut HashMap<u64, (Timespec, u64)>, ino: u64, file: &dyn File) -> bool {
+    match file.getattr() {
+        Ok(attr) => {
+            let current = (attr.mtime, attr.size);
+            opened_attrs.insert(ino, current) == Some(current)
+        }
+        Err(_) => {
+            opened_attrs.remove(&ino);
+            false
+        }
+    }
+}
+
+/// switches all threads of the process to the user and the group, and sets no_new_privs,
+/// so that they are never gained again, like by executing setuid programs.
+/// no_new_privs applies to the calling thread and threads created by it later only, not to
+/// threads running already, like of the control socket.
+pub fn drop_privileges(uid: u32, gid: u32) -> Result<()> {
+    unsafe {
+        if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+            return Err(Error::last_os_error());
+        }
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// unmounts like `fusermount -u`.
+pub fn unmount<P: AsRef<Path>>(target: P) -> Result<()> {
+    let status = Command::new("fusermount")
+        .arg("-u")
+        .arg(target.as_ref())
+        .status()?;
+    if !status.success() {
+        return Err(error::Error::Fuse(format!("fusermount failed: {}", status)).into());
+    }
+    Ok(())
+}
+
+/// MountHandle is a mount served by a thread, which is unmounted when dropped.
+pub struct MountHandle {
+    target: PathBuf,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl MountHandle {
+    // waits until the mount is unmounted by others.
+    pub fn join(mut self) -> Result<()> {
+        match self.thread.take().unwrap().join() {
+            Ok(result) => result,
+            Err(_) => Err(error::Error::Fuse("mount thread panicked".to_string()).into()),
+        }
+    }
+
+    pub fn unmount(self) -> Result<()> {
+        unmount(&self.target)?;
+        self.join()
+    }
+}
+
+impl Drop for MountHandle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            if unmount(&self.target).is_ok() {
+                let _ = thread.join();
             }
         }
-        self.entries.register_root(viewed_root);
-        fuse::mount(self, &target, &[])
     }
 }
 
+/// mounts the filesystem made by f in a new thread, and returns when it is mounted.
+/// ShowFS is made in the thread, because it is not Send.
+pub fn spawn_mount<F, P>(f: F, target: P) -> Result<MountHandle>
+where
+    F: FnOnce() -> ShowFS + Send + 'static,
+    P: AsRef<Path>,
+{
+    let target = target.as_ref().to_path_buf();
+    let thread_target = target.clone();
+    let (tx, rx) = mpsc::channel();
+    let thread = thread::Builder::new()
+        .name("mount".to_string())
+        .spawn(move || {
+            let mut fs = f();
+            let mounted = fs.mounted.take();
+            fs.set_mounted_callback(move || {
+                if let Some(f) = mounted {
+                    f();
+                }
+                let _ = tx.send(());
+            });
+            fs.mount(thread_target)
+        })?;
+    // the callback is dropped without sending if the mount fails.
+    if rx.recv().is_err() {
+        return match thread.join() {
+            Ok(Err(e)) => Err(e),
+            _ => Err(error::Error::Fuse("mount thread exited".to_string()).into()),
+        };
+    }
+    Ok(MountHandle {
+        target: target,
+        thread: Some(thread),
+    })
+}
+
 impl Filesystem for ShowFS {
+    fn init(&mut self, _req: &Request<'_>) -> std::result::Result<(), libc::c_int> {
+        self.control
+            .add_config("capabilities", INIT_CAPABILITIES.to_string());
+        info!(capabilities = INIT_CAPABILITIES, "initialized");
+        Ok(())
+    }
+
+    fn destroy(&mut self, _req: &Request<'_>) {
+        self.shutdown();
+    }
+
     // kernel path resolving function
     fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let span = debug_span!("lookup", parent = parent, name = ?name, path = field::Empty);
+        let _enter = span.enter();
+        if !span.is_disabled() {
+            let path = self.entries.path(parent).join(name);
+            span.record("path", &field::display(path.display()));
+        }
+        let _timer = Timer::new(&self.stats, "lookup");
+        self.load_root();
+        self.reindex_if_requested();
+        self.control.scrub_if_due();
         // check cache.
         match self.entries.get_by_path(parent, name) {
             Some((ino, ent)) => match ent.getattr(ino) {
                 Ok(attr) => {
-                    reply.entry(&TTL, &attr, 0);
+                    let ttl = ent.volatility().ttl();
+                    self.entries.add_lookup(ino);
+                    reply.entry(&ttl, &attr, self.entries.generation(ino));
                     return;
                 }
                 Err(e) => {
-                    error_with_log!(reply, e);
+                    error_with_log!(
+                        reply,
+                        e,
+                        "lookup",
+                        parent,
+                        self.entries.path(parent).join(name)
+                    );
                     return;
                 }
             },
@@ -286,50 +1452,212 @@ impl Filesystem for ShowFS {
         }
 
         // look underlying.
-        let ret_ent = match self.entries.get_by_inode(parent) {
-            Some(&Entry::Dir(ref p)) => p.lookup(name),
+        self.restore(parent);
+        let (ret_ent, parent_attr) = match self.entries.get_by_inode(parent) {
+            Some(&Entry::Dir(ref p)) => (p.lookup(name), p.getattr().ok()),
             _ => {
                 reply.error(libc::ENOENT);
                 return;
             }
         };
-        let attr = match ret_ent {
+        let (attr, ttl) = match ret_ent {
             Ok(ent) => {
-                let ir = self.entries.reserve_inode();
-                let ent = self.viewers.view(ent);
+                let path = self.entries.path(parent).join(name);
+                let ctx = ViewContext::new(path, parent_attr, self.control.clone());
+                let ent = self.viewers.view(ent, &ctx);
+                let ir = self.entries.reserve_inode_for(parent, &ent);
                 let attr = ent.getattr(ir.inode());
+                let ttl = ent.volatility().ttl();
                 self.entries.register_with(parent, ent, ir);
-                attr
+                (attr, ttl)
             }
             Err(e) => {
-                error_with_log!(reply, e);
+                error_with_log!(
+                    reply,
+                    e,
+                    "lookup",
+                    parent,
+                    self.entries.path(parent).join(name)
+                );
                 return;
             }
         };
         match attr {
-            Ok(attr) => reply.entry(&TTL, &attr, 0),
-            Err(e) => error_with_log!(reply, e),
+            Ok(attr) => {
+                self.entries.add_lookup(attr.ino);
+                reply.entry(&ttl, &attr, self.entries.generation(attr.ino));
+            }
+            Err(e) => error_with_log!(
+                reply,
+                e,
+                "lookup",
+                parent,
+                self.entries.path(parent).join(name)
+            ),
         }
+        self.relieve_memory();
+    }
+
+    fn forget(&mut self, _req: &Request<'_>, ino: u64, nlookup: u64) {
+        self.entries.forget(ino, nlookup);
     }
 
     fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        let span = debug_span!("getattr", ino = ino, path = field::Empty);
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        let _timer = Timer::new(&self.stats, "getattr");
+        self.load_root();
+        self.reindex_if_requested();
+        self.control.scrub_if_due();
+        self.restore(ino);
         if let Some(ent) = self.entries.get_by_inode(ino) {
             match ent.getattr(ino) {
-                Ok(attr) => reply.attr(&TTL, &attr),
-                Err(e) => error_with_log!(reply, e),
+                Ok(attr) => reply.attr(&ent.volatility().ttl(), &attr),
+                Err(e) => error_with_log!(reply, e, "getattr", ino, self.entries.path(ino)),
             }
         } else {
             reply.error(libc::ENOENT);
         }
     }
 
-    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: u32, reply: ReplyOpen) {
+    // only truncation of virtual files is supported.
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<Timespec>,
+        _mtime: Option<Timespec>,
+        _fh: Option<u64>,
+        _crtime: Option<Timespec>,
+        _chgtime: Option<Timespec>,
+        _bkuptime: Option<Timespec>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let span = debug_span!("setattr", ino = ino, size = ?size, path = field::Empty);
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        self.load_root();
+        self.restore(ino);
+        let ent = match self.entries.get_by_inode(ino) {
+            Some(ent) => ent,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        if mode.is_some() || uid.is_some() || gid.is_some() {
+            reply.error(libc::EROFS);
+            return;
+        }
+        if let (Some(size), &Entry::File(ref f)) = (size, ent) {
+            if let Err(e) = f.truncate(size) {
+                error_with_log!(reply, e, "setattr", ino, self.entries.path(ino));
+                return;
+            }
+        }
+        match ent.getattr(ino) {
+            Ok(attr) => reply.attr(&ent.volatility().ttl(), &attr),
+            Err(e) => error_with_log!(reply, e, "setattr", ino, self.entries.path(ino)),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        let span = debug_span!("readlink", ino = ino, path = field::Empty);
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        self.load_root();
+        self.restore(ino);
+        let ent = match self.entries.get_by_inode(ino) {
+            Some(ent) => ent,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        match ent.readlink() {
+            Ok(target) => reply.data(target.as_os_str().as_bytes()),
+            Err(e) => error_with_log!(reply, e, "readlink", ino, self.entries.path(ino)),
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        let span =
+            debug_span!("getxattr", ino = ino, name = ?name, size = size, path = field::Empty);
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        self.load_root();
+        self.restore(ino);
+        let ent = match self.entries.get_by_inode(ino) {
+            Some(ent) => ent,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        match ent.getxattr(name) {
+            Ok(value) => reply_xattr(reply, size, &value),
+            // not an error, tools query attributes which may not exist.
+            Err(ref e) if e.raw_os_error() == Some(libc::ENODATA) => reply.error(libc::ENODATA),
+            Err(e) => error_with_log!(reply, e, "getxattr", ino, self.entries.path(ino)),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        let span = debug_span!("listxattr", ino = ino, size = size, path = field::Empty);
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        self.load_root();
+        self.restore(ino);
+        let ent = match self.entries.get_by_inode(ino) {
+            Some(ent) => ent,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        match ent.listxattr() {
+            Ok(names) => {
+                let mut value = Vec::new();
+                for name in names.iter() {
+                    value.extend_from_slice(name.as_bytes());
+                    value.push(0);
+                }
+                reply_xattr(reply, size, &value)
+            }
+            Err(e) => error_with_log!(reply, e, "listxattr", ino, self.entries.path(ino)),
+        }
+    }
+
+    fn open(&mut self, req: &Request<'_>, ino: u64, flags: u32, reply: ReplyOpen) {
+        let span = debug_span!("open", ino = ino, path = field::Empty, fh = field::Empty);
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        let _timer = Timer::new(&self.stats, "open");
+        self.load_root();
+        if !self.permits(req) {
+            reply.error(libc::EACCES);
+            return;
+        }
         if flags & libc::O_RDONLY as u32 != 0 {
             // support read only.
             reply.error(libc::EINVAL);
             return;
         }
 
+        self.restore(ino);
         let file = match self.entries.get_by_inode(ino) {
             Some(&Entry::File(ref file)) => file.clone(),
             Some(_) => {
@@ -344,10 +1672,21 @@ impl Filesystem for ShowFS {
         match file.open() {
             Ok(contents) => {
                 let fh = self.handlers.register_file(contents);
+                span.record("fh", &fh);
+                let path = self.entries.path(ino);
+                self.control
+                    .open_handle(fh, HandleKind::File, ino, path, req.uid(), req.pid());
                 // flag can only be direct_io or keep_cache.
-                reply.opened(fh, 0);
+                let flags = if file.direct_io() {
+                    FOPEN_DIRECT_IO
+                } else if self.auto_cache && keep_cache(&mut self.opened_attrs, ino, &**file) {
+                    FOPEN_KEEP_CACHE
+                } else {
+                    0
+                };
+                reply.opened(fh, flags);
             }
-            Err(e) => error_with_log!(reply, e),
+            Err(e) => error_with_log!(reply, e, "open", ino, self.entries.path(ino)),
         }
     }
 
@@ -362,54 +1701,113 @@ impl Filesystem for ShowFS {
         _flush: bool,
         reply: ReplyEmpty,
     ) {
+        let _span = debug_span!("release", fh = fh).entered();
         if self.handlers.get_file(fh).is_none() {
             reply.error(libc::EBADF);
             return;
         }
         self.handlers.release_file(fh);
+        self.control.close_handle(fh);
         reply.ok();
     }
 
     fn read(
         &mut self,
         _req: &Request<'_>,
-        _ino: u64,
+        ino: u64,
         fh: u64,
         offset: i64,
         size: u32,
         reply: ReplyData,
     ) {
+        let span = debug_span!(
+            "read",
+            ino = ino,
+            fh = fh,
+            offset = offset,
+            size = size,
+            read = field::Empty,
+            path = field::Empty
+        );
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        let _timer = Timer::new(&self.stats, "read");
         if let Some(reader) = self.handlers.get_file_mut(fh) {
             if offset < 0 {
                 reply.error(libc::EINVAL);
                 return;
             }
-            if let Err(e) = reader.seek(SeekFrom::Start(offset as u64)) {
-                error_with_log!(reply, e);
-                return;
-            }
+            // read at the offset, not from a position shared by reads of the handle.
+            // fuse 0.3 replies with a single slice, so contents are copied once into the
+            // buffer, which cache readers fill from all the pages in one call.
             let size = size as usize;
             self.buf.resize(size, 0);
             let mut read = 0;
             while read < size {
-                match reader.read(&mut self.buf[read..]) {
+                match reader.read_at(offset as u64 + read as u64, &mut self.buf[read..]) {
                     Ok(n) if n == 0 => break,
                     Ok(n) => read += n,
                     Err(e) => {
-                        error_with_log!(reply, e);
+                        error_with_log!(reply, e, "read", ino, self.entries.path(ino));
                         return;
                     }
                 }
             }
+            span.record("read", &read);
+            self.control.read_handle(fh, offset as u64, read);
             reply.data(&self.buf[..read])
         } else {
             reply.error(libc::EBADF)
         }
     }
 
-    fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: u32, reply: ReplyOpen) {
-        let handler = match self.entries.get_by_inode(ino) {
-            Some(&Entry::Dir(ref d)) => d.open(),
+    // only control files are written. copy_file_range is not served, fuse 0.3 has no handler
+    // for it, and copies into the tree would fail as writes do. copies out of the tree cross
+    // filesystems, where the kernel falls back to reads.
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        _offset: i64,
+        data: &[u8],
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let span = debug_span!(
+            "write",
+            ino = ino,
+            fh = fh,
+            size = data.len(),
+            path = field::Empty
+        );
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        match self.entries.get_by_inode(ino) {
+            Some(&Entry::File(ref f)) => match f.write(data) {
+                Ok(()) => reply.written(data.len() as u32),
+                Err(e) => error_with_log!(reply, e, "write", ino, self.entries.path(ino)),
+            },
+            Some(_) => reply.error(libc::EISDIR),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn opendir(&mut self, req: &Request<'_>, ino: u64, _flags: u32, reply: ReplyOpen) {
+        let span = debug_span!("opendir", ino = ino, path = field::Empty);
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        let _timer = Timer::new(&self.stats, "opendir");
+        self.load_root();
+        if !self.permits(req) {
+            reply.error(libc::EACCES);
+            return;
+        }
+        self.reindex_if_requested();
+        self.control.scrub_if_due();
+        self.restore(ino);
+        let dir = match self.entries.get_by_inode(ino) {
+            Some(&Entry::Dir(ref d)) => d,
             Some(_) => {
                 reply.error(libc::EBADF);
                 return;
@@ -419,20 +1817,55 @@ impl Filesystem for ShowFS {
                 return;
             }
         };
-        match handler {
-            Ok(dh) => {
+        // listings are remembered until the mtime of the directory changes.
+        let attr = dir.getattr().ok();
+        let mtime = attr.map(|a| a.mtime);
+        let handler = match self.listings.get(&ino) {
+            Some(l) if Some(l.mtime) == mtime => Ok(DirHandler::Listed(l.entries.clone())),
+            _ => dir.open().map(|dh| {
                 let viewer = self.viewers.clone();
-                let fh = self
-                    .handlers
-                    .register_dir(dh.map(move |re| re.map(|e| viewer.view(e))));
+                let control = self.control.clone();
+                let path = self.entries.path(ino);
+                let view = move |e: Entry| {
+                    let ctx = ViewContext::new(path.join(e.name()), attr, control.clone());
+                    viewer.view(e, &ctx)
+                };
+                let dh: Box<dyn Iterator<Item = Result<Entry>>> =
+                    Box::new(dh.map(move |re| re.map(&view)));
+                DirHandler::Reading(dh.peekable(), mtime.map(|m| (m, Vec::new())))
+            }),
+        };
+        match handler {
+            Ok(handler) => {
+                let fh = self.handlers.register_dir(handler);
+                let path = self.entries.path(ino);
+                self.control
+                    .open_handle(fh, HandleKind::Dir, ino, path, req.uid(), req.pid());
                 reply.opened(fh, 0);
             }
-            Err(e) => error_with_log!(reply, e),
+            Err(e) => error_with_log!(reply, e, "opendir", ino, self.entries.path(ino)),
         }
+        // entries of the last listing are registered by now.
+        self.relieve_memory();
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        let _span = debug_span!("statfs").entered();
+        if !self.statfs_cache {
+            reply.statfs(0, 0, 0, 0, 0, STATFS_BLOCK_SIZE, 255, 0);
+            return;
+        }
+        let max = self.control.caches_max_bytes() as u64;
+        let used = min(self.control.caches_used_bytes() as u64, max);
+        let block = STATFS_BLOCK_SIZE as u64;
+        let free = (max - used) / block;
+        reply.statfs(max / block, free, free, 0, 0, STATFS_BLOCK_SIZE, 255, 0);
     }
 
     fn releasedir(&mut self, _req: &Request<'_>, _ino: u64, fh: u64, _flags: u32, reply: ReplyEmpty) {
+        let _span = debug_span!("releasedir", fh = fh).entered();
         if self.handlers.release_dir(fh) {
+            self.control.close_handle(fh);
             reply.ok();
         } else {
             reply.error(libc::EBADF);
@@ -448,8 +1881,33 @@ impl Filesystem for ShowFS {
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
-        let h = match self.handlers.get_dir_mut(fh) {
-            Some(h) => h,
+        let span = debug_span!(
+            "readdir",
+            ino = ino,
+            fh = fh,
+            offset = offset,
+            entries = field::Empty,
+            path = field::Empty
+        );
+        let _enter = span.enter();
+        self.record_path(&span, ino);
+        let _timer = Timer::new(&self.stats, "readdir");
+        let start = offset;
+        let (h, listed) = match self.handlers.get_dir_mut(fh) {
+            Some(DirHandler::Reading(h, listed)) => (h, listed),
+            Some(DirHandler::Listed(entries)) => {
+                let mut n = 0;
+                for (i, e) in entries.iter().enumerate().skip(offset as usize) {
+                    if reply.add(e.ino, i as i64 + 1, e.kind, &e.name) {
+                        // buffer is full.
+                        break;
+                    }
+                    n += 1;
+                }
+                span.record("entries", &n);
+                reply.ok();
+                return;
+            }
             None => {
                 reply.error(libc::ENOENT);
                 return;
@@ -457,31 +1915,34 @@ impl Filesystem for ShowFS {
         };
         for offset in (offset + 1).. {
             let mut reserver = None;
+            let mut added = None;
             // check if an entry can be inserted.
             match h.peek() {
                 Some(&Ok(ref ent)) => {
                     let ent_ino = match self.entries.get_by_path(ino, ent.name()) {
                         Some((ent_ino, _)) => ent_ino,
                         None => {
-                            let r = self.entries.reserve_inode();
+                            let r = self.entries.reserve_inode_for(ino, ent);
                             let i = r.inode();
                             reserver = Some(r);
                             i
                         }
                     };
-                    match ent.file_type(ent_ino) {
-                        Ok(ft) => {
-                            if reply.add(ent_ino, offset, ft, ent.name()) {
-                                // buffer is full.
-                                reply.ok();
-                                return;
-                            }
-                        }
-                        Err(e) => {
-                            error_with_log!(reply, e);
-                            return;
-                        }
+                    // a broken entry does not fail the listing, but lookups of it.
+                    // fuse can not reply DT_UNKNOWN, so it is listed as a regular file,
+                    // and the listing is not kept to list it again.
+                    let ft = ent.file_type().unwrap_or_else(|e| {
+                        warn!(name = ?ent.name(), error = ?e, "failed to get the type");
+                        *listed = None;
+                        FileType::RegularFile
+                    });
+                    if reply.add(ent_ino, offset, ft, ent.name()) {
+                        // buffer is full.
+                        span.record("entries", &(offset - start - 1));
+                        reply.ok();
+                        return;
                     }
+                    added = Some((ent_ino, ft));
                 }
                 _ => {
                     // fallthrough
@@ -490,15 +1951,34 @@ impl Filesystem for ShowFS {
 
             match h.next() {
                 Some(Ok(ent)) => {
+                    if let (Some((_, entries)), Some((ent_ino, ft))) = (listed.as_mut(), added) {
+                        entries.push(Listed {
+                            ino: ent_ino,
+                            name: ent.name().to_os_string(),
+                            kind: ft,
+                        });
+                    }
                     if let Some(r) = reserver {
                         self.entries.register_with(ino, ent, r)
                     }
                 }
                 Some(Err(e)) => {
-                    error_with_log!(reply, e);
+                    *listed = None;
+                    error_with_log!(reply, e, "readdir", ino, self.entries.path(ino));
                     return;
                 }
                 None => {
+                    if let Some((mtime, entries)) = listed.take() {
+                        let entries = Rc::new(entries);
+                        self.listings.insert(
+                            ino,
+                            Listing {
+                                mtime: mtime,
+                                entries: entries,
+                            },
+                        );
+                    }
+                    span.record("entries", &(offset - start - 1));
                     reply.ok();
                     return;
                 }
@@ -506,3 +1986,209 @@ impl Filesystem for ShowFS {
         }
     }
 }
+
+#[test]
+fn test_entry_path() {
+    use std::io::Cursor;
+    use std::mem::zeroed;
+
+    struct NamedFile(OsString);
+    impl File for NamedFile {
+        fn getattr(&self) -> Result<FileAttr> {
+            Ok(unsafe { zeroed::<FileAttr>() })
+        }
+        fn open(&self) -> Result<Box<dyn SeekableRead>> {
+            Ok(Box::new(Cursor::new(Vec::new())))
+        }
+        fn name(&self) -> &OsStr {
+            &self.0
+        }
+    }
+    let entry = |name: &str| Entry::File(Box::new(NamedFile(OsString::from(name))));
+
+    let mut entries = EntryHolder::new();
+    entries.register_root(entry("origin"));
+    let ir = entries.reserve_inode();
+    let dir = ir.inode();
+    entries.register_with(1, entry("dir"), ir);
+    let ir = entries.reserve_inode();
+    let file = ir.inode();
+    entries.register_with(dir, entry("file"), ir);
+    assert_eq!(entries.path(1), Path::new("/"));
+    assert_eq!(entries.path(file), Path::new("/dir/file"));
+
+    // only forgotten entries are evicted.
+    entries.add_lookup(dir);
+    entries.add_lookup(dir);
+    entries.add_lookup(file);
+    entries.forget(dir, 1);
+    entries.forget(file, 1);
+    assert_eq!(entries.evict_forgotten(), 1);
+    assert!(entries.get_by_inode(file).is_none());
+    assert!(entries.get_by_path(dir, OsStr::new("file")).is_none());
+    assert_eq!(entries.path(dir), Path::new("/dir"));
+    assert!(entries.get_by_inode(1).is_some());
+    // evicted inodes keep their paths to be made again.
+    assert_eq!(entries.path(file), Path::new("/dir/file"));
+    assert_eq!(
+        entries.evicted_parent(file),
+        Some((dir, OsString::from("file")))
+    );
+    entries.restore(file, dir, entry("file"));
+    assert!(entries.get_by_inode(file).is_some());
+    assert_eq!(
+        entries.get_by_path(dir, OsStr::new("file")).unwrap().0,
+        file
+    );
+    assert!(entries.evicted_parent(file).is_none());
+    entries.remove(&[file].iter().cloned().collect());
+    // evicted entries are not counted for the budget, and the first evicted are dropped.
+    let used = entries.used_bytes();
+    let mut evicted = Vec::new();
+    for _ in 0..MAX_EVICTED + 1 {
+        let ir = entries.reserve_inode();
+        evicted.push(ir.inode());
+        entries.register_with(dir, entry("evicted"), ir);
+        // the last is evicted after the others.
+        if evicted.len() == MAX_EVICTED {
+            entries.evict_forgotten();
+        }
+    }
+    assert_eq!(entries.used_bytes(), used + ENTRY_BYTES);
+    entries.evict_forgotten();
+    assert_eq!(entries.used_bytes(), used);
+    assert_eq!(entries.evicted.len(), MAX_EVICTED);
+    assert!(entries.evicted_parent(evicted[0]).is_none());
+    assert!(entries.evicted_parent(evicted[1]).is_some());
+    assert!(entries.evicted_parent(evicted[MAX_EVICTED]).is_some());
+    entries.remove(&entries.evicted.keys().cloned().collect());
+
+    // parents are reindexed first.
+    let ir = entries.reserve_inode();
+    let file = ir.inode();
+    entries.register_with(dir, entry("file"), ir);
+    assert_eq!(entries.inodes_by_depth(), vec![dir, file]);
+    entries.remove(&[file].iter().cloned().collect());
+    assert!(entries.get_by_path(dir, OsStr::new("file")).is_none());
+}
+
+#[test]
+fn test_persistent_inodes() {
+    use crate::control::RootDir;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let map_path = tmp.path().join("inodes");
+    let control = Rc::new(Control::new(Rc::new(RefCell::new(Stats::new()))));
+    // the root and the control directory.
+    let holder = || {
+        let ctx = Rc::new(physical::Context::new(Duration::from_secs(1)));
+        let root = physical::Dir::new(tmp.path().to_path_buf(), ctx);
+        let root = Entry::Dir(Box::new(RootDir::new(Box::new(root), control.clone())));
+        let mut entries = EntryHolder::new();
+        entries.set_inode_map(InodeMap::open(&map_path).unwrap());
+        entries.register_root(root);
+        let dir = match entries.get_by_inode(1) {
+            Some(&Entry::Dir(ref d)) => d.lookup(OsStr::new(control::DIR_NAME)).unwrap(),
+            _ => unreachable!(),
+        };
+        (entries, dir)
+    };
+
+    let (mut entries, dir) = holder();
+    // inodes are burned by others.
+    entries.reserve_inode();
+    let ir = entries.reserve_inode_for(1, &dir);
+    let ino = ir.inode();
+    entries.register_with(1, dir, ir);
+    assert_eq!(entries.generation(ino), 1);
+    entries.save_inodes();
+
+    let (mut entries, dir) = holder();
+    let ir = entries.reserve_inode_for(1, &dir);
+    assert_eq!(ir.inode(), ino);
+    entries.register_with(1, dir, ir);
+    assert_eq!(entries.generation(ino), 1);
+    assert_eq!(entries.generation(1), 2);
+    assert!(entries.reserve_inode().inode() > ino);
+}
+
+#[test]
+fn test_file_root() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.log");
+    std::fs::write(&path, "log").unwrap();
+    let mut fs = ShowFS::new(&path);
+    assert!(fs.root_entry().is_err());
+    fs.file_root = true;
+    match fs.root_entry() {
+        Ok(Entry::File(f)) => assert_eq!(f.getattr().unwrap().size, 3),
+        _ => panic!("not a file"),
+    }
+    let mut fs = ShowFS::new(tmp.path());
+    fs.file_root = true;
+    assert!(fs.root_entry().is_err());
+}
+
+#[test]
+fn test_reused_generation() {
+    use crate::testing::SizedFile;
+
+    let entry = |size| Entry::File(Box::new(SizedFile::new(size)));
+
+    let mut entries = EntryHolder::new();
+    entries.register_root(entry(0));
+    let ir = entries.reserve_inode();
+    let file = ir.inode();
+    entries.register_with(1, entry(1), ir);
+    assert_eq!(entries.generation(file), 0);
+    entries.renew(file, entry(1));
+    assert_eq!(entries.generation(file), 0);
+    // another file of the same path.
+    entries.renew(file, entry(2));
+    assert_eq!(entries.generation(file), 1);
+}
+
+#[test]
+fn test_view_context() {
+    let control = Rc::new(Control::new(Rc::new(RefCell::new(Stats::new()))));
+    control.add_config("sort", "native".to_string());
+    control.add_config("sort", "name".to_string());
+    let root = ViewContext::new(PathBuf::from("/"), None, control.clone());
+    assert_eq!(root.depth(), 0);
+    let ctx = ViewContext::new(PathBuf::from("/a/b.zip"), None, control);
+    assert_eq!(ctx.depth(), 2);
+    assert_eq!(ctx.config("sort"), Some("name".to_string()));
+    assert_eq!(ctx.config("none"), None);
+}
+
+#[test]
+fn test_keep_cache() {
+    use crate::testing::SizedFile;
+
+    let file = SizedFile::new(1);
+    let mut opened = HashMap::new();
+    assert!(!keep_cache(&mut opened, 2, &file));
+    assert!(keep_cache(&mut opened, 2, &file));
+    file.set_size(3);
+    assert!(!keep_cache(&mut opened, 2, &file));
+    assert!(keep_cache(&mut opened, 2, &file));
+}
+
+#[test]
+fn test_entry_file_type() {
+    use crate::testing::{Fault, MemTree};
+
+    let tree = MemTree::new();
+    tree.dir("/d").file("/f", b"");
+    tree.fault("/d", Fault::Errno(libc::EIO));
+    let root = match tree.root() {
+        Entry::Dir(d) => d,
+        Entry::File(_) => unreachable!(),
+    };
+    // directories need no getattr, which fails.
+    let d = root.lookup(OsStr::new("d")).unwrap();
+    assert!(d.getattr(1).is_err());
+    assert_eq!(d.file_type().unwrap(), FileType::Directory);
+    let f = root.lookup(OsStr::new("f")).unwrap();
+    assert_eq!(f.file_type().unwrap(), FileType::RegularFile);
+}
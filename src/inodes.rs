@@ -0,0 +1,152 @@
+use tempfile;
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+// the first record of the file, followed by the generation.
+const MAGIC: &str = "showfs-inodes-1";
+
+/// InodeMap keeps inodes of paths in a file, so that they survive restarts of the mount,
+/// for NFS exports and indexers which remember inodes.
+/// each mount has a new generation, which is given to the inodes assigned by it, so that
+/// an inode and its generation never refer to another file.
+pub struct InodeMap {
+    path: PathBuf,
+    generation: u64,
+    // the path from the mount root to the inode and its generation.
+    inodes: HashMap<PathBuf, (u64, u64)>,
+    max_inode: u64,
+    // inodes assigned since the last save.
+    unsaved: usize,
+}
+
+fn invalid(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("{}: not an inode map", path.display()),
+    )
+}
+
+impl InodeMap {
+    // reads the map, or starts an empty one if the file does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<InodeMap> {
+        let path = path.as_ref();
+        let mut map = InodeMap {
+            path: path.to_path_buf(),
+            generation: 1,
+            inodes: HashMap::new(),
+            max_inode: 0,
+            unsaved: 0,
+        };
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(map),
+            Err(e) => return Err(e),
+        };
+        // records are separated by NUL, which paths do not have.
+        let mut records = data.split(|&b| b == 0).filter(|r| !r.is_empty());
+        let header = records.next().ok_or_else(|| invalid(path))?;
+        let header = String::from_utf8_lossy(header);
+        match header.split(' ').collect::<Vec<_>>().as_slice() {
+            [MAGIC, generation] => {
+                let generation: u64 = generation.parse().map_err(|_| invalid(path))?;
+                map.generation = generation + 1;
+            }
+            _ => return Err(invalid(path)),
+        }
+        for record in records {
+            // "INODE GENERATION PATH"
+            let mut fields = record.splitn(3, |&b| b == b' ');
+            let mut number = || -> Result<u64> {
+                let field = fields.next().ok_or_else(|| invalid(path))?;
+                String::from_utf8_lossy(field)
+                    .parse()
+                    .map_err(|_| invalid(path))
+            };
+            let ino = number()?;
+            let generation = number()?;
+            let p = fields.next().ok_or_else(|| invalid(path))?;
+            map.max_inode = map.max_inode.max(ino);
+            map.inodes
+                .insert(PathBuf::from(OsStr::from_bytes(p)), (ino, generation));
+        }
+        Ok(map)
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // the largest inode in the map, which new inodes are assigned after.
+    pub fn max_inode(&self) -> u64 {
+        self.max_inode
+    }
+
+    pub fn get(&self, path: &Path) -> Option<(u64, u64)> {
+        self.inodes.get(path).cloned()
+    }
+
+    // records the inode of the path, of this generation.
+    pub fn insert(&mut self, path: PathBuf, ino: u64) {
+        self.max_inode = self.max_inode.max(ino);
+        self.inodes.insert(path, (ino, self.generation));
+        self.unsaved += 1;
+    }
+
+    pub fn unsaved(&self) -> usize {
+        self.unsaved
+    }
+
+    // replaces the file at once, so that a crash leaves the last saved one.
+    pub fn save(&mut self) -> Result<()> {
+        let dir = match self.path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        {
+            let mut out = std::io::BufWriter::new(tmp.as_file_mut());
+            write!(out, "{} {}\0", MAGIC, self.generation)?;
+            for (path, &(ino, generation)) in self.inodes.iter() {
+                write!(out, "{} {} ", ino, generation)?;
+                out.write_all(path.as_os_str().as_bytes())?;
+                out.write_all(b"\0")?;
+            }
+            out.flush()?;
+        }
+        tmp.as_file().sync_all()?;
+        tmp.persist(&self.path).map_err(|e| e.error)?;
+        self.unsaved = 0;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_inode_map() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("inodes");
+    let mut map = InodeMap::open(&path).unwrap();
+    assert_eq!(map.generation(), 1);
+    map.insert(PathBuf::from("/a.zip/b c"), 5);
+    map.insert(PathBuf::from("/a.zip/d\ne"), 3);
+    assert_eq!(map.unsaved(), 2);
+    map.save().unwrap();
+
+    let mut map = InodeMap::open(&path).unwrap();
+    assert_eq!(map.generation(), 2);
+    assert_eq!(map.max_inode(), 5);
+    assert_eq!(map.get(Path::new("/a.zip/b c")), Some((5, 1)));
+    assert_eq!(map.get(Path::new("/a.zip/d\ne")), Some((3, 1)));
+    map.insert(PathBuf::from("/f"), 6);
+    map.save().unwrap();
+    let map = InodeMap::open(&path).unwrap();
+    assert_eq!(map.generation(), 3);
+    assert_eq!(map.get(Path::new("/f")), Some((6, 2)));
+
+    fs::write(&path, b"other").unwrap();
+    assert!(InodeMap::open(&path).is_err());
+}
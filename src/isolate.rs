@@ -0,0 +1,355 @@
+use fuse;
+
+use self::fuse::{FileAttr, FileType};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{Read, Result, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::error;
+use crate::fs;
+
+// the message given to panic!, if it is a string.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown".to_string()
+    }
+}
+
+// top-level entries which panicked, by their names, until their cooldowns pass.
+struct Quarantine {
+    cooldown: Duration,
+    until: RefCell<HashMap<OsString, Instant>>,
+}
+
+impl Quarantine {
+    fn contains(&self, name: &OsStr) -> bool {
+        let mut until = self.until.borrow_mut();
+        match until.get(name) {
+            Some(&t) if Instant::now() < t => true,
+            Some(_) => {
+                info!(name = ?name, "released from the quarantine");
+                until.remove(name);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn add(&self, name: &OsStr) {
+        let t = Instant::now() + self.cooldown;
+        self.until.borrow_mut().insert(name.to_os_string(), t);
+    }
+}
+
+// runs operations of an entry under the top-level entry of the name, None for the root.
+#[derive(Clone)]
+struct Guard {
+    quarantine: Rc<Quarantine>,
+    top: Option<OsString>,
+}
+
+impl Guard {
+    // fails while the top-level entry is quarantined, and turns a panic of f into an error,
+    // quarantining the top-level entry.
+    fn run<T, F: FnOnce() -> Result<T>>(&self, f: F) -> Result<T> {
+        if let Some(ref top) = self.top {
+            if self.quarantine.contains(top) {
+                let name = top.to_string_lossy().into_owned();
+                return Err(error::Error::Quarantined(name).into());
+            }
+        }
+        let payload = match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(result) => return result,
+            Err(payload) => payload,
+        };
+        let message = panic_message(&*payload);
+        error!(top = ?self.top, "panicked: {}", message);
+        if let Some(ref top) = self.top {
+            self.quarantine.add(top);
+        }
+        Err(error::Error::Panicked(message).into())
+    }
+}
+
+struct File {
+    inner: Box<dyn fs::File>,
+    guard: Guard,
+}
+
+impl fs::File for File {
+    fn getattr(&self) -> Result<FileAttr> {
+        self.guard.run(|| self.inner.getattr())
+    }
+
+    fn open(&self) -> Result<Box<dyn fs::SeekableRead>> {
+        let inner = self.guard.run(|| self.inner.open())?;
+        Ok(Box::new(Reader {
+            inner: inner,
+            guard: self.guard.clone(),
+        }))
+    }
+
+    fn name(&self) -> &OsStr {
+        self.inner.name()
+    }
+
+    fn file_type(&self) -> Result<FileType> {
+        self.guard.run(|| self.inner.file_type())
+    }
+
+    fn hardlink_key(&self) -> Option<(u64, u64)> {
+        self.inner.hardlink_key()
+    }
+
+    fn physical_path(&self) -> Option<&Path> {
+        self.inner.physical_path()
+    }
+
+    fn origin(&self) -> Option<&Path> {
+        self.inner.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.inner.member()
+    }
+
+    fn volatility(&self) -> fs::Volatility {
+        self.inner.volatility()
+    }
+
+    fn nesting(&self) -> usize {
+        self.inner.nesting()
+    }
+
+    fn reopen_cost(&self) -> u64 {
+        self.inner.reopen_cost()
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.guard.run(|| self.inner.listxattr())
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.guard.run(|| self.inner.getxattr(name))
+    }
+
+    fn readlink(&self) -> Result<PathBuf> {
+        self.guard.run(|| self.inner.readlink())
+    }
+
+    fn direct_io(&self) -> bool {
+        self.inner.direct_io()
+    }
+
+    fn write(&self, data: &[u8]) -> Result<()> {
+        self.guard.run(|| self.inner.write(data))
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        self.guard.run(|| self.inner.truncate(size))
+    }
+}
+
+struct Reader {
+    inner: Box<dyn fs::SeekableRead>,
+    guard: Guard,
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let inner = &mut self.inner;
+        self.guard.run(|| inner.read(buf))
+    }
+}
+
+impl Seek for Reader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let inner = &mut self.inner;
+        self.guard.run(|| inner.seek(pos))
+    }
+}
+
+impl fs::SeekableRead for Reader {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let inner = &mut self.inner;
+        self.guard.run(|| inner.read_at(offset, buf))
+    }
+}
+
+struct Dir {
+    inner: Box<dyn fs::Dir>,
+    guard: Guard,
+}
+
+impl fs::Dir for Dir {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        let mut inner = self.guard.run(|| self.inner.open())?;
+        let guard = self.guard.clone();
+        // a panic ends the listing.
+        let mut failed = false;
+        Ok(Box::new(std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            match guard.run(|| Ok(inner.next())) {
+                Ok(next) => next,
+                Err(e) => {
+                    failed = true;
+                    Some(Err(e))
+                }
+            }
+        })))
+    }
+
+    fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
+        self.guard.run(|| self.inner.lookup(name))
+    }
+
+    fn getattr(&self) -> Result<FileAttr> {
+        self.guard.run(|| self.inner.getattr())
+    }
+
+    fn name(&self) -> &OsStr {
+        self.inner.name()
+    }
+
+    fn origin(&self) -> Option<&Path> {
+        self.inner.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.inner.member()
+    }
+
+    fn volatility(&self) -> fs::Volatility {
+        self.inner.volatility()
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.guard.run(|| self.inner.listxattr())
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.guard.run(|| self.inner.getxattr(name))
+    }
+}
+
+/// IsolationViewer keeps a misbehaving entry, like a crafted archive crashing a parser, from
+/// taking the mount down. panics of entries fail their operations with EIO, and the top-level
+/// entry which has the panicked one, like /a.zip for /a.zip/b/c, fails with EIO for the
+/// cooldown, while the rest of the mount goes on. it should be registered the last, to wrap
+/// entries as they are shown by the other viewers.
+pub struct IsolationViewer {
+    quarantine: Rc<Quarantine>,
+}
+
+impl IsolationViewer {
+    pub fn new(cooldown: Duration) -> IsolationViewer {
+        IsolationViewer {
+            quarantine: Rc::new(Quarantine {
+                cooldown: cooldown,
+                until: RefCell::new(HashMap::new()),
+            }),
+        }
+    }
+}
+
+impl fs::Viewer for IsolationViewer {
+    fn view(&self, e: fs::Entry, ctx: &fs::ViewContext) -> fs::Entry {
+        let top = match ctx.path().components().nth(1) {
+            Some(Component::Normal(name)) => Some(name.to_os_string()),
+            _ => None,
+        };
+        let guard = Guard {
+            quarantine: self.quarantine.clone(),
+            top: top,
+        };
+        match e {
+            fs::Entry::File(f) => fs::Entry::File(Box::new(File {
+                inner: f,
+                guard: guard,
+            })),
+            fs::Entry::Dir(d) => fs::Entry::Dir(Box::new(Dir {
+                inner: d,
+                guard: guard,
+            })),
+        }
+    }
+}
+
+#[test]
+fn test_isolation() {
+    use crate::control::Control;
+    use crate::error::errno;
+    use crate::fs::Viewer;
+    use crate::stats::Stats;
+
+    struct PanickingFile;
+    impl fs::File for PanickingFile {
+        fn getattr(&self) -> Result<FileAttr> {
+            panic!("broken");
+        }
+        fn open(&self) -> Result<Box<dyn fs::SeekableRead>> {
+            Ok(Box::new(std::io::Cursor::new(b"ok".to_vec())))
+        }
+        fn name(&self) -> &OsStr {
+            OsStr::new("b")
+        }
+    }
+
+    let viewer = IsolationViewer::new(Duration::from_millis(100));
+    let control = Rc::new(Control::new(Rc::new(RefCell::new(Stats::new()))));
+    let view = |path: &str| {
+        let ctx = fs::ViewContext::new(PathBuf::from(path), None, control.clone());
+        match viewer.view(fs::Entry::File(Box::new(PanickingFile)), &ctx) {
+            fs::Entry::File(f) => f,
+            fs::Entry::Dir(_) => panic!("not a file"),
+        }
+    };
+    let broken = view("/a.zip/b");
+    let other = view("/c.zip/b");
+    let mut contents = String::new();
+    broken
+        .open()
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    assert_eq!(contents, "ok");
+
+    // the panic is an error, after which the top-level entry is quarantined.
+    let e = broken.getattr().unwrap_err();
+    assert_eq!(errno(&e), libc::EIO);
+    assert!(e.to_string().contains("broken"));
+    assert!(view("/a.zip").open().is_err());
+    assert!(other.open().is_ok());
+    std::thread::sleep(Duration::from_millis(150));
+    assert!(view("/a.zip").open().is_ok());
+}
+
+#[test]
+fn test_isolated_walk() {
+    use crate::testing::{Fault, MemTree, Viewed};
+
+    let tree = MemTree::new();
+    tree.file("/a/b", b"b").file("/c", b"c");
+    tree.fault("/a/b", Fault::Panic);
+    let viewer = IsolationViewer::new(Duration::from_secs(60));
+    let viewed = Viewed::new(vec![&viewer]);
+    // the listing of /a fails after the panic.
+    assert_eq!(
+        viewed.walk(tree.root()),
+        vec!["/", "/a/", "/a/b !5", "/a !5", "/c 1"]
+    );
+    // the rest of /a is quarantined with it.
+    assert_eq!(viewed.walk(tree.root()), vec!["/", "/a !5", "/c 1"]);
+}
@@ -0,0 +1,52 @@
+//! showfs shows archives as directories by FUSE.
+//!
+//! [`fs::ShowFS`] mounts a file or a directory, and [`fs::Viewer`]s change how entries are
+//! shown, like [`archive::ArchiveViewer`] which shows archive files as directories.
+//! entries are [`fs::File`]s and [`fs::Dir`]s, and [`tree::VirtualTree`] resolves, lists
+//! and opens them by paths without mounting.
+//!
+//! ```no_run
+//! use showfs::{archive, fs};
+//!
+//! let mut fs = fs::ShowFS::new("/path/to/archives");
+//! let viewer = archive::ArchiveViewer::new(1 << 30, archive::Options::default()).unwrap();
+//! fs.register_viewer(viewer);
+//! fs.mount("/path/to/mountpoint").unwrap();
+//! ```
+
+#[macro_use]
+extern crate tracing;
+
+pub mod access;
+pub mod archive;
+pub mod audit;
+pub mod bench;
+pub mod casefold;
+pub mod collection;
+pub mod confine;
+pub mod control;
+pub mod ctl;
+pub mod digest;
+pub mod du;
+pub mod error;
+pub mod escape;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flat;
+pub mod fs;
+pub mod inodes;
+pub mod isolate;
+pub mod locate;
+pub mod logging;
+pub mod manifest;
+pub mod names;
+pub mod normalize;
+pub mod physical;
+pub mod preflight;
+pub mod profile;
+pub mod shared_cache;
+pub mod stats;
+pub mod systemd;
+#[cfg(test)]
+mod testing;
+pub mod tree;
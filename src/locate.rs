@@ -0,0 +1,245 @@
+use time;
+
+use self::time::Timespec;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::control;
+use crate::flat;
+
+/// Index is the paths in the mount, like /a.zip/b.txt, to answer searches without crawling
+/// the mount for each of them.
+/// it is built by walking the mount in a thread, once like find does, so that archives are
+/// indexed by the filesystem as their directories are listed.
+pub struct Index {
+    mountpoint: PathBuf,
+    // the last build, which is kept while the next one is built.
+    built: Mutex<Option<Arc<Built>>>,
+    building: AtomicBool,
+}
+
+struct Built {
+    // the paths, and whether they are regular files.
+    paths: Vec<(PathBuf, bool)>,
+    at: Timespec,
+}
+
+impl Index {
+    pub fn new<P: AsRef<Path>>(mountpoint: P) -> Arc<Index> {
+        Arc::new(Index {
+            mountpoint: mountpoint.as_ref().to_path_buf(),
+            built: Mutex::new(None),
+            building: AtomicBool::new(false),
+        })
+    }
+
+    /// builds the index again in a thread, unless it is being built.
+    /// this must not be called by the thread which serves the mount.
+    pub fn rebuild(index: Arc<Index>) -> Result<()> {
+        if index.building.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let thread_index = index.clone();
+        let result = thread::Builder::new()
+            .name("locate".to_string())
+            .spawn(move || {
+                thread_index.build();
+                thread_index.building.store(false, Ordering::SeqCst);
+            });
+        if let Err(e) = result {
+            index.building.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // walks the mount in the calling thread.
+    pub(crate) fn build(&self) {
+        let mut paths = Vec::new();
+        walk(&self.mountpoint, Path::new("/"), &mut paths);
+        paths.sort();
+        info!(paths = paths.len(), "the locate index is built");
+        *self.built.lock().unwrap() = Some(Arc::new(Built {
+            paths: paths,
+            at: time::get_time(),
+        }));
+    }
+
+    fn last_built(&self) -> Result<Arc<Built>> {
+        match *self.built.lock().unwrap() {
+            Some(ref built) => Ok(built.clone()),
+            None => Err(Error::new(
+                ErrorKind::WouldBlock,
+                "the locate index is being built",
+            )),
+        }
+    }
+
+    /// when the last build finished, None until the first one does.
+    pub fn built_at(&self) -> Option<Timespec> {
+        self.built.lock().unwrap().as_ref().map(|built| built.at)
+    }
+
+    /// the paths of regular files, sorted.
+    pub fn files(&self) -> Result<Vec<PathBuf>> {
+        let built = self.last_built()?;
+        let files = built.paths.iter().filter(|p| p.1).map(|p| p.0.clone());
+        Ok(files.collect())
+    }
+
+    /// the paths matching the pattern, which is a glob if it has *, ? or [, else a substring.
+    /// globs without / match names, and ones with / match whole paths.
+    pub fn find(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let built = self.last_built()?;
+        let pattern = pattern.as_bytes();
+        let is_glob = pattern.iter().any(|b| b"*?[".contains(b));
+        let whole = pattern.contains(&b'/');
+        let found = built
+            .paths
+            .iter()
+            .map(|p| &p.0)
+            .filter(|p| {
+                let path = p.as_os_str().as_bytes();
+                if !is_glob {
+                    return contains(path, pattern);
+                }
+                if whole {
+                    return glob(pattern, path);
+                }
+                let name = p.file_name().map_or(&b""[..], |n| n.as_bytes());
+                glob(pattern, name)
+            })
+            .cloned()
+            .collect();
+        Ok(found)
+    }
+}
+
+// collects paths under dir, which is at path in the mount, and whether they are regular files.
+// unreadable directories are skipped.
+fn walk(dir: &Path, path: &Path, paths: &mut Vec<(PathBuf, bool)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!(path = %path.display(), error = ?e, "failed to list for the locate index");
+            return;
+        }
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!(path = %path.display(), error = ?e, "failed to list for the locate index");
+                return;
+            }
+        };
+        let name = entry.file_name();
+        // the directory of all files lists them again.
+        if path == Path::new("/") && (name == control::DIR_NAME || name == flat::DIR_NAME) {
+            continue;
+        }
+        let child = path.join(&name);
+        // symlinks are not followed, which may loop.
+        let file_type = entry.file_type().ok();
+        let is_dir = file_type.map_or(false, |t| t.is_dir());
+        let is_file = file_type.map_or(false, |t| t.is_file());
+        paths.push((child.clone(), is_file));
+        if is_dir {
+            walk(&entry.path(), &child, paths);
+        }
+    }
+}
+
+fn contains(s: &[u8], sub: &[u8]) -> bool {
+    sub.is_empty() || s.windows(sub.len()).any(|w| w == sub)
+}
+
+// matches like fnmatch without flags, where * matches / too.
+fn glob(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| glob(rest, &s[i..])),
+        Some((b'?', rest)) => !s.is_empty() && glob(rest, &s[1..]),
+        Some((b'[', rest)) => match rest.iter().skip(1).position(|&b| b == b']') {
+            // [] is a literal [ without the closing ], and ] first is in the class.
+            Some(end) if !s.is_empty() => {
+                let class = &rest[..end + 1];
+                let (negated, class) = match class.split_first() {
+                    Some((b'!', class)) if !class.is_empty() => (true, class),
+                    _ => (false, class),
+                };
+                let c = s[0];
+                let mut matched = false;
+                let mut i = 0;
+                while i < class.len() {
+                    if i + 2 < class.len() && class[i + 1] == b'-' {
+                        matched |= class[i] <= c && c <= class[i + 2];
+                        i += 3;
+                    } else {
+                        matched |= class[i] == c;
+                        i += 1;
+                    }
+                }
+                matched != negated && glob(&rest[end + 2..], &s[1..])
+            }
+            Some(_) => false,
+            None => s.first() == Some(&b'[') && glob(rest, &s[1..]),
+        },
+        Some((&b, rest)) => s.first() == Some(&b) && glob(rest, &s[1..]),
+    }
+}
+
+#[test]
+fn test_glob() {
+    assert!(glob(b"*.txt", b"a.txt"));
+    assert!(!glob(b"*.txt", b"a.txt.gz"));
+    assert!(glob(b"/a/*/c", b"/a/b/x/c"));
+    assert!(glob(b"?.[tj]xt", b"a.txt"));
+    assert!(!glob(b"?.[!tj]xt", b"a.jxt"));
+    assert!(glob(b"[0-9]*", b"7z"));
+    assert!(glob(b"[]]", b"]"));
+    assert!(glob(b"a[b", b"a[b"));
+}
+
+#[test]
+fn test_find() {
+    let tmp = tempfile::tempdir().unwrap();
+    fs::create_dir_all(tmp.path().join("a.zip/Docs")).unwrap();
+    fs::create_dir(tmp.path().join(control::DIR_NAME)).unwrap();
+    fs::write(tmp.path().join("a.zip/Docs/readme.txt"), "").unwrap();
+    fs::write(tmp.path().join("b.txt"), "").unwrap();
+    let index = Index::new(tmp.path());
+    assert!(index.find("txt").is_err());
+    assert!(index.built_at().is_none());
+    index.build();
+    assert!(index.built_at().is_some());
+
+    let find = |pattern| {
+        let found = index.find(pattern).unwrap();
+        found
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(find("Docs/"), vec!["/a.zip/Docs/readme.txt"]);
+    assert_eq!(find("*.txt"), vec!["/a.zip/Docs/readme.txt", "/b.txt"]);
+    assert_eq!(find("/*.txt"), vec!["/a.zip/Docs/readme.txt", "/b.txt"]);
+    assert_eq!(
+        find("/a.zip/*"),
+        vec!["/a.zip/Docs", "/a.zip/Docs/readme.txt"]
+    );
+    assert!(find(".showfs").is_empty());
+    let files = index.files().unwrap();
+    assert_eq!(
+        files,
+        vec![
+            PathBuf::from("/a.zip/Docs/readme.txt"),
+            PathBuf::from("/b.txt")
+        ]
+    );
+}
@@ -0,0 +1,103 @@
+use libc;
+use tracing_subscriber;
+
+use self::tracing_subscriber::fmt::format::FmtSpan;
+use self::tracing_subscriber::reload;
+use self::tracing_subscriber::EnvFilter;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+
+/// LevelHandler replaces the filter of logs, like "debug" or "showfs=trace".
+pub type LevelHandler = Arc<dyn Fn(&str) -> Result<()> + Send + Sync>;
+
+// levels switched by SIGUSR2, in order.
+const CYCLED_LEVELS: [&str; 3] = ["info", "debug", "trace"];
+
+fn level_handler<S: 'static>(handle: reload::Handle<EnvFilter, S>) -> LevelHandler {
+    Arc::new(move |level| {
+        let filter =
+            EnvFilter::try_new(level).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        handle
+            .reload(filter)
+            .map_err(|e| Error::new(ErrorKind::Other, e))
+    })
+}
+
+// logs to stderr, filtered by RUST_LOG.
+// spans of fuse operations are logged when they close, with their durations.
+pub fn init(json: bool) -> LevelHandler {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+    if json {
+        // one object per line, with the fields of the event and its operation.
+        let builder = builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_filter_reloading();
+        let handler = level_handler(builder.reload_handle());
+        builder.init();
+        handler
+    } else {
+        let builder = builder.with_filter_reloading();
+        let handler = level_handler(builder.reload_handle());
+        builder.init();
+        handler
+    }
+}
+
+// the next level of the cycle, which starts from info.
+fn next_level(current: Option<usize>) -> usize {
+    match current {
+        Some(i) => (i + 1) % CYCLED_LEVELS.len(),
+        None => 1,
+    }
+}
+
+/// cycles the level by SIGUSR2, info, debug, trace and info again.
+/// this must be called before other threads start, so that they block the signal.
+pub fn cycle_on_sigusr2(handler: LevelHandler) -> Result<()> {
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGUSR2);
+        let ret = libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        if ret != 0 {
+            return Err(Error::from_raw_os_error(ret));
+        }
+        set
+    };
+    thread::Builder::new()
+        .name("sigusr2".to_string())
+        .spawn(move || {
+            let mut current = None;
+            loop {
+                let mut sig = 0;
+                if unsafe { libc::sigwait(&set, &mut sig) } != 0 {
+                    continue;
+                }
+                let next = next_level(current);
+                match handler(CYCLED_LEVELS[next]) {
+                    Ok(()) => {
+                        current = Some(next);
+                        warn!("log level is {}", CYCLED_LEVELS[next]);
+                    }
+                    Err(e) => error!("failed to change log level: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[test]
+fn test_next_level() {
+    assert_eq!(CYCLED_LEVELS[next_level(None)], "debug");
+    assert_eq!(CYCLED_LEVELS[next_level(Some(1))], "trace");
+    assert_eq!(CYCLED_LEVELS[next_level(Some(2))], "info");
+}
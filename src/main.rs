@@ -1,23 +1,795 @@
-use env_logger;
-
 #[macro_use]
-extern crate log;
+extern crate tracing;
 
-use std::iter::FromIterator;
+use showfs::tree::VirtualTree;
+use showfs::{
+    access, archive, audit, bench, collection, confine, control, ctl, du, error, escape, fs,
+    inodes, isolate, locate, logging, manifest, names, normalize, physical, preflight, profile,
+    shared_cache, systemd,
+};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
 use std::vec::Vec;
 
-mod archive;
-mod fs;
-mod physical;
+const USAGE: &str = "usage: showfs [OPTIONS] TARGET MOUNTPOINT
+       showfs [OPTIONS] --from-list LIST MOUNTPOINT
+       showfs [--backend BACKEND] formats
+       showfs ctl MOUNTPOINT COMMAND [ARG...]
+       showfs cache-server SOCKET BYTES
+       showfs [OPTIONS] manifest TARGET
+       showfs [OPTIONS] bench TARGET
+       showfs [OPTIONS] du TARGET
+options:
+    --profile comic|media|backup
+                              give the options bundled for the kind of archives, which
+                              options given explicitly take precedence over
+    --from-list FILE|-        show the files listed in FILE, or stdin, one per line,
+                              at the root in place of the target
+    --noatime                 report the mount as noatime
+    --allow-other             let other users access the mount
+    --allow uid=UID|exe=NAME
+    --deny uid=UID|exe=NAME   let only processes of the users or the executables open files,
+                              or not, by the first matching rule, can be repeated
+    --lazy                    mount before the target is read, which is read at the first
+                              operation, failing with EIO until it can be
+    --verify-sidecars         verify files having a.iso.sha256 or a.iso.md5 when they are
+                              read to the end, failing the last read with EIO if they differ
+    --auto-cache              let the kernel keep cached pages of files across opens until
+                              they are modified, like auto_cache of libfuse
+    --statfs-cache            report the capacity of caches as the size of the mount
+                              and the unused as the free, for df based alarms
+    --sort native|name        order of entries in directories of the target
+    --normalize nfc|nfd       show names in the unicode normalization form
+    --escape-names transliterate|percent
+                              show names of invalid UTF-8 or with control characters
+                              with U+FFFD or %XX in place of them
+    --casefold                show names colliding by case with suffixes like a (2).txt,
+                              for case insensitive clients like Samba
+    --names CODEC,...         encode names in order after the options above, for clients
+                              which can not store them: nfc, nfd, transliterate, percent,
+                              casefold, reserved (CON, NUL of Windows), truncate=BYTES
+    --formats FORMAT,...      archive formats to read (default: zip,rar,rar5,tar)
+                              7zip, ar, cab, cpio, iso9660, lha, rar, rar5, tar, xar, zip
+    --filters FILTER,...      compression filters to read (default: gzip,xz,zstd)
+                              bzip2, compress, gzip, lz4, lzma, xz, zstd
+    --block-size BYTES        size of reads from archive files (default: 262144)
+    --backend libarchive|rust implementation to read archives (default: libarchive)
+                              rust reads zip, tar and gzip only
+    --hdrcharset CHARSET      charset of names in archive headers, like CP932
+    --passphrase PASSPHRASE   passphrase of encrypted archives, can be repeated
+    --encrypted read|hide|deny|mark
+                              show encrypted members without passphrases as they are,
+                              not at all, failing opens with EACCES, or as empty a.ENCRYPTED
+    --special-files keep|hide|regular
+                              show fifos, devices and sockets in archives as they are,
+                              not at all, or as empty regular files
+    --sandbox                 decode archives in a confined helper process
+    --decode-timeout SECS     kill sandboxed decoding which makes no progress for SECS,
+                              failing the read with EIO
+    --mmap                    map archive files into memory instead of reading them
+    --cache-server SOCKET     share decoded members with other mounts by the cache server
+                              listening to SOCKET
+    --encrypt-cache           encrypt cached contents of archives by a key in memory,
+                              not to leave plaintext in the temporary file of the cache
+    --warm-readers N          keep decoding of the last N members whose entries are dropped
+                              before they are read to the end, to go on when they are reopened
+    --read-rate BYTES         limit reads from archive files to BYTES per second altogether
+    --retry ATTEMPTS[:BACKOFF_MS[:ERRNO,...]]
+                              read archive files again when reads fail by the errnos
+                              (default errnos: EIO,ETIMEDOUT,EAGAIN, backoff: 100)
+    --extension EXT=VIEWER    show files of the extension by the viewer, like cbz=archive
+    --plain-extension EXT     never show files of the extension but as they are, like jar
+    --assume-archive[=FORMAT]
+                              read the target as an archive even without a known extension,
+                              only in the format if it is given, like zip
+    --max-depth N             show files in N nested archives as they are (default: 4)
+    --quarantine SECS         fail a top-level entry with EIO for SECS after reading it
+                              panicked (default: 60)
+    --archive-size [EXT=]MIN:MAX
+                              show archives, or ones of the extension, as directories only
+                              if their sizes are in the range, either bound can be empty
+    --index-threads N         index archives in listed directories by N threads
+                              before they are accessed (default: 0)
+    --scrub SECS              check read archives against their files and cached members
+                              against their checksums every SECS, dropping stale or corrupt
+                              ones, a step at a time along with operations
+    --no-control-dir          do not show the .showfs directory at the root,
+                              nor listen to the control socket
+    --reindex-on-sigusr1      reindex the target by SIGUSR1, like the reindex command
+    --locate-index            walk the mount in the background to answer find by the
+                              control socket, like showfs ctl MOUNTPOINT find '*.pdf'
+    --all-files               list every regular file of the locate index in /.all by names
+                              encoding their paths, like a.zip%2Fb.jpg
+    --inode-map FILE          keep inodes of paths in FILE across mounts, like for NFS
+    --drop-privileges USER|UID:GID
+                              switch to the user once mounted, not to read archives as root
+    --confine                 confine the mount by landlock and seccomp once mounted, to read
+                              the target only
+    --audit-log FILE          append opens and closes of files with uids and pids to FILE
+    --memory-budget BYTES     limit caches, entries and listings altogether, over which
+                              unused ones are dropped
+    --log-format text|json    format of logs to stderr (default: text)
+                              RUST_LOG filters them, like RUST_LOG=showfs=debug";
 
-fn main() {
-    env_logger::init().unwrap();
-    let args = Vec::<String>::from_iter(std::env::args());
-    let ref target = args[1];
-    let ref mountpoint = args[2];
-    let mut fs = fs::ShowFS::new(target);
+// the exit status when the mount loses the kernel while serving, like by an aborted
+// connection. unmounts, lazy ones too, exit with 0.
+const EXIT_DISCONNECTED: i32 = 3;
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+// parses comma separated values.
+fn parse_list<T: FromStr>(arg: Option<String>) -> Vec<T> {
+    let arg = arg.unwrap_or_else(|| usage());
+    arg.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().unwrap_or_else(|_| usage()))
+        .collect()
+}
+
+fn parse_value<T: FromStr>(arg: Option<String>) -> T {
+    let arg = arg.unwrap_or_else(|| usage());
+    arg.parse().unwrap_or_else(|_| usage())
+}
+
+// allows the target, the private temporary directory of caches and the directory of the inode
+// map. system files under /usr are readable for charsets which libarchive loads to convert names.
+fn confinement(
+    target: &str,
+    list: Option<&Vec<PathBuf>>,
+    inode_map: Option<&String>,
+    tmp: &Path,
+) -> confine::Confinement {
+    let mut confinement = confine::Confinement::new();
+    match list {
+        Some(paths) => paths.iter().for_each(|p| confinement.allow_read(p)),
+        None => confinement.allow_read(target),
+    }
+    confinement.allow_read("/usr");
+    confinement.allow_write(tmp);
+    if let Some(path) = inode_map {
+        match Path::new(path).parent() {
+            Some(dir) if dir != Path::new("") => confinement.allow_write(dir),
+            _ => confinement.allow_write("."),
+        }
+    }
+    confinement
+}
+
+// parses UID:GID, or USER whose primary group is taken.
+fn parse_user(arg: Option<String>) -> (u32, u32) {
+    let arg = arg.unwrap_or_else(|| usage());
+    if let Some(i) = arg.find(':') {
+        let uid = parse_value(Some(arg[..i].to_string()));
+        return (uid, parse_value(Some(arg[i + 1..].to_string())));
+    }
+    let name = CString::new(arg.as_str()).unwrap_or_else(|_| usage());
+    let pw = unsafe { libc::getpwnam(name.as_ptr()) };
+    if pw.is_null() {
+        eprintln!("unknown user: {}", arg);
+        process::exit(2);
+    }
+    unsafe { ((*pw).pw_uid, (*pw).pw_gid) }
+}
+
+// parses [EXT=]MIN:MAX.
+fn parse_size_limit(arg: Option<String>) -> (Option<String>, archive::SizeLimit) {
+    let arg = arg.unwrap_or_else(|| usage());
+    let (ext, limit) = match arg.find('=') {
+        Some(i) => (Some(arg[..i].to_string()), &arg[i + 1..]),
+        None => (None, arg.as_str()),
+    };
+    (ext, limit.parse().unwrap_or_else(|_| usage()))
+}
+
+// parses EXT=VIEWER.
+fn parse_extension(arg: Option<String>) -> (String, String) {
+    let arg = arg.unwrap_or_else(|| usage());
+    match arg.find('=') {
+        Some(i) => (arg[..i].to_string(), arg[i + 1..].to_string()),
+        None => usage(),
+    }
+}
+
+// maps extensions to viewers before viewers add their defaults.
+fn map_extensions(control: &control::Control, extensions: &[(String, String)], plain: &[String]) {
+    for &(ref ext, ref viewer) in extensions.iter() {
+        control.map_extension(ext, viewer);
+        control.add_config("extension", format!("{}={}", ext, viewer));
+    }
+    for ext in plain.iter() {
+        control.block_extension(ext);
+        control.add_config("plain_extension", ext.clone());
+    }
+}
+
+// reads the paths to show by --from-list.
+fn read_list(path: &str) -> Vec<PathBuf> {
+    let result = if path == "-" {
+        let stdin = io::stdin();
+        let lock = stdin.lock();
+        collection::read_list(lock)
+    } else {
+        File::open(path).and_then(|f| collection::read_list(BufReader::new(f)))
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path, e);
+        process::exit(1);
+    })
+}
+
+// prints what the backend can read.
+fn print_formats(backend: archive::Backend) {
+    let c = archive::capabilities(backend).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let formats: Vec<_> = c.formats.iter().map(|f| f.name()).collect();
+    let filters: Vec<_> = c.filters.iter().map(|f| f.name()).collect();
+    println!("{}", c.version);
+    println!("formats: {}", formats.join(", "));
+    println!("filters: {}", filters.join(", "));
+    if !c.external_filters.is_empty() {
+        let external: Vec<_> = c.external_filters.iter().map(|f| f.name()).collect();
+        println!("filters by external programs: {}", external.join(", "));
+    }
+}
+
+fn archive_viewer(
+    options: archive::Options,
+    index_threads: usize,
+    confinement: Option<&confine::Confinement>,
+    size_limits: &[(Option<String>, archive::SizeLimit)],
+    // Some with the format, if any, to read the root as an archive.
+    assume_archive: Option<Option<archive::Format>>,
+    encrypt_cache: bool,
+    warm_readers: usize,
+) -> archive::ArchiveViewer {
     let max_cache = 1024 * 1024 * 1024;
-    fs.register_viewer(archive::ArchiveViewer::new(max_cache).unwrap());
+    let mut viewer = archive::ArchiveViewer::new(max_cache, options).unwrap();
+    if encrypt_cache {
+        if let Err(e) = viewer.set_encrypt_cache() {
+            eprintln!("failed to make a key of the cache: {}", e);
+            process::exit(1);
+        }
+    }
+    viewer.set_warm_readers(warm_readers);
+    if let Err(e) = viewer.set_index_threads(index_threads, confinement) {
+        warn!("failed to start indexers: {}", e);
+    }
+    for &(ref ext, limit) in size_limits.iter() {
+        match *ext {
+            Some(ref ext) => viewer.set_extension_size_limit(ext, limit),
+            None => viewer.set_size_limit(limit),
+        }
+    }
+    if let Some(format) = assume_archive {
+        viewer.set_assume_archive(format);
+    }
+    viewer
+}
+
+// the codecs of names in the order they are applied, of --normalize, --escape-names and
+// --casefold before the ones of --names.
+fn name_codecs(
+    normalize: Option<normalize::Form>,
+    escape_names: Option<escape::Mode>,
+    casefold: bool,
+    codecs: Vec<names::Codec>,
+) -> Vec<names::Codec> {
+    let options = vec![
+        normalize.map(names::Codec::Normalize),
+        escape_names.map(names::Codec::Escape),
+        if casefold {
+            Some(names::Codec::CaseFold)
+        } else {
+            None
+        },
+    ];
+    options.into_iter().flatten().chain(codecs).collect()
+}
+
+// the tree which would be mounted, to be walked without fuse.
+fn into_tree(
+    mut fs: fs::ShowFS,
+    viewer: archive::ArchiveViewer,
+    codecs: Vec<names::Codec>,
+) -> VirtualTree {
+    fs.register_viewer(viewer);
+    // one viewer for all the codecs, as each lists directories.
+    if !codecs.is_empty() {
+        fs.register_viewer(names::NameViewer::new(codecs));
+    }
+    fs.into_tree()
+}
+
+// prints the entries of the tree which would be mounted as JSON.
+fn print_manifest(tree: VirtualTree) {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let result = manifest::write(&tree, Path::new("/"), &mut out).and_then(|_| out.flush());
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// prints throughputs and latencies of reading the tree.
+fn print_bench(tree: VirtualTree) {
+    match bench::run(&tree) {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// prints the sizes of directories and files by extensions in the tree.
+fn print_du(tree: VirtualTree) {
+    match du::run(&tree, Path::new("/")) {
+        Ok(usage) => print!("{}", usage),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// shows the options in the control directory, but passphrases.
+fn add_config(
+    control: &control::Control,
+    options: &archive::Options,
+    noatime: bool,
+    sort_order: physical::SortOrder,
+    normalize: Option<normalize::Form>,
+) {
+    let formats: Vec<_> = options.formats().iter().map(|f| f.name()).collect();
+    let filters: Vec<_> = options.filters().iter().map(|f| f.name()).collect();
+    control.add_config("noatime", noatime.to_string());
+    let sort = match sort_order {
+        physical::SortOrder::Native => "native",
+        physical::SortOrder::Name => "name",
+    };
+    control.add_config("sort", sort.to_string());
+    let normalize = match normalize {
+        Some(normalize::Form::NFC) => "nfc",
+        Some(normalize::Form::NFD) => "nfd",
+        None => "none",
+    };
+    control.add_config("normalize", normalize.to_string());
+    control.add_config("formats", formats.join(","));
+    control.add_config("filters", filters.join(","));
+    control.add_config("block_size", options.block_size().to_string());
+    control.add_config("backend", format!("{:?}", options.backend()).to_lowercase());
+    control.add_config("sandbox", options.sandbox().to_string());
+    let timeout = options
+        .decode_timeout()
+        .map_or("none".to_string(), |t| t.as_secs().to_string());
+    control.add_config("decode_timeout", timeout);
+    control.add_config("mmap", options.mmap().to_string());
+    let server = options
+        .shared_cache()
+        .map_or("none".to_string(), |c| c.path().display().to_string());
+    control.add_config("cache_server", server);
+    let rate = options
+        .read_rate()
+        .map_or("none".to_string(), |r| r.to_string());
+    control.add_config("read_rate", rate);
+    let retry = match options.retry_policy() {
+        Some(p) => format!("{}:{}", p.attempts, p.backoff.as_millis()),
+        None => "none".to_string(),
+    };
+    control.add_config("retry", retry);
+    control.add_config("passphrases", options.passphrases().len().to_string());
+    let policy = options.encrypted_policy().name();
+    control.add_config("encrypted", policy.to_string());
+    let special = options.special_files().name();
+    control.add_config("special_files", special.to_string());
+}
+
+fn main() {
+    // the mount runs itself to decode archives in the sandbox.
+    let mut args_os = std::env::args_os().skip(1);
+    match args_os.next() {
+        Some(ref arg) if arg == archive::SANDBOX_HELPER_ARG => {
+            let _ = logging::init(false);
+            archive::sandbox_helper(args_os.collect())
+        }
+        _ => {}
+    }
+    let passed_fds = systemd::close_passed_fds();
+    let mut noatime = false;
+    let mut lazy = false;
+    let mut encrypt_cache = false;
+    let mut warm_readers = 0;
+    let mut verify_sidecars = false;
+    let mut statfs_cache = false;
+    let mut auto_cache = false;
+    let mut confine = false;
+    let mut allow_other = false;
+    let mut access_policy: Option<access::AccessPolicy> = None;
+    let mut json_log = false;
+    let mut control_dir = true;
+    let mut reindex_on_sigusr1 = false;
+    let mut locate_index = false;
+    let mut all_files = false;
+    let mut audit_log: Option<String> = None;
+    let mut inode_map: Option<String> = None;
+    let mut privileges: Option<(u32, u32)> = None;
+    let mut from_list: Option<String> = None;
+    let mut index_threads = 0;
+    let mut scrub: Option<u64> = None;
+    let mut memory_budget: Option<usize> = None;
+    let mut size_limits = Vec::new();
+    let mut extensions = Vec::new();
+    let mut max_depth = None;
+    let mut quarantine = 60;
+    let mut plain_extensions = Vec::new();
+    let mut assume_archive = None;
+    let mut sort_order = physical::SortOrder::Native;
+    let mut normalize = None;
+    let mut escape_names = None;
+    let mut casefold = false;
+    let mut codecs = Vec::new();
+    let mut archive_options = archive::Options::default();
+    let mut positionals = Vec::new();
+    let (args, profiles) =
+        profile::expand(std::env::args().skip(1).collect()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            usage()
+        });
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from-list" => from_list = Some(parse_value(args.next())),
+            "--noatime" => noatime = true,
+            "--allow-other" => allow_other = true,
+            "--allow" => access_policy
+                .get_or_insert_with(access::AccessPolicy::new)
+                .allow(parse_value(args.next())),
+            "--deny" => access_policy
+                .get_or_insert_with(access::AccessPolicy::new)
+                .deny(parse_value(args.next())),
+            "--lazy" => lazy = true,
+            "--verify-sidecars" => verify_sidecars = true,
+            "--statfs-cache" => statfs_cache = true,
+            "--auto-cache" => auto_cache = true,
+            "--sort" => {
+                sort_order = match args.next().as_ref().map(|s| s.as_str()) {
+                    Some("native") => physical::SortOrder::Native,
+                    Some("name") => physical::SortOrder::Name,
+                    _ => usage(),
+                }
+            }
+            "--normalize" => {
+                normalize = match args.next().as_ref().map(|s| s.as_str()) {
+                    Some("nfc") => Some(normalize::Form::NFC),
+                    Some("nfd") => Some(normalize::Form::NFD),
+                    _ => usage(),
+                }
+            }
+            "--casefold" => casefold = true,
+            "--escape-names" => {
+                escape_names = match args.next().as_ref().map(|s| s.as_str()) {
+                    Some("transliterate") => Some(escape::Mode::Transliterate),
+                    Some("percent") => Some(escape::Mode::Percent),
+                    _ => usage(),
+                }
+            }
+            "--names" => codecs = parse_list(args.next()),
+            "--formats" => archive_options.set_formats(parse_list(args.next())),
+            "--filters" => archive_options.set_filters(parse_list(args.next())),
+            "--block-size" => archive_options.set_block_size(parse_value(args.next())),
+            "--backend" => archive_options.set_backend(parse_value(args.next())),
+            "--hdrcharset" => {
+                let charset: String = parse_value(args.next());
+                archive_options.add_read_option(format!("hdrcharset={}", charset));
+            }
+            "--passphrase" => archive_options.add_passphrase(parse_value(args.next())),
+            "--encrypted" => archive_options.set_encrypted_policy(parse_value(args.next())),
+            "--special-files" => archive_options.set_special_files(parse_value(args.next())),
+            "--sandbox" => archive_options.set_sandbox(true),
+            "--decode-timeout" => {
+                let secs = parse_value(args.next());
+                archive_options.set_decode_timeout(Duration::from_secs(secs))
+            }
+            "--mmap" => archive_options.set_mmap(true),
+            "--cache-server" => {
+                let socket: String = parse_value(args.next());
+                archive_options.set_shared_cache(shared_cache::Client::new(socket))
+            }
+            "--encrypt-cache" => encrypt_cache = true,
+            "--warm-readers" => warm_readers = parse_value(args.next()),
+            "--read-rate" => archive_options.set_read_rate(parse_value(args.next())),
+            "--retry" => archive_options.set_retry_policy(parse_value(args.next())),
+            "--archive-size" => size_limits.push(parse_size_limit(args.next())),
+            "--max-depth" => max_depth = Some(parse_value(args.next())),
+            "--quarantine" => quarantine = parse_value(args.next()),
+            "--extension" => extensions.push(parse_extension(args.next())),
+            "--plain-extension" => plain_extensions.push(parse_value::<String>(args.next())),
+            "--index-threads" => index_threads = parse_value(args.next()),
+            "--scrub" => scrub = Some(parse_value(args.next())),
+            "--log-format" => {
+                json_log = match args.next().as_ref().map(|s| s.as_str()) {
+                    Some("text") => false,
+                    Some("json") => true,
+                    _ => usage(),
+                }
+            }
+            "--no-control-dir" => control_dir = false,
+            "--reindex-on-sigusr1" => reindex_on_sigusr1 = true,
+            "--locate-index" => locate_index = true,
+            "--all-files" => all_files = true,
+            "--audit-log" => audit_log = Some(parse_value(args.next())),
+            "--inode-map" => inode_map = Some(parse_value(args.next())),
+            "--confine" => confine = true,
+            "--drop-privileges" => privileges = Some(parse_user(args.next())),
+            "--memory-budget" => memory_budget = Some(parse_value(args.next())),
+            "--assume-archive" => assume_archive = Some(None),
+            _ if arg.starts_with("--assume-archive=") => {
+                let format = arg["--assume-archive=".len()..].to_string();
+                assume_archive = Some(Some(parse_value(Some(format))));
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => positionals.push(arg),
+        }
+    }
+    let log_level_handler = logging::init(json_log);
+    if positionals.len() == 1 && positionals[0] == "formats" {
+        print_formats(archive_options.backend());
+        return;
+    }
+    if !positionals.is_empty() && positionals[0] == "ctl" {
+        ctl::client_main(&positionals[1..]);
+    }
+    if positionals.len() == 3 && positionals[0] == "cache-server" {
+        let max_bytes = parse_value(Some(positionals[2].clone()));
+        if let Err(e) = shared_cache::serve(Path::new(&positionals[1]), max_bytes) {
+            eprintln!("failed to serve the cache: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    if confine && archive_options.sandbox() {
+        eprintln!("--confine can not be used with --sandbox, whose helpers are executed");
+        process::exit(2);
+    }
+    if archive_options.read_rate().is_some() && archive_options.sandbox() {
+        eprintln!("--read-rate can not be used with --sandbox, whose helpers read archives");
+        process::exit(2);
+    }
+    // the list is in place of the target.
+    let list = from_list.map(|path| read_list(&path));
+    let expected = if list.is_some() { 1 } else { 2 };
+    if positionals.len() != expected {
+        usage();
+    }
+    archive::check_capabilities(&archive_options);
+    let new_fs = |target: &str| match list {
+        Some(ref paths) => fs::ShowFS::from_list(paths.clone()),
+        None => fs::ShowFS::new(target),
+    };
+    if ["manifest", "bench", "du"].contains(&positionals[0].as_str()) {
+        let mut fs = new_fs(&positionals[expected - 1]);
+        fs.set_sort_order(sort_order);
+        map_extensions(&fs.control(), &extensions, &plain_extensions);
+        if let Some(depth) = max_depth {
+            fs.set_max_depth(depth);
+        }
+        let viewer = archive_viewer(
+            archive_options,
+            index_threads,
+            None,
+            &size_limits,
+            assume_archive,
+            encrypt_cache,
+            warm_readers,
+        );
+        let codecs = name_codecs(normalize, escape_names, casefold, codecs);
+        let tree = into_tree(fs, viewer, codecs);
+        match positionals[0].as_str() {
+            "bench" => print_bench(tree),
+            "du" => print_du(tree),
+            _ => print_manifest(tree),
+        }
+        return;
+    }
+    let ref target = positionals[0];
+    let ref mountpoint = positionals[expected - 1];
+    // problems which the mount would fail by are told with what to do, by their statuses.
+    let environment = preflight::Environment::default();
+    if let Err(problem) = environment.check(Path::new(mountpoint), allow_other) {
+        eprintln!("failed to mount {}: {}", mountpoint, problem);
+        process::exit(problem.exit_code());
+    }
+    // files mounted on by a file have no control directory to serve the socket by.
+    if std::fs::metadata(mountpoint).map_or(false, |m| m.is_file()) {
+        control_dir = false;
+    }
+    let mut fs = new_fs(target);
+    fs.set_noatime(noatime);
+    fs.set_allow_other(allow_other);
+    let has_access_policy = access_policy.is_some();
+    if let Some(policy) = access_policy {
+        fs.set_access_policy(policy);
+    }
+    fs.set_lazy(lazy);
+    fs.set_sort_order(sort_order);
+    fs.set_verify_sidecars(verify_sidecars);
+    fs.set_statfs_cache(statfs_cache);
+    fs.set_auto_cache(auto_cache);
+    if let Some((uid, gid)) = privileges {
+        fs.set_drop_privileges(uid, gid);
+    }
+    // temporary files are made in a directory of the mount, the only one writable of $TMPDIR
+    // once confined. no other threads are running yet to read the environment.
+    let private_tmp = if confine {
+        let dir = tempfile::Builder::new()
+            .prefix("showfs-")
+            .tempdir()
+            .unwrap_or_else(|e| {
+                eprintln!("failed to make a temporary directory: {}", e);
+                process::exit(1);
+            });
+        std::env::set_var("TMPDIR", dir.path());
+        Some(dir)
+    } else {
+        None
+    };
+    let confinement = private_tmp
+        .as_ref()
+        .map(|dir| confinement(target, list.as_ref(), inode_map.as_ref(), dir.path()));
+    if let Some(ref confinement) = confinement {
+        fs.set_confinement(confinement.clone());
+    }
+    fs.set_control_dir(control_dir);
+    if let Some(bytes) = memory_budget {
+        fs.set_memory_budget(bytes);
+    }
+    if let Some(depth) = max_depth {
+        fs.set_max_depth(depth);
+    }
+    if let Some(ref path) = audit_log {
+        let audit = audit::AuditLog::open(path).unwrap_or_else(|e| {
+            eprintln!("failed to open {}: {}", path, e);
+            process::exit(1);
+        });
+        fs.set_audit_log(audit);
+    }
+    if let Some(ref path) = inode_map {
+        let map = inodes::InodeMap::open(path).unwrap_or_else(|e| {
+            eprintln!("failed to open {}: {}", path, e);
+            process::exit(1);
+        });
+        fs.set_inode_map(map);
+    }
+    let control = fs.control();
+    map_extensions(&control, &extensions, &plain_extensions);
+    if let Err(e) = logging::cycle_on_sigusr2(log_level_handler.clone()) {
+        warn!("log level can not be changed by SIGUSR2: {}", e);
+    }
+    if reindex_on_sigusr1 {
+        if let Err(e) = control::reindex_on_sigusr1(control.reindex_request()) {
+            warn!("failed to reindex by SIGUSR1: {}", e);
+        }
+    }
+    control.set_log_level_handler(move |level| log_level_handler(level));
+    add_config(&control, &archive_options, noatime, sort_order, normalize);
+    let mut viewer = archive_viewer(
+        archive_options,
+        index_threads,
+        confinement.as_ref(),
+        &size_limits,
+        assume_archive,
+        encrypt_cache,
+        warm_readers,
+    );
+    let profiles: Vec<_> = profiles.iter().map(|p| p.name()).collect();
+    let profiles = if profiles.is_empty() {
+        "none".to_string()
+    } else {
+        profiles.join(",")
+    };
+    control.add_config("profile", profiles);
+    control.add_config("lazy", lazy.to_string());
+    control.add_config("allow_other", allow_other.to_string());
+    control.add_config("access_policy", has_access_policy.to_string());
+    control.add_config("verify_sidecars", verify_sidecars.to_string());
+    control.add_config("statfs_cache", statfs_cache.to_string());
+    control.add_config("auto_cache", auto_cache.to_string());
+    control.add_config("encrypt_cache", encrypt_cache.to_string());
+    control.add_config("warm_readers", warm_readers.to_string());
+    control.add_config("index_threads", index_threads.to_string());
+    let budget = memory_budget.map_or("none".to_string(), |b| b.to_string());
+    control.add_config("memory_budget", budget);
+    let privileges = privileges.map_or("none".to_string(), |(u, g)| format!("{}:{}", u, g));
+    control.add_config("drop_privileges", privileges);
+    control.add_config("confine", confine.to_string());
+    control.register_cache(viewer.cache());
+    control.register_counters(viewer.warnings());
+    if let Some(secs) = scrub {
+        control.register_scrubber(viewer.scrubber());
+        control.schedule_scrub(Duration::from_secs(secs));
+    }
+    control.add_config("scrub", scrub.map_or("none".to_string(), |s| s.to_string()));
+    fs.register_viewer(viewer);
+    let escape_config = match escape_names {
+        Some(escape::Mode::Transliterate) => "transliterate",
+        Some(escape::Mode::Percent) => "percent",
+        None => "none",
+    };
+    control.add_config("escape_names", escape_config.to_string());
+    control.add_config("casefold", casefold.to_string());
+    let codecs = name_codecs(normalize, escape_names, casefold, codecs);
+    let names_config: Vec<_> = codecs.iter().map(|c| c.to_string()).collect();
+    control.add_config("names", names_config.join(","));
+    if !codecs.is_empty() {
+        fs.register_viewer(names::NameViewer::new(codecs));
+    }
+    // the last, to wrap entries as they are shown.
+    control.add_config("quarantine", quarantine.to_string());
+    fs.register_viewer(isolate::IsolationViewer::new(Duration::from_secs(
+        quarantine,
+    )));
+    if passed_fds > 0 {
+        warn!(
+            "{} passed fds are not supported and closed, /dev/fuse is opened by mount",
+            passed_fds
+        );
+    }
+    // for Type=notify units of systemd.
+    if let Err(e) = systemd::start_watchdog(fs.activity()) {
+        warn!("failed to start watchdog: {}", e);
+    }
+    // the index needs the socket to be asked, unless it lists all files.
+    let index = if (locate_index && control_dir) || all_files {
+        Some(locate::Index::new(mountpoint))
+    } else {
+        None
+    };
+    control.add_config("locate_index", index.is_some().to_string());
+    control.add_config("all_files", all_files.to_string());
+    if let (true, Some(index)) = (all_files, &index) {
+        fs.set_all_files(index.clone());
+    }
+    let mounted_index = index.clone();
+    fs.set_mounted_callback(move || {
+        if let Err(e) = systemd::notify("READY=1") {
+            warn!("failed to notify systemd: {}", e);
+        }
+        if let Some(index) = mounted_index {
+            if let Err(e) = locate::Index::rebuild(index) {
+                warn!("failed to build the locate index: {}", e);
+            }
+        }
+    });
+    // the socket is removed when the server is dropped after unmount.
+    let _server = if control_dir {
+        ctl::serve(mountpoint, index)
+            .map_err(|e| warn!("failed to listen to the control socket: {}", e))
+            .ok()
+    } else {
+        None
+    };
     let result = fs.mount(mountpoint);
-    result.unwrap();
+    let _ = systemd::notify("STOPPING=1");
+    // the directory is removed before exiting, which skips destructors.
+    drop(private_tmp);
+    if let Err(e) = result {
+        eprintln!("failed to serve {}: {}", mountpoint, e);
+        // supervisors tell mounts lost while serving from ones which failed to mount.
+        let disconnected = match e.get_ref().and_then(|e| e.downcast_ref()) {
+            Some(&error::Error::Disconnected(_)) => true,
+            _ => false,
+        };
+        process::exit(if disconnected { EXIT_DISCONNECTED } else { 1 });
+    }
 }
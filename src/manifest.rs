@@ -0,0 +1,75 @@
+use fuse;
+use serde_json;
+
+use self::fuse::FileType;
+use self::serde_json::{json, Value};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+use crate::fs::Entry;
+use crate::tree::VirtualTree;
+
+fn type_name(kind: FileType) -> &'static str {
+    match kind {
+        FileType::RegularFile => "file",
+        FileType::Directory => "dir",
+        FileType::Symlink => "symlink",
+        FileType::NamedPipe => "fifo",
+        FileType::CharDevice => "char_device",
+        FileType::BlockDevice => "block_device",
+        FileType::Socket => "socket",
+    }
+}
+
+fn describe(path: &Path, entry: &Entry) -> Result<Value> {
+    let attr = entry.getattr(0)?;
+    Ok(json!({
+        "path": path.to_string_lossy(),
+        "type": type_name(attr.kind),
+        "size": attr.size,
+        "mtime": attr.mtime.sec,
+        "origin": entry.origin().map(|p| p.to_string_lossy()),
+        "member": entry.member().map(|p| p.to_string_lossy().into_owned()),
+    }))
+}
+
+/// writes the entries under the path of the tree as a JSON array, parents first.
+/// origin of an entry is the file on the disk which it is read from, the archive for entries
+/// in archives, and member is the path of the entry in it, null for files on the disk.
+pub fn write<W: Write>(tree: &VirtualTree, path: &Path, out: &mut W) -> Result<()> {
+    // entries are written one by one, not to hold large trees in memory.
+    out.write_all(b"[")?;
+    let mut first = true;
+    tree.walk(path, |path, entry| {
+        let value = describe(path, entry)?;
+        if !first {
+            out.write_all(b",")?;
+        }
+        first = false;
+        out.write_all(b"\n")?;
+        serde_json::to_writer(&mut *out, &value).map_err(|e| Error::new(ErrorKind::Other, e))
+    })?;
+    out.write_all(b"\n]\n")
+}
+
+#[test]
+fn test_write() {
+    use crate::fs::ShowFS;
+
+    let tmp = tempfile::tempdir().unwrap();
+    std::fs::write(tmp.path().join("file"), "hello").unwrap();
+    let tree = ShowFS::new(tmp.path()).into_tree();
+    let mut out = Vec::new();
+    write(&tree, Path::new("/"), &mut out).unwrap();
+    let manifest: Value = serde_json::from_slice(&out).unwrap();
+    let entries = manifest.as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["path"], "/");
+    assert_eq!(entries[0]["type"], "dir");
+    assert_eq!(entries[1]["path"], "/file");
+    assert_eq!(entries[1]["type"], "file");
+    assert_eq!(entries[1]["size"], 5);
+    let origin = tmp.path().join("file");
+    assert_eq!(entries[1]["origin"], origin.to_str().unwrap());
+    assert!(entries[1]["member"].is_null());
+}
@@ -0,0 +1,556 @@
+use fuse;
+use libc;
+use time;
+
+use self::fuse::{FileAttr, FileType};
+use self::time::Timespec;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{Error, Result};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::{self, FromStr};
+
+use crate::casefold;
+use crate::escape;
+use crate::fs;
+use crate::normalize;
+
+/// NameCodec changes the names of entries as they are shown by the mount, like into names
+/// which clients can store, while the entries are looked up by the shown names.
+/// the names of the children of a directory are encoded at once, so that codecs can keep them
+/// apart, like from names which collide by case.
+pub trait NameCodec {
+    /// the shown names of the names, in the same order.
+    fn encode(&self, names: Vec<OsString>) -> Vec<OsString>;
+}
+
+// codecs are applied in order.
+impl<C: NameCodec> NameCodec for Vec<C> {
+    fn encode(&self, names: Vec<OsString>) -> Vec<OsString> {
+        self.iter().fold(names, |names, codec| codec.encode(names))
+    }
+}
+
+impl<C: NameCodec + ?Sized> NameCodec for Box<C> {
+    fn encode(&self, names: Vec<OsString>) -> Vec<OsString> {
+        (**self).encode(names)
+    }
+}
+
+// names shorter than this are not truncated to, to leave room for suffixes.
+const MIN_TRUNCATED_BYTES: usize = 16;
+// extensions longer than this are truncated with the rest of names.
+const MAX_KEPT_EXTENSION_BYTES: usize = 8;
+// names of devices of Windows, which can not be the names of files with any extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Codec is a built-in codec, which is configured by its name, like `truncate=143`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Codec {
+    /// `nfc` or `nfd`, names in the unicode normalization form.
+    Normalize(normalize::Form),
+    /// `transliterate` or `percent`, names of invalid UTF-8 or with control characters with
+    /// U+FFFD or %XX in place of them.
+    Escape(escape::Mode),
+    /// `casefold`, names colliding by case with suffixes, like "Readme (2)".
+    CaseFold,
+    /// `reserved`, names reserved by Windows with an underscore, like "CON_.txt" for "CON.txt".
+    Reserved,
+    /// `truncate=BYTES`, names longer than the bytes cut, keeping their extensions.
+    Truncate(usize),
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Codec, String> {
+        let codec = match s {
+            "nfc" => Codec::Normalize(normalize::Form::NFC),
+            "nfd" => Codec::Normalize(normalize::Form::NFD),
+            "transliterate" => Codec::Escape(escape::Mode::Transliterate),
+            "percent" => Codec::Escape(escape::Mode::Percent),
+            "casefold" => Codec::CaseFold,
+            "reserved" => Codec::Reserved,
+            _ => match s.strip_prefix("truncate=").map(str::parse) {
+                Some(Ok(bytes)) if bytes >= MIN_TRUNCATED_BYTES => Codec::Truncate(bytes),
+                Some(_) => return Err(format!("invalid bytes to truncate to: {}", s)),
+                None => return Err(format!("unknown codec: {}", s)),
+            },
+        };
+        Ok(codec)
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Codec::Normalize(normalize::Form::NFC) => write!(f, "nfc"),
+            Codec::Normalize(normalize::Form::NFD) => write!(f, "nfd"),
+            Codec::Escape(escape::Mode::Transliterate) => write!(f, "transliterate"),
+            Codec::Escape(escape::Mode::Percent) => write!(f, "percent"),
+            Codec::CaseFold => write!(f, "casefold"),
+            Codec::Reserved => write!(f, "reserved"),
+            Codec::Truncate(bytes) => write!(f, "truncate={}", bytes),
+        }
+    }
+}
+
+impl NameCodec for Codec {
+    fn encode(&self, names: Vec<OsString>) -> Vec<OsString> {
+        match *self {
+            Codec::Normalize(form) => names
+                .iter()
+                .map(|n| normalize::normalize(form, n))
+                .collect(),
+            Codec::Escape(mode) => names.iter().map(|n| escape::escape(mode, n)).collect(),
+            Codec::CaseFold => {
+                let shown = casefold::disambiguate(names.clone());
+                names.iter().map(|n| shown[n].clone()).collect()
+            }
+            Codec::Reserved => names.iter().map(|n| unreserve(n)).collect(),
+            Codec::Truncate(bytes) => truncate_all(names, bytes),
+        }
+    }
+}
+
+// the position of the dot of the extension, the length if none. dot files have none.
+fn extension_dot(name: &[u8]) -> usize {
+    match name.iter().rposition(|&b| b == b'.') {
+        Some(0) | None => name.len(),
+        Some(i) => i,
+    }
+}
+
+// "CON_.txt" for "CON.txt", as Windows reserves the names whatever their extensions are.
+fn unreserve(name: &OsStr) -> OsString {
+    let bytes = name.as_bytes();
+    let base = match bytes.iter().position(|&b| b == b'.') {
+        Some(i) => i,
+        None => bytes.len(),
+    };
+    let reserved = RESERVED_NAMES
+        .iter()
+        .any(|r| r.as_bytes().eq_ignore_ascii_case(&bytes[..base]));
+    if !reserved {
+        return name.to_os_string();
+    }
+    let mut s = bytes[..base].to_vec();
+    s.push(b'_');
+    s.extend_from_slice(&bytes[base..]);
+    OsString::from_vec(s)
+}
+
+// the name cut to the bytes, keeping a short extension, at a boundary of characters if it is
+// UTF-8.
+fn truncate(name: &OsStr, bytes: usize) -> OsString {
+    let name = name.as_bytes();
+    if name.len() <= bytes {
+        return OsString::from_vec(name.to_vec());
+    }
+    let dot = extension_dot(name);
+    let extension = if name.len() - dot <= MAX_KEPT_EXTENSION_BYTES {
+        &name[dot..]
+    } else {
+        &b""[..]
+    };
+    let mut end = bytes - extension.len();
+    if str::from_utf8(name).is_ok() {
+        while !str::from_utf8(&name[..end]).is_ok() {
+            end -= 1;
+        }
+    }
+    let mut s = name[..end].to_vec();
+    s.extend_from_slice(extension);
+    OsString::from_vec(s)
+}
+
+// truncates the names, and the ones which collide again with suffixes within the bytes.
+// names are suffixed in the order of the original names, so the result does not depend on
+// the order.
+fn truncate_all(names: Vec<OsString>, bytes: usize) -> Vec<OsString> {
+    let mut taken: HashSet<_> = names.iter().filter(|n| n.len() <= bytes).cloned().collect();
+    let mut long: Vec<_> = names.iter().filter(|n| n.len() > bytes).collect();
+    long.sort();
+    let mut truncated = HashMap::new();
+    for name in long {
+        let mut s = truncate(name, bytes);
+        for n in 2.. {
+            if taken.insert(s.clone()) {
+                break;
+            }
+            let suffix = format!(" ({})", n).len();
+            s = casefold::suffixed(&truncate(name, bytes - suffix), n);
+        }
+        truncated.insert(name.clone(), s);
+    }
+    names
+        .into_iter()
+        .map(|n| truncated.get(&n).cloned().unwrap_or(n))
+        .collect()
+}
+
+// the shown names of the original names, which are encoded ones with suffixes if they
+// collide. the smallest of colliding names keeps the encoded name, so the result does not
+// depend on the order.
+fn unique(names: Vec<OsString>, encoded: Vec<OsString>) -> HashMap<OsString, OsString> {
+    let mut pairs: Vec<_> = names.into_iter().zip(encoded).collect();
+    pairs.sort();
+    let all: HashSet<_> = pairs.iter().map(|p| p.1.clone()).collect();
+    let mut taken = HashSet::new();
+    let mut shown = HashMap::new();
+    for (name, encoded) in pairs {
+        let mut s = encoded.clone();
+        if !taken.insert(encoded.clone()) {
+            for n in 2.. {
+                s = casefold::suffixed(&encoded, n);
+                if !all.contains(&s) && taken.insert(s.clone()) {
+                    break;
+                }
+            }
+        }
+        shown.insert(name, s);
+    }
+    shown
+}
+
+// names of children, from the original to the shown and back.
+struct Names {
+    shown: HashMap<OsString, OsString>,
+    original: HashMap<OsString, OsString>,
+    // of the directory when the names are listed.
+    mtime: Option<Timespec>,
+}
+
+impl Names {
+    fn new(names: Vec<OsString>, codec: &dyn NameCodec, mtime: Option<Timespec>) -> Names {
+        let encoded = codec.encode(names.clone());
+        let shown = unique(names, encoded);
+        let original = shown.iter().map(|(o, s)| (s.clone(), o.clone())).collect();
+        Names {
+            shown: shown,
+            original: original,
+            mtime: mtime,
+        }
+    }
+}
+
+struct File {
+    inner: Box<dyn fs::File>,
+    name: OsString,
+}
+
+impl fs::File for File {
+    fn getattr(&self) -> Result<FileAttr> {
+        self.inner.getattr()
+    }
+
+    fn open(&self) -> Result<Box<dyn fs::SeekableRead>> {
+        self.inner.open()
+    }
+
+    fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    fn file_type(&self) -> Result<FileType> {
+        self.inner.file_type()
+    }
+
+    fn hardlink_key(&self) -> Option<(u64, u64)> {
+        self.inner.hardlink_key()
+    }
+
+    fn origin(&self) -> Option<&Path> {
+        self.inner.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.inner.member()
+    }
+
+    fn volatility(&self) -> fs::Volatility {
+        self.inner.volatility()
+    }
+
+    fn nesting(&self) -> usize {
+        self.inner.nesting()
+    }
+
+    fn reopen_cost(&self) -> u64 {
+        self.inner.reopen_cost()
+    }
+
+    fn direct_io(&self) -> bool {
+        self.inner.direct_io()
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.inner.listxattr()
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.inner.getxattr(name)
+    }
+
+    fn readlink(&self) -> Result<PathBuf> {
+        self.inner.readlink()
+    }
+}
+
+// a child directory with the shown name, whose children the viewer encodes in turn.
+struct RenamedDir {
+    inner: Box<dyn fs::Dir>,
+    name: OsString,
+}
+
+impl fs::Dir for RenamedDir {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        self.inner.open()
+    }
+
+    fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
+        self.inner.lookup(name)
+    }
+
+    fn getattr(&self) -> Result<FileAttr> {
+        self.inner.getattr()
+    }
+
+    fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    fn origin(&self) -> Option<&Path> {
+        self.inner.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.inner.member()
+    }
+
+    fn volatility(&self) -> fs::Volatility {
+        self.inner.volatility()
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.inner.listxattr()
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.inner.getxattr(name)
+    }
+}
+
+fn rename(e: fs::Entry, names: &Names) -> fs::Entry {
+    let name = match names.shown.get(e.name()) {
+        Some(name) if name != e.name() => name.clone(),
+        _ => return e,
+    };
+    match e {
+        fs::Entry::File(f) => fs::Entry::File(Box::new(File {
+            inner: f,
+            name: name,
+        })),
+        fs::Entry::Dir(d) => fs::Entry::Dir(Box::new(RenamedDir {
+            inner: d,
+            name: name,
+        })),
+    }
+}
+
+struct Dir {
+    inner: Box<dyn fs::Dir>,
+    codec: Rc<dyn NameCodec>,
+    names: RefCell<Option<Names>>,
+}
+
+impl Dir {
+    // lists all the children, whose names are encoded together.
+    fn list(&self) -> Result<Vec<fs::Entry>> {
+        let mtime = self.mtime();
+        let entries = self.inner.open()?.collect::<Result<Vec<_>>>()?;
+        let names = entries.iter().map(|e| e.name().to_os_string()).collect();
+        *self.names.borrow_mut() = Some(Names::new(names, &*self.codec, mtime));
+        Ok(entries)
+    }
+
+    fn mtime(&self) -> Option<Timespec> {
+        self.inner.getattr().ok().map(|a| a.mtime)
+    }
+
+    // true if children may be changed since the last listing.
+    fn outdated(&self) -> bool {
+        match *self.names.borrow() {
+            Some(ref names) => names.mtime.is_none() || names.mtime != self.mtime(),
+            None => true,
+        }
+    }
+
+    fn original_name(&self, name: &OsStr) -> Option<OsString> {
+        self.names
+            .borrow()
+            .as_ref()
+            .and_then(|n| n.original.get(name).cloned())
+    }
+}
+
+impl fs::Dir for Dir {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        let entries = self.list()?;
+        let names = self.names.borrow();
+        let names = names.as_ref().unwrap();
+        let renamed: Vec<_> = entries.into_iter().map(|e| Ok(rename(e, names))).collect();
+        Ok(Box::new(renamed.into_iter()))
+    }
+
+    // only the shown names are found.
+    fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
+        let original = match self.original_name(name) {
+            Some(original) => original,
+            None if !self.outdated() => return Err(Error::from_raw_os_error(libc::ENOENT)),
+            None => {
+                self.list()?;
+                self.original_name(name)
+                    .ok_or(Error::from_raw_os_error(libc::ENOENT))?
+            }
+        };
+        let e = self.inner.lookup(&original)?;
+        let names = self.names.borrow();
+        Ok(rename(e, names.as_ref().unwrap()))
+    }
+
+    fn getattr(&self) -> Result<FileAttr> {
+        self.inner.getattr()
+    }
+
+    fn name(&self) -> &OsStr {
+        self.inner.name()
+    }
+
+    fn origin(&self) -> Option<&Path> {
+        self.inner.origin()
+    }
+
+    fn member(&self) -> Option<PathBuf> {
+        self.inner.member()
+    }
+
+    fn volatility(&self) -> fs::Volatility {
+        self.inner.volatility()
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.inner.listxattr()
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.inner.getxattr(name)
+    }
+}
+
+/// NameViewer shows the names of entries encoded by the codec, while the original names are
+/// used to access the underlying entries. names which collide once encoded are shown with
+/// suffixes like "a (2).txt", the smallest original name keeping the encoded one.
+/// directories are listed at once to encode the names of their children.
+/// it should be registered after the viewers whose names are encoded, and once, with the
+/// codecs composed, as each lists directories again.
+pub struct NameViewer {
+    codec: Rc<dyn NameCodec>,
+}
+
+impl NameViewer {
+    pub fn new<C: NameCodec + 'static>(codec: C) -> NameViewer {
+        NameViewer {
+            codec: Rc::new(codec),
+        }
+    }
+}
+
+impl fs::Viewer for NameViewer {
+    fn view(&self, e: fs::Entry, _ctx: &fs::ViewContext) -> fs::Entry {
+        match e {
+            fs::Entry::Dir(d) => fs::Entry::Dir(Box::new(Dir {
+                inner: d,
+                codec: self.codec.clone(),
+                names: RefCell::new(None),
+            })),
+            e => e,
+        }
+    }
+}
+
+#[test]
+fn test_codec() {
+    let encode = |spec: &str, names: &[&str]| {
+        let codecs: Vec<Codec> = spec.split(',').map(|s| s.parse().unwrap()).collect();
+        let names = names.iter().map(OsString::from).collect();
+        codecs.encode(names)
+    };
+    let names = ["con", "Con.tar.gz", "COM10", "aux_", "nul.txt"];
+    assert_eq!(
+        encode("reserved", &names),
+        vec!["con_", "Con_.tar.gz", "COM10", "aux_", "nul_.txt"]
+    );
+    let long = "a".repeat(30);
+    let names = [
+        &format!("{}.jpg", long),
+        &format!("{}b.jpg", long),
+        "short.jpg",
+    ];
+    assert_eq!(
+        encode("truncate=20", &names),
+        vec!["aaaaaaaaaaaaaaaa.jpg", "aaaaaaaaaaaa (2).jpg", "short.jpg"]
+    );
+    // characters are not cut, and long extensions are cut with the rest.
+    let name = format!("{}\u{3042}.jpg", "a".repeat(14));
+    assert_eq!(encode("truncate=20", &[&name]), vec!["aaaaaaaaaaaaaa.jpg"]);
+    let name = format!("a.{}", long);
+    assert_eq!(encode("truncate=16", &[&name]), vec![&name[..16]]);
+    // applied in order.
+    let nfd = "\u{304b}\u{3099}";
+    assert_eq!(
+        encode("nfc,casefold", &[nfd, "A", "a"]),
+        vec!["\u{304c}", "A", "a (2)"]
+    );
+
+    assert_eq!("truncate=143".parse(), Ok(Codec::Truncate(143)));
+    assert!("truncate=8".parse::<Codec>().is_err());
+    assert!("upper".parse::<Codec>().is_err());
+    assert_eq!(Codec::Truncate(143).to_string(), "truncate=143");
+}
+
+#[test]
+fn test_lookup_encoded() {
+    use crate::testing::{MemTree, Viewed};
+
+    let tree = MemTree::new();
+    let long = "a".repeat(30);
+    tree.file("/CON", b"con")
+        .file("/CON_", b"con_")
+        .file(format!("/d/{}.txt", long), b"long");
+    let viewer = NameViewer::new(vec![Codec::Reserved, Codec::Truncate(20)]);
+    let viewed = Viewed::new(vec![&viewer]);
+    // the encoded names which collide are suffixed.
+    assert_eq!(
+        viewed.walk(tree.root()),
+        vec![
+            "/",
+            "/CON_ 3",
+            "/CON_ (2) 4",
+            "/d/",
+            "/d/aaaaaaaaaaaaaaaa.txt 4"
+        ]
+    );
+    assert_eq!(viewed.read(tree.root(), "/CON_").unwrap(), b"con");
+    assert_eq!(viewed.read(tree.root(), "/CON_ (2)").unwrap(), b"con_");
+    assert!(viewed.resolve(tree.root(), "/CON").is_err());
+    let read = viewed.read(tree.root(), "/d/aaaaaaaaaaaaaaaa.txt");
+    assert_eq!(read.unwrap(), b"long");
+}
@@ -0,0 +1,41 @@
+use unicode_normalization;
+
+use self::unicode_normalization::UnicodeNormalization;
+use std::ffi::{OsStr, OsString};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Form {
+    NFC,
+    NFD,
+}
+
+pub(crate) fn normalize(form: Form, name: &OsStr) -> OsString {
+    match name.to_str() {
+        Some(s) => match form {
+            Form::NFC => OsString::from(s.nfc().collect::<String>()),
+            Form::NFD => OsString::from(s.nfd().collect::<String>()),
+        },
+        // not unicode, leave it.
+        None => name.to_os_string(),
+    }
+}
+
+#[test]
+fn test_lookup_normalized() {
+    use crate::names::{Codec, NameViewer};
+    use crate::testing::{MemTree, Viewed};
+
+    // "ga" in NFD.
+    let nfd = format!("/{}", "\u{304b}\u{3099}");
+    let nfc = format!("/{}", "\u{304c}");
+    let tree = MemTree::new();
+    tree.file(&nfd, b"ga");
+    let viewer = NameViewer::new(Codec::Normalize(Form::NFC));
+    let viewed = Viewed::new(vec![&viewer]);
+    assert_eq!(
+        viewed.walk(tree.root()),
+        vec!["/".to_string(), nfc.clone() + " 2"]
+    );
+    assert_eq!(viewed.read(tree.root(), &nfc).unwrap(), b"ga");
+    assert!(viewed.resolve(tree.root(), &nfd).is_err());
+}
@@ -1,81 +1,487 @@
 use fuse;
-
+use libc;
+use memmap;
 use time;
 
 use self::fuse::{FileAttr, FileType};
 use self::time::Timespec;
-use std::ffi::OsStr;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr, OsString};
 use std::fs as stdfs;
-use std::io::Result;
-use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
-use std::path::PathBuf;
+use std::io::{Error, Read, Result, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirEntryExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::digest::{Algorithm, Hasher};
 use crate::fs;
 
+// expired entries are swept when the cache grows beyond this.
+const STAT_CACHE_SWEEP_LEN: usize = 4096;
+
+/// StatCache keeps results of stat(2) for a while,
+/// so that slow underlying filesystems are not hit on every getattr and lookup.
+pub struct StatCache {
+    ttl: Duration,
+    entries: RefCell<HashMap<PathBuf, (Instant, stdfs::Metadata)>>,
+}
+
+impl StatCache {
+    pub fn new(ttl: Duration) -> StatCache {
+        StatCache {
+            ttl: ttl,
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn metadata(&self, path: &Path) -> Result<stdfs::Metadata> {
+        if let Some(&(at, ref m)) = self.entries.borrow().get(path) {
+            if at.elapsed() < self.ttl {
+                return Ok(m.clone());
+            }
+        }
+        match stdfs::metadata(path) {
+            Ok(m) => {
+                let mut entries = self.entries.borrow_mut();
+                if entries.len() >= STAT_CACHE_SWEEP_LEN {
+                    let ttl = self.ttl;
+                    entries.retain(|_, &mut (at, _)| at.elapsed() < ttl);
+                }
+                entries.insert(path.to_path_buf(), (Instant::now(), m.clone()));
+                Ok(m)
+            }
+            Err(e) => {
+                self.invalidate(path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops the cached result of the path.
+    /// A change notifier (e.g. inotify) should call this when the path is modified.
+    pub fn invalidate(&self, path: &Path) {
+        self.entries.borrow_mut().remove(path);
+    }
+
+    /// Drops all cached results.
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+    }
+}
+
+// the number of entries fetched from readdir(3) at a time.
+const READDIR_BATCH: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    // readdir(3) order.
+    Native,
+    // byte order of names.
+    Name,
+}
+
+/// Context holds states shared among physical entries.
+pub struct Context {
+    stat_cache: StatCache,
+    sort_order: SortOrder,
+    verify_sidecars: bool,
+}
+
+impl Context {
+    pub fn new(stat_ttl: Duration) -> Context {
+        Context {
+            stat_cache: StatCache::new(stat_ttl),
+            sort_order: SortOrder::Native,
+            verify_sidecars: false,
+        }
+    }
+
+    pub fn set_sort_order(&mut self, order: SortOrder) {
+        self.sort_order = order;
+    }
+
+    // if set, files with sidecars like a.iso.sha256 are verified as they are read through.
+    pub fn set_verify_sidecars(&mut self, verify: bool) {
+        self.verify_sidecars = verify;
+    }
+
+    // stats are made again, like after changes of the disk.
+    pub fn clear_stat_cache(&self) {
+        self.stat_cache.clear();
+    }
+}
+
+/// File is a file on the disk.
 pub struct File {
     path: PathBuf,
+    // known from d_type without stat.
+    kind: Option<FileType>,
+    // (dev, ino) known from the parent and d_ino without stat.
+    id: Option<(u64, u64)>,
+    // shown in place of the file name, like in collections.
+    name: Option<OsString>,
+    ctx: Rc<Context>,
 }
 
 impl File {
-    pub fn new(path: PathBuf) -> File {
-        File { path: path }
+    pub fn new(path: PathBuf, ctx: Rc<Context>) -> File {
+        File {
+            path: path,
+            kind: None,
+            id: None,
+            name: None,
+            ctx: ctx,
+        }
+    }
+
+    pub fn named(path: PathBuf, name: OsString, ctx: Rc<Context>) -> File {
+        File {
+            name: Some(name),
+            ..File::new(path, ctx)
+        }
+    }
+}
+
+/// maps the file on the disk into memory, which is read without copies.
+/// empty files, which can not be mapped, are None.
+/// the file must not be truncated while it is mapped, or reads of the lost pages fail by SIGBUS.
+pub fn map(path: &Path) -> Result<Option<memmap::Mmap>> {
+    let file = stdfs::File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
     }
+    unsafe { memmap::Mmap::map(&file).map(Some) }
 }
 
 impl fs::File for File {
     fn getattr(&self) -> Result<FileAttr> {
-        stdfs::metadata(self.path.clone()).map(|m| to_fuse_file_attr(m))
+        self.ctx
+            .stat_cache
+            .metadata(&self.path)
+            .map(|m| to_fuse_file_attr(m))
     }
     fn open(&self) -> Result<Box<dyn fs::SeekableRead>> {
-        Ok(Box::new(stdfs::File::open(&self.path)?))
+        let file = stdfs::File::open(&self.path)?;
+        if self.ctx.verify_sidecars {
+            if let Some((algorithm, expected)) = read_sidecar(&self.path) {
+                let size = file.metadata()?.len();
+                return Ok(Box::new(Verifying {
+                    inner: file,
+                    path: self.path.clone(),
+                    hasher: Some(algorithm.hasher()),
+                    expected: expected,
+                    size: size,
+                    pos: 0,
+                    hashed: 0,
+                }));
+            }
+        }
+        Ok(Box::new(file))
     }
     fn name(&self) -> &OsStr {
-        self.path.file_name().unwrap()
+        match self.name {
+            Some(ref name) => name,
+            None => self.path.file_name().unwrap(),
+        }
+    }
+    fn file_type(&self) -> Result<FileType> {
+        match self.kind {
+            Some(kind) => Ok(kind),
+            None => self.getattr().map(|a| a.kind),
+        }
+    }
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        list_xattrs(&self.path)
+    }
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        get_xattr(&self.path, name)
+    }
+    fn readlink(&self) -> Result<PathBuf> {
+        stdfs::read_link(&self.path)
+    }
+    fn physical_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+    fn hardlink_key(&self) -> Option<(u64, u64)> {
+        self.id.or_else(|| {
+            self.ctx
+                .stat_cache
+                .metadata(&self.path)
+                .ok()
+                .map(|m| (m.dev(), m.ino()))
+        })
+    }
+}
+
+// the hash in the first sidecar of the file, which is ignored if it is not readable.
+fn read_sidecar(path: &Path) -> Option<(Algorithm, Vec<u8>)> {
+    Algorithm::ALL.iter().find_map(|&algorithm| {
+        let sidecar = algorithm.sidecar(path);
+        let s = stdfs::read_to_string(&sidecar).ok()?;
+        match algorithm.parse(&s) {
+            Some(hash) => Some((algorithm, hash)),
+            None => {
+                warn!(path = %sidecar.display(), "invalid sidecar");
+                None
+            }
+        }
+    })
+}
+
+// Verifying hashes the file while it is read from the start to the end in order,
+// and fails the read of the end if the hash differs from the sidecar.
+// reads out of order, like seeks by archive readers, stop the verification.
+struct Verifying {
+    inner: stdfs::File,
+    path: PathBuf,
+    // None after the verification ends.
+    hasher: Option<Hasher>,
+    expected: Vec<u8>,
+    size: u64,
+    pos: u64,
+    // the bytes hashed from the start.
+    hashed: u64,
+}
+
+impl Read for Verifying {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        if self.pos == self.hashed {
+            if let Some(ref mut hasher) = self.hasher {
+                hasher.update(&buf[..n]);
+                self.hashed += n as u64;
+            }
+        }
+        self.pos += n as u64;
+        if self.hashed < self.size && n > 0 {
+            return Ok(n);
+        }
+        if let Some(hasher) = self.hasher.take() {
+            if self.pos == self.hashed && hasher.finish() != self.expected {
+                error!(path = %self.path.display(), "the content differs from the sidecar");
+                return Err(Error::from_raw_os_error(libc::EIO));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for Verifying {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
     }
 }
 
+// reads at offsets seek, so that the file is hashed while they are in order.
+impl fs::SeekableRead for Verifying {}
+
+/// Dir is a directory on the disk.
 pub struct Dir {
     path: PathBuf,
+    name: Option<OsString>,
+    ctx: Rc<Context>,
 }
 
 impl Dir {
-    pub fn new(path: PathBuf) -> Self {
-        Dir { path: path }
+    pub fn new(path: PathBuf, ctx: Rc<Context>) -> Self {
+        Dir {
+            path: path,
+            name: None,
+            ctx: ctx,
+        }
+    }
+
+    pub fn named(path: PathBuf, name: OsString, ctx: Rc<Context>) -> Self {
+        Dir {
+            name: Some(name),
+            ..Dir::new(path, ctx)
+        }
     }
 }
 
 impl fs::Dir for Dir {
     fn open(&self) -> Result<Box<dyn Iterator<Item = Result<fs::Entry>>>> {
+        let ctx = self.ctx.clone();
+        let dev = self.ctx.stat_cache.metadata(&self.path)?.dev();
         stdfs::read_dir(&self.path).map(|rd| -> Box<dyn Iterator<Item = Result<fs::Entry>>> {
-            Box::new(DirHandler { iter: rd })
+            Box::new(DirHandler {
+                iter: rd,
+                dev: dev,
+                pending: Vec::new(),
+                ctx: ctx,
+            })
         })
     }
     fn lookup(&self, name: &OsStr) -> Result<fs::Entry> {
         let path = self.path.join(name);
-        let m = stdfs::metadata(path.clone())?;
+        let m = self.ctx.stat_cache.metadata(&path)?;
         if m.is_dir() {
-            Ok(fs::Entry::Dir(Box::new(Dir::new(path))))
+            Ok(fs::Entry::Dir(Box::new(Dir::new(path, self.ctx.clone()))))
         } else {
-            Ok(fs::Entry::File(Box::new(File::new(path))))
+            Ok(fs::Entry::File(Box::new(File::new(path, self.ctx.clone()))))
         }
     }
     fn getattr(&self) -> Result<FileAttr> {
-        stdfs::metadata(self.path.clone()).map(|m| to_fuse_file_attr(m))
+        self.ctx
+            .stat_cache
+            .metadata(&self.path)
+            .map(|m| to_fuse_file_attr(m))
     }
     fn name(&self) -> &OsStr {
-        self.path.file_name().unwrap()
+        match self.name {
+            Some(ref name) => name,
+            None => self.path.file_name().unwrap(),
+        }
+    }
+    fn origin(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        list_xattrs(&self.path)
+    }
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        get_xattr(&self.path, name)
     }
 }
 
+// user attributes and ACLs are passed through, others are internal to the source.
+fn is_passthrough_xattr(name: &OsStr) -> bool {
+    name.as_bytes().starts_with(b"user.")
+        || name == "system.posix_acl_access"
+        || name == "system.posix_acl_default"
+}
+
+fn is_not_supported(e: &Error) -> bool {
+    e.raw_os_error() == Some(libc::ENOTSUP)
+}
+
+// calls listxattr(2) or getxattr(2) like function f until the buffer is large enough.
+fn read_xattr<F>(f: F) -> Result<Vec<u8>>
+where
+    F: Fn(*mut u8, usize) -> libc::ssize_t,
+{
+    loop {
+        let size = f(ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut buf = vec![0; size as usize];
+        let n = f(buf.as_mut_ptr(), buf.len());
+        if n < 0 {
+            let e = Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ERANGE) {
+                // grown in the meantime.
+                continue;
+            }
+            return Err(e);
+        }
+        buf.truncate(n as usize);
+        return Ok(buf);
+    }
+}
+
+fn list_xattrs(path: &Path) -> Result<Vec<OsString>> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let names = match read_xattr(|buf, size| unsafe {
+        libc::listxattr(path.as_ptr(), buf as *mut libc::c_char, size)
+    }) {
+        Ok(names) => names,
+        Err(ref e) if is_not_supported(e) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(names
+        .split(|&b| b == 0)
+        .map(OsStr::from_bytes)
+        .filter(|name| !name.is_empty() && is_passthrough_xattr(name))
+        .map(|name| name.to_os_string())
+        .collect())
+}
+
+fn get_xattr(path: &Path, name: &OsStr) -> Result<Vec<u8>> {
+    if !is_passthrough_xattr(name) {
+        return Err(Error::from_raw_os_error(libc::ENODATA));
+    }
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name.as_bytes())?;
+    match read_xattr(|buf, size| unsafe {
+        libc::getxattr(path.as_ptr(), name.as_ptr(), buf as *mut libc::c_void, size)
+    }) {
+        Err(ref e) if is_not_supported(e) => Err(Error::from_raw_os_error(libc::ENODATA)),
+        r => r,
+    }
+}
+
+// path, d_type and d_ino.
+type PendingEntry = Result<(PathBuf, Option<stdfs::FileType>, u64)>;
+
 struct DirHandler {
     iter: stdfs::ReadDir,
+    dev: u64,
+    // fetched entries in reverse order.
+    pending: Vec<PendingEntry>,
+    ctx: Rc<Context>,
 }
 
-fn to_fuse_entry<'a>(e: stdfs::DirEntry) -> fs::Entry {
-    if e.file_type().unwrap().is_dir() {
-        fs::Entry::Dir(Box::new(Dir::new(e.path())))
+impl DirHandler {
+    fn fill(&mut self) {
+        let limit = match self.ctx.sort_order {
+            // sorting needs all entries.
+            SortOrder::Name => usize::max_value(),
+            SortOrder::Native => READDIR_BATCH,
+        };
+        // file_type() uses d_type, so no stat is issued here.
+        self.pending.extend(
+            self.iter
+                .by_ref()
+                .take(limit)
+                .map(|r| r.map(|e| (e.path(), e.file_type().ok(), e.ino()))),
+        );
+        match self.ctx.sort_order {
+            SortOrder::Name => self.pending.sort_by(|a, b| match (a, b) {
+                (&Ok((ref a, _, _)), &Ok((ref b, _, _))) => b.cmp(a),
+                // errors go last, so that they are popped first.
+                (&Err(_), &Ok(_)) => Ordering::Greater,
+                (&Ok(_), &Err(_)) => Ordering::Less,
+                (&Err(_), &Err(_)) => Ordering::Equal,
+            }),
+            SortOrder::Native => self.pending.reverse(),
+        }
+    }
+}
+
+fn to_fuse_entry(
+    path: PathBuf,
+    t: Option<stdfs::FileType>,
+    id: (u64, u64),
+    ctx: Rc<Context>,
+) -> fs::Entry {
+    let is_dir = match t {
+        // symlinks are followed like lookup does.
+        Some(t) if !t.is_symlink() => t.is_dir(),
+        _ => ctx
+            .stat_cache
+            .metadata(&path)
+            .map(|m| m.is_dir())
+            .unwrap_or(false),
+    };
+    if is_dir {
+        fs::Entry::Dir(Box::new(Dir::new(path, ctx)))
     } else {
-        fs::Entry::File(Box::new(File::new(e.path())))
+        let t = t.filter(|t| !t.is_symlink());
+        fs::Entry::File(Box::new(File {
+            path: path,
+            kind: t.map(to_fuse_file_type),
+            // d_ino of a symlink is not of the target.
+            id: t.map(|_| id),
+            name: None,
+            ctx: ctx,
+        }))
     }
 }
 
@@ -83,7 +489,14 @@ impl Iterator for DirHandler {
     type Item = Result<fs::Entry>;
 
     fn next(&mut self) -> Option<Result<fs::Entry>> {
-        self.iter.next().map(|r| r.map(|e| to_fuse_entry(e)))
+        if self.pending.is_empty() {
+            self.fill();
+        }
+        let ctx = &self.ctx;
+        let dev = self.dev;
+        self.pending
+            .pop()
+            .map(|r| r.map(|(path, t, ino)| to_fuse_entry(path, t, (dev, ino), ctx.clone())))
     }
 }
 
@@ -106,7 +519,36 @@ fn to_fuse_file_type(t: stdfs::FileType) -> FileType {
     }
 }
 
+fn to_timespec(t: SystemTime) -> Timespec {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => Timespec {
+            sec: d.as_secs() as i64,
+            nsec: d.subsec_nanos() as i32,
+        },
+        Err(e) => {
+            // before the epoch.
+            let d = e.duration();
+            if d.subsec_nanos() == 0 {
+                Timespec {
+                    sec: -(d.as_secs() as i64),
+                    nsec: 0,
+                }
+            } else {
+                Timespec {
+                    sec: -(d.as_secs() as i64) - 1,
+                    nsec: (1_000_000_000 - d.subsec_nanos()) as i32,
+                }
+            }
+        }
+    }
+}
+
 fn to_fuse_file_attr(m: stdfs::Metadata) -> FileAttr {
+    // created() is backed by statx(2), and fails if the filesystem does not know birth time.
+    let crtime = m
+        .created()
+        .map(to_timespec)
+        .unwrap_or(Timespec { sec: 0, nsec: 0 });
     FileAttr {
         ino: 0, // dummy
         size: m.size(),
@@ -123,7 +565,7 @@ fn to_fuse_file_attr(m: stdfs::Metadata) -> FileAttr {
             sec: m.ctime(),
             nsec: m.ctime_nsec() as i32,
         },
-        crtime: Timespec { sec: 0, nsec: 0 }, // mac only
+        crtime: crtime, // reported on mac only
         kind: to_fuse_file_type(m.file_type()),
         perm: m.permissions().mode() as u16,
         nlink: m.nlink() as u32,
@@ -133,3 +575,110 @@ fn to_fuse_file_attr(m: stdfs::Metadata) -> FileAttr {
         flags: 0, // mac only
     }
 }
+
+#[test]
+fn test_sorted_readdir() {
+    use crate::fs::Dir as FSDir;
+    use tempfile;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let names = vec!["c", "a", "d", "b"];
+    for name in names.iter() {
+        stdfs::File::create(tmp.path().join(name)).unwrap();
+    }
+    stdfs::create_dir(tmp.path().join("e")).unwrap();
+    let mut ctx = Context::new(Duration::from_secs(1));
+    ctx.set_sort_order(SortOrder::Name);
+    let dir = Dir::new(tmp.path().to_path_buf(), Rc::new(ctx));
+    let entries: Vec<_> = dir.open().unwrap().map(|re| re.unwrap()).collect();
+    let actual: Vec<_> = entries.iter().map(|e| e.name().to_os_string()).collect();
+    assert_eq!(actual, vec!["a", "b", "c", "d", "e"]);
+    assert_eq!(entries[0].file_type().unwrap(), FileType::RegularFile);
+    assert_eq!(entries[4].file_type().unwrap(), FileType::Directory);
+}
+
+#[test]
+fn test_hardlink_key() {
+    use crate::fs::Dir as FSDir;
+    use tempfile;
+
+    let tmp = tempfile::tempdir().unwrap();
+    stdfs::File::create(tmp.path().join("a")).unwrap();
+    stdfs::hard_link(tmp.path().join("a"), tmp.path().join("b")).unwrap();
+    stdfs::File::create(tmp.path().join("c")).unwrap();
+    let mut ctx = Context::new(Duration::from_secs(1));
+    ctx.set_sort_order(SortOrder::Name);
+    let dir = Dir::new(tmp.path().to_path_buf(), Rc::new(ctx));
+    let keys: Vec<_> = dir
+        .open()
+        .unwrap()
+        .map(|re| re.unwrap().hardlink_key().unwrap())
+        .collect();
+    assert_eq!(keys[0], keys[1]);
+    assert_ne!(keys[0], keys[2]);
+    let looked_up = dir.lookup(OsStr::new("b")).unwrap();
+    assert_eq!(looked_up.hardlink_key().unwrap(), keys[0]);
+}
+
+#[test]
+fn test_xattr_passthrough() {
+    use crate::fs::File as FSFile;
+    use tempfile;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a");
+    stdfs::File::create(&path).unwrap();
+    let cpath = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let value = b"value";
+    let ret = unsafe {
+        libc::setxattr(
+            cpath.as_ptr(),
+            CString::new("user.test").unwrap().as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if ret != 0 && is_not_supported(&Error::last_os_error()) {
+        // the filesystem of tmpdir does not support user attributes.
+        return;
+    }
+    assert_eq!(ret, 0);
+    let file = File::new(path, Rc::new(Context::new(Duration::from_secs(1))));
+    assert_eq!(file.listxattr().unwrap(), vec![OsString::from("user.test")]);
+    assert_eq!(file.getxattr(OsStr::new("user.test")).unwrap(), value);
+    assert!(file.getxattr(OsStr::new("security.selinux")).is_err());
+}
+
+#[test]
+fn test_verify_sidecars() {
+    use crate::fs::File as FSFile;
+    use std::io::Read;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.txt");
+    stdfs::write(&path, b"The quick brown fox jumps over the lazy dog").unwrap();
+    stdfs::write(
+        tmp.path().join("a.txt.md5"),
+        "9e107d9d372bb6826bd81d3542a419d6  a.txt\n",
+    )
+    .unwrap();
+    let mut ctx = Context::new(Duration::from_secs(1));
+    ctx.set_verify_sidecars(true);
+    let file = File::new(path.clone(), Rc::new(ctx));
+    let mut s = String::new();
+    file.open().unwrap().read_to_string(&mut s).unwrap();
+
+    stdfs::write(&path, b"The quick brown fox jumps over the lazy cat").unwrap();
+    let mut r = file.open().unwrap();
+    let mut buf = [0; 40];
+    r.read_exact(&mut buf).unwrap();
+    assert_eq!(
+        r.read(&mut buf).unwrap_err().raw_os_error(),
+        Some(libc::EIO)
+    );
+    // out of order reads are not verified.
+    let mut r = file.open().unwrap();
+    r.seek(SeekFrom::Start(4)).unwrap();
+    r.read_to_string(&mut String::new()).unwrap();
+}
@@ -0,0 +1,250 @@
+use libc;
+
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::Error;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Problem is what keeps a mount from working, found before mounting, which tells what to do
+/// about it.
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// /dev/fuse can not be opened, with the errno.
+    DevFuse(i32),
+    /// fusermount, which mounts for users but root, is not found in PATH.
+    NoFusermount,
+    /// other users are let in, but /etc/fuse.conf does not allow users but root to.
+    NoUserAllowOther,
+    /// the mountpoint is a fuse mount already, like of another showfs.
+    Mounted(PathBuf),
+    /// the mountpoint is a fuse mount whose process is gone.
+    Stale(PathBuf),
+}
+
+impl Problem {
+    /// the exit status of the mount which failed by the problem. other failures to mount exit
+    /// with 1.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Problem::DevFuse(_) => 4,
+            Problem::NoFusermount => 5,
+            Problem::NoUserAllowOther => 6,
+            Problem::Mounted(_) | Problem::Stale(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Problem::DevFuse(libc::ENOENT) => write!(
+                f,
+                "/dev/fuse is missing. load the module by `modprobe fuse`, or pass the device \
+                 to the container, like `docker run --device /dev/fuse`"
+            ),
+            Problem::DevFuse(errno) if errno == libc::EACCES || errno == libc::EPERM => write!(
+                f,
+                "/dev/fuse can not be read and written by the user. add the user to the group \
+                 which owns it, or fix its permissions"
+            ),
+            Problem::DevFuse(errno) => write!(
+                f,
+                "failed to open /dev/fuse: {}",
+                Error::from_raw_os_error(errno)
+            ),
+            Problem::NoFusermount => write!(
+                f,
+                "fusermount is not found in PATH, which mounts for users but root. install \
+                 fuse, which has it, or mount as root"
+            ),
+            Problem::NoUserAllowOther => write!(
+                f,
+                "--allow-other needs user_allow_other in /etc/fuse.conf for users but root. \
+                 add the line to it, or mount without --allow-other"
+            ),
+            Problem::Mounted(ref path) => write!(
+                f,
+                "{} is mounted by fuse already. unmount it first, like by `fusermount -u {}`",
+                path.display(),
+                path.display()
+            ),
+            Problem::Stale(ref path) => write!(
+                f,
+                "{} is a mount whose process is gone. unmount it first by `fusermount -u {}`",
+                path.display(),
+                path.display()
+            ),
+        }
+    }
+}
+
+// decodes the octal escapes of spaces, tabs, newlines and backslashes in /proc/self/mountinfo.
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut path = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes.get(i + 1..i + 4).and_then(|octal| {
+            let octal = std::str::from_utf8(octal).ok()?;
+            u8::from_str_radix(octal, 8).ok()
+        });
+        match code {
+            Some(b) if bytes[i] == b'\\' => {
+                path.push(b);
+                i += 4;
+            }
+            _ => {
+                path.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(path))
+}
+
+// whether the path is a fuse mount in the mountinfo, whose lines are like
+// "36 35 0:42 / /mnt/a rw,nosuid - fuse showfs rw,user_id=1000".
+fn fuse_mounted(mountinfo: &str, path: &Path) -> bool {
+    mountinfo.lines().any(|line| {
+        let mut halves = line.splitn(2, " - ");
+        let mount = halves.next().unwrap_or("");
+        let fstype = halves.next().and_then(|s| s.split(' ').next());
+        match (mount.split(' ').nth(4), fstype) {
+            (Some(point), Some(fstype)) => fstype.starts_with("fuse") && unescape(point) == path,
+            _ => false,
+        }
+    })
+}
+
+/// Environment is where the check looks for what mounts need.
+pub struct Environment {
+    dev_fuse: PathBuf,
+    fuse_conf: PathBuf,
+    mountinfo: PathBuf,
+    // PATH, searched for fusermount.
+    path: Option<OsString>,
+    root: bool,
+}
+
+// the environment of the process.
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment {
+            dev_fuse: PathBuf::from("/dev/fuse"),
+            fuse_conf: PathBuf::from("/etc/fuse.conf"),
+            mountinfo: PathBuf::from("/proc/self/mountinfo"),
+            path: env::var_os("PATH"),
+            root: unsafe { libc::geteuid() } == 0,
+        }
+    }
+}
+
+impl Environment {
+    /// finds the first problem which would fail mounting on the mountpoint, so that it is told
+    /// with what to do, instead of by the error of the mount.
+    pub fn check(&self, mountpoint: &Path, allow_other: bool) -> Result<(), Problem> {
+        let opened = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.dev_fuse);
+        if let Err(e) = opened {
+            return Err(Problem::DevFuse(e.raw_os_error().unwrap_or(libc::EIO)));
+        }
+        // root mounts by itself.
+        if !self.root && !self.has_fusermount() {
+            return Err(Problem::NoFusermount);
+        }
+        if allow_other && !self.root && !self.allows_other() {
+            return Err(Problem::NoUserAllowOther);
+        }
+        // missing mountpoints are told by the mount.
+        match fs::metadata(mountpoint) {
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOTCONN) => {
+                return Err(Problem::Stale(mountpoint.to_path_buf()));
+            }
+            _ => (),
+        }
+        let path = match fs::canonicalize(mountpoint) {
+            Ok(path) => path,
+            Err(_) => return Ok(()),
+        };
+        match fs::read_to_string(&self.mountinfo) {
+            Ok(ref mountinfo) if fuse_mounted(mountinfo, &path) => Err(Problem::Mounted(path)),
+            _ => Ok(()),
+        }
+    }
+
+    fn has_fusermount(&self) -> bool {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return false,
+        };
+        env::split_paths(path).any(|dir| {
+            fs::metadata(dir.join("fusermount")).map_or(false, |m| {
+                m.is_file() && m.permissions().mode() & 0o111 != 0
+            })
+        })
+    }
+
+    fn allows_other(&self) -> bool {
+        let conf = match fs::read_to_string(&self.fuse_conf) {
+            Ok(conf) => conf,
+            Err(_) => return false,
+        };
+        conf.lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim())
+            .any(|line| line == "user_allow_other")
+    }
+}
+
+#[test]
+fn test_check() {
+    let tmp = tempfile::tempdir().unwrap();
+    let bin = tmp.path().join("bin");
+    let mountpoint = tmp.path().join("mnt point");
+    fs::create_dir(&bin).unwrap();
+    fs::create_dir(&mountpoint).unwrap();
+    let mut env = Environment {
+        dev_fuse: tmp.path().join("fuse"),
+        fuse_conf: tmp.path().join("fuse.conf"),
+        mountinfo: tmp.path().join("mountinfo"),
+        path: Some(bin.clone().into_os_string()),
+        root: false,
+    };
+    let check = |env: &Environment, allow_other| env.check(&mountpoint, allow_other);
+    assert_eq!(check(&env, false), Err(Problem::DevFuse(libc::ENOENT)));
+    fs::write(&env.dev_fuse, "").unwrap();
+    assert_eq!(check(&env, false), Err(Problem::NoFusermount));
+    fs::write(bin.join("fusermount"), "").unwrap();
+    assert_eq!(check(&env, false), Err(Problem::NoFusermount));
+    let permissions = fs::Permissions::from_mode(0o755);
+    fs::set_permissions(bin.join("fusermount"), permissions).unwrap();
+    assert_eq!(check(&env, false), Ok(()));
+
+    assert_eq!(check(&env, true), Err(Problem::NoUserAllowOther));
+    fs::write(&env.fuse_conf, "# user_allow_other\nmount_max = 1000\n").unwrap();
+    assert_eq!(check(&env, true), Err(Problem::NoUserAllowOther));
+    fs::write(&env.fuse_conf, "user_allow_other  # for samba\n").unwrap();
+    assert_eq!(check(&env, true), Ok(()));
+    env.root = true;
+    env.path = None;
+    fs::remove_file(&env.fuse_conf).unwrap();
+    assert_eq!(check(&env, true), Ok(()));
+
+    // the mountpoint is escaped in the mountinfo, and only fuse mounts are mounts over it.
+    let point = fs::canonicalize(&mountpoint).unwrap();
+    let escaped = point.to_str().unwrap().replace(' ', "\\040");
+    let line = format!("36 35 0:42 / {} rw - tmpfs tmpfs rw\n", escaped);
+    fs::write(&env.mountinfo, &line).unwrap();
+    assert_eq!(check(&env, false), Ok(()));
+    let line = format!("36 35 0:42 / {} rw shared:1 - fuse showfs rw\n", escaped);
+    fs::write(&env.mountinfo, &line).unwrap();
+    let problem = check(&env, false).unwrap_err();
+    assert_eq!(problem, Problem::Mounted(point));
+    assert_eq!(problem.exit_code(), 7);
+    assert!(problem.to_string().contains("fusermount -u"));
+}
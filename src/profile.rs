@@ -0,0 +1,115 @@
+use std::str::FromStr;
+
+/// Profile is a bundle of options for a kind of archives, which users give by one flag
+/// instead of learning each of them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Profile {
+    // comic books, read page by page in the order of their names.
+    Comic,
+    // videos and music, read sequentially and large.
+    Media,
+    // backups, whose contents should be read as they are or not at all.
+    Backup,
+}
+
+impl Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Comic => "comic",
+            Profile::Media => "media",
+            Profile::Backup => "backup",
+        }
+    }
+
+    /// the options which the profile stands for, as given on the command line.
+    pub fn args(self) -> &'static [&'static str] {
+        match self {
+            Profile::Comic => &[
+                "--sort",
+                "name",
+                "--extension",
+                "cbz=archive",
+                "--extension",
+                "cbr=archive",
+                "--extension",
+                "cb7=archive",
+                "--extension",
+                "cbt=archive",
+                "--index-threads",
+                "2",
+                "--warm-readers",
+                "2",
+            ],
+            Profile::Media => &[
+                "--noatime",
+                "--block-size",
+                "1048576",
+                "--auto-cache",
+                "--warm-readers",
+                "4",
+            ],
+            Profile::Backup => &[
+                "--verify-sidecars",
+                "--encrypted",
+                "deny",
+                "--retry",
+                "3",
+                "--noatime",
+            ],
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Profile, String> {
+        match s {
+            "comic" => Ok(Profile::Comic),
+            "media" => Ok(Profile::Media),
+            "backup" => Ok(Profile::Backup),
+            _ => Err(format!("unknown profile: {}", s)),
+        }
+    }
+}
+
+/// replaces `--profile NAME` in the arguments by the options of the profile, put before the
+/// others so that options given explicitly take precedence. the profiles are returned too.
+pub fn expand(args: Vec<String>) -> Result<(Vec<String>, Vec<Profile>), String> {
+    let mut profiles: Vec<Profile> = Vec::new();
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg != "--profile" {
+            rest.push(arg);
+            continue;
+        }
+        let name = args.next().ok_or("--profile needs a name")?;
+        profiles.push(name.parse()?);
+    }
+    let mut expanded: Vec<String> = profiles
+        .iter()
+        .flat_map(|p| p.args().iter().map(|a| a.to_string()))
+        .collect();
+    expanded.extend(rest);
+    Ok((expanded, profiles))
+}
+
+#[test]
+fn test_expand() {
+    let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+    let (expanded, profiles) = expand(args("--sort native --profile comic a b")).unwrap();
+    assert_eq!(profiles, vec![Profile::Comic]);
+    assert_eq!(expanded[..2], args("--sort name")[..]);
+    // the explicit option comes later and wins.
+    assert_eq!(
+        expanded[expanded.len() - 4..],
+        args("--sort native a b")[..]
+    );
+    assert_eq!(expand(args("a b")).unwrap(), (args("a b"), Vec::new()));
+    assert!(expand(args("--profile")).is_err());
+    assert!(expand(args("--profile games a b")).is_err());
+    for p in [Profile::Comic, Profile::Media, Profile::Backup].iter() {
+        assert_eq!(p.name().parse::<Profile>(), Ok(*p));
+    }
+}
@@ -0,0 +1,468 @@
+use libc;
+use time;
+
+use self::time::Timespec;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::digest::Algorithm;
+use crate::fs::SeekableRead;
+
+/// members larger than this are decoded by each mount, not to hold them in memory twice.
+pub const MAX_MEMBER_BYTES: u64 = 64 * 1024 * 1024;
+
+// a mount waits for the server at most this long, and decodes by itself after it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+// connections served at once, over which connections are closed and the mounts decode
+// members by themselves.
+const MAX_CONNECTIONS: usize = 64;
+
+fn hex(digest: Vec<u8>) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// the key of the member of the archive file on the disk, which is the same among mounts
+/// as long as the archive is not modified.
+pub fn member_key(archive: &Path, mtime: Timespec, size: u64, member: &Path) -> String {
+    let mut hasher = Algorithm::Sha256.hasher();
+    hasher.update(archive.as_os_str().as_bytes());
+    hasher.update(&[0]);
+    hasher.update(format!("{}.{}:{}", mtime.sec, mtime.nsec, size).as_bytes());
+    hasher.update(&[0]);
+    hasher.update(member.as_os_str().as_bytes());
+    hex(hasher.finish())
+}
+
+// the checksum of contents, which is sent with them and checked by the receiver.
+fn checksum(data: &[u8]) -> String {
+    let mut hasher = Algorithm::Sha256.hasher();
+    hasher.update(data);
+    hex(hasher.finish())
+}
+
+// the uid of the process at the other end of the socket.
+fn peer_uid(stream: &UnixStream) -> Result<libc::uid_t> {
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let r = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if r != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+// keys are known to anyone who can see the archives, so the server and mounts only talk to
+// processes of the same user or root, which could put other contents by the keys.
+fn check_peer(stream: &UnixStream) -> Result<()> {
+    let uid = peer_uid(stream)?;
+    if uid != unsafe { libc::geteuid() } && uid != 0 {
+        let message = format!("the peer of uid {} is not of the user", uid);
+        return Err(Error::new(ErrorKind::PermissionDenied, message));
+    }
+    Ok(())
+}
+
+// the length of the data of a request or a response, which is at most MAX_MEMBER_BYTES.
+fn parse_len(len: &str) -> Result<usize> {
+    match len.parse() {
+        Ok(len) if len as u64 <= MAX_MEMBER_BYTES => Ok(len),
+        _ => Err(Error::new(ErrorKind::InvalidData, "invalid length")),
+    }
+}
+
+// reads the data of the length, which must have the checksum.
+fn read_data<R: Read>(reader: &mut R, len: usize, sum: &str) -> Result<Vec<u8>> {
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    if checksum(&data) != sum {
+        return Err(Error::new(ErrorKind::InvalidData, "checksum mismatch"));
+    }
+    Ok(data)
+}
+
+struct Stored {
+    data: Arc<Vec<u8>>,
+    checksum: Arc<String>,
+    // the tick when it is used last.
+    used: u64,
+}
+
+// contents by keys, which are dropped from the least recently used over the budget.
+struct Store {
+    max_bytes: usize,
+    used_bytes: usize,
+    tick: u64,
+    entries: HashMap<String, Stored>,
+}
+
+impl Store {
+    fn new(max_bytes: usize) -> Store {
+        Store {
+            max_bytes: max_bytes,
+            used_bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    // the data and its checksum.
+    fn get(&mut self, key: &str) -> Option<(Arc<Vec<u8>>, Arc<String>)> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).map(|entry| {
+            entry.used = tick;
+            (entry.data.clone(), entry.checksum.clone())
+        })
+    }
+
+    // false if the data does not fit in the budget at all.
+    fn put(&mut self, key: String, data: Vec<u8>, checksum: String) -> bool {
+        if data.len() > self.max_bytes {
+            return false;
+        }
+        self.tick += 1;
+        self.used_bytes += data.len();
+        let stored = Stored {
+            data: Arc::new(data),
+            checksum: Arc::new(checksum),
+            used: self.tick,
+        };
+        if let Some(old) = self.entries.insert(key, stored) {
+            self.used_bytes -= old.data.len();
+        }
+        while self.used_bytes > self.max_bytes {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            let old = self.entries.remove(&oldest).unwrap();
+            self.used_bytes -= old.data.len();
+        }
+        true
+    }
+}
+
+// answers requests of a mount, a line of "GET KEY" or "PUT KEY LEN CHECKSUM" followed by the
+// data, by "HIT LEN CHECKSUM" followed by the data, "MISS", "OK" or "FULL".
+// data is at most MAX_MEMBER_BYTES, and data of a wrong checksum is not kept.
+fn serve_connection(store: &Mutex<Store>, stream: UnixStream) -> Result<()> {
+    check_peer(&stream)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["GET", key] => match store.lock().unwrap().get(key) {
+                Some((data, sum)) => {
+                    writeln!(writer, "HIT {} {}", data.len(), sum)?;
+                    writer.write_all(&data)?;
+                }
+                None => writeln!(writer, "MISS")?,
+            },
+            ["PUT", key, len, sum] => {
+                let data = read_data(&mut reader, parse_len(len)?, sum)?;
+                let stored = store
+                    .lock()
+                    .unwrap()
+                    .put(key.to_string(), data, sum.to_string());
+                writeln!(writer, "{}", if stored { "OK" } else { "FULL" })?;
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unknown request")),
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+fn not_private(path: &Path, what: &str) -> Error {
+    let message = format!("{} is {}", path.display(), what);
+    Error::new(ErrorKind::PermissionDenied, message)
+}
+
+// binds the socket of mode 0600 in a directory which only the user can access, made if it is
+// missing, so that other users can neither connect to the socket nor replace it.
+fn bind(path: &Path) -> Result<UnixListener> {
+    let uid = unsafe { libc::geteuid() };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => (),
+        r => r?,
+    }
+    let m = fs::metadata(dir)?;
+    if m.uid() != uid || m.mode() & 0o077 != 0 {
+        return Err(not_private(dir, "not a directory only the user can access"));
+    }
+    match fs::symlink_metadata(path) {
+        // a socket left by a crashed server refuses connections.
+        Ok(ref m) if m.file_type().is_socket() && m.uid() == uid => {
+            if UnixStream::connect(path).is_err() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(_) => return Err(not_private(path, "not a socket of the user")),
+        Err(_) => (),
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// decrements the number of connections served when the connection is done.
+struct Served(Arc<AtomicUsize>);
+
+impl Drop for Served {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn run(listener: UnixListener, max_bytes: usize) {
+    let store = Arc::new(Mutex::new(Store::new(max_bytes)));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let store = store.clone();
+        let result = stream.and_then(|s| {
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                debug!("cache server: too many connections");
+                return Ok(());
+            }
+            let served = Served(connections.clone());
+            thread::Builder::new()
+                .name("cache".to_string())
+                .spawn(move || {
+                    let _served = served;
+                    if let Err(e) = serve_connection(&store, s) {
+                        warn!("cache server: {}", e);
+                    }
+                })
+                .map(|_| ())
+        });
+        if let Err(e) = result {
+            warn!("cache server: {}", e);
+        }
+    }
+}
+
+/// serves contents of members to mounts at the socket, keeping at most max_bytes of them.
+pub fn serve(path: &Path, max_bytes: usize) -> Result<()> {
+    let listener = bind(path)?;
+    info!(path = %path.display(), max_bytes = max_bytes, "cache server is ready");
+    run(listener, max_bytes);
+    Ok(())
+}
+
+/// Client asks the cache server for members before they are decoded.
+/// each request connects to the server, so that mounts survive restarts of it.
+pub struct Client {
+    path: PathBuf,
+}
+
+impl Client {
+    pub fn new<P: AsRef<Path>>(path: P) -> Client {
+        Client {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn connect(&self) -> Result<UnixStream> {
+        let stream = UnixStream::connect(&self.path)?;
+        check_peer(&stream)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        Ok(stream)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut stream = self.connect()?;
+        writeln!(stream, "GET {}", key)?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["HIT", len, sum] => Ok(Some(read_data(&mut reader, parse_len(len)?, sum)?)),
+            ["MISS"] => Ok(None),
+            _ => Err(Error::new(ErrorKind::InvalidData, "invalid response")),
+        }
+    }
+
+    // false if the server did not keep it.
+    pub fn put(&self, key: &str, data: &[u8]) -> Result<bool> {
+        let mut stream = self.connect()?;
+        writeln!(stream, "PUT {} {} {}", key, data.len(), checksum(data))?;
+        stream.write_all(data)?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        match line.trim_end() {
+            "OK" => Ok(true),
+            "FULL" => Ok(false),
+            _ => Err(Error::new(ErrorKind::InvalidData, "invalid response")),
+        }
+    }
+}
+
+/// Publisher keeps the contents as they are read from the start, and puts them to the server
+/// when they are read to the end. seeks elsewhere give up.
+pub struct Publisher<R> {
+    inner: R,
+    client: Arc<Client>,
+    key: String,
+    size: u64,
+    // None once given up.
+    data: Option<Vec<u8>>,
+}
+
+impl<R> Publisher<R> {
+    pub fn new(inner: R, client: Arc<Client>, key: String, size: u64) -> Publisher<R> {
+        Publisher {
+            inner: inner,
+            client: client,
+            key: key,
+            size: size,
+            data: Some(Vec::new()),
+        }
+    }
+
+    fn publish(&mut self) {
+        let data = match self.data.take() {
+            Some(data) => data,
+            None => return,
+        };
+        if data.len() as u64 != self.size {
+            return;
+        }
+        if let Err(e) = self.client.put(&self.key, &data) {
+            debug!(error = %e, "failed to put to the cache server");
+        }
+    }
+}
+
+impl<R: Read> Read for Publisher<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.publish();
+        } else if let Some(ref mut data) = self.data {
+            data.extend_from_slice(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Publisher<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = self.inner.seek(pos)?;
+        if self.data.as_ref().map_or(false, |d| d.len() as u64 != pos) {
+            self.data = None;
+        }
+        Ok(pos)
+    }
+}
+
+// reads at offsets seek, so that the contents are only kept while read in order.
+impl<R: Read + Seek> SeekableRead for Publisher<R> {}
+
+#[test]
+fn test_shared_cache() {
+    use std::io::Cursor;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("showfs/cache.sock");
+    let listener = bind(&path).unwrap();
+    thread::spawn(move || run(listener, 10));
+    let client = Arc::new(Client::new(&path));
+
+    assert_eq!(client.get("a").unwrap(), None);
+    assert!(client.put("a", b"aaaa").unwrap());
+    assert!(client.put("b", b"bbbb").unwrap());
+    assert_eq!(client.get("a").unwrap(), Some(b"aaaa".to_vec()));
+    // b is the least recently used.
+    assert!(client.put("c", b"cccc").unwrap());
+    assert_eq!(client.get("b").unwrap(), None);
+    assert_eq!(client.get("a").unwrap(), Some(b"aaaa".to_vec()));
+    assert!(!client.put("d", &[0; 11]).unwrap());
+
+    // read to the end.
+    let mut r = Publisher::new(Cursor::new(b"012".to_vec()), client.clone(), "e".into(), 3);
+    let mut buf = Vec::new();
+    r.read_to_end(&mut buf).unwrap();
+    assert_eq!(client.get("e").unwrap(), Some(b"012".to_vec()));
+    // seeked in the middle.
+    let mut r = Publisher::new(Cursor::new(b"012".to_vec()), client.clone(), "f".into(), 3);
+    r.seek(SeekFrom::Start(1)).unwrap();
+    r.read_to_end(&mut buf).unwrap();
+    assert_eq!(client.get("f").unwrap(), None);
+
+    // requests over the limit or of wrong checksums are refused.
+    let refused = |request: &str, data: &[u8]| {
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.write_all(data).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.is_empty()
+    };
+    let too_large = format!("PUT g {} {}\n", MAX_MEMBER_BYTES + 1, checksum(b""));
+    assert!(refused(&too_large, b""));
+    assert!(refused(&format!("PUT g 3 {}\n", checksum(b"xyz")), b"012"));
+    assert!(!refused(&format!("PUT g 3 {}\n", checksum(b"012")), b"012"));
+
+    let a = member_key(Path::new("/a.zip"), Timespec::new(1, 0), 2, Path::new("x"));
+    let b = member_key(Path::new("/a.zip"), Timespec::new(1, 1), 2, Path::new("x"));
+    assert_eq!(a.len(), 64);
+    assert_ne!(a, b);
+}
+
+#[test]
+fn test_bind() {
+    let dir = tempfile::tempdir().unwrap();
+    // the directory is made only for the user.
+    let path = dir.path().join("showfs/cache.sock");
+    let listener = bind(&path).unwrap();
+    let mode = |path: &Path| fs::metadata(path).unwrap().mode() & 0o777;
+    assert_eq!(mode(path.parent().unwrap()), 0o700);
+    assert_eq!(mode(&path), 0o600);
+    // stale sockets are replaced, but not live ones.
+    assert!(bind(&path).is_err());
+    drop(listener);
+    bind(&path).unwrap();
+
+    // other files are not removed.
+    let file = dir.path().join("showfs/file");
+    fs::write(&file, b"data").unwrap();
+    assert!(bind(&file).is_err());
+    assert_eq!(fs::read(&file).unwrap(), b"data");
+    // nor are sockets in directories which others can access.
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(bind(&dir.path().join("cache.sock")).is_err());
+}
@@ -0,0 +1,269 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// bucket i counts latencies below 2^(i+1) microseconds, the last one counts the rest.
+const BUCKETS: usize = 32;
+
+/// Histogram counts latencies in power of 2 buckets.
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum: Duration,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: [0; BUCKETS],
+            count: 0,
+            sum: Duration::from_secs(0),
+        }
+    }
+
+    pub fn record(&mut self, d: Duration) {
+        let us = d.as_micros() as u64;
+        let i = (64 - (us | 1).leading_zeros() as usize - 1).min(BUCKETS - 1);
+        self.buckets[i] += 1;
+        self.count += 1;
+        self.sum += d;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::from_secs(0);
+        }
+        self.sum / self.count as u32
+    }
+
+    // the upper bound of the bucket which has the p-th percentile.
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::from_secs(0);
+        }
+        let rank = (self.count as f64 * p / 100.0).ceil().max(1.0) as u64;
+        let mut n = 0;
+        for (i, c) in self.buckets.iter().enumerate() {
+            n += c;
+            if n >= rank {
+                return Duration::from_micros(1 << (i + 1));
+            }
+        }
+        Duration::from_micros(1 << BUCKETS)
+    }
+}
+
+/// Activity tells other threads whether an operation is running.
+pub struct Activity {
+    start: Instant,
+    // milliseconds from start to the beginning of the running operation, plus 1.
+    // 0 if no operation is running.
+    busy_since: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Activity {
+        Activity {
+            start: Instant::now(),
+            busy_since: AtomicU64::new(0),
+        }
+    }
+
+    fn begin(&self) {
+        let ms = self.start.elapsed().as_millis() as u64 + 1;
+        self.busy_since.store(ms, Ordering::Relaxed);
+    }
+
+    fn end(&self) {
+        self.busy_since.store(0, Ordering::Relaxed);
+    }
+
+    // how long the running operation takes, None if idle.
+    pub fn busy_for(&self) -> Option<Duration> {
+        match self.busy_since.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(self.start.elapsed() - Duration::from_millis(ms - 1)),
+        }
+    }
+}
+
+/// ScrubStats is the progress and the findings of scrubbing.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct ScrubStats {
+    // finished passes over what is kept.
+    pub passes: u64,
+    // entries checked in the current pass.
+    pub checked: u64,
+    // entries evicted as their sources are modified, or by the checksums of their contents.
+    pub stale: u64,
+    pub corrupt: u64,
+}
+
+/// Counters are counts kept by others, like viewers, which are shown in the stats.
+pub trait Counters {
+    fn name(&self) -> &str;
+    fn counts(&self) -> Vec<(&'static str, u64)>;
+}
+
+/// Stats collects latencies of filesystem operations.
+pub struct Stats {
+    ops: BTreeMap<&'static str, Histogram>,
+    activity: Arc<Activity>,
+    // None unless scrubbing is enabled.
+    scrub: Option<ScrubStats>,
+    counters: Vec<Arc<dyn Counters>>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            ops: BTreeMap::new(),
+            activity: Arc::new(Activity::new()),
+            scrub: None,
+            counters: Vec::new(),
+        }
+    }
+
+    pub fn scrub_mut(&mut self) -> &mut ScrubStats {
+        self.scrub.get_or_insert_with(ScrubStats::default)
+    }
+
+    pub fn add_counters(&mut self, counters: Arc<dyn Counters>) {
+        self.counters.push(counters);
+    }
+
+    pub fn activity(&self) -> Arc<Activity> {
+        self.activity.clone()
+    }
+
+    pub fn record(&mut self, op: &'static str, d: Duration) {
+        self.ops.entry(op).or_insert_with(Histogram::new).record(d)
+    }
+
+    pub fn get(&self, op: &str) -> Option<&Histogram> {
+        self.ops.get(op)
+    }
+}
+
+// one line per operation, like "read count=10 p50=64us p95=1024us p99=2048us".
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (op, h) in self.ops.iter() {
+            writeln!(
+                f,
+                "{} count={} p50={}us p95={}us p99={}us",
+                op,
+                h.count(),
+                h.percentile(50.0).as_micros(),
+                h.percentile(95.0).as_micros(),
+                h.percentile(99.0).as_micros()
+            )?;
+        }
+        if let Some(ref s) = self.scrub {
+            writeln!(
+                f,
+                "scrub passes={} checked={} stale={} corrupt={}",
+                s.passes, s.checked, s.stale, s.corrupt
+            )?;
+        }
+        // like "archive_warnings logged=1 suppressed=10".
+        for c in self.counters.iter() {
+            write!(f, "{}", c.name())?;
+            for (key, value) in c.counts() {
+                write!(f, " {}={}", key, value)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Timer records the time until it is dropped.
+pub struct Timer {
+    stats: Rc<RefCell<Stats>>,
+    op: &'static str,
+    start: Instant,
+}
+
+impl Timer {
+    pub fn new(stats: &Rc<RefCell<Stats>>, op: &'static str) -> Timer {
+        stats.borrow().activity.begin();
+        Timer {
+            stats: stats.clone(),
+            op: op,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let mut stats = self.stats.borrow_mut();
+        stats.activity.end();
+        stats.record(self.op, self.start.elapsed());
+    }
+}
+
+#[test]
+fn test_histogram() {
+    let mut h = Histogram::new();
+    assert_eq!(h.percentile(50.0), Duration::from_secs(0));
+    for _ in 0..90 {
+        h.record(Duration::from_micros(100));
+    }
+    for _ in 0..10 {
+        h.record(Duration::from_millis(10));
+    }
+    assert_eq!(h.count(), 100);
+    // 100us is in [64us, 128us), and 10ms is in [8192us, 16384us).
+    assert_eq!(h.percentile(50.0), Duration::from_micros(128));
+    assert_eq!(h.percentile(90.0), Duration::from_micros(128));
+    assert_eq!(h.percentile(95.0), Duration::from_micros(16384));
+    assert_eq!(h.mean(), Duration::from_micros(1090));
+}
+
+#[test]
+fn test_stats() {
+    let stats = Rc::new(RefCell::new(Stats::new()));
+    let activity = stats.borrow().activity();
+    let timer = Timer::new(&stats, "read");
+    assert!(activity.busy_for().is_some());
+    drop(timer);
+    assert!(activity.busy_for().is_none());
+    stats
+        .borrow_mut()
+        .record("lookup", Duration::from_micros(3));
+    assert_eq!(stats.borrow().get("read").unwrap().count(), 1);
+    assert_eq!(
+        stats.borrow().to_string().lines().next(),
+        Some("lookup count=1 p50=4us p95=4us p99=4us")
+    );
+    stats.borrow_mut().scrub_mut().stale += 1;
+    assert_eq!(
+        stats.borrow().to_string().lines().last(),
+        Some("scrub passes=0 checked=0 stale=1 corrupt=0")
+    );
+
+    struct Warnings;
+    impl Counters for Warnings {
+        fn name(&self) -> &str {
+            "warnings"
+        }
+        fn counts(&self) -> Vec<(&'static str, u64)> {
+            vec![("logged", 1), ("suppressed", 2)]
+        }
+    }
+    stats.borrow_mut().add_counters(Arc::new(Warnings));
+    assert_eq!(
+        stats.borrow().to_string().lines().last(),
+        Some("warnings logged=1 suppressed=2")
+    );
+}
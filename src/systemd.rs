@@ -0,0 +1,115 @@
+use libc;
+
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::stats::Activity;
+
+/// sends the state to systemd like sd_notify, returns false if not run by systemd.
+pub fn notify(state: &str) -> Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let path = path
+        .into_string()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "NOTIFY_SOCKET is not utf-8"))?;
+    // a leading @ means the abstract namespace.
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(&path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+// the interval which systemd expects pings in, if the watchdog is for this process.
+fn watchdog_timeout() -> Option<Duration> {
+    if let Some(pid) = env::var("WATCHDOG_PID").ok() {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec))
+}
+
+/// pings the systemd watchdog at half the timeout.
+/// pings stop while an operation takes longer than the timeout, so that systemd
+/// restarts the hung mount.
+pub fn start_watchdog(activity: Arc<Activity>) -> Result<()> {
+    let timeout = match watchdog_timeout() {
+        Some(timeout) => timeout,
+        None => return Ok(()),
+    };
+    thread::Builder::new()
+        .name("watchdog".to_string())
+        .spawn(move || loop {
+            thread::sleep(timeout / 2);
+            match activity.busy_for() {
+                Some(busy) if busy >= timeout => {
+                    error!("an operation is running for {:?}, no ping", busy)
+                }
+                _ => {
+                    if let Err(e) = notify("WATCHDOG=1") {
+                        warn!("failed to ping watchdog: {}", e);
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+// the first fd passed by systemd, SD_LISTEN_FDS_START.
+const LISTEN_FDS_START: libc::c_int = 3;
+
+/// showfs does not accept a /dev/fuse fd passed by socket activation, as fuse 0.3 can only
+/// mount by itself. passed fds are closed, so that they are not held open while showfs serves
+/// another /dev/fuse. returns the number of closed fds, called before any file is opened.
+pub fn close_passed_fds() -> libc::c_int {
+    // fds are for this process only if systemd says so.
+    match env::var("LISTEN_PID") {
+        Ok(ref pid) if pid.parse() == Ok(process::id()) => {}
+        _ => return 0,
+    }
+    let n = match env::var("LISTEN_FDS").map(|n| n.parse::<libc::c_int>()) {
+        Ok(Ok(n)) if n > 0 => n,
+        _ => return 0,
+    };
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + n {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+    // not to pass them to workers.
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    n
+}
+
+#[test]
+fn test_notify() {
+    use std::os::unix::net::UnixDatagram;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notify");
+    let socket = UnixDatagram::bind(&path).unwrap();
+    env::set_var("NOTIFY_SOCKET", &path);
+    assert!(notify("READY=1").unwrap());
+    let mut buf = [0; 16];
+    let n = socket.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"READY=1");
+    env::remove_var("NOTIFY_SOCKET");
+    assert!(!notify("READY=1").unwrap());
+}
@@ -0,0 +1,498 @@
+use fuse;
+use libc;
+use time;
+
+use self::fuse::{FileAttr, FileType};
+use self::time::Timespec;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{Cursor, Error, Read, Result};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+#[cfg(feature = "rust-backend")]
+use std::time::Duration;
+
+use crate::archive::Metadata;
+#[cfg(feature = "rust-backend")]
+use crate::archive::{self, Backend, Options, PageManager};
+use crate::control::Control;
+use crate::fs::{self, Entry, SeekableRead, ViewContext, Viewer};
+#[cfg(feature = "rust-backend")]
+use crate::physical;
+use crate::stats::Stats;
+
+/// Fault is how operations of an entry fail, for tests of error paths.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Fault {
+    // getattr, open, lookup and listing fail with the errno.
+    Errno(i32),
+    // they panic.
+    Panic,
+}
+
+enum Node {
+    File(Vec<u8>),
+    Dir,
+}
+
+struct Inner {
+    // by paths from the root, which is the empty path.
+    nodes: RefCell<BTreeMap<PathBuf, Node>>,
+    faults: RefCell<BTreeMap<PathBuf, Fault>>,
+    xattrs: RefCell<BTreeMap<PathBuf, Vec<(OsString, Vec<u8>)>>>,
+    opens: Cell<usize>,
+}
+
+impl Inner {
+    fn check(&self, path: &Path) -> Result<()> {
+        match self.faults.borrow().get(path) {
+            Some(&Fault::Errno(errno)) => Err(Error::from_raw_os_error(errno)),
+            Some(&Fault::Panic) => panic!("fault of {}", path.display()),
+            None => Ok(()),
+        }
+    }
+
+    fn entry(self: &Rc<Self>, path: PathBuf) -> Entry {
+        let is_dir = match self.nodes.borrow().get(&path) {
+            Some(&Node::Dir) => true,
+            _ => false,
+        };
+        let name = path.file_name().unwrap_or_default().to_os_string();
+        let e = MemEntry {
+            inner: self.clone(),
+            path: path,
+            name: name,
+        };
+        if is_dir {
+            Entry::Dir(Box::new(e))
+        } else {
+            Entry::File(Box::new(e))
+        }
+    }
+
+    fn attr(&self, path: &Path) -> Result<FileAttr> {
+        self.check(path)?;
+        let (kind, perm, size) = match self.nodes.borrow().get(path) {
+            Some(&Node::File(ref data)) => (FileType::RegularFile, 0o644, data.len() as u64),
+            Some(&Node::Dir) => (FileType::Directory, 0o755, 0),
+            None => return Err(Error::from_raw_os_error(libc::ENOENT)),
+        };
+        // fixed, so that tests do not depend on the time or the user.
+        let t = Timespec::new(0, 0);
+        Ok(FileAttr {
+            ino: 0,
+            size: size,
+            blocks: (size + 511) / 512,
+            atime: t,
+            mtime: t,
+            ctime: t,
+            crtime: t,
+            kind: kind,
+            perm: perm,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            flags: 0,
+        })
+    }
+
+    fn listxattr(&self, path: &Path) -> Result<Vec<OsString>> {
+        self.check(path)?;
+        let xattrs = self.xattrs.borrow();
+        let names = xattrs.get(path).into_iter().flatten();
+        Ok(names.map(|&(ref name, _)| name.clone()).collect())
+    }
+
+    fn getxattr(&self, path: &Path, name: &OsStr) -> Result<Vec<u8>> {
+        self.check(path)?;
+        let xattrs = self.xattrs.borrow();
+        xattrs
+            .get(path)
+            .into_iter()
+            .flatten()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref value)| value.clone())
+            .ok_or_else(|| Error::from_raw_os_error(libc::ENODATA))
+    }
+}
+
+/// MemTree is a tree of files and directories in memory, which can be changed while its
+/// entries are used, for tests which need neither fuse nor files on the disk.
+/// entries are listed in the order of their names.
+pub(crate) struct MemTree {
+    inner: Rc<Inner>,
+}
+
+impl MemTree {
+    pub fn new() -> MemTree {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::new(), Node::Dir);
+        MemTree {
+            inner: Rc::new(Inner {
+                nodes: RefCell::new(nodes),
+                faults: RefCell::new(BTreeMap::new()),
+                xattrs: RefCell::new(BTreeMap::new()),
+                opens: Cell::new(0),
+            }),
+        }
+    }
+
+    // the path relative to the root, like a/b for /a/b.
+    fn key<P: AsRef<Path>>(path: P) -> PathBuf {
+        path.as_ref()
+            .components()
+            .filter(|c| match *c {
+                Component::Normal(_) => true,
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// adds the directory and its parents.
+    pub fn dir<P: AsRef<Path>>(&self, path: P) -> &Self {
+        let mut nodes = self.inner.nodes.borrow_mut();
+        let key = MemTree::key(path);
+        for dir in key.ancestors() {
+            nodes.entry(dir.to_path_buf()).or_insert(Node::Dir);
+        }
+        drop(nodes);
+        self
+    }
+
+    /// adds or replaces the file with the contents, adding its parents.
+    pub fn file<P: AsRef<Path>>(&self, path: P, data: &[u8]) -> &Self {
+        let key = MemTree::key(path);
+        if let Some(parent) = key.parent() {
+            self.dir(parent);
+        }
+        let node = Node::File(data.to_vec());
+        self.inner.nodes.borrow_mut().insert(key, node);
+        self
+    }
+
+    /// removes the entry and everything under it.
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> &Self {
+        let key = MemTree::key(path);
+        let mut nodes = self.inner.nodes.borrow_mut();
+        let removed: Vec<_> = nodes
+            .keys()
+            .filter(|p| p.starts_with(&key))
+            .cloned()
+            .collect();
+        for p in removed.iter() {
+            nodes.remove(p);
+        }
+        drop(nodes);
+        self
+    }
+
+    /// makes operations of the entry fail.
+    pub fn fault<P: AsRef<Path>>(&self, path: P, fault: Fault) -> &Self {
+        self.inner
+            .faults
+            .borrow_mut()
+            .insert(MemTree::key(path), fault);
+        self
+    }
+
+    pub fn xattr<P: AsRef<Path>>(&self, path: P, name: &str, value: &[u8]) -> &Self {
+        let mut xattrs = self.inner.xattrs.borrow_mut();
+        let values = xattrs.entry(MemTree::key(path)).or_insert_with(Vec::new);
+        values.push((OsString::from(name), value.to_vec()));
+        drop(xattrs);
+        self
+    }
+
+    /// the root directory, whose entries see changes of the tree.
+    pub fn root(&self) -> Entry {
+        self.inner.entry(PathBuf::new())
+    }
+
+    /// how many times files are opened, like by caches missing.
+    pub fn opens(&self) -> usize {
+        self.inner.opens.get()
+    }
+}
+
+struct MemEntry {
+    inner: Rc<Inner>,
+    path: PathBuf,
+    name: OsString,
+}
+
+impl fs::File for MemEntry {
+    fn getattr(&self) -> Result<FileAttr> {
+        self.inner.attr(&self.path)
+    }
+
+    fn open(&self) -> Result<Box<dyn SeekableRead>> {
+        self.inner.check(&self.path)?;
+        let data = match self.inner.nodes.borrow().get(&self.path) {
+            Some(&Node::File(ref data)) => data.clone(),
+            Some(&Node::Dir) => return Err(Error::from_raw_os_error(libc::EISDIR)),
+            None => return Err(Error::from_raw_os_error(libc::ENOENT)),
+        };
+        self.inner.opens.set(self.inner.opens.get() + 1);
+        Ok(Box::new(Cursor::new(data)))
+    }
+
+    fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.inner.listxattr(&self.path)
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.inner.getxattr(&self.path, name)
+    }
+}
+
+impl fs::Dir for MemEntry {
+    fn open(&self) -> Result<Box<dyn Iterator<Item = Result<Entry>>>> {
+        self.inner.check(&self.path)?;
+        let children: Vec<_> = self
+            .inner
+            .nodes
+            .borrow()
+            .keys()
+            .filter(|p| p.parent() == Some(&self.path))
+            .cloned()
+            .collect();
+        let inner = self.inner.clone();
+        Ok(Box::new(
+            children.into_iter().map(move |p| Ok(inner.entry(p))),
+        ))
+    }
+
+    fn lookup(&self, name: &OsStr) -> Result<Entry> {
+        self.inner.check(&self.path)?;
+        let path = self.path.join(name);
+        if !self.inner.nodes.borrow().contains_key(&path) {
+            return Err(Error::from_raw_os_error(libc::ENOENT));
+        }
+        Ok(self.inner.entry(path))
+    }
+
+    fn getattr(&self) -> Result<FileAttr> {
+        self.inner.attr(&self.path)
+    }
+
+    fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    fn listxattr(&self) -> Result<Vec<OsString>> {
+        self.inner.listxattr(&self.path)
+    }
+
+    fn getxattr(&self, name: &OsStr) -> Result<Vec<u8>> {
+        self.inner.getxattr(&self.path, name)
+    }
+}
+
+/// SizedFile is an empty file whose attributes tell the size, which may be changed.
+pub(crate) struct SizedFile(Cell<u64>);
+
+impl SizedFile {
+    pub fn new(size: u64) -> SizedFile {
+        SizedFile(Cell::new(size))
+    }
+
+    pub fn set_size(&self, size: u64) {
+        self.0.set(size)
+    }
+}
+
+impl fs::File for SizedFile {
+    fn getattr(&self) -> Result<FileAttr> {
+        let mut attr = unsafe { mem::zeroed::<FileAttr>() };
+        attr.kind = FileType::RegularFile;
+        attr.size = self.0.get();
+        Ok(attr)
+    }
+
+    fn open(&self) -> Result<Box<dyn SeekableRead>> {
+        Ok(Box::new(Cursor::new(Vec::new())))
+    }
+
+    fn name(&self) -> &OsStr {
+        OsStr::new("file")
+    }
+}
+
+/// Viewed shows a tree by viewers in the order of registration, as the mount does, though
+/// without limits of nesting or mappings of extensions.
+pub(crate) struct Viewed<'a> {
+    viewers: Vec<&'a dyn Viewer>,
+    control: Rc<Control>,
+}
+
+impl<'a> Viewed<'a> {
+    pub fn new(viewers: Vec<&'a dyn Viewer>) -> Viewed<'a> {
+        Viewed {
+            viewers: viewers,
+            control: Rc::new(Control::new(Rc::new(RefCell::new(Stats::new())))),
+        }
+    }
+
+    fn view(&self, e: Entry, path: &Path, parent_attr: Option<FileAttr>) -> Entry {
+        let ctx = ViewContext::new(path.to_path_buf(), parent_attr, self.control.clone());
+        self.viewers.iter().fold(e, |e, v| v.view(e, &ctx))
+    }
+
+    /// the viewed entry at the path, like /a.zip/b.
+    pub fn resolve<P: AsRef<Path>>(&self, root: Entry, path: P) -> Result<Entry> {
+        let mut entry = self.view(root, Path::new("/"), None);
+        let mut current = PathBuf::from("/");
+        for name in MemTree::key(path).iter() {
+            let dir = match entry {
+                Entry::Dir(d) => d,
+                Entry::File(_) => return Err(Error::from_raw_os_error(libc::ENOTDIR)),
+            };
+            current.push(name);
+            entry = self.view(dir.lookup(name)?, &current, dir.getattr().ok());
+        }
+        Ok(entry)
+    }
+
+    /// the contents of the viewed file at the path.
+    pub fn read<P: AsRef<Path>>(&self, root: Entry, path: P) -> Result<Vec<u8>> {
+        let f = match self.resolve(root, path)? {
+            Entry::File(f) => f,
+            Entry::Dir(_) => return Err(Error::from_raw_os_error(libc::EISDIR)),
+        };
+        let mut v = Vec::new();
+        f.open()?.read_to_end(&mut v)?;
+        Ok(v)
+    }
+
+    /// lines telling the viewed entries, parents first and then children in the listed order:
+    /// "/dir/" for directories, "/dir/file 5" for files with their sizes, and "/dir !5" for
+    /// entries whose attributes or listings fail with errno 5.
+    pub fn walk(&self, root: Entry) -> Vec<String> {
+        let mut lines = Vec::new();
+        let root = self.view(root, Path::new("/"), None);
+        self.walk_entry(Path::new("/"), root, &mut lines);
+        lines
+    }
+
+    fn walk_entry(&self, path: &Path, entry: Entry, lines: &mut Vec<String>) {
+        let failed = |e: Error| format!("{} !{}", path.display(), crate::error::errno(&e));
+        let dir = match entry {
+            Entry::File(f) => {
+                lines.push(match f.getattr() {
+                    Ok(attr) => format!("{} {}", path.display(), attr.size),
+                    Err(e) => failed(e),
+                });
+                return;
+            }
+            Entry::Dir(d) => d,
+        };
+        let (attr, entries) = match dir.getattr().and_then(|attr| Ok((attr, dir.open()?))) {
+            Ok(r) => r,
+            Err(e) => {
+                lines.push(failed(e));
+                return;
+            }
+        };
+        lines.push(format!("{}", path.join("").display()));
+        for e in entries {
+            let e = match e {
+                Ok(e) => e,
+                Err(e) => {
+                    lines.push(failed(e));
+                    continue;
+                }
+            };
+            let child = path.join(e.name());
+            let e = self.view(e, &child, Some(attr));
+            self.walk_entry(&child, e, lines);
+        }
+    }
+}
+
+/// the header of a member with mode 0644 and nothing else recorded, which tests update.
+pub fn member<P: AsRef<Path>>(path: P, filetype: libc::mode_t, size: i64) -> Metadata {
+    Metadata {
+        path: path.as_ref().to_path_buf(),
+        size: size,
+        filetype: filetype,
+        perm: 0o644,
+        owner: None,
+        atime: None,
+        mtime: None,
+        ctime: None,
+        birthtime: None,
+        symlink: None,
+        encrypted: false,
+    }
+}
+
+/// a tar archive of the files, with mode 0644 and mtime 0.
+pub fn tar_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for &(path, data) in files {
+        let mut header = tar::Header::new_ustar();
+        header.set_path(path).unwrap();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// the archive file shown as a directory by the rust backend, with 1 MiB of cache.
+#[cfg(feature = "rust-backend")]
+pub fn archive_dir_of(file: Box<dyn fs::File>, mut options: Options) -> archive::Dir {
+    options.set_backend(Backend::Rust);
+    let page_manager = Rc::new(RefCell::new(PageManager::new(1024 * 1024).unwrap()));
+    archive::Dir::new(file, Rc::new(options), page_manager, None)
+}
+
+/// like archive_dir_of, of the archive file on the disk, which is not cached by stat.
+#[cfg(feature = "rust-backend")]
+pub fn archive_dir<P: AsRef<Path>>(path: P, options: Options) -> archive::Dir {
+    let ctx = Rc::new(physical::Context::new(Duration::from_secs(0)));
+    let file = physical::File::new(path.as_ref().to_path_buf(), ctx);
+    archive_dir_of(Box::new(file), options)
+}
+
+#[test]
+fn test_mem_tree() {
+    let tree = MemTree::new();
+    tree.file("/b/c", b"hello").file("a", b"").dir("d/e");
+    tree.xattr("a", "user.x", b"1");
+    let viewed = Viewed::new(Vec::new());
+    assert_eq!(
+        viewed.walk(tree.root()),
+        vec!["/", "/a 0", "/b/", "/b/c 5", "/d/", "/d/e/"]
+    );
+    assert_eq!(viewed.read(tree.root(), "/b/c").unwrap(), b"hello");
+    assert_eq!(tree.opens(), 1);
+    match viewed.resolve(tree.root(), "/a").unwrap() {
+        Entry::File(f) => assert_eq!(f.getxattr(OsStr::new("user.x")).unwrap(), b"1"),
+        Entry::Dir(_) => panic!("not a file"),
+    }
+    let errno = |r: Result<Vec<u8>>| r.err().and_then(|e| e.raw_os_error());
+    assert_eq!(errno(viewed.read(tree.root(), "/b")), Some(libc::EISDIR));
+    assert_eq!(errno(viewed.read(tree.root(), "/a/x")), Some(libc::ENOTDIR));
+
+    // entries see changes, and faults fail them.
+    let b = viewed.resolve(tree.root(), "/b").unwrap();
+    tree.file("/b/c", b"changed")
+        .fault("/d", Fault::Errno(libc::EIO));
+    tree.remove("a");
+    assert_eq!(errno(viewed.read(tree.root(), "/a")), Some(libc::ENOENT));
+    assert_eq!(viewed.walk(b), vec!["/", "/c 7"]);
+    assert_eq!(
+        viewed.walk(tree.root()),
+        vec!["/", "/b/", "/b/c 7", "/d !5"]
+    );
+}
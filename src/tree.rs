@@ -0,0 +1,182 @@
+use libc;
+
+use std::io::{Error, Result};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::control::Control;
+use crate::fs::{self, CompositeViewer, Entry, SeekableRead, ViewContext};
+use crate::physical;
+
+/// VirtualTree is the tree shown by ShowFS, which is walked without fuse.
+/// paths are from the root of the tree, like /a.zip/b.txt.
+pub struct VirtualTree {
+    origin: fs::Origin,
+    physical: Rc<physical::Context>,
+    viewers: Rc<CompositeViewer>,
+    control: Rc<Control>,
+}
+
+impl VirtualTree {
+    pub(crate) fn new(
+        origin: fs::Origin,
+        physical: Rc<physical::Context>,
+        viewers: Rc<CompositeViewer>,
+        control: Rc<Control>,
+    ) -> VirtualTree {
+        VirtualTree {
+            origin: origin,
+            physical: physical,
+            viewers: viewers,
+            control: control,
+        }
+    }
+
+    fn view(&self, e: Entry, path: PathBuf, parent: &dyn fs::Dir) -> Entry {
+        let ctx = ViewContext::new(path, parent.getattr().ok(), self.control.clone());
+        self.viewers.view(e, &ctx)
+    }
+
+    /// the entry at the path.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Result<Entry> {
+        let mut entry =
+            fs::view_origin(&self.origin, &self.physical, &self.viewers, &self.control)?;
+        let mut current = PathBuf::from("/");
+        for c in path.as_ref().components() {
+            let name = match c {
+                Component::RootDir | Component::CurDir => continue,
+                Component::Normal(name) => name,
+                _ => return Err(Error::from_raw_os_error(libc::EINVAL)),
+            };
+            let dir = match entry {
+                Entry::Dir(d) => d,
+                Entry::File(_) => return Err(Error::from_raw_os_error(libc::ENOTDIR)),
+            };
+            current.push(name);
+            entry = self.view(dir.lookup(name)?, current.clone(), &*dir);
+        }
+        Ok(entry)
+    }
+
+    /// the entry of the name in the directory at the parent of the path, which is resolved
+    /// before, so that entries in the same directory are resolved without walking again.
+    pub fn lookup(&self, dir: &dyn fs::Dir, path: &Path) -> Result<Entry> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| Error::from_raw_os_error(libc::EINVAL))?;
+        Ok(self.view(dir.lookup(name)?, path.to_path_buf(), dir))
+    }
+
+    /// the entries of the directory at the path.
+    pub fn list<P: AsRef<Path>>(&self, path: P) -> Result<Vec<Entry>> {
+        let dir = match self.resolve(path.as_ref())? {
+            Entry::Dir(d) => d,
+            Entry::File(_) => return Err(Error::from_raw_os_error(libc::ENOTDIR)),
+        };
+        let parent = Path::new("/").join(path.as_ref());
+        let mut entries = Vec::new();
+        for e in dir.open()? {
+            let e = e?;
+            let path = parent.join(e.name());
+            entries.push(self.view(e, path, &*dir));
+        }
+        Ok(entries)
+    }
+
+    /// the contents of the file at the path.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<Box<dyn SeekableRead>> {
+        match self.resolve(path)? {
+            Entry::File(f) => f.open(),
+            Entry::Dir(_) => Err(Error::from_raw_os_error(libc::EISDIR)),
+        }
+    }
+
+    /// calls f with the path and the entry of the path and everything under it, parents first.
+    /// directories which can not be listed, like broken archives, are skipped with warnings.
+    pub fn walk<P, F>(&self, path: P, mut f: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&Path, &Entry) -> Result<()>,
+    {
+        let path = Path::new("/").join(path.as_ref());
+        let entry = self.resolve(&path)?;
+        self.walk_entry(&path, entry, &mut f)
+    }
+
+    fn walk_entry(
+        &self,
+        path: &Path,
+        entry: Entry,
+        f: &mut dyn FnMut(&Path, &Entry) -> Result<()>,
+    ) -> Result<()> {
+        f(path, &entry)?;
+        if let Entry::Dir(dir) = entry {
+            let entries = match dir.open() {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!(error = ?e, "failed to list {}", path.display());
+                    return Ok(());
+                }
+            };
+            for e in entries {
+                let e = match e {
+                    Ok(e) => e,
+                    Err(e) => {
+                        warn!(error = ?e, "failed to list {}", path.display());
+                        continue;
+                    }
+                };
+                let child = path.join(e.name());
+                let e = self.view(e, child.clone(), &*dir);
+                self.walk_entry(&child, e, f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tree() {
+    use std::fs as stdfs;
+    use std::io::Read;
+
+    let tmp = tempfile::tempdir().unwrap();
+    stdfs::create_dir(tmp.path().join("dir")).unwrap();
+    stdfs::write(tmp.path().join("dir/file"), "hello").unwrap();
+    let tree = fs::ShowFS::new(tmp.path()).into_tree();
+
+    let names: Vec<_> = tree
+        .list("/")
+        .unwrap()
+        .iter()
+        .map(|e| e.name().to_os_string())
+        .collect();
+    assert_eq!(names, vec!["dir"]);
+    let mut s = String::new();
+    tree.open("/dir/file")
+        .unwrap()
+        .read_to_string(&mut s)
+        .unwrap();
+    assert_eq!(s, "hello");
+    assert_eq!(
+        tree.open("/dir").err().and_then(|e| e.raw_os_error()),
+        Some(libc::EISDIR)
+    );
+    assert_eq!(
+        tree.list("/dir/file").err().and_then(|e| e.raw_os_error()),
+        Some(libc::ENOTDIR)
+    );
+    assert!(tree.resolve("/none").is_err());
+
+    let mut paths = Vec::new();
+    tree.walk("/", |path, _| {
+        paths.push(path.to_path_buf());
+        Ok(())
+    })
+    .unwrap();
+    let expect: Vec<_> = ["/", "/dir", "/dir/file"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(paths, expect);
+}